@@ -8,6 +8,7 @@ static void _cmd_cb_help(const char *consolebuf, uint16_t buflen);
 static void _cmd_cb_led(const char *consolebuf, uint16_t buflen);
 static void _cmd_cb_servo(const char *consolebuf, uint16_t buflen);
 static void _cmd_cb_home(const char *consolebuf, uint16_t buflen);
+static void _cmd_cb_status(const char *consolebuf, uint16_t buflen);
 
 ///////////////////////// Defines ///////////////////////////////////
 /* Some useful macros */
@@ -99,6 +100,7 @@ static console_command_t _console_commands[] = {
     {"servo", _cmd_cb_servo, "Move servo to angle"},
     {"led", _cmd_cb_led, "Turn LED on or off"},
     {"home", _cmd_cb_home, "Move all servos to home location"},
+    {"status", _cmd_cb_status, "Report the angle of each servo"},
 };
 
 static char _console_buf[CONSOLE_BUF_LEN];
@@ -145,15 +147,21 @@ static void _process_cmdbuffer(const uint16_t bufidx, uint16_t *readidx) {
         memcpy(tokbuf, tmpbuf, ARRAY_LEN(tokbuf));
 
         /* Check if tmpbuf holds a valid command */
+        bool matched = false;
         for (unsigned int i = 0; i < ARRAY_LEN(_console_commands); i++) {
             /* Tokenize the tokbuf by whitespace */
             char *cmd = strtok(tokbuf, " \n\r");
             if ((cmd != NULL) && (strncmp(_console_commands[i].str, cmd, MIN(bufidx, MAX_COMMAND_LEN)) == 0)) {
                 _console_commands[i].func((const char *)tmpbuf, ARRAY_LEN(tmpbuf));
+                matched = true;
                 break;
             }
             memcpy(tokbuf, tmpbuf, ARRAY_LEN(tokbuf));
         }
+
+        /* Every command gets exactly one reply of either 'ok' or 'error: ...' so the host knows we are done with it */
+        if (!matched)
+            Serial.println("error: Unknown command");
     }
 }
 
@@ -243,6 +251,7 @@ static void _cmd_cb_help(const char *consolebuf, uint16_t buflen) {
         snprintf(buf, ARRAY_LEN(buf), "%s: %s\n", _console_commands[i].str, _console_commands[i].description);
         Serial.print(buf);
     }
+    Serial.println("ok");
 }
 
 static void _cmd_cb_led(const char *consolebuf, uint16_t buflen) {
@@ -257,10 +266,12 @@ static void _cmd_cb_led(const char *consolebuf, uint16_t buflen) {
         } else if (index == 1) {
             if (strncmp(tok, "on", ARRAY_LEN(buf)) == 0) {
                 digitalWrite(PIN_LED, HIGH);
+                Serial.println("ok");
             } else if (strncmp(tok, "off", ARRAY_LEN(buf)) == 0) {
                 digitalWrite(PIN_LED, LOW);
+                Serial.println("ok");
             } else {
-                Serial.println("USAGE: led <on/off>");
+                Serial.println("error: USAGE: led <on/off>");
             }
             return;
         } else {
@@ -270,6 +281,8 @@ static void _cmd_cb_led(const char *consolebuf, uint16_t buflen) {
         index++;
         tok = strtok(NULL, " \n\r");
     }
+
+    Serial.println("error: USAGE: led <on/off>");
 }
 
 static void _cmd_cb_servo(const char *consolebuf, uint16_t buflen) {
@@ -287,7 +300,7 @@ static void _cmd_cb_servo(const char *consolebuf, uint16_t buflen) {
             // Parse out the servo id
             int a = atoi(tok);
             if ((a < 0) || (a >= NSERVOS)) {
-                Serial.println("Illegal servo ID");
+                Serial.println("error: Illegal servo ID");
                 return;
             } else {
                 servoid = (servo_id_t)a;
@@ -296,13 +309,14 @@ static void _cmd_cb_servo(const char *consolebuf, uint16_t buflen) {
             // Parse out the angle and execute the command
             int a = atoi(tok);
             if ((a < _arm_joints[servoid].lower_limit) || (a > _arm_joints[servoid].upper_limit)) {
-                Serial.println("Illegal angle");
+                Serial.println("error: Illegal angle");
                 Serial.print("Angle for id "); Serial.print(id); Serial.print(" should be between ");
                 Serial.print(_arm_joints[servoid].lower_limt); Serial.print(" and ");
                 Serial.println(_arm_joints[servoid].upper_limit);
                 return;
             } else {
                 _servo_goto(servoid, a);
+                Serial.println("ok");
                 return;
             }
         } else {
@@ -314,7 +328,7 @@ static void _cmd_cb_servo(const char *consolebuf, uint16_t buflen) {
     }
 
     if (index != 3)
-        Serial.println("USAGE: servo <id> <angle>");
+        Serial.println("error: USAGE: servo <id> <angle>");
 }
 
 static void _cmd_cb_home(const char *consolebuf, uint16_t buflen) {
@@ -323,4 +337,16 @@ static void _cmd_cb_home(const char *consolebuf, uint16_t buflen) {
     _servo_goto(SERVO_HAND, DEFAULT_ANGLE_HAND);
     _servo_goto(SERVO_SHOULDER, DEFAULT_ANGLE_SHOULDER);
     _servo_goto(SERVO_WRIST, DEFAULT_ANGLE_WRIST);
+    Serial.println("ok");
+}
+
+static void _cmd_cb_status(const char *consolebuf, uint16_t buflen) {
+    /* One line of 'status <angle of servo 0> ... <angle of servo N-1>' */
+    Serial.print("status");
+    for (int i = 0; i < NSERVOS; i++) {
+        Serial.print(" ");
+        Serial.print(_arm_joints[i].angle);
+    }
+    Serial.println("");
+    Serial.println("ok");
 }
@@ -0,0 +1,29 @@
+//! Tells the experiment which git revision it is being built from, for its results manifest (see `records`).
+//! Nothing is set when building outside a git checkout, or without git.
+
+use std::process::Command;
+
+fn main() {
+    if let Some(revision) = git(&["rev-parse", "HEAD"]) {
+        println!("cargo:rustc-env=EXPERIMENT_GIT_REVISION={}", revision.trim());
+        if let Some(status) = git(&["status", "--porcelain", "--untracked-files=no"]) {
+            println!("cargo:rustc-env=EXPERIMENT_GIT_DIRTY={}", !status.trim().is_empty());
+        }
+    }
+
+    // Build again when the checkout moves to another commit, or its files change
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/logs/HEAD");
+    println!("cargo:rerun-if-changed=../.git/index");
+    println!("cargo:rerun-if-changed=src");
+}
+
+/// What git prints for `args`, if it succeeds.
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if output.status.success() {
+        String::from_utf8(output.stdout).ok()
+    } else {
+        None
+    }
+}
@@ -0,0 +1,403 @@
+//! Checkpoints of a genetic or evolution strategy run, which `experiment --resume <dir>` carries on from exactly as
+//! if the run had never stopped.
+//!
+//! A checkpoint is a directory holding:
+//!
+//! * `config.<ext>`: the config file the run was started with,
+//! * `state.bin`: the seed, which episode is next, the experiment state (see `expstate::Snapshot`), and how long each
+//!   of the structured results' files was (see `records::Recorder::lengths`),
+//! * `population/network_<i>.bin`: the last generation's networks, as `weightfile` saves them,
+//! * `results/episode_<n>.txt`: what each episode so far has recorded,
+//! * `tables/<name>`: the structured results' files, as far as they had got (see `records`).
+//!
+//! Episodes draw their random numbers from their own generators (see `episode_rng`), so that is all there is to keep
+//! of the random number generator. Checkpoints are written next to the last one (in `<dir>.partial`), and only replace
+//! it once complete. If the run stops while one is replacing the other, whichever of the two is complete is loaded.
+
+use rand::SeedableRng;
+use std::f64;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::expconfig::ExperimentConfig;
+use super::expresults::ExperimentResults;
+use super::expstate::{ExperimentState, Snapshot};
+use super::network::MultilayerPerceptron;
+use super::weightfile::{self, Reader};
+
+/// The version of `state.bin` written by `save`. Version 1 had no structured results.
+pub const CHECKPOINT_VERSION: u32 = 2;
+
+/// `state.bin` starts with these bytes.
+const MAGIC: &[u8; 8] = b"ROBOARMC";
+
+/// A checkpoint, loaded by `load`.
+pub struct Checkpoint {
+    /// The run's seed, which may have been drawn at random (if the config's seed is "none").
+    pub seed: u64,
+    /// The episode to carry on from.
+    pub next_episode: u64,
+    /// The name and length of each of the structured results' files.
+    pub records: Vec<(String, u64)>,
+    /// Where the checkpoint was loaded from.
+    dir: PathBuf,
+    snapshot: Snapshot,
+    networks: Vec<MultilayerPerceptron>,
+    /// What each episode before `next_episode` recorded.
+    logs: Vec<String>,
+}
+
+impl Checkpoint {
+    /// Puts the experiment state and results back as they were, and returns the episode to carry on from.
+    pub fn resume(self, experiment: &ExperimentConfig, results: &mut ExperimentResults) -> Result<(ExperimentState, u64), String> {
+        for (episode, log) in self.logs.into_iter().enumerate() {
+            results.restore_episode(episode as u64, log);
+        }
+        let state = ExperimentState::restore(experiment, self.snapshot, self.networks)?;
+        Ok((state, self.next_episode))
+    }
+
+    /// Puts the structured results' files back in the results directory as they were at the checkpoint, in case they
+    /// were lost since (or the run is resumed somewhere else). Checkpoints from before the files were kept leave
+    /// them be.
+    pub fn restore_tables(&self, experiment: &ExperimentConfig) -> io::Result<()> {
+        let tables = self.dir.join("tables");
+        if !tables.is_dir() {
+            return Ok(());
+        }
+        fs::create_dir_all(&experiment.results_dir)?;
+        for &(ref name, _) in self.records.iter() {
+            fs::copy(tables.join(name), Path::new(&experiment.results_dir).join(name))?;
+        }
+        Ok(())
+    }
+}
+
+/// The random number generator for an episode. Episode 0's is seeded with the seed itself.
+pub fn episode_rng(seed: u64, episode: u64) -> rand::StdRng {
+    rand::StdRng::seed_from_u64(seed ^ episode.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+}
+
+/// The config file kept in the checkpoint directory `dir`.
+pub fn config_path(dir: &str) -> Result<PathBuf, String> {
+    let dir = complete_dir(dir);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) => return Err(format!("Could not read the checkpoint {}: {}", dir.display(), e)),
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        if entry.path().file_stem().map_or(false, |stem| stem == "config") {
+            return Ok(entry.path());
+        }
+    }
+    Err(format!("The checkpoint {} has no config file.", dir.display()))
+}
+
+/// Where the checkpoint `dir` really is: `dir`, unless the run stopped after setting it aside for a new checkpoint but
+/// before moving the new one in, in which case the new one is still at `<dir>.partial`. A checkpoint is only complete
+/// once its `state.bin`, which is written last, is there.
+fn complete_dir(dir: &str) -> PathBuf {
+    let partial = PathBuf::from(format!("{}.partial", dir));
+    if !is_complete(Path::new(dir)) && is_complete(&partial) {
+        partial
+    } else {
+        PathBuf::from(dir)
+    }
+}
+
+fn is_complete(dir: &Path) -> bool {
+    dir.join("state.bin").is_file()
+}
+
+/// Checkpoints the run into `experiment.checkpoint`, with the config file from `configpath`, before `next_episode`.
+/// `records` has the name and length of each of the structured results' files.
+pub fn save(experiment: &ExperimentConfig, configpath: &Path, next_episode: u64, state: &ExperimentState, results: &ExperimentResults, records: &[(String, u64)]) -> io::Result<()> {
+    let dir = Path::new(&experiment.checkpoint);
+    let partial = PathBuf::from(format!("{}.partial", experiment.checkpoint));
+    let old = PathBuf::from(format!("{}.old", experiment.checkpoint));
+
+    // If the last checkpoint never made it into place, put it there before starting on this one
+    if complete_dir(&experiment.checkpoint) == partial {
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+        fs::rename(&partial, dir)?;
+    }
+    if partial.exists() {
+        fs::remove_dir_all(&partial)?;
+    }
+    fs::create_dir_all(partial.join("population"))?;
+    fs::create_dir_all(partial.join("results"))?;
+    fs::create_dir_all(partial.join("tables"))?;
+
+    let extension = configpath.extension().and_then(|extension| extension.to_str()).unwrap_or("yaml");
+    fs::copy(configpath, partial.join(format!("config.{}", extension)))?;
+
+    let snapshot = state.snapshot();
+    for (i, network) in state.networks.iter().enumerate() {
+        let metadata = weightfile::Metadata {
+            seed: experiment.seed,
+            generation: snapshot.generation,
+            fitness: snapshot.evaluations.get(i).cloned().unwrap_or(f64::NAN),
+            config_hash: weightfile::hash_config(experiment),
+        };
+        let path = partial.join("population").join(format!("network_{}.bin", i));
+        weightfile::save(network, &metadata, &path.to_string_lossy().to_string())?;
+    }
+    for episode in 0..next_episode {
+        let log = results.episode_log(episode).map_or("", |log| log.as_str());
+        fs::write(partial.join("results").join(format!("episode_{}.txt", episode)), log)?;
+    }
+    for &(ref name, length) in records.iter() {
+        let table = partial.join("tables").join(name);
+        fs::copy(Path::new(&experiment.results_dir).join(name), &table)?;
+        fs::OpenOptions::new().write(true).open(&table)?.set_len(length)?;
+    }
+    fs::write(partial.join("state.bin"), encode(experiment.seed, next_episode, &snapshot, records))?;
+
+    // Only now that the new checkpoint is whole does it replace the old one. The old one is set aside rather than
+    // deleted until the new one is in its place, so that there is always a complete checkpoint to load.
+    if old.exists() {
+        fs::remove_dir_all(&old)?;
+    }
+    if dir.exists() {
+        fs::rename(dir, &old)?;
+    }
+    fs::rename(&partial, dir)?;
+    if old.exists() {
+        fs::remove_dir_all(&old)?;
+    }
+    Ok(())
+}
+
+/// Loads the checkpoint in `dir`.
+pub fn load(dir: &str) -> Result<Checkpoint, String> {
+    let dir = complete_dir(dir);
+    let bytes = match fs::read(dir.join("state.bin")) {
+        Ok(bytes) => bytes,
+        Err(e) => return Err(format!("Could not read the checkpoint's state from {}: {}", dir.join("state.bin").display(), e)),
+    };
+    let (seed, next_episode, snapshot, records) = match decode(&bytes) {
+        Ok(decoded) => decoded,
+        Err(msg) => return Err(format!("Could not load the checkpoint's state from {}: {}", dir.join("state.bin").display(), msg)),
+    };
+
+    let mut networks = Vec::new();
+    for i in 0..snapshot.evaluations.len() {
+        let path = dir.join("population").join(format!("network_{}.bin", i)).to_string_lossy().to_string();
+        match weightfile::load(&path) {
+            Ok(Some(saved)) => networks.push(saved.network),
+            Ok(None) => return Err(format!("Could not load the network from file {}: it isn't in a checkpoint's format", path)),
+            Err(e) => return Err(format!("Could not load the network from file {}: {}", path, e)),
+        }
+    }
+
+    let mut logs = Vec::new();
+    for episode in 0..next_episode {
+        let path = dir.join("results").join(format!("episode_{}.txt", episode));
+        match fs::read_to_string(&path) {
+            Ok(log) => logs.push(log),
+            Err(e) => return Err(format!("Could not read episode {}'s results from {}: {}", episode, path.display(), e)),
+        }
+    }
+
+    Ok(Checkpoint {
+        seed: seed,
+        next_episode: next_episode,
+        records: records,
+        dir: dir,
+        snapshot: snapshot,
+        networks: networks,
+        logs: logs,
+    })
+}
+
+/// The magic bytes, version, and a checksum of the rest: the seed, the next episode, the generation, the mutation
+/// scale, the best fitness (if any), the fitnesses and their objectives, the evolution strategy's state (if any), and
+/// the structured results' files' names and lengths.
+fn encode(seed: u64, next_episode: u64, snapshot: &Snapshot, records: &[(String, u64)]) -> Vec<u8> {
+    let mut body = Vec::new();
+    weightfile::put_u64(&mut body, seed);
+    weightfile::put_u64(&mut body, next_episode);
+    weightfile::put_u64(&mut body, snapshot.generation);
+    weightfile::put_u64(&mut body, snapshot.mutation_scale.to_bits());
+    put_optional(&mut body, snapshot.best_fitness.map(|best| vec![best]));
+
+    let nterms = snapshot.objectives.first().map_or(0, |objectives| objectives.len());
+    weightfile::put_u64(&mut body, snapshot.evaluations.len() as u64);
+    weightfile::put_u64(&mut body, nterms as u64);
+    for (fitness, objectives) in snapshot.evaluations.iter().zip(snapshot.objectives.iter()) {
+        weightfile::put_u64(&mut body, fitness.to_bits());
+        for objective in objectives.iter() {
+            weightfile::put_u64(&mut body, objective.to_bits());
+        }
+    }
+    put_optional(&mut body, snapshot.strategy.clone());
+    weightfile::put_u64(&mut body, records.len() as u64);
+    for &(ref name, length) in records.iter() {
+        weightfile::put_u64(&mut body, name.len() as u64);
+        body.extend(name.as_bytes());
+        weightfile::put_u64(&mut body, length);
+    }
+
+    let mut bytes = MAGIC.to_vec();
+    weightfile::put_u32(&mut bytes, CHECKPOINT_VERSION);
+    weightfile::put_u32(&mut bytes, weightfile::crc32(&body));
+    bytes.extend(body);
+    bytes
+}
+
+fn decode(bytes: &[u8]) -> Result<(u64, u64, Snapshot, Vec<(String, u64)>), String> {
+    if !bytes.starts_with(MAGIC) {
+        return Err("it isn't a checkpoint".to_string());
+    }
+    let mut reader = Reader { bytes: bytes, at: MAGIC.len() };
+    let version = reader.u32("the version")?;
+    if version < 1 || version > CHECKPOINT_VERSION {
+        return Err(format!("it is version {}, but only versions 1 to {} can be read", version, CHECKPOINT_VERSION));
+    }
+    let checksum = reader.u32("the checksum")?;
+    if weightfile::crc32(&bytes[reader.at..]) != checksum {
+        return Err("its checksum doesn't match its contents, so it is corrupt or was cut short".to_string());
+    }
+
+    let seed = reader.u64("the seed")?;
+    let next_episode = reader.u64("the next episode")?;
+    let generation = reader.u64("the generation")?;
+    let mutation_scale = f64::from_bits(reader.u64("the mutation scale")?);
+    let best_fitness = read_optional(&mut reader, "the best fitness")?.and_then(|best| best.first().cloned());
+
+    let nnetworks = reader.u64("the number of networks")? as usize;
+    let nterms = reader.u64("the number of objectives")? as usize;
+    let mut evaluations = Vec::new();
+    let mut objectives = Vec::new();
+    for i in 0..nnetworks {
+        let what = format!("network {}'s fitness", i);
+        evaluations.push(f64::from_bits(reader.u64(&what)?));
+        objectives.push(reader.f64s(nterms, &what)?);
+    }
+    let strategy = read_optional(&mut reader, "the evolution strategy's state")?;
+    let mut records = Vec::new();
+    if version >= 2 {
+        for _ in 0..reader.u64("the number of results files")? {
+            let length = reader.u64("a results file's name")? as usize;
+            let name = match String::from_utf8(reader.take(length, "a results file's name")?.to_vec()) {
+                Ok(name) => name,
+                Err(_) => return Err("a results file's name isn't UTF-8".to_string()),
+            };
+            records.push((name, reader.u64("a results file's length")?));
+        }
+    }
+    if reader.at != bytes.len() {
+        return Err(format!("there are {} bytes left over at the end", bytes.len() - reader.at));
+    }
+
+    let snapshot = Snapshot {
+        generation: generation,
+        evaluations: evaluations,
+        objectives: objectives,
+        mutation_scale: mutation_scale,
+        best_fitness: best_fitness,
+        strategy: strategy,
+    };
+    Ok((seed, next_episode, snapshot, records))
+}
+
+/// Whether there are any values, then how many, then the values.
+fn put_optional(bytes: &mut Vec<u8>, values: Option<Vec<f64>>) {
+    match values {
+        Some(values) => {
+            weightfile::put_u64(bytes, 1);
+            weightfile::put_u64(bytes, values.len() as u64);
+            for value in values.iter() {
+                weightfile::put_u64(bytes, value.to_bits());
+            }
+        },
+        None => weightfile::put_u64(bytes, 0),
+    }
+}
+
+fn read_optional(reader: &mut Reader, what: &str) -> Result<Option<Vec<f64>>, String> {
+    if reader.u64(what)? == 0 {
+        return Ok(None);
+    }
+    let n = reader.u64(what)? as usize;
+    if n > (reader.bytes.len() - reader.at) / 8 {
+        return Err(format!("it ends in the middle of {}", what));
+    }
+    reader.f64s(n, what).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_round_trip() {
+        let snapshot = Snapshot {
+            generation: 12,
+            evaluations: vec![1.5, f64::NEG_INFINITY],
+            objectives: vec![vec![1.0, 0.5], vec![f64::NEG_INFINITY, 0.25]],
+            mutation_scale: 0.85,
+            best_fitness: Some(2.0),
+            strategy: Some(vec![0.1, -0.2, 3.0]),
+        };
+        let records = vec![("steps.csv".to_string(), 4096)];
+        let bytes = encode(1234, 13, &snapshot, &records);
+        let (seed, next_episode, decoded, decoded_records) = decode(&bytes).unwrap();
+        assert_eq!((seed, next_episode), (1234, 13));
+        assert_eq!(decoded_records, records);
+        assert_eq!(decoded.generation, 12);
+        assert_eq!(decoded.evaluations, snapshot.evaluations);
+        assert_eq!(decoded.objectives, snapshot.objectives);
+        assert_eq!(decoded.mutation_scale, 0.85);
+        assert_eq!(decoded.best_fitness, Some(2.0));
+        assert_eq!(decoded.strategy, snapshot.strategy);
+
+        // A flipped bit or a missing byte is caught
+        let mut corrupt = bytes.clone();
+        corrupt[30] ^= 1;
+        assert!(decode(&corrupt).is_err());
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_load_finds_complete_checkpoint() {
+        let snapshot = |generation| Snapshot {
+            generation: generation,
+            evaluations: Vec::new(),
+            objectives: Vec::new(),
+            mutation_scale: 1.0,
+            best_fitness: None,
+            strategy: None,
+        };
+        let write = |dir: &str, seed| {
+            fs::create_dir_all(dir).unwrap();
+            fs::write(Path::new(dir).join("config.yaml"), "mode: genetic\n").unwrap();
+            fs::write(Path::new(dir).join("state.bin"), encode(seed, 0, &snapshot(seed), &[])).unwrap();
+        };
+
+        // The run stopped after setting the old checkpoint aside, but before moving the new one in
+        write("swap_test.partial", 2);
+        assert_eq!(load("swap_test").unwrap().seed, 2);
+        assert_eq!(config_path("swap_test").unwrap(), Path::new("swap_test.partial").join("config.yaml"));
+
+        // A checkpoint still being written is passed over for the complete one
+        write("swap_test", 1);
+        fs::remove_file(Path::new("swap_test.partial").join("state.bin")).unwrap();
+        assert_eq!(load("swap_test").unwrap().seed, 1);
+
+        fs::remove_dir_all("swap_test").expect("Could not remove the test checkpoint for some reason.");
+        fs::remove_dir_all("swap_test.partial").expect("Could not remove the test checkpoint for some reason.");
+    }
+
+    #[test]
+    fn test_episode_rng() {
+        use rand::Rng;
+        let mut seeded: rand::StdRng = rand::StdRng::seed_from_u64(7);
+        assert_eq!(episode_rng(7, 0).gen::<u64>(), seeded.gen::<u64>());
+        assert_eq!(episode_rng(7, 3).gen::<u64>(), episode_rng(7, 3).gen::<u64>());
+        assert!(episode_rng(7, 3).gen::<u64>() != episode_rng(7, 4).gen::<u64>());
+    }
+}
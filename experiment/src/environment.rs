@@ -0,0 +1,107 @@
+//! What the episodes run against: a simulation of the arm, the real arm, or a recording of either.
+
+use k::prelude::*;
+use nalgebra as na;
+use std::fmt;
+
+use super::{ANGLE_LOWER_LIMIT_BASE, ANGLE_LOWER_LIMIT_ELBOW, ANGLE_LOWER_LIMIT_SHOULDER};
+use super::{ANGLE_UPPER_LIMIT_BASE, ANGLE_UPPER_LIMIT_ELBOW, ANGLE_UPPER_LIMIT_SHOULDER};
+
+/// What an environment reports after each reset or step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Observation {
+    /// Base, shoulder, and elbow angles, in degrees.
+    pub angles: [f64; 3],
+}
+
+/// Something the arm's joints can be moved in.
+pub trait Environment {
+    /// Puts the joints at the `start` angles (base, shoulder, elbow in degrees), clamped to their limits.
+    fn reset(&mut self, start: [f64; 3]) -> Result<Observation, String>;
+
+    /// Moves each joint by its delta (in degrees), stopping at its limits.
+    fn step(&mut self, deltas: [f64; 3]) -> Result<Observation, String>;
+
+    /// Where the end of the arm is (in meters) and which way it faces, according to the kinematic model.
+    fn end_effector_pose(&mut self) -> Result<na::Isometry3<f64>, String>;
+
+    /// Where the end of the arm was measured to be, in meters, for environments that can measure it.
+    fn measure(&mut self) -> Result<Option<na::Translation3<f64>>, String> {
+        Ok(None)
+    }
+
+    /// Called at the start of each episode.
+    fn start_episode(&mut self, _episode: u64) {}
+
+    /// Called at the end of each episode. Writes anything worth keeping about it into `results`.
+    fn finish_episode(&mut self, _results: &mut dyn fmt::Write) -> fmt::Result {
+        Ok(())
+    }
+}
+
+/// Clamps base, shoulder, and elbow angles to the range each joint is allowed to move in.
+pub fn clamp_to_limits(angles: [f64; 3]) -> [f64; 3] {
+    [
+        num::clamp(angles[0], ANGLE_LOWER_LIMIT_BASE, ANGLE_UPPER_LIMIT_BASE),
+        num::clamp(angles[1], ANGLE_LOWER_LIMIT_SHOULDER, ANGLE_UPPER_LIMIT_SHOULDER),
+        num::clamp(angles[2], ANGLE_LOWER_LIMIT_ELBOW, ANGLE_UPPER_LIMIT_ELBOW),
+    ]
+}
+
+/// The joints after moving each of `angles` by its delta, stopping at the joint limits.
+pub fn apply_deltas(angles: [f64; 3], deltas: [f64; 3]) -> [f64; 3] {
+    clamp_to_limits([angles[0] + deltas[0], angles[1] + deltas[1], angles[2] + deltas[2]])
+}
+
+/// A kinematic simulation of the arm. Joints go wherever they are told to, instantly.
+pub struct Simulation {
+    arm: k::Manipulator<f64>,
+    angles: [f64; 3],
+}
+
+impl Simulation {
+    pub fn new(arm: k::Manipulator<f64>) -> Self {
+        Simulation {
+            arm: arm,
+            angles: [0.0; 3],
+        }
+    }
+
+    /// Puts the joints at exactly these angles.
+    pub fn set_angles(&mut self, angles: [f64; 3]) -> Result<Observation, String> {
+        match self.arm.set_joint_angles(&vec![angles[0], angles[1], angles[2], 0.0]) {
+            Ok(_) => (),
+            Err(e) => return Err(format!("Problem setting joint angles: {:?}", e)),
+        }
+        self.angles = angles;
+        Ok(Observation { angles: angles })
+    }
+
+    /// Where the end of the arm would be with the joints at `angles`. Leaves the joints where they were.
+    pub fn pose_at(&mut self, angles: [f64; 3]) -> Result<na::Isometry3<f64>, String> {
+        let current = self.angles;
+        self.set_angles(angles)?;
+        let pose = self.end_effector_pose();
+        self.set_angles(current)?;
+        pose
+    }
+}
+
+impl Environment for Simulation {
+    fn reset(&mut self, start: [f64; 3]) -> Result<Observation, String> {
+        self.set_angles(clamp_to_limits(start))
+    }
+
+    fn step(&mut self, deltas: [f64; 3]) -> Result<Observation, String> {
+        let angles = apply_deltas(self.angles, deltas);
+        self.set_angles(angles)
+    }
+
+    fn end_effector_pose(&mut self) -> Result<na::Isometry3<f64>, String> {
+        // k is built on an older nalgebra than ours, so the pose is rebuilt from its coordinates
+        let end = self.arm.end_transform();
+        let (position, rotation) = (end.translation.vector, end.rotation.quaternion().coords);
+        let rotation = na::UnitQuaternion::new_unchecked(na::Quaternion::new(rotation[3], rotation[0], rotation[1], rotation[2]));
+        Ok(na::Isometry3::from_parts(na::Translation3::new(position[0], position[1], position[2]), rotation))
+    }
+}
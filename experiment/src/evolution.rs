@@ -0,0 +1,383 @@
+//! Evolution strategies, which search over a network's weights (biases included) as one flat vector. Each generation,
+//! the strategy samples weight vectors around its mean, the networks they make are rolled out and scored like the
+//! genetic algorithm's, and the strategy moves toward the ones that did best.
+
+use nalgebra as na;
+use rand::distributions::StandardNormal;
+use rand::Rng;
+use std::cmp::Ordering::Equal;
+use std::f64;
+
+use super::expconfig::{ExperimentConfig, Mode};
+use super::network::Optimizer;
+
+pub trait EvolutionStrategy {
+    /// Samples a generation's weight vectors.
+    fn ask(&mut self, rng: &mut rand::StdRng) -> Vec<Vec<f64>>;
+
+    /// Updates the strategy from the fitness of each weight vector from the last `ask`, in the same order.
+    fn tell(&mut self, fitnesses: &[f64]);
+
+    /// Everything the strategy has learned, and what it needs from the last `ask`, for a checkpoint to keep.
+    fn state(&self) -> Vec<f64>;
+
+    /// Carries on from what `state` gave, for a strategy made with the same settings and number of weights.
+    fn restore(&mut self, state: &[f64]) -> Result<(), String>;
+}
+
+/// The experiment's evolution strategy, starting from `mean`. None if the experiment's mode isn't one.
+pub fn new_strategy(experiment: &ExperimentConfig, mean: Vec<f64>) -> Option<Box<dyn EvolutionStrategy>> {
+    let lambda = experiment.generation_size as usize;
+    match experiment.mode {
+        Mode::CmaEs => Some(Box::new(CmaEs::new(mean, experiment.es_sigma, lambda))),
+        Mode::OpenAiEs => Some(Box::new(OpenAiEs::new(mean, experiment.es_sigma, lambda, Optimizer::new(experiment.optimizer, experiment.learning_rate)))),
+        Mode::Random | Mode::Genetic | Mode::Inference | Mode::Supervised => None,
+    }
+}
+
+/// The indexes of `fitnesses`, fittest first.
+fn fittest_first(fitnesses: &[f64]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..fitnesses.len()).collect();
+    order.sort_by(|&a, &b| fitnesses[b].partial_cmp(&fitnesses[a]).unwrap_or(Equal));
+    order
+}
+
+/// Takes the next `n` numbers of a strategy's saved state, which are `what`.
+fn take<'a>(state: &mut &'a [f64], n: usize, what: &str) -> Result<&'a [f64], String> {
+    if state.len() < n {
+        return Err(format!("The evolution strategy's state ends in the middle of {}.", what));
+    }
+    let (taken, rest) = state.split_at(n);
+    *state = rest;
+    Ok(taken)
+}
+
+fn sample_standard_normal(n: usize, rng: &mut rand::StdRng) -> na::DVector<f64> {
+    na::DVector::<f64>::from_fn(n, |_, _| rng.sample(StandardNormal))
+}
+
+/// The covariance matrix adaptation evolution strategy (Hansen, 2016: "The CMA Evolution Strategy: A Tutorial"),
+/// with the default settings from there for everything but the population size.
+///
+/// Samples come from a Gaussian whose covariance is learned from which steps did well, so that it searches along the
+/// directions that pay off. Its cost grows with the square of the number of weights.
+pub struct CmaEs {
+    mean: na::DVector<f64>,
+    sigma: f64,
+    /// How many samples are taken each generation. The mean moves toward the best half of them.
+    lambda: usize,
+    /// How much each of the best half of the samples counts, best first, and the number of samples they are worth together.
+    weights: Vec<f64>,
+    mueff: f64,
+    /// The learning rates: for the evolution paths (cc and cs), for the covariance from the path (c1) and from the
+    /// generation (cmu), and the damping for the step size.
+    cc: f64,
+    cs: f64,
+    c1: f64,
+    cmu: f64,
+    damps: f64,
+    /// The expected length of a standard normal vector.
+    chi_n: f64,
+    /// The evolution paths of the covariance and step size.
+    pc: na::DVector<f64>,
+    ps: na::DVector<f64>,
+    covariance: na::DMatrix<f64>,
+    /// The covariance's eigenvectors, the square roots of its eigenvalues, and its inverse square root.
+    b: na::DMatrix<f64>,
+    d: na::DVector<f64>,
+    inv_sqrt_c: na::DMatrix<f64>,
+    /// How many samples have been taken, and how many had when the covariance was last decomposed.
+    nevaluations: usize,
+    decomposed_at: usize,
+    /// The last generation's samples, as steps from the mean before they were scaled by sigma.
+    steps: Vec<na::DVector<f64>>,
+}
+
+impl CmaEs {
+    pub fn new(mean: Vec<f64>, sigma: f64, lambda: usize) -> Self {
+        let n = mean.len() as f64;
+        let mu = lambda / 2;
+        let raw: Vec<f64> = (0..mu).map(|i| (mu as f64 + 0.5).ln() - (i as f64 + 1.0).ln()).collect();
+        let total: f64 = raw.iter().sum();
+        let weights: Vec<f64> = raw.iter().map(|w| w / total).collect();
+        let mueff = 1.0 / weights.iter().map(|w| w * w).sum::<f64>();
+
+        let cc = (4.0 + mueff / n) / (n + 4.0 + 2.0 * mueff / n);
+        let cs = (mueff + 2.0) / (n + mueff + 5.0);
+        let c1 = 2.0 / ((n + 1.3).powi(2) + mueff);
+        let cmu = (1.0 - c1).min(2.0 * (mueff - 2.0 + 1.0 / mueff) / ((n + 2.0).powi(2) + mueff));
+        let damps = 1.0 + 2.0 * (((mueff - 1.0) / (n + 1.0)).sqrt() - 1.0).max(0.0) + cs;
+
+        let dim = mean.len();
+        CmaEs {
+            mean: na::DVector::<f64>::from_vec(dim, mean),
+            sigma: sigma,
+            lambda: lambda,
+            weights: weights,
+            mueff: mueff,
+            cc: cc,
+            cs: cs,
+            c1: c1,
+            cmu: cmu,
+            damps: damps,
+            chi_n: n.sqrt() * (1.0 - 1.0 / (4.0 * n) + 1.0 / (21.0 * n * n)),
+            pc: na::DVector::<f64>::zeros(dim),
+            ps: na::DVector::<f64>::zeros(dim),
+            covariance: na::DMatrix::<f64>::identity(dim, dim),
+            b: na::DMatrix::<f64>::identity(dim, dim),
+            d: na::DVector::<f64>::from_element(dim, 1.0),
+            inv_sqrt_c: na::DMatrix::<f64>::identity(dim, dim),
+            nevaluations: 0,
+            decomposed_at: 0,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Works out the covariance's eigenvectors and eigenvalues again, which sampling and the step size's path use.
+    fn decompose(&mut self) {
+        let dim = self.mean.len();
+        let covariance = (&self.covariance + self.covariance.transpose()) * 0.5;
+        let eigen = covariance.clone().symmetric_eigen();
+        self.covariance = covariance;
+        self.b = eigen.eigenvectors;
+        self.d = eigen.eigenvalues.map(|value| value.max(f64::EPSILON).sqrt());
+        let inv_d = na::DMatrix::<f64>::from_fn(dim, dim, |i, j| if i == j { 1.0 / self.d[i] } else { 0.0 });
+        self.inv_sqrt_c = &self.b * inv_d * self.b.transpose();
+        self.decomposed_at = self.nevaluations;
+    }
+}
+
+impl EvolutionStrategy for CmaEs {
+    fn ask(&mut self, rng: &mut rand::StdRng) -> Vec<Vec<f64>> {
+        let dim = self.mean.len();
+        let (b, d) = (&self.b, &self.d);
+        let steps = (0..self.lambda).map(|_| b * sample_standard_normal(dim, rng).component_mul(d)).collect();
+        self.steps = steps;
+        self.steps.iter().map(|step| (&self.mean + step * self.sigma).iter().cloned().collect()).collect()
+    }
+
+    fn tell(&mut self, fitnesses: &[f64]) {
+        assert!(fitnesses.len() == self.steps.len());
+        let n = self.mean.len() as f64;
+        self.nevaluations += self.lambda;
+
+        // Move the mean toward the best samples
+        let order = fittest_first(fitnesses);
+        let mut step = na::DVector::<f64>::zeros(self.mean.len());
+        for (w, &idx) in self.weights.iter().zip(order.iter()) {
+            step += &self.steps[idx] * *w;
+        }
+        self.mean += &step * self.sigma;
+
+        // Follow the path the mean took, and stop the covariance path while the step size's is too long
+        self.ps = &self.ps * (1.0 - self.cs) + &self.inv_sqrt_c * &step * (self.cs * (2.0 - self.cs) * self.mueff).sqrt();
+        let generations = self.nevaluations as f64 / self.lambda as f64;
+        let ps_norm = self.ps.norm() / (1.0 - (1.0 - self.cs).powf(2.0 * generations)).sqrt();
+        let hsig = if ps_norm / self.chi_n < 1.4 + 2.0 / (n + 1.0) { 1.0 } else { 0.0 };
+        self.pc = &self.pc * (1.0 - self.cc) + &step * (hsig * (self.cc * (2.0 - self.cc) * self.mueff).sqrt());
+
+        // Stretch the covariance along the path (rank one) and along the best steps (rank mu)
+        let mut rank_mu = na::DMatrix::<f64>::zeros(self.mean.len(), self.mean.len());
+        for (w, &idx) in self.weights.iter().zip(order.iter()) {
+            rank_mu += &self.steps[idx] * self.steps[idx].transpose() * *w;
+        }
+        let rank_one = &self.pc * self.pc.transpose() + &self.covariance * ((1.0 - hsig) * self.cc * (2.0 - self.cc));
+        self.covariance = &self.covariance * (1.0 - self.c1 - self.cmu) + rank_one * self.c1 + rank_mu * self.cmu;
+
+        // Take bigger steps while the path is longer than chance would make it, and smaller ones while it's shorter
+        self.sigma *= ((self.cs / self.damps) * (self.ps.norm() / self.chi_n - 1.0)).exp();
+
+        // Decomposing is the slow part, so it's done only as often as the covariance changes enough to matter
+        if (self.nevaluations - self.decomposed_at) as f64 > self.lambda as f64 / (self.c1 + self.cmu) / n / 10.0 {
+            self.decompose();
+        }
+    }
+
+    /// The step size, the counts, how many steps were sampled last, then the mean, paths, decomposition, covariance,
+    /// and the steps.
+    fn state(&self) -> Vec<f64> {
+        let mut state = vec![self.sigma, self.nevaluations as f64, self.decomposed_at as f64, self.steps.len() as f64];
+        for vector in [&self.mean, &self.pc, &self.ps, &self.d].iter() {
+            state.extend(vector.iter());
+        }
+        for matrix in [&self.covariance, &self.b, &self.inv_sqrt_c].iter() {
+            state.extend(matrix.iter());
+        }
+        for step in self.steps.iter() {
+            state.extend(step.iter());
+        }
+        state
+    }
+
+    fn restore(&mut self, state: &[f64]) -> Result<(), String> {
+        let dim = self.mean.len();
+        let mut state = state;
+        let counts = take(&mut state, 4, "the step size and counts")?;
+        let nsteps = counts[3] as usize;
+        let vector = |state: &mut &[f64], what: &str| take(state, dim, what).map(|values| na::DVector::<f64>::from_vec(dim, values.to_vec()));
+        let matrix = |state: &mut &[f64], what: &str| take(state, dim * dim, what).map(|values| na::DMatrix::<f64>::from_column_slice(dim, dim, values));
+
+        self.mean = vector(&mut state, "the mean")?;
+        self.pc = vector(&mut state, "the covariance's path")?;
+        self.ps = vector(&mut state, "the step size's path")?;
+        self.d = vector(&mut state, "the eigenvalues")?;
+        self.covariance = matrix(&mut state, "the covariance")?;
+        self.b = matrix(&mut state, "the eigenvectors")?;
+        self.inv_sqrt_c = matrix(&mut state, "the covariance's inverse square root")?;
+        self.steps = (0..nsteps).map(|_| vector(&mut state, "the steps")).collect::<Result<_, _>>()?;
+        if !state.is_empty() {
+            return Err(format!("The evolution strategy's state has {} numbers left over.", state.len()));
+        }
+        self.sigma = counts[0];
+        self.nevaluations = counts[1] as usize;
+        self.decomposed_at = counts[2] as usize;
+        Ok(())
+    }
+}
+
+/// OpenAI's evolution strategy (Salimans et al., 2017: "Evolution Strategies as a Scalable Alternative to Reinforcement
+/// Learning").
+///
+/// Samples come in mirrored pairs from a Gaussian of fixed size around the mean. The samples' fitnesses, ranked so that
+/// only their order matters, give an estimate of the fitness's gradient, which an optimizer steps the mean up.
+pub struct OpenAiEs {
+    mean: Vec<f64>,
+    sigma: f64,
+    npairs: usize,
+    optimizer: Optimizer,
+    /// The last generation's noise, one per pair: the pair is the mean plus and minus sigma times it.
+    noise: Vec<na::DVector<f64>>,
+}
+
+impl OpenAiEs {
+    pub fn new(mean: Vec<f64>, sigma: f64, lambda: usize, optimizer: Optimizer) -> Self {
+        OpenAiEs {
+            mean: mean,
+            sigma: sigma,
+            npairs: lambda / 2,
+            optimizer: optimizer,
+            noise: Vec::new(),
+        }
+    }
+}
+
+impl EvolutionStrategy for OpenAiEs {
+    fn ask(&mut self, rng: &mut rand::StdRng) -> Vec<Vec<f64>> {
+        let dim = self.mean.len();
+        self.noise = (0..self.npairs).map(|_| sample_standard_normal(dim, rng)).collect();
+        let mut samples = Vec::new();
+        for epsilon in self.noise.iter() {
+            samples.push(self.mean.iter().zip(epsilon.iter()).map(|(m, e)| m + self.sigma * e).collect());
+            samples.push(self.mean.iter().zip(epsilon.iter()).map(|(m, e)| m - self.sigma * e).collect());
+        }
+        samples
+    }
+
+    fn tell(&mut self, fitnesses: &[f64]) {
+        assert!(fitnesses.len() == 2 * self.noise.len());
+
+        // Rank the fitnesses from -0.5 (the worst) to 0.5 (the best), so that outliers don't swamp the rest
+        let order = fittest_first(fitnesses);
+        let mut utilities = vec![0.0; fitnesses.len()];
+        for (rank, &idx) in order.iter().enumerate() {
+            utilities[idx] = 0.5 - rank as f64 / (fitnesses.len() - 1) as f64;
+        }
+
+        // The optimizer steps down its gradient, so give it the way down
+        let mut gradient = vec![0.0; self.mean.len()];
+        for (pair, epsilon) in self.noise.iter().enumerate() {
+            let difference = utilities[2 * pair] - utilities[2 * pair + 1];
+            for (g, e) in gradient.iter_mut().zip(epsilon.iter()) {
+                *g -= difference * e / (fitnesses.len() as f64 * self.sigma);
+            }
+        }
+        self.optimizer.step(self.mean.iter_mut(), &gradient);
+    }
+
+    /// How many pairs were sampled last, then the mean, the noise, and the optimizer's state.
+    fn state(&self) -> Vec<f64> {
+        let mut state = vec![self.noise.len() as f64];
+        state.extend(self.mean.iter());
+        for epsilon in self.noise.iter() {
+            state.extend(epsilon.iter());
+        }
+        state.extend(self.optimizer.state());
+        state
+    }
+
+    fn restore(&mut self, state: &[f64]) -> Result<(), String> {
+        let dim = self.mean.len();
+        let mut state = state;
+        let npairs = take(&mut state, 1, "the number of pairs")?[0] as usize;
+        self.mean = take(&mut state, dim, "the mean")?.to_vec();
+        self.noise = (0..npairs).map(|_| take(&mut state, dim, "the noise").map(|values| na::DVector::<f64>::from_vec(dim, values.to_vec()))).collect::<Result<_, _>>()?;
+        self.optimizer.restore(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::prelude::*;
+    use super::super::network::OptimizerKind;
+
+    /// Runs a strategy on a sphere centered on 1, and returns the distance from the center of the best sample in its
+    /// first and last generations.
+    fn run_on_sphere(strategy: &mut dyn EvolutionStrategy, ngenerations: usize) -> (f64, f64) {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(11);
+        let mut first = 0.0;
+        let mut last = 0.0;
+        for generation in 0..ngenerations {
+            let samples = strategy.ask(&mut rng);
+            let fitnesses: Vec<f64> = samples.iter().map(|x| -x.iter().map(|v| (v - 1.0).powi(2)).sum::<f64>()).collect();
+            let best = -fitnesses.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+            if generation == 0 {
+                first = best.sqrt();
+            }
+            last = best.sqrt();
+            strategy.tell(&fitnesses);
+        }
+        (first, last)
+    }
+
+    #[test]
+    fn test_cma_es_converges_on_sphere() {
+        let mut strategy = CmaEs::new(vec![0.0; 8], 0.5, 12);
+        let (first, last) = run_on_sphere(&mut strategy, 150);
+        assert!(last < 1E-3, "went from {} to {}", first, last);
+    }
+
+    #[test]
+    fn test_restored_strategies_carry_on_the_same() {
+        let strategies: Vec<(Box<dyn EvolutionStrategy>, Box<dyn EvolutionStrategy>)> = vec![
+            (Box::new(CmaEs::new(vec![0.0; 5], 0.5, 8)), Box::new(CmaEs::new(vec![0.0; 5], 0.5, 8))),
+            (Box::new(OpenAiEs::new(vec![0.0; 5], 0.1, 8, Optimizer::new(OptimizerKind::Adam, 0.05))),
+             Box::new(OpenAiEs::new(vec![0.0; 5], 0.1, 8, Optimizer::new(OptimizerKind::Adam, 0.05)))),
+        ];
+        for (mut strategy, mut restored) in strategies {
+            let mut rng: StdRng = SeedableRng::seed_from_u64(3);
+            for _ in 0..10 {
+                let fitnesses: Vec<f64> = strategy.ask(&mut rng).iter().map(|x| -x.iter().map(|v| v * v).sum::<f64>()).collect();
+                strategy.tell(&fitnesses);
+            }
+            let fitnesses: Vec<f64> = strategy.ask(&mut rng).iter().map(|x| x[0]).collect();
+
+            // The restored strategy moves the same way from the same fitnesses, and samples the same from the same numbers
+            restored.restore(&strategy.state()).unwrap();
+            strategy.tell(&fitnesses);
+            restored.tell(&fitnesses);
+            let mut rngcopy = rng.clone();
+            assert_eq!(strategy.ask(&mut rng), restored.ask(&mut rngcopy));
+            let state = strategy.state();
+            assert!(restored.restore(&state[..state.len() - 1]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_openai_es_improves_on_sphere() {
+        let mut strategy = OpenAiEs::new(vec![0.0; 8], 0.1, 20, Optimizer::new(OptimizerKind::Adam, 0.05));
+        let (first, last) = run_on_sphere(&mut strategy, 150);
+        assert!(last < first / 4.0, "went from {} to {}", first, last);
+    }
+}
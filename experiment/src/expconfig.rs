@@ -1,5 +1,10 @@
 use nalgebra as na;
 use rand::prelude::*;
+use super::fitness::FitnessFunction;
+use super::netconfig::Architecture;
+use super::network::OptimizerKind;
+use super::observation::ObservationBuilder;
+use super::records;
 use std::collections::hash_map::{self, HashMap};
 use std::fmt::{self, Write};
 use std::path::Path;
@@ -10,13 +15,13 @@ use std::str::FromStr;
 pub struct ExperimentConfig {
     /// Number of steps in a single episode
     pub nsteps_per_episode: u64,
-    /// Number of episodes in the experiment. If mode is genetic, this is also the number of generations.
+    /// Number of episodes in the experiment. If mode is genetic or an evolution strategy, this is also the number of generations.
     pub nepisodes: u64,
     /// Mode of the experiment.
     pub mode: Mode,
-    /// The COM port to find the Robot on.
+    /// The COM port to find the Robot on, "test" for teleop's test port, or "simulate" to run against a simulation of the arm instead.
     pub comstr: String,
-    /// The number of networks in a generation. Only parsed if mode is Genetic.
+    /// The number of networks in a generation. Only parsed if mode is Genetic or an evolution strategy.
     pub generation_size: u64,
     /// A random new network will have weights in the interval [low, high]
     pub low: f64,
@@ -28,17 +33,75 @@ pub struct ExperimentConfig {
     pub percent_mutate: f64,
     /// Mutant weights are formed by drawing from a Gaussian of mu=weight_i, stdev=mutation_stdev
     pub mutation_stdev: f64,
-    /// Target location for the gripper. Orientation doesn't matter to us, hence, Translation rather than Isometry. Values are in meters.
-    pub target: na::Translation3<f64>,
+    /// Where the gripper should reach. Each network is evaluated reaching for every target from every start pose.
+    pub targets: Targets,
+    /// Where the joints start each reach from.
+    pub start_poses: StartPoses,
+    /// What the networks are given to decide each step on.
+    pub observation: ObservationBuilder,
+    /// The layers of the networks. The first takes the observation, and the last gives the joint deltas.
+    pub architecture: Architecture,
+    /// Which way the gripper should face at the target, given as roll, pitch, and yaw in radians. Only needed for the orientation fitness term.
+    pub target_orientation: Option<na::UnitQuaternion<f64>>,
+    /// How close (in meters) the gripper has to get to the target to count as having reached it.
+    pub reach_tolerance: f64,
+    /// How a network's rollout is scored.
+    pub fitness: FitnessFunction,
+    /// How the networks that seed the next generation are chosen.
+    pub survivor_selection: SurvivorSelection,
+    /// How parents are picked for each new network that isn't an elite or an immigrant.
+    pub parent_selection: ParentSelection,
+    /// How two parents are combined into a new network before it is mutated.
+    pub crossover: Crossover,
+    /// The number of the best networks that go into the next generation unchanged. Defaults to nkeep.
+    pub elitism: u64,
+    /// The number of brand new random networks put into each generation.
+    pub immigrants: u64,
+    /// Whether to mutate less while the best fitness keeps improving, and more while it stalls.
+    pub adaptive_mutation: bool,
+    /// A network to start the first generation from, rather than random ones (if genetic or an evolution strategy).
+    pub initial_weights: Option<String>,
+    /// How supervised training (or OpenAI's evolution strategy) steps along the gradient.
+    pub optimizer: OptimizerKind,
+    /// How big a step supervised training (or OpenAI's evolution strategy) takes.
+    pub learning_rate: f64,
+    /// How many examples supervised training averages the gradient over for each step.
+    pub batch_size: u64,
+    /// How many examples supervised training learns from. Another tenth as many are kept aside to check it on.
+    pub nsamples: u64,
+    /// The standard deviation evolution strategies sample weights with around their mean. CMA-ES adapts it from here.
+    pub es_sigma: f64,
     /// Path to the Arm URDF file
     pub urdfpath: String,
     /// Seed for the random number generator - the file may specify "none", in which case a random number is used to seed the RNG.
     pub seed: u64,
-    /// Path to the weights file (if doing inference).
+    /// Path to the weights file: the network to run if doing inference, or where the best network is saved if genetic.
     pub weights: String,
+    /// If given, everything the arm (or its simulation) does is recorded to this file.
+    pub record: Option<String>,
+    /// If given, the experiment runs against this recording instead of the arm or its simulation.
+    pub replay: Option<String>,
+    /// How the real arm's position is measured after each network's rollout.
+    pub measurement: Measurement,
+    /// How much of a network's fitness comes from where the arm was measured to be, rather than where the
+    /// kinematic model says it is. In interval [0.0, 1.0]. Only used when the environment can measure the arm.
+    pub measured_fitness_weight: f64,
+    /// How many threads a simulated generation's networks are evaluated on. 0 is one per core.
+    pub threads: usize,
+    /// The directory to keep a checkpoint of the run in, to resume it from.
+    pub checkpoint: String,
+    /// How many generations apart checkpoints are taken. 0 is never. Only for genetic and evolution strategy modes.
+    pub checkpoint_every: u64,
+    /// The directory to write the structured results (see `records`) to.
+    pub results_dir: String,
+    /// The formats to write the structured results in.
+    pub results_formats: Vec<records::Format>,
+    /// Whether each generation's statistics are drawn as a dashboard (see `progress`), rather than printed a line at a
+    /// time. Only for genetic and evolution strategy modes.
+    pub dashboard: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 /// The different modes the experiment can be run in.
 pub enum Mode {
     /// Joint deltas are generated within limits randomly each step.
@@ -47,6 +110,76 @@ pub enum Mode {
     Genetic,
     /// Use an already-trained network to control an arm.
     Inference,
+    /// Train a network by backpropagation to do inverse kinematics, from examples made with the kinematic model.
+    Supervised,
+    /// Evolve a network's weights with the covariance matrix adaptation evolution strategy (CMA-ES).
+    CmaEs,
+    /// Evolve a network's weights with OpenAI's evolution strategy, which estimates the gradient of the fitness from its samples.
+    OpenAiEs,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// The ways of finding out where the real arm ended up.
+pub enum Measurement {
+    /// Ask the device where its servos are, and work out where that puts the hand.
+    Servos,
+    /// Ask an external tracker at this address (host:port) where the hand is.
+    Tracker(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// Where a generation's targets come from. Values are in meters.
+pub enum Targets {
+    /// The same targets every generation.
+    Listed(Vec<na::Translation3<f64>>),
+    /// This many targets drawn anew each generation from the box between two opposite corners.
+    Region(na::Translation3<f64>, na::Translation3<f64>, usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// Where a generation's start poses come from. Poses are base, shoulder, and elbow angles in degrees.
+pub enum StartPoses {
+    /// A single random start pose drawn from the seed, so the same one every generation.
+    Seeded,
+    /// The same start poses every generation.
+    Listed(Vec<[f64; 3]>),
+    /// This many random start poses drawn anew each generation.
+    Random(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The ways of choosing which networks survive into the next generation.
+pub enum SurvivorSelection {
+    /// Keep the networks with the highest fitness.
+    Best,
+    /// Keep networks from the best Pareto fronts of the fitness terms, preferring the less crowded ones (NSGA-II).
+    Nsga2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The ways of picking parents for new networks.
+pub enum ParentSelection {
+    /// Go round the nkeep surviving networks in order.
+    RoundRobin,
+    /// Pick this many networks at random and take the best of them.
+    Tournament(usize),
+    /// Pick networks with probability in proportion to their fitness.
+    Roulette,
+    /// Pick networks with probability in proportion to how near the top of the ranking they are.
+    Rank,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The ways of combining two parent networks.
+pub enum Crossover {
+    /// The new network is a copy of its first parent.
+    Clone,
+    /// Each weight comes from either parent.
+    Uniform,
+    /// Each layer's weights come from either parent.
+    LayerWise,
+    /// The weights are a random blend of both parents' weights.
+    Arithmetic,
 }
 
 impl ExperimentConfig {
@@ -64,9 +197,12 @@ impl ExperimentConfig {
             "random" => Mode::Random,
             "genetic" => Mode::Genetic,
             "inference" => Mode::Inference,
+            "supervised" => Mode::Supervised,
+            "cma_es" => Mode::CmaEs,
+            "openai_es" => Mode::OpenAiEs,
             m => {
                 let mut errmsg = String::new();
-                writeln!(errmsg, "Mode must be 'random' or 'genetic' but is {}", m).unwrap();
+                writeln!(errmsg, "Mode must be 'random', 'genetic', 'inference', 'supervised', 'cma_es', or 'openai_es' but is {}", m).unwrap();
                 return Err(errmsg);
             },
         };
@@ -89,13 +225,16 @@ impl ExperimentConfig {
             }
         };
 
-        // Parse out nsteps_per_episode if mode is genetic or random
-        let nsteps_per_episode = parse_parameter::<u64>(&mut setting_strings, "nsteps_per_episode".to_string())?;
+        // Parse out nsteps_per_episode unless the mode is supervised, which doesn't take any steps
+        let nsteps_per_episode = match mode {
+            Mode::Supervised => 0,
+            Mode::Genetic | Mode::Random | Mode::Inference | Mode::CmaEs | Mode::OpenAiEs => parse_parameter::<u64>(&mut setting_strings, "nsteps_per_episode".to_string())?,
+        };
 
-        // Parse out the number of episodes if mode is genetic or random
+        // Parse out the number of episodes if mode is genetic or random, or the number of epochs if supervised
         let nepisodes = match mode {
             Mode::Inference => 1,
-            Mode::Genetic | Mode::Random => parse_parameter::<u64>(&mut setting_strings, "nepisodes".to_string())?,
+            Mode::Genetic | Mode::Random | Mode::Supervised | Mode::CmaEs | Mode::OpenAiEs => parse_parameter::<u64>(&mut setting_strings, "nepisodes".to_string())?,
         };
 
         // Parse out 'com'
@@ -104,39 +243,45 @@ impl ExperimentConfig {
         // Parse out 'arm_urdf'
         let urdfpath = parse_parameter::<String>(&mut setting_strings, "arm_urdf".to_string())?;
 
-        // Parse out the number of networks in a generation if the mode is genetic
+        // Parse out the number of networks in a generation if the mode is genetic or an evolution strategy
         let generation_size = match mode {
-            Mode::Random | Mode::Inference => 0,
-            Mode::Genetic => parse_parameter::<u64>(&mut setting_strings, "generation_size".to_string())?,
+            Mode::Random | Mode::Inference | Mode::Supervised => 0,
+            Mode::Genetic | Mode::CmaEs | Mode::OpenAiEs => parse_parameter::<u64>(&mut setting_strings, "generation_size".to_string())?,
         };
 
-        // Parse out 'randomized_weights_low' if the mode is genetic
+        // Parse out 'randomized_weights_low' if the mode is genetic, supervised, or an evolution strategy
         let low = match mode {
             Mode::Random | Mode::Inference => 0.0,
-            Mode::Genetic => parse_parameter::<f64>(&mut setting_strings, "randomized_weights_low".to_string())?,
+            Mode::Genetic | Mode::Supervised | Mode::CmaEs | Mode::OpenAiEs => parse_parameter::<f64>(&mut setting_strings, "randomized_weights_low".to_string())?,
         };
 
-        // Parse out 'randomized_weights_high' if the mode is genetic
+        // Parse out 'randomized_weights_high' if the mode is genetic, supervised, or an evolution strategy
         let high = match mode {
             Mode::Random | Mode::Inference => 0.0,
-            Mode::Genetic => parse_parameter::<f64>(&mut setting_strings, "randomized_weights_high".to_string())?,
+            Mode::Genetic | Mode::Supervised | Mode::CmaEs | Mode::OpenAiEs => parse_parameter::<f64>(&mut setting_strings, "randomized_weights_high".to_string())?,
         };
 
         // Parse out 'nkeep_between_generations' if the mode is genetic
         let nkeep = match mode {
-            Mode::Random | Mode::Inference => 0,
+            Mode::Random | Mode::Inference | Mode::Supervised | Mode::CmaEs | Mode::OpenAiEs => 0,
             Mode::Genetic => parse_parameter::<u64>(&mut setting_strings, "nkeep_between_generations".to_string())?,
         };
 
+        // Parse out the optional genetic algorithm strategies if the mode is genetic
+        let (parent_selection, crossover, elitism, immigrants, adaptive_mutation) = match mode {
+            Mode::Random | Mode::Inference | Mode::Supervised | Mode::CmaEs | Mode::OpenAiEs => (ParentSelection::RoundRobin, Crossover::Clone, 0, 0, false),
+            Mode::Genetic => parse_strategies(&mut setting_strings, generation_size, nkeep)?,
+        };
+
         // Parse out 'mutation_stdev' if the mode is genetic
         let mutation_stdev = match mode {
-            Mode::Random | Mode::Inference => 0.0,
+            Mode::Random | Mode::Inference | Mode::Supervised | Mode::CmaEs | Mode::OpenAiEs => 0.0,
             Mode::Genetic => parse_parameter::<f64>(&mut setting_strings, "mutation_stdev".to_string())?,
         };
 
         // Parse out 'percent_mutate' if the mode is genetic
         let percent_mutate = match mode {
-            Mode::Random | Mode::Inference => 0.0,
+            Mode::Random | Mode::Inference | Mode::Supervised | Mode::CmaEs | Mode::OpenAiEs => 0.0,
             Mode::Genetic => parse_parameter::<f64>(&mut setting_strings, "percent_mutate".to_string())?,
         };
 
@@ -147,26 +292,146 @@ impl ExperimentConfig {
             return Err(msg);
         };
 
-        // Parse out 'weights' if the mode is inference
+        // Parse out 'weights' if the mode is inference, or if anything else but random, where to save the best network.
+        // Text if it ends in '.toml', binary otherwise.
         let weights = match mode {
-            Mode::Genetic | Mode::Random => String::new(),
+            Mode::Random => String::new(),
+            Mode::Genetic | Mode::Supervised | Mode::CmaEs | Mode::OpenAiEs => parse_optional_parameter(&mut setting_strings, "weights".to_string())?.unwrap_or("network_weights.toml".to_string()),
             Mode::Inference => parse_parameter(&mut setting_strings, "weights".to_string())?,
         };
 
-        // Parse out 'target_*' if the mode is genetic
-        let target_x = match mode {
-            Mode::Random | Mode::Inference => 0.0,
-            Mode::Genetic => parse_parameter::<f64>(&mut setting_strings, "target_x".to_string())?,
+        // Parse out 'observation', which defaults to giving the networks only the joint angles
+        let observation = match parse_optional_parameter::<String>(&mut setting_strings, "observation".to_string())? {
+            Some(observationstr) => ObservationBuilder::parse(&observationstr)?,
+            None => ObservationBuilder::default(),
         };
-        let target_y = match mode {
-            Mode::Random | Mode::Inference => 0.0,
-            Mode::Genetic => parse_parameter::<f64>(&mut setting_strings, "target_y".to_string())?,
+
+        // Parse out 'layers', which defaults to the network the experiment has always used
+        let architecture = match parse_optional_parameter::<String>(&mut setting_strings, "layers".to_string())? {
+            Some(layersstr) => Architecture::parse(&layersstr)?,
+            None => Architecture::default(),
         };
-        let target_z = match mode {
-            Mode::Random | Mode::Inference => 0.0,
-            Mode::Genetic => parse_parameter::<f64>(&mut setting_strings, "target_z".to_string())?,
+
+        // Parse out the targets if the mode is genetic or an evolution strategy, or if the network needs one to reach for in inference
+        let targets = match mode {
+            Mode::Genetic | Mode::CmaEs | Mode::OpenAiEs => parse_targets(&mut setting_strings)?,
+            Mode::Inference if observation.uses_target() => parse_targets(&mut setting_strings)?,
+            Mode::Random | Mode::Inference | Mode::Supervised => Targets::Listed(vec![na::Translation3::new(0.0, 0.0, 0.0)]),
+        };
+
+        // Parse out 'start_poses' or 'nstart_poses', which default to a single start pose drawn from the seed
+        let start_poses = match mode {
+            Mode::Genetic | Mode::CmaEs | Mode::OpenAiEs => parse_start_poses(&mut setting_strings)?,
+            Mode::Random | Mode::Inference | Mode::Supervised => StartPoses::Seeded,
+        };
+
+        // Parse out 'target_roll', 'target_pitch', and 'target_yaw', which are optional, but go together
+        let roll = parse_optional_parameter::<f64>(&mut setting_strings, "target_roll".to_string())?;
+        let pitch = parse_optional_parameter::<f64>(&mut setting_strings, "target_pitch".to_string())?;
+        let yaw = parse_optional_parameter::<f64>(&mut setting_strings, "target_yaw".to_string())?;
+        let target_orientation = match (roll, pitch, yaw) {
+            (Some(roll), Some(pitch), Some(yaw)) => Some(na::UnitQuaternion::from_euler_angles(roll, pitch, yaw)),
+            (None, None, None) => None,
+            _ => return Err("Give all of 'target_roll', 'target_pitch', and 'target_yaw', or none of them.".to_string()),
+        };
+
+        // Parse out 'reach_tolerance', which defaults to a centimeter
+        let reach_tolerance = parse_optional_parameter::<f64>(&mut setting_strings, "reach_tolerance".to_string())?.unwrap_or(0.01);
+
+        // Parse out 'fitness', which defaults to the inverse of the final distance to the target
+        let fitness = match parse_optional_parameter::<String>(&mut setting_strings, "fitness".to_string())? {
+            Some(fitnessstr) => FitnessFunction::parse(&fitnessstr)?,
+            None => FitnessFunction::default(),
+        };
+        if fitness.uses_orientation() && target_orientation.is_none() {
+            return Err("The orientation fitness term needs 'target_roll', 'target_pitch', and 'target_yaw'.".to_string());
+        }
+
+        // Parse out 'survivor_selection', which defaults to keeping the fittest networks
+        let survivor_selection = match parse_optional_parameter::<String>(&mut setting_strings, "survivor_selection".to_string())?.as_ref().map(|s| s.as_str()) {
+            None | Some("best") => SurvivorSelection::Best,
+            Some("nsga2") => SurvivorSelection::Nsga2,
+            Some(s) => {
+                let mut errmsg = String::new();
+                writeln!(errmsg, "'survivor_selection' must be 'best' or 'nsga2' but is {}", s).unwrap();
+                return Err(errmsg);
+            },
+        };
+
+        // Parse out 'initial_weights' if the mode is genetic or an evolution strategy
+        let initial_weights = match mode {
+            Mode::Genetic | Mode::CmaEs | Mode::OpenAiEs => parse_optional_parameter::<String>(&mut setting_strings, "initial_weights".to_string())?,
+            Mode::Random | Mode::Inference | Mode::Supervised => None,
+        };
+
+        // Parse out 'optimizer', 'learning_rate', 'batch_size', and 'nsamples' if the mode is supervised. OpenAI's
+        // evolution strategy steps up its estimated gradient with an optimizer too.
+        let (optimizer, learning_rate, batch_size, nsamples) = match mode {
+            Mode::Random | Mode::Inference | Mode::Genetic | Mode::CmaEs => (OptimizerKind::Adam, 0.0, 0, 0),
+            Mode::Supervised => parse_training(&mut setting_strings)?,
+            Mode::OpenAiEs => {
+                let (optimizer, learning_rate) = parse_optimizer(&mut setting_strings, 0.01)?;
+                (optimizer, learning_rate, 0, 0)
+            },
+        };
+
+        // Parse out 'es_sigma' if the mode is an evolution strategy
+        let es_sigma = match mode {
+            Mode::Random | Mode::Inference | Mode::Genetic | Mode::Supervised => 0.0,
+            Mode::CmaEs | Mode::OpenAiEs => parse_evolution_strategy(&mut setting_strings, &mode, generation_size)?,
+        };
+        if mode == Mode::Supervised && !observation.uses_target() {
+            return Err("Supervised training teaches the network to reach for targets, so the observation must include the 'target' or 'distance_vector'.".to_string());
+        }
+
+        // Parse out 'record' and 'replay', which are optional in every mode
+        let record = parse_optional_parameter::<String>(&mut setting_strings, "record".to_string())?;
+        let replay = parse_optional_parameter::<String>(&mut setting_strings, "replay".to_string())?;
+
+        // Parse out 'measurement' (and 'tracker' if measuring with one), which default to asking the servos
+        let measurementstr = parse_optional_parameter::<String>(&mut setting_strings, "measurement".to_string())?;
+        let measurement = match measurementstr.as_ref().map(|m| m.as_str()) {
+            None | Some("servos") => Measurement::Servos,
+            Some("tracker") => Measurement::Tracker(parse_parameter::<String>(&mut setting_strings, "tracker".to_string())?),
+            Some(m) => {
+                let mut errmsg = String::new();
+                writeln!(errmsg, "'measurement' must be 'servos' or 'tracker' but is {}", m).unwrap();
+                return Err(errmsg);
+            },
+        };
+
+        // Parse out 'measured_fitness_weight', which defaults to using only the measured fitness
+        let measured_fitness_weight = parse_optional_parameter::<f64>(&mut setting_strings, "measured_fitness_weight".to_string())?.unwrap_or(1.0);
+        if measured_fitness_weight < 0.0 || measured_fitness_weight > 1.0 {
+            let mut msg = String::new();
+            write!(msg, "'measured_fitness_weight' must be in interval [0, 1], but is {}", measured_fitness_weight).unwrap();
+            return Err(msg);
+        }
+
+        // Parse out 'threads', which defaults to one per core
+        let threads = parse_optional_parameter::<usize>(&mut setting_strings, "threads".to_string())?.unwrap_or(0);
+
+        // Parse out 'checkpoint' and 'checkpoint_every' if the mode makes generations. There are no checkpoints by default.
+        let (checkpoint, checkpoint_every) = match mode {
+            Mode::Random | Mode::Inference | Mode::Supervised => (String::new(), 0),
+            Mode::Genetic | Mode::CmaEs | Mode::OpenAiEs => (
+                parse_optional_parameter(&mut setting_strings, "checkpoint".to_string())?.unwrap_or("checkpoint".to_string()),
+                parse_optional_parameter::<u64>(&mut setting_strings, "checkpoint_every".to_string())?.unwrap_or(0),
+            ),
+        };
+
+        // Parse out 'dashboard' if the mode makes generations, which is off by default
+        let dashboard = match mode {
+            Mode::Random | Mode::Inference | Mode::Supervised => false,
+            Mode::Genetic | Mode::CmaEs | Mode::OpenAiEs => parse_optional_parameter::<bool>(&mut setting_strings, "dashboard".to_string())?.unwrap_or(false),
+        };
+
+        // Parse out 'results_dir' and 'results_formats', which default to JSON Lines and CSV in 'results'
+        let results_dir = parse_optional_parameter::<String>(&mut setting_strings, "results_dir".to_string())?.unwrap_or("results".to_string());
+        let results_formats = match parse_optional_parameter::<String>(&mut setting_strings, "results_formats".to_string())? {
+            Some(formatstr) => records::Format::parse_list(&formatstr)?,
+            None => vec![records::Format::JsonLines, records::Format::Csv],
         };
-        let target = na::Translation3::new(target_x, target_y, target_z);
 
         // Now print out the settings as we interpreted them
         Ok(ExperimentConfig {
@@ -180,12 +445,46 @@ impl ExperimentConfig {
             nkeep: nkeep,
             mutation_stdev: mutation_stdev,
             percent_mutate: percent_mutate,
-            target: target,
+            targets: targets,
+            start_poses: start_poses,
+            observation: observation,
+            architecture: architecture,
+            target_orientation: target_orientation,
+            reach_tolerance: reach_tolerance,
+            fitness: fitness,
+            survivor_selection: survivor_selection,
+            parent_selection: parent_selection,
+            crossover: crossover,
+            elitism: elitism,
+            immigrants: immigrants,
+            adaptive_mutation: adaptive_mutation,
+            initial_weights: initial_weights,
+            optimizer: optimizer,
+            learning_rate: learning_rate,
+            batch_size: batch_size,
+            nsamples: nsamples,
+            es_sigma: es_sigma,
             urdfpath: urdfpath,
             seed: seed,
             weights: weights,
+            record: record,
+            replay: replay,
+            measurement: measurement,
+            measured_fitness_weight: measured_fitness_weight,
+            threads: threads,
+            checkpoint: checkpoint,
+            checkpoint_every: checkpoint_every,
+            results_dir: results_dir,
+            results_formats: results_formats,
+            dashboard: dashboard,
         })
     }
+
+    /// Whether a generation's networks can be evaluated at the same time, each on its own copy of the simulation: they
+    /// can't share the real arm, or a recording, which has to be in order.
+    pub fn simulated_in_parallel(&self) -> bool {
+        self.comstr == "simulate" && self.record.is_none() && self.replay.is_none()
+    }
 }
 
 /// Attempts to parse the given string `s` into a new instance of type `T`.
@@ -207,6 +506,172 @@ fn parse_parameter<T: FromStr>(setting_strings: &mut HashMap<String, String>, s:
     }
 }
 
+/// Parses out the targets: either 'targets' (a list), 'target_region' (two opposite corners of a box) with 'ntargets',
+/// or a single target from 'target_x', 'target_y', and 'target_z'.
+fn parse_targets(setting_strings: &mut HashMap<String, String>) -> Result<Targets, String> {
+    let listed = parse_optional_parameter::<String>(setting_strings, "targets".to_string())?;
+    let region = parse_optional_parameter::<String>(setting_strings, "target_region".to_string())?;
+    let to_translation = |p: &[f64; 3]| na::Translation3::new(p[0], p[1], p[2]);
+    match (listed, region) {
+        (Some(_), Some(_)) => Err("Give either 'targets' or 'target_region', not both.".to_string()),
+        (Some(listed), None) => Ok(Targets::Listed(parse_points(&listed, "targets")?.iter().map(to_translation).collect())),
+        (None, Some(region)) => {
+            let corners = parse_points(&region, "target_region")?;
+            if corners.len() != 2 {
+                return Err("'target_region' should be two opposite corners: '<x> <y> <z>, <x> <y> <z>'".to_string());
+            }
+            let ntargets = parse_optional_parameter::<usize>(setting_strings, "ntargets".to_string())?.unwrap_or(1);
+            if ntargets == 0 {
+                return Err("'ntargets' must be at least 1".to_string());
+            }
+            Ok(Targets::Region(to_translation(&corners[0]), to_translation(&corners[1]), ntargets))
+        },
+        (None, None) => {
+            let x = parse_parameter::<f64>(setting_strings, "target_x".to_string())?;
+            let y = parse_parameter::<f64>(setting_strings, "target_y".to_string())?;
+            let z = parse_parameter::<f64>(setting_strings, "target_z".to_string())?;
+            Ok(Targets::Listed(vec![na::Translation3::new(x, y, z)]))
+        },
+    }
+}
+
+/// Parses out either 'start_poses' (a list of base, shoulder, and elbow angles) or 'nstart_poses' (how many to draw at random).
+fn parse_start_poses(setting_strings: &mut HashMap<String, String>) -> Result<StartPoses, String> {
+    let listed = parse_optional_parameter::<String>(setting_strings, "start_poses".to_string())?;
+    let nrandom = parse_optional_parameter::<usize>(setting_strings, "nstart_poses".to_string())?;
+    match (listed, nrandom) {
+        (Some(_), Some(_)) => Err("Give either 'start_poses' or 'nstart_poses', not both.".to_string()),
+        (Some(listed), None) => Ok(StartPoses::Listed(parse_points(&listed, "start_poses")?)),
+        (None, Some(0)) => Err("'nstart_poses' must be at least 1".to_string()),
+        (None, Some(n)) => Ok(StartPoses::Random(n)),
+        (None, None) => Ok(StartPoses::Seeded),
+    }
+}
+
+/// Parses a comma separated list of three numbers each, like '0.1 0.2 0.3, 0.2 0.2 0.1', from the parameter `name`.
+fn parse_points(s: &str, name: &str) -> Result<Vec<[f64; 3]>, String> {
+    let mut points = Vec::new();
+    for point in s.split(',') {
+        let values: Vec<f64> = point.split_whitespace().filter_map(|token| token.parse::<f64>().ok()).collect();
+        if values.len() != 3 || point.split_whitespace().count() != 3 {
+            let mut msg = String::new();
+            write!(msg, "Each entry in '{}' should be three numbers, but one is '{}'", name, point.trim()).unwrap();
+            return Err(msg);
+        }
+        points.push([values[0], values[1], values[2]]);
+    }
+    Ok(points)
+}
+
+/// Parses out 'parent_selection' (with 'tournament_size'), 'crossover', 'elitism', 'immigrants', and 'adaptive_mutation'.
+/// Without any of them, the genetic algorithm keeps the best nkeep networks and fills the rest of the generation with their mutants.
+fn parse_strategies(setting_strings: &mut HashMap<String, String>, generation_size: u64, nkeep: u64) -> Result<(ParentSelection, Crossover, u64, u64, bool), String> {
+    let selectionstr = parse_optional_parameter::<String>(setting_strings, "parent_selection".to_string())?;
+    let parent_selection = match selectionstr.as_ref().map(|s| s.as_str()) {
+        None | Some("round_robin") => ParentSelection::RoundRobin,
+        Some("tournament") => ParentSelection::Tournament(parse_optional_parameter::<usize>(setting_strings, "tournament_size".to_string())?.unwrap_or(3)),
+        Some("roulette") => ParentSelection::Roulette,
+        Some("rank") => ParentSelection::Rank,
+        Some(s) => {
+            let mut errmsg = String::new();
+            writeln!(errmsg, "'parent_selection' must be 'round_robin', 'tournament', 'roulette', or 'rank' but is {}", s).unwrap();
+            return Err(errmsg);
+        },
+    };
+    if parent_selection == ParentSelection::Tournament(0) {
+        return Err("'tournament_size' must be at least 1".to_string());
+    }
+
+    let crossoverstr = parse_optional_parameter::<String>(setting_strings, "crossover".to_string())?;
+    let crossover = match crossoverstr.as_ref().map(|s| s.as_str()) {
+        None | Some("none") => Crossover::Clone,
+        Some("uniform") => Crossover::Uniform,
+        Some("layerwise") => Crossover::LayerWise,
+        Some("arithmetic") => Crossover::Arithmetic,
+        Some(s) => {
+            let mut errmsg = String::new();
+            writeln!(errmsg, "'crossover' must be 'none', 'uniform', 'layerwise', or 'arithmetic' but is {}", s).unwrap();
+            return Err(errmsg);
+        },
+    };
+
+    let elitism = parse_optional_parameter::<u64>(setting_strings, "elitism".to_string())?.unwrap_or(nkeep);
+    let immigrants = parse_optional_parameter::<u64>(setting_strings, "immigrants".to_string())?.unwrap_or(0);
+    if elitism + immigrants > generation_size {
+        let mut msg = String::new();
+        write!(msg, "'elitism' and 'immigrants' add up to more than the generation size of {}", generation_size).unwrap();
+        return Err(msg);
+    }
+    if parent_selection == ParentSelection::RoundRobin && nkeep == 0 && elitism + immigrants < generation_size {
+        return Err("'nkeep_between_generations' must be at least 1 to pick parents round robin".to_string());
+    }
+
+    let adaptive_mutation = parse_optional_parameter::<bool>(setting_strings, "adaptive_mutation".to_string())?.unwrap_or(false);
+    Ok((parent_selection, crossover, elitism, immigrants, adaptive_mutation))
+}
+
+/// Parses out 'optimizer' (default adam), 'learning_rate' (default 0.001), 'batch_size' (default 32), and 'nsamples'.
+fn parse_training(setting_strings: &mut HashMap<String, String>) -> Result<(OptimizerKind, f64, u64, u64), String> {
+    let (optimizer, learning_rate) = parse_optimizer(setting_strings, 0.001)?;
+    let batch_size = parse_optional_parameter::<u64>(setting_strings, "batch_size".to_string())?.unwrap_or(32);
+    let nsamples = parse_parameter::<u64>(setting_strings, "nsamples".to_string())?;
+    if batch_size == 0 || nsamples == 0 {
+        return Err("'batch_size' and 'nsamples' must be at least 1".to_string());
+    }
+    Ok((optimizer, learning_rate, batch_size, nsamples))
+}
+
+/// Parses out 'optimizer' (default adam) and 'learning_rate'.
+fn parse_optimizer(setting_strings: &mut HashMap<String, String>, default_learning_rate: f64) -> Result<(OptimizerKind, f64), String> {
+    let optimizerstr = parse_optional_parameter::<String>(setting_strings, "optimizer".to_string())?;
+    let optimizer = match optimizerstr.as_ref().map(|s| s.as_str()) {
+        None | Some("adam") => OptimizerKind::Adam,
+        Some("sgd") => OptimizerKind::Sgd,
+        Some(s) => {
+            let mut errmsg = String::new();
+            writeln!(errmsg, "'optimizer' must be 'adam' or 'sgd' but is {}", s).unwrap();
+            return Err(errmsg);
+        },
+    };
+
+    let learning_rate = parse_optional_parameter::<f64>(setting_strings, "learning_rate".to_string())?.unwrap_or(default_learning_rate);
+    if learning_rate <= 0.0 {
+        let mut msg = String::new();
+        write!(msg, "'learning_rate' must be positive, but is {}", learning_rate).unwrap();
+        return Err(msg);
+    }
+    Ok((optimizer, learning_rate))
+}
+
+/// Parses out 'es_sigma' (default 0.1), and checks that the generation is big enough for the strategy: CMA-ES needs
+/// at least two networks to rank, and OpenAI's evolution strategy samples its networks in mirrored pairs.
+fn parse_evolution_strategy(setting_strings: &mut HashMap<String, String>, mode: &Mode, generation_size: u64) -> Result<f64, String> {
+    let es_sigma = parse_optional_parameter::<f64>(setting_strings, "es_sigma".to_string())?.unwrap_or(0.1);
+    if es_sigma <= 0.0 {
+        let mut msg = String::new();
+        write!(msg, "'es_sigma' must be positive, but is {}", es_sigma).unwrap();
+        return Err(msg);
+    }
+    if generation_size < 2 || (*mode == Mode::OpenAiEs && generation_size % 2 != 0) {
+        let mut msg = String::new();
+        match *mode {
+            Mode::OpenAiEs => write!(msg, "'generation_size' must be even and at least 2 for openai_es, but is {}", generation_size).unwrap(),
+            _ => write!(msg, "'generation_size' must be at least 2 for cma_es, but is {}", generation_size).unwrap(),
+        }
+        return Err(msg);
+    }
+    Ok(es_sigma)
+}
+
+/// Like `parse_parameter`, but a missing parameter is None rather than an error.
+fn parse_optional_parameter<T: FromStr>(setting_strings: &mut HashMap<String, String>, s: String) -> Result<Option<T>, String> {
+    if setting_strings.contains_key(&s) {
+        parse_parameter::<T>(setting_strings, s).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
 impl fmt::Display for ExperimentConfig {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "Experiment Configuration:")?;
@@ -222,12 +687,88 @@ impl fmt::Display for ExperimentConfig {
                 writeln!(f, "Number of networks to keep between generations: {}", self.nkeep)?;
                 writeln!(f, "Rough percentage of weights in a network to mutate: {}", self.percent_mutate)?;
                 writeln!(f, "Mutation Standard Deviation: {}", self.mutation_stdev)?;
+                writeln!(f, "Fitness: {}", self.fitness)?;
+                writeln!(f, "Survivor selection: {:?}", self.survivor_selection)?;
+                writeln!(f, "Parent selection: {:?}", self.parent_selection)?;
+                writeln!(f, "Crossover: {:?}", self.crossover)?;
+                writeln!(f, "Number of elite networks: {}", self.elitism)?;
+                writeln!(f, "Number of immigrant networks: {}", self.immigrants)?;
+                writeln!(f, "Adaptive mutation: {}", self.adaptive_mutation)?;
+                writeln!(f, "Reach tolerance: {}", self.reach_tolerance)?;
+                writeln!(f, "Measurement: {:?}", self.measurement)?;
+                writeln!(f, "Weight of measured fitness: {}", self.measured_fitness_weight)?;
+                if let Some(ref initial_weights) = self.initial_weights {
+                    writeln!(f, "Initial network: {}", initial_weights)?;
+                }
+            },
+            Mode::CmaEs | Mode::OpenAiEs => {
+                writeln!(f, "Generation size: {}", self.generation_size)?;
+                writeln!(f, "Random weight low threshold: {}", self.low)?;
+                writeln!(f, "Random weight high threshold: {}", self.high)?;
+                writeln!(f, "Initial standard deviation: {}", self.es_sigma)?;
+                if self.mode == Mode::OpenAiEs {
+                    writeln!(f, "Optimizer: {:?}", self.optimizer)?;
+                    writeln!(f, "Learning rate: {}", self.learning_rate)?;
+                }
+                writeln!(f, "Fitness: {}", self.fitness)?;
+                writeln!(f, "Reach tolerance: {}", self.reach_tolerance)?;
+                writeln!(f, "Measurement: {:?}", self.measurement)?;
+                writeln!(f, "Weight of measured fitness: {}", self.measured_fitness_weight)?;
+                if let Some(ref initial_weights) = self.initial_weights {
+                    writeln!(f, "Initial network: {}", initial_weights)?;
+                }
+            },
+            Mode::Supervised => {
+                writeln!(f, "Random weight low threshold: {}", self.low)?;
+                writeln!(f, "Random weight high threshold: {}", self.high)?;
+                writeln!(f, "Optimizer: {:?}", self.optimizer)?;
+                writeln!(f, "Learning rate: {}", self.learning_rate)?;
+                writeln!(f, "Batch size: {}", self.batch_size)?;
+                writeln!(f, "Number of examples: {}", self.nsamples)?;
             },
             _ => (),
         }
-        writeln!(f, "Target for gripper: {:?}", self.target)?;
+        writeln!(f, "Targets for gripper: {:?}", self.targets)?;
+        writeln!(f, "Start poses: {:?}", self.start_poses)?;
+        writeln!(f, "Observation: {}", self.observation)?;
+        writeln!(f, "Layers: {}", self.architecture)?;
+        if let Some(orientation) = self.target_orientation {
+            writeln!(f, "Target orientation for gripper (roll, pitch, yaw): {:?}", orientation.euler_angles())?;
+        }
         writeln!(f, "Path to URDF file: {}", self.urdfpath)?;
+        if self.simulated_in_parallel() {
+            match self.threads {
+                0 => writeln!(f, "Threads: one per core")?,
+                n => writeln!(f, "Threads: {}", n)?,
+            }
+        }
         writeln!(f, "Seed: {}", self.seed)?;
+        if let Some(ref record) = self.record {
+            writeln!(f, "Recording to: {}", record)?;
+        }
+        if let Some(ref replay) = self.replay {
+            writeln!(f, "Replaying: {}", replay)?;
+        }
+        if self.checkpoint_every > 0 {
+            writeln!(f, "Checkpoint every {} generations to: {}", self.checkpoint_every, self.checkpoint)?;
+        }
+        let formats: Vec<&str> = self.results_formats.iter().map(|format| format.name()).collect();
+        writeln!(f, "Results: {} in {}", formats.join(", "), self.results_dir)?;
+        if self.dashboard {
+            writeln!(f, "Dashboard: on")?;
+        }
         writeln!(f, "Weights: {}", self.weights)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_points() {
+        assert_eq!(parse_points("0.1 0.2 0.3, 90 10 -5", "targets").unwrap(), vec![[0.1, 0.2, 0.3], [90.0, 10.0, -5.0]]);
+        assert!(parse_points("0.1 0.2", "targets").is_err());
+        assert!(parse_points("0.1 0.2 0.3, 0.1 up 0.3", "targets").is_err());
+    }
+}
@@ -0,0 +1,293 @@
+//! Exports a trained network for the arm's firmware to run itself (see `roboarm/src/policy`).
+//!
+//! ```text
+//! experiment export <config.yaml> <output> [--fixed-point]
+//! ```
+//!
+//! The network comes from the config's `weights`, and is given its `observation`. Outputs ending in `.rs` are
+//! Rust modules to build into the firmware as `roboarm/src/policy/model.rs`. Anything else is a binary model,
+//! which the firmware checks with `Model::from_blob`. With `--fixed-point`, each layer's weights are 16 bit
+//! fixed point rather than `f32`, which halves their size. The biases stay `f32`.
+//!
+//! The firmware has no kinematic model, so networks given the end effector or distance vector can't be exported.
+
+use super::expconfig::ExperimentConfig;
+use super::network::{Activation, LayerParameters};
+use super::observation::Feature;
+use super::weightfile::{self, Metadata};
+use rand::prelude::*;
+use std::fmt::Write;
+use std::fs;
+
+#[cfg(test)]
+#[path = "../../roboarm/src/policy/network.rs"]
+#[allow(dead_code)]
+mod firmware;
+
+/// The firmware's binary models start with these bytes, then this version.
+const BLOB_MAGIC: &[u8; 4] = b"RANN";
+const BLOB_VERSION: u8 = 1;
+
+/// Marks a layer in a binary model as having `f32` weights rather than fixed point ones.
+const FLOAT_WEIGHTS: u8 = 0xff;
+
+/// How a layer's weights are exported.
+enum Weights {
+    F32(Vec<f32>),
+    /// Each weight is `w / 2^frac_bits`.
+    Fixed(Vec<i16>, u8),
+}
+
+/// A layer as the firmware will have it.
+struct ExportedLayer {
+    ninputs: usize,
+    nnodes: usize,
+    activation: Activation,
+    weights: Weights,
+    biases: Vec<f32>,
+}
+
+/// Exports the experiment's network to `output`. See the module docs.
+pub fn export(experiment: &ExperimentConfig, output: &str, fixed_point: bool) -> Result<(), String> {
+    let mut rng: StdRng = SeedableRng::seed_from_u64(experiment.seed);
+    let saved = weightfile::load_experiment_network(experiment, &experiment.weights, &mut rng)?;
+
+    let mut features = Vec::new();
+    for &feature in experiment.observation.features() {
+        match feature_code(feature) {
+            Some(_) => features.push(feature),
+            None => return Err(format!("The firmware has no kinematic model, so it can't give the network the {}.", feature.name())),
+        }
+    }
+    let mut layers = Vec::new();
+    for (i, layer) in saved.network.layer_parameters().into_iter().enumerate() {
+        match export_layer(layer, fixed_point) {
+            Ok(layer) => layers.push(layer),
+            Err(msg) => return Err(format!("Could not export layer {}: {}", i, msg)),
+        }
+    }
+    if layers[layers.len() - 1].nnodes != 3 {
+        return Err("The firmware needs a network that gives the base, shoulder, and elbow deltas.".to_string());
+    }
+
+    let contents = if output.ends_with(".rs") {
+        to_rust(&features, &layers, &experiment.weights, saved.metadata.as_ref()).into_bytes()
+    } else {
+        to_blob(&features, &layers)
+    };
+    fs::write(output, contents).map_err(|e| format!("Could not write {}: {}", output, e))
+}
+
+fn export_layer(layer: LayerParameters, fixed_point: bool) -> Result<ExportedLayer, String> {
+    if layer.weights.iter().chain(layer.biases.iter()).any(|w| !w.is_finite()) {
+        return Err("it has weights that aren't finite".to_string());
+    }
+    if layer.ninputs > u16::max_value() as usize || layer.nnodes > u16::max_value() as usize {
+        return Err("it is too big for the firmware".to_string());
+    }
+
+    let weights = if fixed_point {
+        // Use as many fractional bits as the biggest weight leaves room for
+        let biggest = layer.weights.iter().fold(0.0f64, |biggest, w| biggest.max(w.abs()));
+        let mut frac_bits = 15;
+        while frac_bits > 0 && biggest * (1 << frac_bits) as f64 > i16::max_value() as f64 {
+            frac_bits -= 1;
+        }
+        if biggest > i16::max_value() as f64 {
+            return Err(format!("its biggest weight ({}) is too big for 16 bit fixed point", biggest));
+        }
+        let scale = (1 << frac_bits) as f64;
+        Weights::Fixed(layer.weights.iter().map(|w| (w * scale).round() as i16).collect(), frac_bits)
+    } else {
+        Weights::F32(layer.weights.iter().map(|&w| w as f32).collect())
+    };
+    Ok(ExportedLayer {
+        ninputs: layer.ninputs,
+        nnodes: layer.nnodes,
+        activation: layer.activation,
+        weights: weights,
+        biases: layer.biases.iter().map(|&b| b as f32).collect(),
+    })
+}
+
+/// The feature's number in binary models, if the firmware can build it.
+fn feature_code(feature: Feature) -> Option<u8> {
+    match feature {
+        Feature::JointAngles => Some(0),
+        Feature::NormalizedJointAngles => Some(1),
+        Feature::Target => Some(2),
+        Feature::PreviousAction => Some(3),
+        Feature::EndEffector | Feature::DistanceVector => None,
+    }
+}
+
+/// The activation's number in binary models.
+fn activation_code(activation: Activation) -> u8 {
+    match activation {
+        Activation::Linear => 0,
+        Activation::Relu => 1,
+        Activation::Tanh => 2,
+        Activation::Sigmoid => 3,
+        Activation::LeakyRelu => 4,
+        Activation::Softsign => 5,
+    }
+}
+
+/// The name of the firmware's variant of `Feature` or `Activation`.
+fn variant_name(name: &str) -> String {
+    name.split('_').map(|word| word[..1].to_uppercase() + &word[1..]).collect()
+}
+
+/// Writes the Rust module, which the firmware uses as `policy::model`.
+fn to_rust(features: &[Feature], layers: &[ExportedLayer], weights: &str, metadata: Option<&Metadata>) -> String {
+    let mut s = String::new();
+    writeln!(s, "//! Exported by `experiment export` from {}. Export it again rather than editing it.", weights);
+    if let Some(metadata) = metadata {
+        writeln!(s, "//!");
+        writeln!(s, "//! Seed {}, generation {}, fitness {}, config hash {:016x}.", metadata.seed, metadata.generation, metadata.fitness, metadata.config_hash);
+    }
+    writeln!(s, "");
+    writeln!(s, "use super::network::network::{{Activation, Feature, Layer, Model, Weights}};");
+    writeln!(s, "");
+    writeln!(s, "pub static MODEL: Model<'static> = Model::Static {{");
+    let names: Vec<String> = features.iter().map(|feature| format!("Feature::{}", variant_name(feature.name()))).collect();
+    writeln!(s, "    observation: &[{}],", names.join(", "));
+    writeln!(s, "    layers: &[");
+    for layer in layers.iter() {
+        writeln!(s, "        Layer {{");
+        writeln!(s, "            ninputs: {},", layer.ninputs);
+        writeln!(s, "            nnodes: {},", layer.nnodes);
+        writeln!(s, "            activation: Activation::{},", variant_name(layer.activation.name()));
+        match layer.weights {
+            Weights::F32(ref weights) => {
+                writeln!(s, "            weights: Weights::F32(&[");
+                write_rows(&mut s, weights.iter().map(|w| format!("{:?}", w)).collect(), layer.ninputs);
+                writeln!(s, "            ]),");
+            },
+            Weights::Fixed(ref weights, frac_bits) => {
+                writeln!(s, "            weights: Weights::Fixed(&[");
+                write_rows(&mut s, weights.iter().map(|w| w.to_string()).collect(), layer.ninputs);
+                writeln!(s, "            ], {}),", frac_bits);
+            },
+        }
+        let biases: Vec<String> = layer.biases.iter().map(|b| format!("{:?}", b)).collect();
+        writeln!(s, "            biases: Weights::F32(&[{}]),", biases.join(", "));
+        writeln!(s, "        }},");
+    }
+    writeln!(s, "    ],");
+    writeln!(s, "}};");
+    s
+}
+
+/// Writes a line for each node's weights.
+fn write_rows(s: &mut String, numbers: Vec<String>, ninputs: usize) {
+    for row in numbers.chunks(ninputs.max(1)) {
+        writeln!(s, "                {},", row.join(", "));
+    }
+}
+
+/// Writes the binary model: `RANN`, the version, the number of features and each one's number, then the number of
+/// layers, and each layer's inputs and nodes (`u16`s), activation, weight format (`frac_bits`, or 0xff for `f32`),
+/// weights, and (`f32`) biases. All little-endian.
+fn to_blob(features: &[Feature], layers: &[ExportedLayer]) -> Vec<u8> {
+    let mut bytes = BLOB_MAGIC.to_vec();
+    bytes.push(BLOB_VERSION);
+    bytes.push(features.len() as u8);
+    bytes.extend(features.iter().map(|&feature| feature_code(feature).unwrap()));
+    bytes.push(layers.len() as u8);
+    for layer in layers.iter() {
+        put_u16(&mut bytes, layer.ninputs as u16);
+        put_u16(&mut bytes, layer.nnodes as u16);
+        bytes.push(activation_code(layer.activation));
+        match layer.weights {
+            Weights::F32(ref weights) => {
+                bytes.push(FLOAT_WEIGHTS);
+                weights.iter().for_each(|w| put_u32(&mut bytes, w.to_bits()));
+            },
+            Weights::Fixed(ref weights, frac_bits) => {
+                bytes.push(frac_bits);
+                weights.iter().for_each(|&w| put_u16(&mut bytes, w as u16));
+            },
+        }
+        layer.biases.iter().for_each(|b| put_u32(&mut bytes, b.to_bits()));
+    }
+    bytes
+}
+
+fn put_u16(bytes: &mut Vec<u8>, n: u16) {
+    bytes.push(n as u8);
+    bytes.push((n >> 8) as u8);
+}
+
+fn put_u32(bytes: &mut Vec<u8>, n: u32) {
+    for i in 0..4 {
+        bytes.push((n >> (8 * i)) as u8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::firmware::network as fw;
+    use super::super::network::{Layer, MultilayerPerceptron};
+    use nalgebra as na;
+
+    fn build_network() -> MultilayerPerceptron {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(11);
+        let mut net = MultilayerPerceptron::new();
+        let activations = [Activation::Tanh, Activation::Sigmoid, Activation::LeakyRelu, Activation::Softsign, Activation::Linear];
+        let widths = [6, 20, 12, 9, 5, 3];
+        for i in 0..activations.len() {
+            let layer = Layer::new().inputs(widths[i]).length(widths[i + 1]).activation(activations[i]).initialize_weights(-1.0, 1.0, &mut rng).finalize();
+            let biases = (0..widths[i + 1]).map(|_| rng.gen_range(-0.5, 0.5)).collect();
+            net.add_layer(Layer::from_parameters(LayerParameters { biases: biases, ..layer.to_parameters() }).unwrap());
+        }
+        net.finalize().unwrap()
+    }
+
+    /// Checks that the firmware's outputs for the exported network are within `tolerance` of `forward`'s.
+    fn check_blob(network: &MultilayerPerceptron, fixed_point: bool, tolerance: f64) {
+        let features = [Feature::NormalizedJointAngles, Feature::PreviousAction];
+        let layers: Vec<ExportedLayer> = network.layer_parameters().into_iter().map(|layer| export_layer(layer, fixed_point).unwrap()).collect();
+        let blob = to_blob(&features, &layers);
+        let model = fw::Model::from_blob(&blob).unwrap();
+        assert_eq!(model.input_length(), 6);
+        assert_eq!(model.feature(1), Some(fw::Feature::PreviousAction));
+
+        let mut rng: StdRng = SeedableRng::seed_from_u64(12);
+        let mut scratch = vec![0.0f32; model.scratch_length()];
+        for _ in 0..50 {
+            let input: Vec<f64> = (0..6).map(|_| rng.gen_range(-2.0, 2.0)).collect();
+            let expected = network.forward(&na::DVector::<f64>::from_vec(6, input.clone()));
+            let input: Vec<f32> = input.iter().map(|&x| x as f32).collect();
+            let mut output = [0.0f32; 3];
+            model.forward(&input, &mut scratch, &mut output).unwrap();
+            for j in 0..3 {
+                assert!((output[j] as f64 - expected[j]).abs() < tolerance, "{} and {} differ by more than {}", output[j], expected[j], tolerance);
+            }
+        }
+    }
+
+    #[test]
+    fn test_exported_model_matches_forward() {
+        let network = build_network();
+        check_blob(&network, false, 1E-5);
+        check_blob(&network, true, 1E-2);
+
+        // A model cut short is refused rather than run
+        let layers: Vec<ExportedLayer> = network.layer_parameters().into_iter().map(|layer| export_layer(layer, false).unwrap()).collect();
+        let blob = to_blob(&[Feature::JointAngles, Feature::Target], &layers);
+        assert_eq!(fw::Model::from_blob(&blob[..blob.len() - 1]).unwrap_err(), fw::ModelError::TooShort);
+    }
+
+    #[test]
+    fn test_rust_module() {
+        let network = build_network();
+        let layers: Vec<ExportedLayer> = network.layer_parameters().into_iter().map(|layer| export_layer(layer, true).unwrap()).collect();
+        let module = to_rust(&[Feature::NormalizedJointAngles, Feature::PreviousAction], &layers, "network.toml", None);
+        assert!(module.contains("observation: &[Feature::NormalizedJointAngles, Feature::PreviousAction],"));
+        assert!(module.contains("activation: Activation::LeakyRelu,"));
+        assert_eq!(module.matches("Weights::Fixed(&[").count(), 5);
+        assert_eq!(variant_name("leaky_relu"), "LeakyRelu");
+    }
+}
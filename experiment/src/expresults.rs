@@ -1,4 +1,5 @@
 use super::expconfig::ExperimentConfig;
+use super::records::{Log, Record};
 use std::collections::HashMap;
 use std::fmt;
 use std::io::Write;
@@ -10,6 +11,7 @@ pub struct ExperimentResults<'a> {
     cur_episode: u64,               // The current episode
     episode_buffer: String,         // The log for the current episode
     logs: HashMap<u64, String>,     // The log for each episode
+    records: Vec<Record>,           // The records for the current episode, until they are taken to be written
 }
 
 impl<'a> ExperimentResults<'a> {
@@ -19,6 +21,7 @@ impl<'a> ExperimentResults<'a> {
             cur_episode: 0,
             episode_buffer: String::new(),
             logs: HashMap::<u64, String>::new(),
+            records: Vec::new(),
         }
     }
 
@@ -36,6 +39,29 @@ impl<'a> ExperimentResults<'a> {
         }
     }
 
+    /// What has been recorded for episode `ep`, if anything.
+    pub fn episode_log(&self, ep: u64) -> Option<&String> {
+        if ep == self.cur_episode {
+            Some(&self.episode_buffer)
+        } else {
+            self.logs.get(&ep)
+        }
+    }
+
+    /// Puts back what was recorded for an episode before the experiment was resumed.
+    pub fn restore_episode(&mut self, ep: u64, log: String) {
+        if ep == self.cur_episode {
+            self.episode_buffer = log;
+        } else {
+            self.logs.insert(ep, log);
+        }
+    }
+
+    /// Takes the records made since they were last taken, to write them to the tables.
+    pub fn take_records(&mut self) -> Vec<Record> {
+        self.records.split_off(0)
+    }
+
     pub fn finish(&mut self) {
         self.logs.insert(self.cur_episode, self.episode_buffer.clone());
     }
@@ -80,3 +106,9 @@ impl<'a> fmt::Write for ExperimentResults<'a> {
         write!(self.episode_buffer, "{}", s)
     }
 }
+
+impl<'a> Log for ExperimentResults<'a> {
+    fn record(&mut self, record: Record) {
+        self.records.push(record);
+    }
+}
@@ -1,8 +1,32 @@
 use rand;
-use super::expconfig;
+use rand::Rng;
+use super::evolution::{self, EvolutionStrategy};
+use super::expconfig::{self, Crossover, Mode, ParentSelection, SurvivorSelection};
 use super::netconfig;
 use super::network;
+use super::supervised;
+use super::weightfile;
 use std::cmp::Ordering::Equal;
+use std::f64;
+use std::fmt::Write;
+
+/// With adaptive mutation, how much the mutation rate and size shrink after a generation that improved on the best
+/// fitness so far. They grow by the same factor after one that didn't.
+const ADAPTIVE_MUTATION_FACTOR: f64 = 0.85;
+/// The bounds on how far adaptive mutation can scale the configured mutation rate and size.
+const MIN_MUTATION_SCALE: f64 = 0.1;
+const MAX_MUTATION_SCALE: f64 = 10.0;
+
+/// What a checkpoint keeps of the state, besides the networks themselves.
+pub struct Snapshot {
+    pub generation: u64,
+    pub evaluations: Vec<f64>,
+    pub objectives: Vec<Vec<f64>>,
+    pub mutation_scale: f64,
+    pub best_fitness: Option<f64>,
+    /// The evolution strategy's state, if there is one (see `EvolutionStrategy::state`).
+    pub strategy: Option<Vec<f64>>,
+}
 
 /// A struct to maintain state across the whole experiment
 pub struct ExperimentState {
@@ -13,6 +37,20 @@ pub struct ExperimentState {
     /// How fit each network is. The ith evaluation is the evaluation for the ith network.
     /// This vector will be cleared of values each time we move to a new generation.
     evaluations: Vec<f64>,
+    /// The fitness terms that each evaluation was made from, weighted and signed so that higher is better.
+    /// NSGA-II selects on these.
+    objectives: Vec<Vec<f64>>,
+    /// How much the configured mutation rate and size are scaled by. Only changes with adaptive mutation.
+    mutation_scale: f64,
+    /// The best fitness of any generation so far.
+    best_fitness: Option<f64>,
+    /// The network the first generation is made from, instead of random ones (see `start_from`). Evolution strategies
+    /// make their networks in its shape.
+    initial_network: Option<network::MultilayerPerceptron>,
+    /// The evolution strategy that makes each generation, if the mode is one. Made with the first generation.
+    strategy: Option<Box<dyn EvolutionStrategy>>,
+    /// What trains the network in supervised mode. Made in the first epoch.
+    pub trainer: Option<supervised::Trainer>,
 }
 
 impl ExperimentState {
@@ -21,40 +59,153 @@ impl ExperimentState {
             generation: 0,
             networks: Vec::new(),
             evaluations: Vec::new(),
+            objectives: Vec::new(),
+            mutation_scale: 1.0,
+            best_fitness: None,
+            initial_network: None,
+            strategy: None,
+            trainer: None,
+        }
+    }
+
+    /// Makes the first generation from `network` (say, one pre-trained by backpropagation): the network itself,
+    /// and mutants of it to fill the rest of the generation.
+    pub fn start_from(&mut self, network: network::MultilayerPerceptron) {
+        self.initial_network = Some(network);
+    }
+
+    /// Everything but the networks, for a checkpoint.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            generation: self.generation as u64,
+            evaluations: self.evaluations.clone(),
+            objectives: self.objectives.clone(),
+            mutation_scale: self.mutation_scale,
+            best_fitness: self.best_fitness,
+            strategy: self.strategy.as_ref().map(|strategy| strategy.state()),
         }
     }
 
-    /// Adds the next fitness value
-    pub fn add_fitness(&mut self, fitness: f64) {
+    /// Makes the state a checkpoint was taken of, from its snapshot and its generation's networks.
+    pub fn restore(experiment: &expconfig::ExperimentConfig, snapshot: Snapshot, networks: Vec<network::MultilayerPerceptron>) -> Result<Self, String> {
+        if snapshot.evaluations.len() != networks.len() || snapshot.objectives.len() != networks.len() {
+            let mut msg = String::new();
+            write!(msg, "The checkpoint has {} networks, but {} fitnesses.", networks.len(), snapshot.evaluations.len()).unwrap();
+            return Err(msg);
+        }
+
+        // Evolution strategies carry on from where they were, making networks shaped like the ones they made before
+        let strategy = match (snapshot.strategy, networks.first()) {
+            (Some(values), Some(template)) => match evolution::new_strategy(experiment, template.flat_weights()) {
+                Some(mut strategy) => {
+                    strategy.restore(&values)?;
+                    Some(strategy)
+                },
+                None => return Err("The checkpoint has an evolution strategy's state, but the experiment doesn't use one.".to_string()),
+            },
+            (Some(_), None) => return Err("The checkpoint has an evolution strategy's state, but no networks.".to_string()),
+            (None, _) => None,
+        };
+
+        Ok(ExperimentState {
+            generation: snapshot.generation as usize,
+            initial_network: networks.first().cloned(),
+            networks: networks,
+            evaluations: snapshot.evaluations,
+            objectives: snapshot.objectives,
+            mutation_scale: snapshot.mutation_scale,
+            best_fitness: snapshot.best_fitness,
+            strategy: strategy,
+            trainer: None,
+        })
+    }
+
+    /// How fit each network in the current generation is, in the order of `networks`.
+    pub fn evaluations(&self) -> &[f64] {
+        &self.evaluations
+    }
+
+    /// Adds the next network's fitness, along with the terms it was made from
+    pub fn add_evaluation(&mut self, fitness: f64, objectives: Vec<f64>) {
         self.evaluations.push(fitness);
+        self.objectives.push(objectives);
     }
 
     /// Creates the next generation of neural networks
     ///
     /// If the current generation does not contain any networks,
     /// a brand new generation is created with random weights between
-    /// the low and high parameters.
+    /// the low and high parameters, or from the network given to `start_from`.
+    ///
+    /// If the current generation does contain networks, the next one is made of the
+    /// elite networks (unchanged), immigrants (brand new random networks), and
+    /// mutants of parents picked from the current generation, crossed over if the
+    /// experiment says so. By default, the elites are the top `nkeep` networks and
+    /// the mutants go round them in order.
     ///
-    /// If the current generation does contain networks,
-    /// the top `nkeep` networks are kept and mutated, while the others are
-    /// discarded.
+    /// If the mode is an evolution strategy, the strategy makes every generation instead.
     pub fn create_next_generation<'a>(&mut self, experiment: &'a expconfig::ExperimentConfig, rng: &mut rand::StdRng) {
         let gensize = experiment.generation_size as usize;
-        let nkeep = experiment.nkeep as usize;
 
-        self.networks = if self.generation == 0 {
-            self.spawn_n_networks(gensize, experiment.low, experiment.high, rng)
+        self.networks = if experiment.mode == Mode::CmaEs || experiment.mode == Mode::OpenAiEs {
+            self.spawn_from_strategy(experiment, rng)
+        } else if self.generation == 0 {
+            match self.initial_network {
+                Some(ref initial) => spawn_from_initial_network(initial, gensize, experiment, rng),
+                None => self.spawn_n_networks(gensize, experiment, rng),
+            }
         } else {
-            self.spawn_from_networks(gensize, nkeep, rng, experiment.percent_mutate, experiment.mutation_stdev)
+            if experiment.adaptive_mutation {
+                self.adapt_mutation();
+            }
+            let percent_mutate = (experiment.percent_mutate * self.mutation_scale).min(100.0);
+            let mutation_stdev = experiment.mutation_stdev * self.mutation_scale;
+            self.spawn_from_networks(experiment, rng, percent_mutate, mutation_stdev)
         };
         self.generation += 1;
         self.evaluations.clear();
+        self.objectives.clear();
     }
 
-    fn spawn_n_networks(&self, n: usize, low: f64, high: f64, rng: &mut rand::StdRng) -> Vec<network::MultilayerPerceptron> {
+    /// Mutates less if this generation beat the best fitness so far, and more if it didn't.
+    fn adapt_mutation(&mut self) {
+        let best = self.evaluations.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        if let Some(previous) = self.best_fitness {
+            self.mutation_scale = if best > previous {
+                self.mutation_scale * ADAPTIVE_MUTATION_FACTOR
+            } else {
+                self.mutation_scale / ADAPTIVE_MUTATION_FACTOR
+            };
+            self.mutation_scale = self.mutation_scale.max(MIN_MUTATION_SCALE).min(MAX_MUTATION_SCALE);
+        }
+        self.best_fitness = Some(self.best_fitness.map_or(best, |previous| previous.max(best)));
+    }
+
+    /// Tells the evolution strategy how the current generation did, if there is one, and asks it for the next. The
+    /// strategy starts from the network given to `start_from`, or a random one.
+    fn spawn_from_strategy(&mut self, experiment: &expconfig::ExperimentConfig, rng: &mut rand::StdRng) -> Vec<network::MultilayerPerceptron> {
+        if self.initial_network.is_none() {
+            self.initial_network = Some(netconfig::build_network(&experiment.architecture, &experiment.observation, experiment.low, experiment.high, rng));
+        }
+        let template = match self.initial_network {
+            Some(ref network) => network,
+            None => unreachable!(),
+        };
+
+        match self.strategy {
+            Some(ref mut strategy) => strategy.tell(&self.evaluations),
+            None => self.strategy = evolution::new_strategy(experiment, template.flat_weights()),
+        }
+        match self.strategy {
+            Some(ref mut strategy) => strategy.ask(rng).iter().map(|weights| template.with_flat_weights(weights)).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn spawn_n_networks(&self, n: usize, experiment: &expconfig::ExperimentConfig, rng: &mut rand::StdRng) -> Vec<network::MultilayerPerceptron> {
         let mut v = Vec::<network::MultilayerPerceptron>::new();
         for _netidx in 0..n {
-            let net = netconfig::build_network(low, high, rng);
+            let net = netconfig::build_network(&experiment.architecture, &experiment.observation, experiment.low, experiment.high, rng);
             v.push(net);
         }
         v
@@ -76,40 +227,101 @@ impl ExperimentState {
         idx_val_nets
     }
 
-    fn spawn_from_networks(&self, gensize: usize, nkeep: usize, rng: &mut rand::StdRng, percent_mutate: f64, mutation_stdev: f64) -> Vec<network::MultilayerPerceptron> {
+    /// The indexes of the networks, in the order they should be picked to survive.
+    fn survivor_order(&self, selection: SurvivorSelection) -> Vec<usize> {
+        match selection {
+            SurvivorSelection::Best => self.sort_networks().iter().map(|x| x.0).collect(),
+            SurvivorSelection::Nsga2 => nsga2_order(&self.objectives),
+        }
+    }
+
+    fn spawn_from_networks(&self, experiment: &expconfig::ExperimentConfig, rng: &mut rand::StdRng, percent_mutate: f64, mutation_stdev: f64) -> Vec<network::MultilayerPerceptron> {
+        let gensize = experiment.generation_size as usize;
+        let nkeep = experiment.nkeep as usize;
+
         // If generation is sized zero, we don't have to do anything. Note that this would be
         // an atypical use case.
         if gensize == 0 {
             return Vec::<network::MultilayerPerceptron>::new();
         }
 
-        // sort the networks along with their indexes by how well they did
-        let idx_val_nets = self.sort_networks();
+        // sort the networks' indexes by how well they did
+        let order = self.survivor_order(experiment.survivor_selection);
 
-        // Now keep only the top-performing nkeep nets from self.networks
-        let mut nets_to_keep = Vec::new();
-        for i in 0..nkeep {
-            let idx = idx_val_nets[i].0;
-            nets_to_keep.push(self.networks[idx].clone());
-        }
+        // The elite networks go into the next generation unchanged
+        let mut next: Vec<network::MultilayerPerceptron> = order.iter().take(experiment.elitism as usize).map(|&idx| self.networks[idx].clone()).collect();
 
-        // Spawn the rest of the nets as random mutations of the others
-        let mut rest = Vec::new();
-        for i in 0..(gensize - nkeep) {
-            let mutant = nets_to_keep[i % nkeep].mutate(rng, percent_mutate / 100.0, mutation_stdev);
-            rest.push(mutant);
-        }
+        // Immigrants are brand new
+        let immigrants = self.spawn_n_networks(experiment.immigrants as usize, experiment, rng);
+        next.extend(immigrants);
 
-        // Move the rest into nets_to_keep
-        for net in rest {
-            nets_to_keep.push(net);
+        // Spawn the rest of the nets as mutations of parents, crossed over with a second parent if need be
+        for i in 0..(gensize - next.len()) {
+            let first = &self.networks[self.pick_parent(experiment.parent_selection, &order, nkeep, i, rng)];
+            let child = match experiment.crossover {
+                Crossover::Clone => first.clone(),
+                crossover => {
+                    let second = &self.networks[self.pick_parent(experiment.parent_selection, &order, nkeep, i + 1, rng)];
+                    match crossover {
+                        Crossover::Uniform => first.crossover_uniform(second, rng),
+                        Crossover::LayerWise => first.crossover_layerwise(second, rng),
+                        Crossover::Arithmetic => first.crossover_arithmetic(second, rng),
+                        Crossover::Clone => unreachable!("cloning doesn't take a second parent"),
+                    }
+                },
+            };
+            next.push(child.mutate(rng, percent_mutate / 100.0, mutation_stdev));
         }
 
-        assert!(nets_to_keep.len() == gensize);
-        nets_to_keep
+        assert!(next.len() == gensize);
+        next
     }
 
-    pub fn save_best_network(&self, path: &String) -> std::io::Result<()> {
+    /// Picks a parent for the `i`th new network. `order` is the networks' indexes from best to worst.
+    /// Returns the index of the parent.
+    fn pick_parent(&self, selection: ParentSelection, order: &[usize], nkeep: usize, i: usize, rng: &mut rand::StdRng) -> usize {
+        match selection {
+            ParentSelection::RoundRobin => order[i % nkeep],
+            ParentSelection::Tournament(size) => {
+                // Whichever of the contestants is nearest the top of the order wins
+                let winner = (0..size).map(|_| rng.gen_range(0, order.len())).min().unwrap_or(0);
+                order[winner]
+            },
+            ParentSelection::Rank => {
+                // The best network gets n tickets, the next n - 1, and so on down to 1 for the worst
+                let n = order.len();
+                let mut ticket = rng.gen_range(0, n * (n + 1) / 2);
+                for (position, &idx) in order.iter().enumerate() {
+                    if ticket < n - position {
+                        return idx;
+                    }
+                    ticket -= n - position;
+                }
+                order[n - 1]
+            },
+            ParentSelection::Roulette => {
+                // Fitness can be negative, so measure it up from the worst. Networks without a fitness get no chance.
+                let worst = self.evaluations.iter().cloned().filter(|f| f.is_finite()).fold(f64::INFINITY, f64::min);
+                let shares: Vec<f64> = self.evaluations.iter().map(|&f| if f.is_finite() { f - worst } else { 0.0 }).collect();
+                let total: f64 = shares.iter().sum();
+                if !(total > 0.0) {
+                    return rng.gen_range(0, self.networks.len());
+                }
+
+                let mut spin = rng.gen_range(0.0, total);
+                for (idx, share) in shares.iter().enumerate() {
+                    if spin < *share {
+                        return idx;
+                    }
+                    spin -= share;
+                }
+                self.networks.len() - 1
+            },
+        }
+    }
+
+    /// Saves the best network of the current generation to `path`, with how it was trained.
+    pub fn save_best_network(&self, experiment: &expconfig::ExperimentConfig, path: &String) -> std::io::Result<()> {
         // If there are no networks for some reason, ignore
         if self.networks.len() == 0 {
             return Ok(());
@@ -117,11 +329,114 @@ impl ExperimentState {
 
         // Figure out which network has the highest fitness
         let idx_val_nets = self.sort_networks();
-        let best_network = (idx_val_nets[0].1).1;
+        let (best_fitness, best_network) = idx_val_nets[0].1;
 
         // Save the network's weights
-        best_network.save_weights(path)
+        let metadata = weightfile::Metadata {
+            seed: experiment.seed,
+            generation: self.generation as u64,
+            fitness: *best_fitness,
+            config_hash: weightfile::hash_config(experiment),
+        };
+        weightfile::save(best_network, &metadata, path)
+    }
+}
+
+/// The initial network, then `gensize - 1` mutants of it, mutated as the experiment says.
+fn spawn_from_initial_network(initial: &network::MultilayerPerceptron, gensize: usize, experiment: &expconfig::ExperimentConfig, rng: &mut rand::StdRng) -> Vec<network::MultilayerPerceptron> {
+    let mut v = Vec::<network::MultilayerPerceptron>::new();
+    if gensize == 0 {
+        return v;
     }
+    v.push(initial.clone());
+    for _netidx in 1..gensize {
+        v.push(initial.mutate(rng, experiment.percent_mutate / 100.0, experiment.mutation_stdev));
+    }
+    v
+}
+
+/// Orders the networks by NSGA-II: by which Pareto front their objectives are on, then by how far
+/// they are from their neighbours on that front, so that the survivors stay spread out.
+fn nsga2_order(objectives: &[Vec<f64>]) -> Vec<usize> {
+    let mut order = Vec::new();
+    for front in pareto_fronts(objectives) {
+        let distances = crowding_distances(&front, objectives);
+        let mut ranked: Vec<(usize, f64)> = front.into_iter().zip(distances).collect();
+        ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Equal));
+        order.extend(ranked.into_iter().map(|(idx, _)| idx));
+    }
+    order
+}
+
+/// Whether `a` is at least as good as `b` on every objective, and better on at least one.
+fn dominates(a: &[f64], b: &[f64]) -> bool {
+    a.iter().zip(b).all(|(x, y)| x >= y) && a.iter().zip(b).any(|(x, y)| x > y)
+}
+
+/// Splits the indexes of `objectives` into fronts. Nothing in a front is dominated by anything in
+/// the same or a later front.
+fn pareto_fronts(objectives: &[Vec<f64>]) -> Vec<Vec<usize>> {
+    let n = objectives.len();
+    let mut ndominating = vec![0; n];
+    let mut dominated: Vec<Vec<usize>> = vec![Vec::new(); n];
+    for i in 0..n {
+        for j in 0..n {
+            if dominates(&objectives[i], &objectives[j]) {
+                dominated[i].push(j);
+            } else if dominates(&objectives[j], &objectives[i]) {
+                ndominating[i] += 1;
+            }
+        }
+    }
+
+    let mut fronts = Vec::new();
+    let mut front: Vec<usize> = (0..n).filter(|&i| ndominating[i] == 0).collect();
+    while !front.is_empty() {
+        let mut next = Vec::new();
+        for &i in front.iter() {
+            for &j in dominated[i].iter() {
+                ndominating[j] -= 1;
+                if ndominating[j] == 0 {
+                    next.push(j);
+                }
+            }
+        }
+        fronts.push(front);
+        front = next;
+    }
+    fronts
+}
+
+/// How much room there is around each member of the front, summed over the objectives. The members at
+/// either end of an objective's range get infinite room. Objectives that aren't finite (a network whose rollout
+/// failed) count as the front's lowest finite value, so they can't turn the distances into NaN.
+fn crowding_distances(front: &[usize], objectives: &[Vec<f64>]) -> Vec<f64> {
+    let mut distances = vec![0.0; front.len()];
+    if front.is_empty() {
+        return distances;
+    }
+
+    let last = front.len() - 1;
+    for m in 0..objectives[front[0]].len() {
+        let floor = front.iter().map(|&i| objectives[i][m]).filter(|v| v.is_finite()).fold(f64::INFINITY, f64::min);
+        let floor = if floor.is_finite() { floor } else { 0.0 };
+        let value = |k: usize| {
+            let v = objectives[front[k]][m];
+            if v.is_finite() { v } else { floor }
+        };
+        let mut sorted: Vec<usize> = (0..front.len()).collect();
+        sorted.sort_by(|&a, &b| value(a).partial_cmp(&value(b)).unwrap_or(Equal));
+
+        let range = value(sorted[last]) - value(sorted[0]);
+        distances[sorted[0]] = f64::INFINITY;
+        distances[sorted[last]] = f64::INFINITY;
+        if range > 0.0 {
+            for k in 1..last {
+                distances[sorted[k]] += (value(sorted[k + 1]) - value(sorted[k - 1])) / range;
+            }
+        }
+    }
+    distances
 }
 
 #[cfg(test)]
@@ -132,6 +447,10 @@ mod tests {
 
     use nalgebra as na;
     use rand::prelude::*;
+    use super::super::fitness::FitnessFunction;
+    use super::super::netconfig::Architecture;
+    use super::super::network::OptimizerKind;
+    use super::super::observation::ObservationBuilder;
 
     fn approx_equal(a: f64, b: f64, decimal_places: u8) -> bool {
         let factor = 10.0f64.powi(decimal_places as i32);
@@ -160,10 +479,38 @@ mod tests {
             nkeep: nkeep,
             percent_mutate: 2.0,
             mutation_stdev: 0.25,
-            target: na::Translation3::new(0.0, 0.0, 0.0),
+            targets: expconfig::Targets::Listed(vec![na::Translation3::new(0.0, 0.0, 0.0)]),
+            start_poses: expconfig::StartPoses::Seeded,
+            observation: ObservationBuilder::default(),
+            architecture: Architecture::default(),
+            target_orientation: None,
+            reach_tolerance: 0.01,
+            fitness: FitnessFunction::default(),
+            survivor_selection: expconfig::SurvivorSelection::Best,
+            parent_selection: expconfig::ParentSelection::RoundRobin,
+            crossover: expconfig::Crossover::Clone,
+            elitism: nkeep,
+            immigrants: 0,
+            adaptive_mutation: false,
             urdfpath: "".to_string(),
             seed: 1234,
             weights: "".to_string(),
+            initial_weights: None,
+            optimizer: OptimizerKind::Adam,
+            learning_rate: 0.001,
+            batch_size: 32,
+            nsamples: 0,
+            es_sigma: 0.1,
+            record: None,
+            replay: None,
+            measurement: expconfig::Measurement::Servos,
+            measured_fitness_weight: 1.0,
+            threads: 1,
+            checkpoint: "".to_string(),
+            checkpoint_every: 0,
+            results_dir: "".to_string(),
+            results_formats: Vec::new(),
+            dashboard: false,
         }
     }
 
@@ -187,7 +534,7 @@ mod tests {
             fitnesses.push(fitness);
         }
         for fitness in fitnesses {
-            state.add_fitness(fitness);
+            state.add_evaluation(fitness, vec![fitness]);
         }
         let maxfitness = state.evaluations.iter().cloned().fold(-1.0/0.0, f64::max);
 
@@ -201,4 +548,84 @@ mod tests {
             assert!(approx_equal(output, maxfitness, ndecimals));
         }
     }
+
+    #[test]
+    fn test_strategies_fill_generation() {
+        let mut config = create_experiment_config(20, 4);
+        config.parent_selection = expconfig::ParentSelection::Tournament(3);
+        config.crossover = expconfig::Crossover::Uniform;
+        config.elitism = 2;
+        config.immigrants = 5;
+        config.adaptive_mutation = true;
+        let mut state = ExperimentState::new();
+        let mut rng: StdRng = SeedableRng::seed_from_u64(5);
+        state.create_next_generation(&config, &mut rng);
+
+        // Network i is the fittest, the more of i
+        for i in 0..20 {
+            state.add_evaluation(i as f64, vec![i as f64]);
+        }
+        let best = state.networks[19].clone();
+        let input = build_input(best.input_length());
+        state.create_next_generation(&config, &mut rng);
+        assert_eq!(state.networks.len(), 20);
+        assert_eq!(state.networks[0].forward(&input), best.forward(&input));
+
+        // Rank and roulette pick the fittest more often than the least fit
+        for selection in [expconfig::ParentSelection::Rank, expconfig::ParentSelection::Roulette].iter() {
+            for i in 0..20 {
+                state.add_evaluation(i as f64, vec![i as f64]);
+            }
+            let order = state.survivor_order(SurvivorSelection::Best);
+            let picks: Vec<usize> = (0..1000).map(|i| state.pick_parent(*selection, &order, 4, i, &mut rng)).collect();
+            assert!(picks.iter().filter(|&&p| p == 19).count() > picks.iter().filter(|&&p| p == 1).count());
+            state.evaluations.clear();
+            state.objectives.clear();
+        }
+    }
+
+    #[test]
+    fn test_first_generation_starts_from_initial_network() {
+        let mut config = create_experiment_config(10, 1);
+        config.percent_mutate = 50.0;
+        let mut rng: StdRng = SeedableRng::seed_from_u64(8);
+        let initial = netconfig::build_network(&config.architecture, &config.observation, config.low, config.high, &mut rng);
+        let input = build_input(initial.input_length());
+        let mut state = ExperimentState::new();
+        state.start_from(initial.clone());
+        state.create_next_generation(&config, &mut rng);
+
+        // The initial network is kept as it is, and the rest are mutants of it
+        assert_eq!(state.networks.len(), 10);
+        assert_eq!(state.networks[0].forward(&input), initial.forward(&input));
+        assert!(state.networks[1..].iter().all(|net| net.forward(&input) != initial.forward(&input)));
+        assert!(state.networks[1..].iter().all(|net| net.layer_parameters().len() == initial.layer_parameters().len()));
+    }
+
+    #[test]
+    fn test_nsga2_order() {
+        // The last is dominated by the third, and the third is between the first two on the first front
+        let objectives = vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![0.5, 0.5], vec![0.2, 0.2]];
+        assert_eq!(pareto_fronts(&objectives), vec![vec![0, 1, 2], vec![3]]);
+        assert_eq!(nsga2_order(&objectives), vec![0, 1, 2, 3]);
+
+        // On a single front, the ends go first and the most crowded member goes last
+        let objectives = vec![vec![0.0, 1.0], vec![0.55, 0.45], vec![1.0, 0.0], vec![0.5, 0.5], vec![0.25, 0.75]];
+        let order = nsga2_order(&objectives);
+        assert_eq!(order[..2], [0, 2]);
+        assert_eq!(order[4], 3);
+    }
+
+    #[test]
+    fn test_crowding_distances_with_failed_network() {
+        // The last network's rollout failed, so it has no objectives to speak of
+        let objectives = vec![vec![1.0, 3.0], vec![2.0, 2.0], vec![3.0, 1.0], vec![f64::NEG_INFINITY, f64::NEG_INFINITY]];
+        let distances = crowding_distances(&[0, 1, 2, 3], &objectives);
+        assert!(distances.iter().all(|d| !d.is_nan()));
+        assert!(distances[1].is_finite() && distances[1] > 0.0);
+
+        // A front of nothing but failures has no room to share out
+        let failed = vec![vec![f64::NEG_INFINITY]; 3];
+        assert!(crowding_distances(&[0, 1, 2], &failed).iter().all(|d| !d.is_nan()));
+    }
 }
@@ -0,0 +1,243 @@
+//! Scoring a network's rollout.
+//!
+//! Fitness is a weighted sum of terms, given in the config as a comma separated list of
+//! `<term> <weight>` pairs:
+//!
+//! ```yaml
+//! fitness: inverse_distance 1.0, path_length 0.5, joint_limits 0.01
+//! ```
+//!
+//! `inverse_distance` is a reward. Every other term is a cost, and counts against the fitness.
+
+use nalgebra as na;
+use std::fmt;
+
+use super::environment::{self, Observation};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Term {
+    /// 1 / the distance from the hand to the target at the end of the rollout. The original fitness.
+    InverseDistance,
+    /// Distance from the hand to the target at the end of the rollout, in meters.
+    Distance,
+    /// How far the hand travelled over the rollout, in meters.
+    PathLength,
+    /// How many degrees the joints were asked to go past their limits, summed over the rollout.
+    JointLimits,
+    /// Sum of the squared joint movements (in degrees) over the rollout. Lower is smoother.
+    Energy,
+    /// The fraction of the rollout taken to first get the hand within the reach tolerance of the target. 1 if it never did.
+    TimeToReach,
+    /// The angle (in radians) between which way the hand faces at the end of the rollout and the target orientation.
+    Orientation,
+}
+
+impl Term {
+    fn from_name(name: &str) -> Option<Term> {
+        match name {
+            "inverse_distance" => Some(Term::InverseDistance),
+            "distance" => Some(Term::Distance),
+            "path_length" => Some(Term::PathLength),
+            "joint_limits" => Some(Term::JointLimits),
+            "energy" => Some(Term::Energy),
+            "time_to_reach" => Some(Term::TimeToReach),
+            "orientation" => Some(Term::Orientation),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Term::InverseDistance => "inverse_distance",
+            Term::Distance => "distance",
+            Term::PathLength => "path_length",
+            Term::JointLimits => "joint_limits",
+            Term::Energy => "energy",
+            Term::TimeToReach => "time_to_reach",
+            Term::Orientation => "orientation",
+        }
+    }
+
+    fn is_reward(&self) -> bool {
+        *self == Term::InverseDistance
+    }
+}
+
+/// Where the hand should end up.
+#[derive(Clone, Debug)]
+pub struct Target {
+    /// In meters.
+    pub position: na::Translation3<f64>,
+    /// Which way the hand should face, if that matters.
+    pub orientation: Option<na::UnitQuaternion<f64>>,
+    /// How close (in meters) the hand has to get to count as having reached the target.
+    pub tolerance: f64,
+}
+
+/// What happened over a single rollout.
+#[derive(Clone, Debug)]
+pub struct Rollout {
+    /// The joint angles and hand pose after the reset and after every step.
+    states: Vec<(Observation, na::Isometry3<f64>)>,
+    /// Degrees the joints were asked to go past their limits.
+    violation: f64,
+}
+
+impl Rollout {
+    /// Starts a rollout from where the reset put the arm.
+    pub fn new(start: Observation, pose: na::Isometry3<f64>) -> Self {
+        Rollout { states: vec![(start, pose)], violation: 0.0 }
+    }
+
+    /// Adds a step: the deltas that were asked for, and where the arm ended up.
+    pub fn add_step(&mut self, deltas: [f64; 3], obs: Observation, pose: na::Isometry3<f64>) {
+        let last = self.states[self.states.len() - 1].0.angles;
+        let requested = [last[0] + deltas[0], last[1] + deltas[1], last[2] + deltas[2]];
+        let allowed = environment::clamp_to_limits(requested);
+        self.violation += (0..3).map(|i| (requested[i] - allowed[i]).abs()).sum::<f64>();
+        self.states.push((obs, pose));
+    }
+
+    /// The hand's pose at the end of the rollout.
+    pub fn final_pose(&self) -> na::Isometry3<f64> {
+        self.states[self.states.len() - 1].1
+    }
+
+    fn path_length(&self) -> f64 {
+        self.states.windows(2).map(|w| (w[1].1.translation.vector - w[0].1.translation.vector).norm()).sum()
+    }
+
+    fn energy(&self) -> f64 {
+        self.states.windows(2).map(|w| (0..3).map(|i| (w[1].0.angles[i] - w[0].0.angles[i]).powi(2)).sum::<f64>()).sum()
+    }
+
+    fn time_to_reach(&self, target: &Target) -> f64 {
+        let nsteps = self.states.len() - 1;
+        match self.states.iter().position(|s| distance(&s.1.translation, &target.position) <= target.tolerance) {
+            Some(step) if nsteps > 0 => step as f64 / nsteps as f64,
+            Some(_) => 0.0,
+            None => 1.0,
+        }
+    }
+}
+
+/// A weighted sum of terms.
+#[derive(Clone, Debug)]
+pub struct FitnessFunction {
+    terms: Vec<(Term, f64)>,
+}
+
+impl FitnessFunction {
+    /// Parses the list of `<term> <weight>` pairs from the config.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut terms = Vec::new();
+        for pair in s.split(',') {
+            let tokens: Vec<&str> = pair.split_whitespace().collect();
+            if tokens.len() != 2 {
+                return Err(format!("Each fitness term should be '<term> <weight>', but one is '{}'", pair.trim()));
+            }
+            let term = match Term::from_name(tokens[0]) {
+                Some(term) => term,
+                None => return Err(format!("Unknown fitness term {}", tokens[0])),
+            };
+            let weight = match tokens[1].parse::<f64>() {
+                Ok(weight) => weight,
+                Err(_) => return Err(format!("Weight {} of fitness term {} is not a number", tokens[1], tokens[0])),
+            };
+            terms.push((term, weight));
+        }
+        Ok(FitnessFunction { terms: terms })
+    }
+
+    pub fn nterms(&self) -> usize {
+        self.terms.len()
+    }
+
+    /// The names of the terms, in the order their objectives are in.
+    pub fn term_names(&self) -> Vec<String> {
+        self.terms.iter().map(|&(term, _)| term.name().to_string()).collect()
+    }
+
+    /// Whether any of the terms needs the target orientation.
+    pub fn uses_orientation(&self) -> bool {
+        self.terms.iter().any(|&(term, _)| term == Term::Orientation)
+    }
+
+    /// Each term for the rollout, weighted, and signed so that higher is always better.
+    /// `end` is where the hand ended up, which need not be where the rollout's model says it is.
+    pub fn objectives(&self, rollout: &Rollout, end: &na::Translation3<f64>, target: &Target) -> Vec<f64> {
+        self.terms.iter().map(|&(term, weight)| {
+            let value = match term {
+                Term::InverseDistance => 1.0 / (distance(end, &target.position) + 1E-9),
+                Term::Distance => distance(end, &target.position),
+                Term::PathLength => rollout.path_length(),
+                Term::JointLimits => rollout.violation,
+                Term::Energy => rollout.energy(),
+                Term::TimeToReach => rollout.time_to_reach(target),
+                Term::Orientation => match target.orientation {
+                    Some(orientation) => orientation.angle_to(&rollout.final_pose().rotation),
+                    None => 0.0,
+                },
+            };
+            if term.is_reward() { weight * value } else { -weight * value }
+        }).collect()
+    }
+
+    /// The fitness that the objectives add up to.
+    pub fn fitness(objectives: &[f64]) -> f64 {
+        objectives.iter().sum()
+    }
+}
+
+impl Default for FitnessFunction {
+    fn default() -> Self {
+        FitnessFunction { terms: vec![(Term::InverseDistance, 1.0)] }
+    }
+}
+
+impl fmt::Display for FitnessFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let terms: Vec<String> = self.terms.iter().map(|&(term, weight)| format!("{} {}", term.name(), weight)).collect();
+        write!(f, "{}", terms.join(", "))
+    }
+}
+
+fn distance(a: &na::Translation3<f64>, b: &na::Translation3<f64>) -> f64 {
+    (a.vector - b.vector).norm()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: f64) -> na::Isometry3<f64> {
+        na::Isometry3::from_parts(na::Translation3::new(x, 0.0, 0.0), na::UnitQuaternion::identity())
+    }
+
+    #[test]
+    fn test_objectives() {
+        let fitness = FitnessFunction::parse("inverse_distance 1.0, path_length 2, joint_limits 0.5, energy 1, time_to_reach 1").unwrap();
+        let target = Target { position: na::Translation3::new(1.0, 0.0, 0.0), orientation: None, tolerance: 0.1 };
+
+        let mut rollout = Rollout::new(Observation { angles: [90.0, 10.0, 150.0] }, at(0.0));
+        rollout.add_step([2.0, -15.0, 0.0], Observation { angles: [92.0, 0.0, 150.0] }, at(0.5));
+        rollout.add_step([0.0, 0.0, 1.0], Observation { angles: [92.0, 0.0, 151.0] }, at(1.0));
+
+        let objectives = fitness.objectives(&rollout, &na::Translation3::new(0.5, 0.0, 0.0), &target);
+        assert_eq!(objectives.len(), 5);
+        assert!((objectives[0] - 2.0).abs() < 1E-6);
+        assert_eq!(objectives[1], -2.0);
+        assert_eq!(objectives[2], -2.5);
+        assert_eq!(objectives[3], -(4.0 + 100.0 + 1.0));
+        assert_eq!(objectives[4], -1.0);
+        assert!((FitnessFunction::fitness(&objectives) - (2.0 - 2.0 - 2.5 - 105.0 - 1.0)).abs() < 1E-6);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(FitnessFunction::parse("distance 1, orientation 0.5").unwrap().to_string(), "distance 1, orientation 0.5");
+        assert!(FitnessFunction::parse("distance").is_err());
+        assert!(FitnessFunction::parse("closeness 1").is_err());
+        assert!(FitnessFunction::parse("distance x").is_err());
+    }
+}
@@ -0,0 +1,212 @@
+//! The real arm as an environment.
+
+use nalgebra as na;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use teleop::commands::ServoId;
+use teleop::errors::TeleopError;
+use teleop::ArmClient;
+
+use super::environment::{self, Environment, Observation, Simulation};
+use super::expconfig::Measurement;
+
+/// How long to wait for a tracker to say where the hand is.
+const TRACKER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What happened when a single step was sent to the arm.
+#[derive(Clone, Debug)]
+pub struct StepRecord {
+    pub episode: u64,
+    /// Which move this was within the episode (resets count as moves too).
+    pub step: u64,
+    /// The base, shoulder, and elbow angles that were sent.
+    pub angles: [u16; 3],
+    /// When the step was sent, measured from when the connection was opened.
+    pub sent_at: Duration,
+    /// How long the device took to acknowledge every command in the step (or to fail).
+    pub latency: Duration,
+    /// Ok if the device acknowledged every command, otherwise what went wrong.
+    pub outcome: Result<(), String>,
+}
+
+impl fmt::Display for StepRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "episode {} step {}: servos {:?} sent at {:.3}s ", self.episode, self.step, self.angles, as_secs(self.sent_at))?;
+        match self.outcome {
+            Ok(_) => write!(f, "acknowledged after {:.1}ms", as_secs(self.latency) * 1000.0),
+            Err(ref e) => write!(f, "failed after {:.1}ms: {}", as_secs(self.latency) * 1000.0, e),
+        }
+    }
+}
+
+/// The real arm. The serial connection stays open for the whole experiment, and every step waits
+/// for the device to acknowledge it, so each one is recorded with its own outcome and timing.
+///
+/// The arm can't tell us where its hand is, so that comes from a simulation kept at the angles the device acknowledged.
+pub struct Hardware {
+    arm: ArmClient,
+    model: Simulation,
+    measurement: Measurement,
+    /// Where the joints were last sent, before rounding to whole degrees, so that small steps add up.
+    commanded: [f64; 3],
+    opened: Instant,
+    episode: u64,
+    records: Vec<StepRecord>,
+}
+
+impl Hardware {
+    /// Opens the serial port given in the config's `com` (a device path, or 'test' for teleop's test port).
+    /// `measurement` is how `measure` finds out where the arm really is.
+    pub fn connect(comstr: &str, model: Simulation, measurement: Measurement) -> Result<Self, TeleopError> {
+        let arm = ArmClient::connect(Some(comstr))?;
+        Ok(Hardware {
+            arm: arm,
+            model: model,
+            measurement: measurement,
+            commanded: [0.0; 3],
+            opened: Instant::now(),
+            episode: 0,
+            records: Vec::new(),
+        })
+    }
+
+    /// Every step sent so far this episode, in order.
+    pub fn records(&self) -> &[StepRecord] {
+        &self.records
+    }
+
+    /// Sends the joints to `angles`, records how it went, and keeps the model where the device now is.
+    fn move_to(&mut self, commanded: [f64; 3]) -> Result<Observation, String> {
+        let angles = [to_servo_angle(commanded[0]), to_servo_angle(commanded[1]), to_servo_angle(commanded[2])];
+        let sent = Instant::now();
+        let outcome = self.send(angles).map_err(|e| e.to_string());
+        let record = StepRecord {
+            episode: self.episode,
+            step: self.records.len() as u64,
+            angles: angles,
+            sent_at: sent.duration_since(self.opened),
+            latency: sent.elapsed(),
+            outcome: outcome.clone(),
+        };
+        self.records.push(record);
+
+        outcome?;
+        self.commanded = commanded;
+        self.model.set_angles([angles[0] as f64, angles[1] as f64, angles[2] as f64])
+    }
+
+    fn send(&mut self, angles: [u16; 3]) -> Result<(), TeleopError> {
+        self.arm.move_joint(ServoId::Base, angles[0])?;
+        self.arm.move_joint(ServoId::Shoulder, angles[1])?;
+        self.arm.move_joint(ServoId::Elbow, angles[2])
+    }
+}
+
+impl Environment for Hardware {
+    fn reset(&mut self, start: [f64; 3]) -> Result<Observation, String> {
+        self.move_to(environment::clamp_to_limits(start))
+    }
+
+    fn step(&mut self, deltas: [f64; 3]) -> Result<Observation, String> {
+        let angles = environment::apply_deltas(self.commanded, deltas);
+        self.move_to(angles)
+    }
+
+    fn end_effector_pose(&mut self) -> Result<na::Isometry3<f64>, String> {
+        self.model.end_effector_pose()
+    }
+
+    fn measure(&mut self) -> Result<Option<na::Translation3<f64>>, String> {
+        match self.measurement {
+            Measurement::Servos => {
+                let angles = self.arm.status().map_err(|e| e.to_string())?;
+                let angles = [angles[ServoId::Base as usize] as f64, angles[ServoId::Shoulder as usize] as f64, angles[ServoId::Elbow as usize] as f64];
+                self.model.pose_at(angles).map(|pose| Some(pose.translation))
+            },
+            Measurement::Tracker(ref addr) => ask_tracker(addr).map(Some),
+        }
+    }
+
+    /// Starts recording steps for a new episode, forgetting the last one's.
+    fn start_episode(&mut self, episode: u64) {
+        self.episode = episode;
+        self.records.clear();
+    }
+
+    fn finish_episode(&mut self, results: &mut dyn fmt::Write) -> fmt::Result {
+        let records = self.records();
+        let nfailed = records.iter().filter(|r| r.outcome.is_err()).count();
+        writeln!(results, "{} of {} steps acknowledged.", records.len() - nfailed, records.len())?;
+        for record in records {
+            writeln!(results, "{}", record)?;
+        }
+        Ok(())
+    }
+}
+
+/// The servos only take whole degrees in [0, 180].
+fn to_servo_angle(angle: f64) -> u16 {
+    num::clamp(angle.round(), 0.0, 180.0) as u16
+}
+
+/// Asks the tracker at `addr` where the hand is. Trackers take a connection, are sent "pose", and answer
+/// with a line giving the hand's x, y, and z in meters, in the same frame as the arm's URDF.
+fn ask_tracker(addr: &str) -> Result<na::Translation3<f64>, String> {
+    let mut stream = TcpStream::connect(addr).map_err(|e| format!("Could not connect to the tracker at {}: {:?}", addr, e))?;
+    stream.set_read_timeout(Some(TRACKER_TIMEOUT)).map_err(|e| format!("Could not set up the tracker connection: {:?}", e))?;
+    stream.write_all(b"pose\n").map_err(|e| format!("Could not ask the tracker for the pose: {:?}", e))?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).map_err(|e| format!("The tracker did not answer: {:?}", e))?;
+    parse_tracker_pose(&line)
+}
+
+fn parse_tracker_pose(line: &str) -> Result<na::Translation3<f64>, String> {
+    let values: Vec<f64> = line.split_whitespace().filter_map(|token| token.parse::<f64>().ok()).collect();
+    if values.len() != 3 || line.split_whitespace().count() != 3 {
+        return Err(format!("Expected 'x y z' from the tracker, but got {:?}", line.trim()));
+    }
+    Ok(na::Translation3::new(values[0], values[1], values[2]))
+}
+
+fn as_secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 * 1E-9
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k::urdf::FromUrdf;
+
+    #[test]
+    fn test_records_each_step_against_test_port() {
+        let model = Simulation::new(k::Manipulator::from_link_tree("hand", &k::LinkTree::<f64>::from_urdf_file("arm.urdf").unwrap()).unwrap());
+        let mut hardware = Hardware::connect("test", model, Measurement::Servos).unwrap();
+        hardware.start_episode(3);
+        assert_eq!(hardware.reset([95.4, 20.0, 130.6]).unwrap().angles, [95.0, 20.0, 131.0]);
+        assert_eq!(hardware.step([-100.0, 2.2, 0.0]).unwrap().angles, [0.0, 22.0, 131.0]);
+        assert_eq!(hardware.step([0.4, 0.4, 0.0]).unwrap().angles, [0.0, 23.0, 131.0]);
+
+        let records = hardware.records().to_vec();
+        assert_eq!(records.len(), 3);
+        assert_eq!((records[0].episode, records[0].step), (3, 0));
+        assert_eq!(records[1].angles, [0, 22, 131]);
+        assert!(records.iter().all(|r| r.outcome.is_ok()));
+        assert!(records[1].sent_at >= records[0].sent_at + records[0].latency);
+
+        let measured = hardware.measure().unwrap().unwrap();
+        assert_eq!(measured, hardware.end_effector_pose().unwrap().translation);
+
+        hardware.start_episode(4);
+        assert!(hardware.records().is_empty());
+    }
+
+    #[test]
+    fn test_parse_tracker_pose() {
+        assert_eq!(parse_tracker_pose("0.1 -0.2 0.3\n").unwrap(), na::Translation3::new(0.1, -0.2, 0.3));
+        assert!(parse_tracker_pose("0.1 0.2\n").is_err());
+        assert!(parse_tracker_pose("0.1 0.2 up\n").is_err());
+    }
+}
@@ -9,6 +9,12 @@
 //!
 //! The other mode (Genetic Algorithm mode), the robot arm will get its
 //! joint angles from a genetic algorithm.
+//!
+//! The evolution strategy modes (CMA-ES and OpenAI's) evolve the network's weights like the
+//! genetic algorithm does, but make each generation by sampling around a mean that they move.
+//!
+//! Supervised mode trains the network by backpropagation instead, on examples from the
+//! kinematic model, and never moves the arm.
 #![allow(unused_must_use)]
 
 /* Externs */
@@ -17,30 +23,51 @@ extern crate k;
 extern crate nalgebra;
 extern crate num;
 extern crate rand;
+extern crate rayon;
+extern crate teleop;
+extern crate toml;
+extern crate urdf_rs;
 
 /* Modules */
+mod checkpoint;
+mod evolution;
+mod export;
 mod expconfig;
 mod expresults;
+mod environment;
 mod expstate;
+mod fitness;
+mod hardware;
 mod netconfig;
 mod network;
+mod observation;
+mod progress;
+mod recording;
+mod records;
+mod supervised;
+mod weightfile;
 
 /* Uses */
-use k::prelude::*;
 use k::urdf::FromUrdf;
 use nalgebra as na;
 use rand::prelude::*;
+use rayon::prelude::*;
 use std::env;
+use std::f64;
 use std::path;
 use std::process;
+use std::time::Instant;
 use std::fmt::Write;
-use std::io::Write as _w; // have to include two different Writes
-use std::fs;
 
 /* Selfs */
-use self::expconfig::{Mode, ExperimentConfig};
+use self::expconfig::{Mode, ExperimentConfig, StartPoses, Targets};
 use self::expresults::ExperimentResults;
 use self::expstate::ExperimentState;
+use self::fitness::{FitnessFunction, Rollout, Target};
+use self::environment::{Environment, Observation, Simulation};
+use self::hardware::Hardware;
+use self::recording::{Recording, Replay};
+use self::records::{Log, Reach, Record};
 
 /* Consts */
 const ANGLE_START_BASE: f64 = 90.0;
@@ -60,17 +87,43 @@ const SHOULDERNUM: usize = 1;
 const ELBOWNUM: usize = 2;
 
 fn main() {
-    let usage = "Need a path to a valid configuration file.";
+    let usage = "Need a path to a valid configuration file.\nTo export its network for the firmware instead: export <config file> <output> [--fixed-point]\nTo carry on from a checkpoint: --resume <checkpoint directory>";
 
     // Get the config file from the user or give them the usage
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
+    let exporting = args.len() >= 4 && args.len() <= 5 && args[1] == "export";
+    let resuming = args.len() == 3 && args[1] == "--resume";
+    if args.len() != 2 && !exporting && !resuming {
         println!("{}", usage);
         process::exit(1);
     }
 
+    // Load the checkpoint to resume from, which keeps its own copy of the config file
+    let checkpoint = if resuming {
+        match checkpoint::load(&args[2]) {
+            Ok(checkpoint) => Some(checkpoint),
+            Err(msg) => {
+                println!("{}", msg);
+                process::exit(7);
+            },
+        }
+    } else {
+        None
+    };
+    let configpath = if resuming {
+        match checkpoint::config_path(&args[2]) {
+            Ok(path) => path,
+            Err(msg) => {
+                println!("{}", msg);
+                process::exit(7);
+            },
+        }
+    } else {
+        path::PathBuf::from(if exporting { &args[2] } else { &args[1] })
+    };
+    let configpath = configpath.as_path();
+
     // Make sure the config file really exists
-    let configpath = path::Path::new(&args[1]);
     if !configpath.exists() {
         println!("{}", usage);
         println!("{:?} does not exist.", configpath);
@@ -78,7 +131,7 @@ fn main() {
     }
 
     // Try to parse the YAML file
-    let experiment = match ExperimentConfig::new(configpath) {
+    let mut experiment = match ExperimentConfig::new(configpath) {
         Ok(exp) => exp,
         Err(msg) => {
             println!("{}", msg);
@@ -86,260 +139,536 @@ fn main() {
         },
     };
 
+    // A run resumes with the seed it started with, even if that was drawn at random
+    if let Some(ref checkpoint) = checkpoint {
+        experiment.seed = checkpoint.seed;
+    }
+
+    // Export the network instead of running the experiment, if that's what we're here for
+    if exporting {
+        let fixed_point = match args.get(4).map(|s| s.as_str()) {
+            None => false,
+            Some("--fixed-point") => true,
+            Some(_) => {
+                println!("{}", usage);
+                process::exit(1);
+            },
+        };
+        match export::export(&experiment, &args[3], fixed_point) {
+            Ok(_) => println!("Exported the network in {} to {}", experiment.weights, args[3]),
+            Err(msg) => {
+                println!("{}", msg);
+                process::exit(6);
+            },
+        }
+        return;
+    }
+
     // Parse the URDF file
-    let robot = match k::LinkTree::<f64>::from_urdf_file(&experiment.urdfpath) {
-        Ok(robot) => robot,
-        Err(e) => {
-            println!("Problem with loading the URDF file: {:?}", e);
-            process::exit(4);
-        },
-    };
-    let mut arm = match k::Manipulator::from_link_tree("hand", &robot) {
-        Some(arm) => arm,
-        None => {
-            println!("Problem pulling out the 'hand' link from the URDF robot. Could not find 'hand' in tree.");
+    let (robot, arm) = match load_robot(&experiment.urdfpath).and_then(|robot| build_arm(&robot).map(|arm| (robot, arm))) {
+        Ok(loaded) => loaded,
+        Err(msg) => {
+            println!("{}", msg);
             process::exit(4);
         },
     };
 
+    // Simulated generations are evaluated on this many threads
+    if let Err(e) = rayon::ThreadPoolBuilder::new().num_threads(experiment.threads).build_global() {
+        println!("Could not start the threads to evaluate networks on: {}", e);
+    }
+
     // Inform the user how we parsed their config file
     println!("Running Experiment with Configuration:\n{}", experiment);
 
+    // Set up whatever the experiment runs against
+    let mut env = match make_environment(&experiment, arm) {
+        Ok(env) => env,
+        Err(msg) => {
+            println!("{}", msg);
+            process::exit(5);
+        },
+    };
+
     // Run the experiment
-    let results = run_experiment(&experiment, &mut arm);
+    let results = run_experiment(&experiment, &robot, configpath, &args, checkpoint, env.as_mut());
 
     // Save the results
     results.save("results.txt".to_string());
 }
 
-fn run_experiment<'a>(experiment: &'a ExperimentConfig, arm: &mut k::Manipulator<f64>) -> ExperimentResults<'a> {
-    // Create the random number generator
-    let mut rng: StdRng = SeedableRng::seed_from_u64(experiment.seed);
+/// Parses the URDF file.
+fn load_robot(urdfpath: &str) -> Result<urdf_rs::Robot, String> {
+    match urdf_rs::read_file(urdfpath) {
+        Ok(robot) => Ok(robot),
+        Err(e) => Err(format!("Problem with loading the URDF file: {:?}", e)),
+    }
+}
+
+/// Pulls the arm out of the parsed URDF robot. The arm can't be copied or shared between threads, so each thread that
+/// needs one builds its own from the robot.
+fn build_arm(robot: &urdf_rs::Robot) -> Result<k::Manipulator<f64>, String> {
+    let tree = k::LinkTree::<f64>::from_urdf_robot(robot);
+    match k::Manipulator::from_link_tree("hand", &tree) {
+        Some(arm) => Ok(arm),
+        None => Err("Problem pulling out the 'hand' link from the URDF robot. Could not find 'hand' in tree.".to_string()),
+    }
+}
+
+/// Builds the environment the config asks for: a replay of a recording, the real arm, or a simulation of it,
+/// possibly with a recording of everything it does. Supervised experiments always get the simulation.
+fn make_environment(experiment: &ExperimentConfig, arm: k::Manipulator<f64>) -> Result<Box<dyn Environment>, String> {
+    let env: Box<dyn Environment> = if experiment.mode == Mode::Supervised {
+        // The examples come from the kinematic model, so there is nothing to move
+        Box::new(Simulation::new(arm))
+    } else if let Some(ref replay) = experiment.replay {
+        Box::new(Replay::open(replay)?)
+    } else if experiment.comstr == "simulate" {
+        Box::new(Simulation::new(arm))
+    } else {
+        // Keep the connection to the arm open for the whole experiment
+        match Hardware::connect(&experiment.comstr, Simulation::new(arm), experiment.measurement.clone()) {
+            Ok(hardware) => Box::new(hardware),
+            Err(e) => return Err(format!("Could not connect to the arm on {}: {}", experiment.comstr, e)),
+        }
+    };
 
-    // Create the results to record to
+    match experiment.record {
+        Some(ref record) => Ok(Box::new(Recording::create(record, env)?)),
+        None => Ok(env),
+    }
+}
+
+/// Runs the experiment from the start, or from `checkpoint` if there is one. `args` is the command line it was run with.
+fn run_experiment<'a>(experiment: &'a ExperimentConfig, robot: &urdf_rs::Robot, configpath: &path::Path, args: &[String], checkpoint: Option<checkpoint::Checkpoint>, env: &mut dyn Environment) -> ExperimentResults<'a> {
+    // Create the results to record to, and the tables to write their records to, carrying on with them if resuming
     let mut results = ExperimentResults::new(&experiment);
+    let resumed_records = checkpoint.as_ref().map_or(Vec::new(), |checkpoint| checkpoint.records.clone());
+    if let Some(ref checkpoint) = checkpoint {
+        if let Err(e) = checkpoint.restore_tables(experiment) {
+            println!("Could not put the results tables back from the checkpoint: {}", e);
+        }
+    }
+    let mut recorder = match records::Recorder::create(experiment, &resumed_records) {
+        Ok(recorder) => recorder,
+        Err(e) => panic!("Could not create the results tables in {}: {}", experiment.results_dir, e),
+    };
+    let resumed_from = checkpoint.as_ref().map(|checkpoint| checkpoint.next_episode);
+    let mut manifest = match records::Manifest::new(experiment, configpath, args.to_vec(), resumed_from) {
+        Ok(manifest) => manifest,
+        Err(e) => panic!("Could not read the config file back for the results manifest: {}", e),
+    };
+    if let Err(e) = manifest.save(experiment) {
+        println!("Could not save the results manifest: {}", e);
+    }
 
-    // Create the Experiment state which will track anything that persists between episodes
-    let mut state = ExperimentState::new();
+    // Create the Experiment state which will track anything that persists between episodes, or put it back as it was
+    let (mut state, first_episode) = match checkpoint {
+        Some(checkpoint) => match checkpoint.resume(experiment, &mut results) {
+            Ok(resumed) => resumed,
+            Err(msg) => panic!("Could not resume from the checkpoint: {}", msg),
+        },
+        None => {
+            let mut state = ExperimentState::new();
+
+            // Start the genetic algorithm from a network trained already, if there is one
+            if let Some(ref initial_weights) = experiment.initial_weights {
+                let mut rng: StdRng = SeedableRng::seed_from_u64(experiment.seed);
+                match weightfile::load_experiment_network(experiment, initial_weights, &mut rng) {
+                    Ok(saved) => state.start_from(saved.network),
+                    Err(msg) => panic!("{}", msg),
+                }
+            }
+            (state, 0)
+        },
+    };
+    if first_episode > 0 {
+        println!("Resuming from episode {}", first_episode);
+    }
+    let mut progress = progress::Progress::new(experiment, first_episode);
 
-    // Run the whole experiment (each episode)
-    for episode in 0..experiment.nepisodes {
-        println!("=== Starting episode {} ===", episode);
+    // Run the whole experiment (each episode), each with its own random numbers so that it can be resumed at any of them
+    for episode in first_episode..experiment.nepisodes {
+        if !experiment.dashboard {
+            println!("=== Starting episode {} ===", episode);
+        }
         results.set_episode(episode);
-        if experiment.comstr == "simulate" {
-            run_simulation(experiment, &mut results, &mut rng, &mut state, arm);
-        } else {
-            run_episode(episode, experiment, &mut results, &mut rng, &mut state, arm);
+        let mut rng = checkpoint::episode_rng(experiment.seed, episode);
+        let started = Instant::now();
+        run_episode(episode, experiment, robot, &mut results, &mut rng, &mut state, env);
+
+        // Sum the episode up, and add it and everything recorded in it to the tables
+        let elapsed = started.elapsed();
+        let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1E-9;
+        results.record(Record::episode(seconds, state.evaluations(), state.trainer.as_ref().map(|trainer| trainer.losses())));
+        let mut records = results.take_records();
+
+        // Report how the generation did, if the episode made one
+        match experiment.mode {
+            Mode::Genetic | Mode::CmaEs | Mode::OpenAiEs => {
+                let weights: Vec<Vec<f64>> = state.networks.iter().map(|network| network.flat_weights()).collect();
+                let (elapsed, remaining) = progress.times(episode);
+                let stats = progress::GenerationStats::new(episode, state.evaluations(), &weights, &records, elapsed, remaining);
+                progress.report(&stats);
+                records.push(Record::Generation(stats));
+            },
+            Mode::Random | Mode::Inference | Mode::Supervised => (),
+        }
+        if let Err(e) = recorder.write(episode, &records) {
+            println!("Could not write episode {} to the results tables: {}", episode, e);
+        }
+
+        if experiment.checkpoint_every > 0 && (episode + 1) % experiment.checkpoint_every == 0 && episode + 1 < experiment.nepisodes {
+            let saved = recorder.lengths().and_then(|lengths| checkpoint::save(experiment, configpath, episode + 1, &state, &results, &lengths));
+            match saved {
+                Ok(_) => println!("Checkpointed before episode {} to {}", episode + 1, experiment.checkpoint),
+                Err(e) => println!("Could not checkpoint to {}: {}", experiment.checkpoint, e),
+            }
         }
     }
 
-    // Save the best network if mode is genetic
+    // Save the best network if mode is genetic or an evolution strategy, or the trained one if supervised
     match experiment.mode {
-        Mode::Genetic => match state.save_best_network(&"network_weights.wghts".to_string()) {
+        Mode::Genetic | Mode::CmaEs | Mode::OpenAiEs => match state.save_best_network(experiment, &experiment.weights) {
             Ok(_) => (),
             Err(e) => panic!("Could not save network: {:?}", e),
         },
+        Mode::Supervised => if let Some(ref trainer) = state.trainer {
+            if let Err(e) = trainer.save_network(experiment, &experiment.weights) {
+                panic!("Could not save network: {:?}", e);
+            }
+        },
         Mode::Inference | Mode::Random => (),
     }
     results.finish();
+    if let Err(e) = recorder.finish() {
+        println!("Could not finish the results tables: {}", e);
+    }
+    manifest.finish();
+    if let Err(e) = manifest.save(experiment) {
+        println!("Could not save the results manifest: {}", e);
+    }
     results
 }
 
-fn run_simulation<'a>(experiment: &'a ExperimentConfig, results: &mut ExperimentResults, rng: &mut rand::StdRng, state: &mut ExperimentState, arm: &mut k::Manipulator<f64>) {
-    println!("Running simulation");
+fn run_episode<'a>(episode: u64, experiment: &'a ExperimentConfig, robot: &urdf_rs::Robot, results: &mut ExperimentResults, rng: &mut rand::StdRng, state: &mut ExperimentState, env: &mut dyn Environment) {
+    env.start_episode(episode);
 
-    // Create a buffer to put the commands (the run_*_episode functions need a script to write to)
-    let mut f = Vec::<u8>::new();
+    // Take a bunch of steps, with behavior dependent on the experiment configuration
     match experiment.mode {
-        Mode::Random => run_random_episode(experiment, rng, results, &mut f),
-        Mode::Genetic => run_genetic_episode(experiment, rng, results, &mut f, state, arm),
-        Mode::Inference => run_inference_episode(experiment, rng, results, &mut f),
+        Mode::Random => run_random_episode(experiment, rng, results, env),
+        Mode::Genetic | Mode::CmaEs | Mode::OpenAiEs => run_genetic_episode(episode, experiment, robot, rng, results, env, state),
+        Mode::Inference => run_inference_episode(experiment, rng, results, env),
+        Mode::Supervised => run_supervised_epoch(experiment, rng, results, env, state),
     };
+
+    // Make sure to go to home after every episode
+    if let Err(e) = env.reset([ANGLE_START_BASE, ANGLE_START_SHOULDER, ANGLE_START_ELBOW]) {
+        println!("Could not send the arm home: {}", e);
+        writeln!(results, "Could not send the arm home: {}", e);
+    }
+
+    writeln!(results, "Executed episode {}", episode);
+    env.finish_episode(results);
 }
 
-fn run_episode<'a>(episode: u64, experiment: &'a ExperimentConfig, results: &mut ExperimentResults, rng: &mut rand::StdRng, state: &mut ExperimentState, arm: &mut k::Manipulator<f64>) {
-    // Create the script for this episode
-    let mut scriptname = String::new();
-    write!(scriptname, "tmpscript_episode_{}.txt", episode);
-    let mut f = match fs::File::create(&scriptname) {
-        Ok(file) => file,
+/// Writes the joint angles after a reset or step to the results. If the environment could not take it,
+/// logs why and returns None, so that the episode can stop moving.
+fn record_observation(outcome: Result<Observation, String>, results: &mut dyn Log) -> Option<Observation> {
+    match outcome {
+        Ok(obs) => {
+            writeln!(results, "servo {} {}", BASENUM, obs.angles[0]);
+            writeln!(results, "servo {} {}", SHOULDERNUM, obs.angles[1]);
+            writeln!(results, "servo {} {}", ELBOWNUM, obs.angles[2]);
+            Some(obs)
+        },
         Err(e) => {
-            let mut msg = String::new();
-            writeln!(msg, "Could not run episode {} due to error in opening script file: {:?}", episode, e);
-
-            write!(results, "{}", msg);
-            print!("{}", msg);
-            return;
+            println!("Could not move the arm: {}", e);
+            writeln!(results, "Could not move the arm: {}", e);
+            None
         },
-    };
+    }
+}
 
-    // Take a bunch of steps, with behavior dependent on the experiment configuration
-    match experiment.mode {
-        Mode::Random => run_random_episode(experiment, rng, results, &mut f),
-        Mode::Genetic => run_genetic_episode(experiment, rng, results, &mut f, state, arm),
-        Mode::Inference => run_inference_episode(experiment, rng, results, &mut f),
-    };
+/// The start position for inference and genetic episodes: random, but the same every time.
+fn seeded_start_angles(experiment: &ExperimentConfig) -> [f64; 3] {
+    let mut rngcopy: StdRng = rand::SeedableRng::seed_from_u64(experiment.seed);
+    random_start_angles(&mut rngcopy)
+}
 
-    // Make sure to go to home after every episode and spend a few cycles there.
-    writeln!(f, "home");
-    writeln!(f, "home");
-    writeln!(f, "home");
-
-    // Execute the script
-    let status = if cfg!(target_os = "windows") {
-        process::Command::new("target/debug/teleop.exe")
-                            .arg(experiment.comstr.as_str())
-                            .arg(scriptname.as_str())
-                            .status()
-    } else {
-        process::Command::new("target/debug/teleop")
-                            .arg(experiment.comstr.as_str())
-                            .arg(scriptname.as_str())
-                            .status()
-    };
+/// A random start position within 30 degrees of home on each joint.
+fn random_start_angles(rng: &mut rand::StdRng) -> [f64; 3] {
+    let base = ANGLE_START_BASE + rng.gen_range(-30.0, 30.0);
+    let shoulder = ANGLE_START_SHOULDER + rng.gen_range(-30.0, 30.0);
+    let elbow = ANGLE_START_ELBOW + rng.gen_range(-30.0, 30.0);
+    environment::clamp_to_limits([base, shoulder, elbow])
+}
 
-    // Check the results of running the script
-    let s = match status {
-        Ok(status) => {
-            writeln!(results, "Executed episode {}", episode);
-            status
-        },
-        Err(e) => {
-            writeln!(results, "Could not execute episode {}. Error: {:?}", episode, e);
-            return;
-        },
-    };
+/// The start positions for a generation. Only random ones use up numbers from `rng`.
+fn sample_start_poses(experiment: &ExperimentConfig, rng: &mut rand::StdRng) -> Vec<[f64; 3]> {
+    match experiment.start_poses {
+        StartPoses::Seeded => vec![seeded_start_angles(experiment)],
+        StartPoses::Listed(ref poses) => poses.clone(),
+        StartPoses::Random(n) => (0..n).map(|_| random_start_angles(rng)).collect(),
+    }
+}
 
-    // Check the status code
-    match s.code() {
-        Some(0) => writeln!(results, "Script executed successfully.").unwrap(),
-        Some(v) => writeln!(results, "Script exited abnormally with exit status {}", v).unwrap(),
-        None => (),
+/// The targets for a generation. Only ones drawn from a region use up numbers from `rng`.
+fn sample_targets(experiment: &ExperimentConfig, rng: &mut rand::StdRng) -> Vec<Target> {
+    let positions = match experiment.targets {
+        Targets::Listed(ref targets) => targets.clone(),
+        Targets::Region(ref a, ref b, n) => (0..n).map(|_| {
+            let mut position = [0.0; 3];
+            for i in 0..3 {
+                let (low, high) = (a.vector[i].min(b.vector[i]), a.vector[i].max(b.vector[i]));
+                position[i] = if high > low { rng.gen_range(low, high) } else { low };
+            }
+            na::Translation3::new(position[0], position[1], position[2])
+        }).collect(),
     };
 
-    // Remove the temporary script
-    match fs::remove_file(&scriptname) {
-        Err(e) => writeln!(results, "Could not remove file {}. Error: {:?}", scriptname, e).unwrap(),
-        Ok(_) => (),
+    positions.into_iter().map(|position| Target {
+        position: position,
+        orientation: experiment.target_orientation,
+        tolerance: experiment.reach_tolerance,
+    }).collect()
+}
+
+fn run_inference_episode<'a>(experiment: &'a ExperimentConfig, rng: &mut rand::StdRng, results: &mut ExperimentResults, env: &mut dyn Environment) {
+    // Rebuild the network the weights were saved from
+    let network = match weightfile::load_experiment_network(experiment, &experiment.weights, rng) {
+        Ok(saved) => {
+            if let Some(metadata) = saved.metadata {
+                println!("Network from seed {}, generation {}, with fitness {} (config hash {:016x})", metadata.seed, metadata.generation, metadata.fitness, metadata.config_hash);
+            }
+            saved.network
+        },
+        Err(msg) => panic!("{}", msg),
     };
+
+    // Reach for the first target (if the network is told where it is) from a random start position - but
+    // the same start position every time
+    let target = sample_targets(experiment, rng).swap_remove(0);
+    rollout_network(experiment, &network, seeded_start_angles(experiment), &target, Reach { network: 0, index: 0 }, env, results);
 }
 
-fn run_inference_episode<'a>(experiment: &'a ExperimentConfig, rng: &mut rand::StdRng, results: &mut ExperimentResults, f: &mut dyn std::io::Write) {
-    // Go to random start position - but should be the same start position every time
-    let mut rngcopy: StdRng = rand::SeedableRng::seed_from_u64(experiment.seed);
-    let mut base: f64 = num::clamp(ANGLE_START_BASE as f64 + rngcopy.gen_range(-30.0, 30.0), ANGLE_LOWER_LIMIT_BASE as f64, ANGLE_UPPER_LIMIT_BASE as f64);
-    let mut shoulder: f64 = num::clamp(ANGLE_START_SHOULDER as f64 + rngcopy.gen_range(-30.0, 30.0), ANGLE_LOWER_LIMIT_SHOULDER as f64, ANGLE_UPPER_LIMIT_SHOULDER as f64);
-    let mut elbow: f64 = num::clamp(ANGLE_START_ELBOW as f64 + rngcopy.gen_range(-30.0, 30.0), ANGLE_LOWER_LIMIT_ELBOW as f64, ANGLE_UPPER_LIMIT_ELBOW as f64);
-    writeln!(f, "servo {} {}", BASENUM, base);
-    writeln!(f, "servo {} {}", SHOULDERNUM, shoulder);
-    writeln!(f, "servo {} {}", ELBOWNUM, elbow);
-    writeln!(results, "servo {} {}", BASENUM, base);
-    writeln!(results, "servo {} {}", SHOULDERNUM, shoulder);
-    writeln!(results, "servo {} {}", ELBOWNUM, elbow);
-
-    // Create a network with the appropriate weights
-    let mut network: network::MultilayerPerceptron = netconfig::build_network(0.0, 1.0, rng);
-    match network.load_weights(&experiment.weights) {
-        Err(e) => panic!("Could not load the network weights from file {}: {:?}", experiment.weights, e),
-        Ok(_) => (),
-    }
-
-    // Write each step's actions to the results and script
-    for _ in 0..experiment.nsteps_per_episode {
-        run_step_using_network(&network, &mut base, &mut shoulder, &mut elbow, results, f);
+/// Trains the network on every example once. The examples are made in the first epoch.
+fn run_supervised_epoch<'a>(experiment: &'a ExperimentConfig, rng: &mut rand::StdRng, results: &mut ExperimentResults, env: &mut dyn Environment, state: &mut ExperimentState) {
+    if state.trainer.is_none() {
+        println!("Making {} examples", experiment.nsamples);
+        match supervised::Trainer::new(experiment, rng, env) {
+            Ok(trainer) => state.trainer = Some(trainer),
+            Err(msg) => panic!("Could not make the examples: {}", msg),
+        }
+    }
+
+    if let Some(ref mut trainer) = state.trainer {
+        let (training_loss, validation_loss) = trainer.run_epoch(experiment, rng);
+        println!("Training loss {}, validation loss {}", training_loss, validation_loss);
+        writeln!(results, "Training loss {}", training_loss);
+        writeln!(results, "Validation loss {}", validation_loss);
     }
 }
 
-fn run_random_episode<'a>(experiment: &'a ExperimentConfig, rng: &mut rand::StdRng, results: &mut ExperimentResults, f: &mut dyn std::io::Write) {
+fn run_random_episode<'a>(experiment: &'a ExperimentConfig, rng: &mut rand::StdRng, results: &mut ExperimentResults, env: &mut dyn Environment) {
     // Starting angles
-    let mut base: f64 = ANGLE_START_BASE;
-    let mut shoulder: f64 = ANGLE_START_SHOULDER;
-    let mut elbow: f64 = ANGLE_START_ELBOW;
+    let obs = match record_observation(env.reset([ANGLE_START_BASE, ANGLE_START_SHOULDER, ANGLE_START_ELBOW]), results) {
+        Some(obs) => obs,
+        None => return,
+    };
+    let pose = env.end_effector_pose().ok();
+    results.record(Record::step(None, 0, &obs, None, pose.as_ref(), None));
 
     // For each step, do a random step of up to 15 degrees in either direction on each servo
-    for _step in 0..experiment.nsteps_per_episode {
+    for step in 0..experiment.nsteps_per_episode {
         // Generate a bunch of values
         let randbase = rng.gen_range(-15.0, 16.0);
         let randshoulder = rng.gen_range(-15.0, 16.0);
         let randelbow = rng.gen_range(-15.0, 16.0);
 
-        // Add the values to the joints
-        base += randbase;
-        shoulder += randshoulder;
-        elbow += randelbow;
-
-        // Clamp the joints to between min and max for each joint
-        base = num::clamp(base, ANGLE_LOWER_LIMIT_BASE, ANGLE_UPPER_LIMIT_BASE);
-        shoulder = num::clamp(shoulder, ANGLE_LOWER_LIMIT_SHOULDER, ANGLE_UPPER_LIMIT_SHOULDER);
-        elbow = num::clamp(elbow, ANGLE_LOWER_LIMIT_ELBOW, ANGLE_UPPER_LIMIT_ELBOW);
-
-        // Write to the file
-        writeln!(f, "servo {} {}", BASENUM, base);
-        writeln!(f, "servo {} {}", SHOULDERNUM, shoulder);
-        writeln!(f, "servo {} {}", ELBOWNUM, elbow);
-
-        // Also write to results
-        writeln!(results, "servo {} {}", BASENUM, base);
-        writeln!(results, "servo {} {}", SHOULDERNUM, shoulder);
-        writeln!(results, "servo {} {}", ELBOWNUM, elbow);
+        // Add the values to the joints, which stop at their limits
+        let deltas = [randbase, randshoulder, randelbow];
+        let obs = match record_observation(env.step(deltas), results) {
+            Some(obs) => obs,
+            None => return,
+        };
+        let pose = env.end_effector_pose().ok();
+        results.record(Record::step(None, step + 1, &obs, Some(deltas), pose.as_ref(), None));
     }
 }
 
-fn run_genetic_episode<'a>(experiment: &'a ExperimentConfig, rng: &mut rand::StdRng, results: &mut ExperimentResults, f: &mut dyn std::io::Write, state: &mut ExperimentState, arm: &mut k::Manipulator<f64>) {
+fn run_genetic_episode<'a>(episode: u64, experiment: &'a ExperimentConfig, robot: &urdf_rs::Robot, rng: &mut rand::StdRng, results: &mut ExperimentResults, env: &mut dyn Environment, state: &mut ExperimentState) {
     // Crate a generation
     state.create_next_generation(experiment, rng);
 
-    // Go to random start position - but should be the same start position every time
-    let mut rngcopy: StdRng = rand::SeedableRng::seed_from_u64(experiment.seed);
-    let base_start: f64 = num::clamp(ANGLE_START_BASE as f64 + rngcopy.gen_range(-30.0, 30.0), ANGLE_LOWER_LIMIT_BASE as f64, ANGLE_UPPER_LIMIT_BASE as f64);
-    let shoulder_start: f64 = num::clamp(ANGLE_START_SHOULDER as f64 + rngcopy.gen_range(-30.0, 30.0), ANGLE_LOWER_LIMIT_SHOULDER as f64, ANGLE_UPPER_LIMIT_SHOULDER as f64);
-    let elbow_start: f64 = num::clamp(ANGLE_START_ELBOW as f64 + rngcopy.gen_range(-30.0, 30.0), ANGLE_LOWER_LIMIT_ELBOW as f64, ANGLE_UPPER_LIMIT_ELBOW as f64);
-    writeln!(f, "servo {} {}", BASENUM, base_start);
-    writeln!(f, "servo {} {}", SHOULDERNUM, shoulder_start);
-    writeln!(f, "servo {} {}", ELBOWNUM, elbow_start);
-    writeln!(results, "servo {} {}", BASENUM, base_start);
-    writeln!(results, "servo {} {}", SHOULDERNUM, shoulder_start);
-    writeln!(results, "servo {} {}", ELBOWNUM, elbow_start);
-
-    // Evaluate each network in the generation
-    let mut evaluations = Vec::<f64>::new();
-    for (networkidx, network) in state.networks.iter().enumerate() {
-        writeln!(results, "network {}", networkidx);
-
-        // For each step, get the values for each joint delta from a forward pass through the current network
-        let (mut base, mut shoulder, mut elbow) = (base_start, shoulder_start, elbow_start);
-        for _step in 0..experiment.nsteps_per_episode {
-            run_step_using_network(&network, &mut base, &mut shoulder, &mut elbow, results, f);
+    // Every network reaches for every target from every start position
+    let starts = sample_start_poses(experiment, rng);
+    let targets = sample_targets(experiment, rng);
+
+    // Evaluate each network in the generation. In a simulation, they are independent of each other, so they are
+    // evaluated all at once, each batch of them with its own arm built from the already parsed robot. Each network draws
+    // its random numbers from its own generator, and its log is kept apart and added to the results in order, so the
+    // results are the same however the networks were shared out.
+    let evaluations: Vec<(records::Buffer, (f64, Vec<f64>))> = if experiment.simulated_in_parallel() {
+        state.networks.par_iter().enumerate().map_init(|| build_arm(robot).map(Simulation::new), |sim, (networkidx, network)| {
+            let mut log = records::Buffer::default();
+            let mut netrng = network_rng(experiment.seed, episode, networkidx);
+            let evaluation = match *sim {
+                Ok(ref mut sim) => evaluate_network(experiment, network, networkidx, &starts, &targets, &mut netrng, sim, &mut log),
+                Err(ref msg) => {
+                    writeln!(log, "Could not load the arm to evaluate network {} on: {}", networkidx, msg);
+                    (f64::NEG_INFINITY, vec![f64::NEG_INFINITY; experiment.fitness.nterms()])
+                },
+            };
+            (log, evaluation)
+        }).collect()
+    } else {
+        state.networks.iter().enumerate().map(|(networkidx, network)| {
+            let mut log = records::Buffer::default();
+            let mut netrng = network_rng(experiment.seed, episode, networkidx);
+            let evaluation = evaluate_network(experiment, network, networkidx, &starts, &targets, &mut netrng, env, &mut log);
+            (log, evaluation)
+        }).collect()
+    };
+
+    for (log, (fitness, objectives)) in evaluations {
+        write!(results, "{}", log.text);
+        for record in log.records {
+            results.record(record);
         }
+        state.add_evaluation(fitness, objectives);
+    }
+}
 
-        // Now figure out how fit this network is based on how close the arm ended up to the goal position
-        match arm.set_joint_angles(&vec![base, shoulder, elbow, 0.0]) {
-            Ok(_) => (),
-            Err(e) => panic!("Problem setting joint angles: {:?}", e),
+/// A generator for the random numbers network `networkidx` of generation `generation` uses while it is evaluated. It only
+/// depends on the experiment's seed and which network it is, so a network scores the same whichever thread evaluates it.
+/// It is seeded from the first number the generation's own generator draws, so it never repeats that generator.
+fn network_rng(seed: u64, generation: u64, networkidx: usize) -> StdRng {
+    let episode = checkpoint::episode_rng(seed, generation).next_u64();
+    SeedableRng::seed_from_u64(episode ^ (networkidx as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F))
+}
+
+/// Has the network reach for every target from every start position, writing what it does to `log`. Returns its fitness
+/// and objectives, averaged over all of its reaches.
+///
+/// Each generation's start positions and targets are drawn before its networks are evaluated, and nothing here is random
+/// yet. `_rng` is the network's own stream (see `network_rng`): anything random added to an evaluation has to draw from
+/// it, so that a network scores the same whichever thread evaluates it.
+fn evaluate_network(experiment: &ExperimentConfig, network: &network::MultilayerPerceptron, networkidx: usize, starts: &[[f64; 3]], targets: &[Target], _rng: &mut StdRng, env: &mut dyn Environment, log: &mut dyn Log) -> (f64, Vec<f64>) {
+    writeln!(log, "network {}", networkidx);
+
+    let mut reaches = Vec::new();
+    let mut distance = 0.0;
+    for start in starts.iter() {
+        for target in targets.iter() {
+            writeln!(log, "reach {} for target {:?} from {:?}", reaches.len(), target.position.vector.as_slice(), start);
+
+            // Each reach gets its own rollout from its start position
+            let reach = Reach { network: networkidx, index: reaches.len() };
+            let rollout = rollout_network(experiment, network, *start, target, reach, env, log);
+
+            // Now figure out how fit this network is based on what it did
+            reaches.push(match rollout {
+                Some(rollout) => {
+                    distance += (rollout.final_pose().translation.vector - target.position.vector).norm();
+                    evaluate_rollout(experiment, &rollout, target, env, networkidx, log)
+                },
+                None => {
+                    distance = f64::INFINITY;
+                    (f64::NEG_INFINITY, vec![f64::NEG_INFINITY; experiment.fitness.nterms()])
+                },
+            });
         }
-        let end = arm.end_transform().translation.vector;
-        let (endx, endy, endz) = (end[0], end[1], end[2]);
-        let (dx, dy, dz) = (endx - experiment.target.vector[0], endy - experiment.target.vector[1], endz - experiment.target.vector[2]);
-        let fitness = calculate_fitness(dx, dy, dz);
-        writeln!(results, "Fitness for network {} {}", networkidx, fitness);
-        evaluations.push(fitness);
+    }
 
-        // Put the joints back to their start positions for the next network
-        writeln!(f, "servo {} {}", BASENUM, base_start);
-        writeln!(f, "servo {} {}", SHOULDERNUM, shoulder_start);
-        writeln!(f, "servo {} {}", ELBOWNUM, elbow_start);
-        writeln!(results, "servo {} {}", BASENUM, base_start);
-        writeln!(results, "servo {} {}", SHOULDERNUM, shoulder_start);
-        writeln!(results, "servo {} {}", ELBOWNUM, elbow_start);
+    // The network's fitness is its average over all its reaches
+    let (fitness, objectives) = average_evaluations(&reaches, experiment.fitness.nterms());
+    writeln!(log, "Fitness for network {} {}", networkidx, fitness);
+    log.record(Record::Network {
+        network: networkidx,
+        fitness: fitness,
+        distance: distance / reaches.len() as f64,
+        objectives: objectives.clone(),
+    });
+    (fitness, objectives)
+}
+
+/// Resets the arm to `start`, then lets the network move it toward `target` for an episode's worth of steps. Returns None
+/// if the arm could not be reset. Otherwise the rollout stops early if the arm stops taking steps.
+fn rollout_network(experiment: &ExperimentConfig, network: &network::MultilayerPerceptron, start: [f64; 3], target: &Target, reach: Reach, env: &mut dyn Environment, results: &mut dyn Log) -> Option<Rollout> {
+    let mut obs = record_observation(env.reset(start), results)?;
+    let mut pose = record_pose(env, results)?;
+    results.record(Record::step(Some(reach), 0, &obs, None, Some(&pose), Some(&target.position)));
+    let mut rollout = Rollout::new(obs, pose);
+    let mut previous = [0.0; 3];
+
+    // For each step, get the values for each joint delta from a forward pass through the current network
+    for step in 0..experiment.nsteps_per_episode {
+        let deltas = network_deltas(network, experiment.observation.build(&obs, &pose, &target.position, previous));
+        obs = match record_observation(env.step(deltas), results) {
+            Some(obs) => obs,
+            None => break,
+        };
+        pose = match record_pose(env, results) {
+            Some(pose) => pose,
+            None => break,
+        };
+        results.record(Record::step(Some(reach), step + 1, &obs, Some(deltas), Some(&pose), Some(&target.position)));
+        rollout.add_step(deltas, obs, pose);
+        previous = deltas;
     }
+    Some(rollout)
+}
 
-    for fitness in evaluations {
-        state.add_fitness(fitness);
+/// Where the kinematic model says the end of the arm is, logging why if it can't say.
+fn record_pose(env: &mut dyn Environment, results: &mut dyn Log) -> Option<na::Isometry3<f64>> {
+    match env.end_effector_pose() {
+        Ok(pose) => Some(pose),
+        Err(e) => {
+            writeln!(results, "Could not tell where the arm is: {}", e);
+            None
+        },
+    }
+}
+
+/// Scores a network's rollout, returning its fitness and the objectives that add up to it. Fitness terms that depend on
+/// where the arm ended up are blended between where the model says it is and where it was measured to be (if the
+/// environment can measure it), according to the configured weight.
+fn evaluate_rollout(experiment: &ExperimentConfig, rollout: &Rollout, target: &Target, env: &mut dyn Environment, networkidx: usize, results: &mut dyn Log) -> (f64, Vec<f64>) {
+    let simulated = experiment.fitness.objectives(rollout, &rollout.final_pose().translation, target);
+    let measured = match env.measure() {
+        Ok(end) => end.map(|end| experiment.fitness.objectives(rollout, &end, target)),
+        Err(e) => {
+            writeln!(results, "Could not measure where network {} left the arm: {}", networkidx, e);
+            None
+        },
+    };
+
+    let objectives = match measured {
+        Some(measured) => {
+            writeln!(results, "Simulated fitness {} measured fitness {}", FitnessFunction::fitness(&simulated), FitnessFunction::fitness(&measured));
+            let weight = experiment.measured_fitness_weight;
+            simulated.iter().zip(measured.iter()).map(|(s, m)| (1.0 - weight) * s + weight * m).collect()
+        },
+        None => simulated,
+    };
+    (FitnessFunction::fitness(&objectives), objectives)
+}
+
+/// The fitness and objectives of a network over all its reaches: the average of each.
+fn average_evaluations(reaches: &[(f64, Vec<f64>)], nterms: usize) -> (f64, Vec<f64>) {
+    let mut objectives = vec![0.0; nterms];
+    for &(_, ref reach) in reaches {
+        for (total, objective) in objectives.iter_mut().zip(reach.iter()) {
+            *total += objective / reaches.len() as f64;
+        }
     }
+    (FitnessFunction::fitness(&objectives), objectives)
 }
 
-fn run_step_using_network(network: &network::MultilayerPerceptron, base: &mut f64, shoulder: &mut f64, elbow: &mut f64, results: &mut ExperimentResults, f: &mut dyn std::io::Write) {
-    let input = na::DVector::<f64>::from_vec(network.input_length(), vec!(*base, *shoulder, *elbow));
+/// Asks the network how to move each joint, given the observation built for this step.
+fn network_deltas(network: &network::MultilayerPerceptron, observation: Vec<f64>) -> [f64; 3] {
+    let input = na::DVector::<f64>::from_vec(network.input_length(), observation);
     let mut output = network.forward(&input);
 
     // Clamp output deltas to -15, +15
@@ -347,29 +676,134 @@ fn run_step_using_network(network: &network::MultilayerPerceptron, base: &mut f6
     output[1] = num::clamp(output[1], -15.0, 15.0);
     output[2] = num::clamp(output[2], -15.0, 15.0);
 
-    // Add the resulting values from the network to the current angles
-    *base += output[0];
-    *shoulder += output[1];
-    *elbow += output[2];
-
-    // Clamp the joints to between min and max for each joint
-    *base = num::clamp(*base, ANGLE_LOWER_LIMIT_BASE, ANGLE_UPPER_LIMIT_BASE);
-    *shoulder = num::clamp(*shoulder, ANGLE_LOWER_LIMIT_SHOULDER, ANGLE_UPPER_LIMIT_SHOULDER);
-    *elbow = num::clamp(*elbow, ANGLE_LOWER_LIMIT_ELBOW, ANGLE_UPPER_LIMIT_ELBOW);
-
-    // Write to the file
-    writeln!(f, "servo {} {}", BASENUM, base);
-    writeln!(f, "servo {} {}", SHOULDERNUM, shoulder);
-    writeln!(f, "servo {} {}", ELBOWNUM, elbow);
-
-    // Also write to results
-    writeln!(results, "servo {} {}", BASENUM, base);
-    writeln!(results, "servo {} {}", SHOULDERNUM, shoulder);
-    writeln!(results, "servo {} {}", ELBOWNUM, elbow);
+    [output[0], output[1], output[2]]
 }
 
-/// Calculate a value that is higher the closer dx, dy, and dz are to zero without.
-fn calculate_fitness(dx: f64, dy: f64, dz: f64) -> f64 {
-    let distance = (dx * dx + dy * dy + dz * dz).sqrt();
-    1.0 / (distance + 1E-9)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    /// Runs two generations of the genetic algorithm on `nthreads` threads, and returns each one's fitnesses.
+    fn generation_fitnesses(experiment: &ExperimentConfig, robot: &urdf_rs::Robot, nthreads: usize) -> Vec<Vec<f64>> {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(nthreads).build().unwrap();
+        pool.install(|| {
+            let mut rng: StdRng = SeedableRng::seed_from_u64(experiment.seed);
+            let mut results = ExperimentResults::new(experiment);
+            let mut state = ExperimentState::new();
+            let mut env = Simulation::new(build_arm(robot).unwrap());
+            (0..2).map(|episode| {
+                run_genetic_episode(episode, experiment, robot, &mut rng, &mut results, &mut env, &mut state);
+                state.evaluations().to_vec()
+            }).collect()
+        })
+    }
+
+    #[test]
+    fn test_fitnesses_are_the_same_on_any_number_of_threads() {
+        let mut experiment = ExperimentConfig::new(path::Path::new("configs/genetic.yaml")).unwrap();
+        experiment.urdfpath = "arm.urdf".to_string();
+        experiment.generation_size = 12;
+        experiment.nkeep = 4;
+        experiment.elitism = 4;
+        experiment.nsteps_per_episode = 5;
+        assert!(experiment.simulated_in_parallel());
+        let robot = load_robot(&experiment.urdfpath).unwrap();
+
+        let serial = generation_fitnesses(&experiment, &robot, 1);
+        assert_eq!(serial.len(), 2);
+        assert_eq!(serial[0].len(), 12);
+        assert_eq!(serial, generation_fitnesses(&experiment, &robot, 4));
+    }
+
+    /// A short genetic run that checkpoints halfway, writing everything to files starting with `name`.
+    fn resume_test_experiment(name: &str) -> ExperimentConfig {
+        let mut experiment = ExperimentConfig::new(path::Path::new("configs/genetic.yaml")).unwrap();
+        experiment.urdfpath = "arm.urdf".to_string();
+        experiment.nepisodes = 6;
+        experiment.generation_size = 8;
+        experiment.nkeep = 3;
+        experiment.elitism = 3;
+        experiment.nsteps_per_episode = 5;
+        experiment.start_poses = StartPoses::Random(2);
+        experiment.adaptive_mutation = true;
+        experiment.weights = format!("{}_network_weights.toml", name);
+        experiment.checkpoint = format!("{}_checkpoint", name);
+        experiment.checkpoint_every = 3;
+        experiment.results_dir = format!("{}_results", name);
+        experiment
+    }
+
+    /// Runs the experiment (from `checkpoint`, if given) on a new arm, and returns the best network's file and the
+    /// networks table.
+    fn run_to_files(experiment: &ExperimentConfig, robot: &urdf_rs::Robot, checkpoint: Option<checkpoint::Checkpoint>) -> (String, String) {
+        let mut env = Simulation::new(build_arm(robot).unwrap());
+        run_experiment(experiment, robot, path::Path::new("configs/genetic.yaml"), &[], checkpoint, &mut env);
+        let weights = fs::read_to_string(&experiment.weights).unwrap();
+        let networks = fs::read_to_string(path::Path::new(&experiment.results_dir).join("networks.csv")).unwrap();
+        fs::remove_file(&experiment.weights).expect("Could not remove the test network file for some reason.");
+        (weights, networks)
+    }
+
+    fn remove_run(experiment: &ExperimentConfig) {
+        fs::remove_dir_all(&experiment.checkpoint).expect("Could not remove the test checkpoint for some reason.");
+        fs::remove_dir_all(&experiment.results_dir).expect("Could not remove the test results for some reason.");
+    }
+
+    #[test]
+    fn test_resumed_run_matches_uninterrupted_run() {
+        let experiment = resume_test_experiment("resume_test");
+        let robot = load_robot(&experiment.urdfpath).unwrap();
+
+        // Run the whole thing, then carry on from the checkpoint halfway, as if the run had stopped there
+        let whole = run_to_files(&experiment, &robot, None);
+        let checkpoint = checkpoint::load(&experiment.checkpoint).unwrap();
+        assert_eq!(checkpoint.next_episode, 3);
+        let resumed = run_to_files(&experiment, &robot, Some(checkpoint));
+        remove_run(&experiment);
+
+        assert_eq!(resumed, whole);
+        assert_eq!(whole.1.lines().count(), 1 + 6 * 8);
+    }
+
+    #[test]
+    fn test_resume_into_empty_results_dir() {
+        let experiment = resume_test_experiment("resume_empty_test");
+        let robot = load_robot(&experiment.urdfpath).unwrap();
+        let whole = run_to_files(&experiment, &robot, None);
+
+        // The checkpoint puts back the tables as they were
+        fs::remove_dir_all(&experiment.results_dir).unwrap();
+        let resumed = run_to_files(&experiment, &robot, Some(checkpoint::load(&experiment.checkpoint).unwrap()));
+        assert_eq!(resumed, whole);
+
+        // Without them, the tables start afresh from where the run is resumed
+        fs::remove_dir_all(&experiment.results_dir).unwrap();
+        fs::remove_dir_all(path::Path::new(&experiment.checkpoint).join("tables")).unwrap();
+        let (_, networks) = run_to_files(&experiment, &robot, Some(checkpoint::load(&experiment.checkpoint).unwrap()));
+        remove_run(&experiment);
+        assert_eq!(networks.lines().count(), 1 + 3 * 8);
+        assert_eq!(networks.lines().last(), whole.1.lines().last());
+    }
+
+    #[test]
+    fn test_network_rng_is_deterministic() {
+        let draws = |seed, generation, networkidx| -> Vec<u64> {
+            let mut rng = network_rng(seed, generation, networkidx);
+            (0..8).map(|_| rng.next_u64()).collect()
+        };
+
+        // The same network of the same generation always gets the same numbers
+        let first = draws(1234, 0, 0);
+        assert_eq!(first, draws(1234, 0, 0));
+        assert_eq!(draws(1234, 7, 3), draws(1234, 7, 3));
+
+        // Any other network, generation, or seed gets different ones, as does the generation's own generator
+        assert!(first != draws(1234, 0, 1));
+        assert!(first != draws(1234, 1, 0));
+        assert!(first != draws(1235, 0, 0));
+        let mut episode = checkpoint::episode_rng(1234, 0);
+        assert!(first != (0..8).map(|_| episode.next_u64()).collect::<Vec<u64>>());
+    }
 }
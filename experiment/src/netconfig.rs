@@ -1,50 +1,134 @@
 /// This module breaks out the network configuration, to make it easy to find mainly.
+///
+/// The network's layers come from the config as a comma separated list of
+/// `<nnodes> <activation>` pairs, each optionally followed by the range its incoming weights are drawn from:
+///
+/// ```yaml
+/// layers: 25 tanh, 25 relu -0.1 0.1, 3 linear
+/// ```
+///
+/// The first layer takes an input for each value in the observation.
 
-use super::network::{MultilayerPerceptron, Layer, linear, tanh};
+use super::network::{Activation, MultilayerPerceptron, Layer};
+use super::observation::ObservationBuilder;
 use rand;
+use std::fmt;
+
+/// One of the network's layers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerConfig {
+    pub nnodes: usize,
+    pub activation: Activation,
+    /// The range the weights coming into this layer are drawn from, if not the experiment's. Biases start at zero.
+    pub init: Option<(f64, f64)>,
+}
+
+/// The shape of the networks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Architecture {
+    layers: Vec<LayerConfig>,
+}
+
+impl Architecture {
+    /// Parses the list of layers from the config. The last one is the output layer, which must have a node for each joint.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut layers = Vec::new();
+        for spec in s.split(',') {
+            let tokens: Vec<&str> = spec.split_whitespace().collect();
+            if tokens.len() != 2 && tokens.len() != 4 {
+                return Err(format!("Each layer should be '<nnodes> <activation>' or '<nnodes> <activation> <low> <high>', but one is '{}'", spec.trim()));
+            }
+            let nnodes = match tokens[0].parse::<usize>() {
+                Ok(nnodes) if nnodes > 0 => nnodes,
+                _ => return Err(format!("The number of nodes in layer '{}' is not a positive whole number", spec.trim())),
+            };
+            let activation = match Activation::from_name(tokens[1]) {
+                Some(activation) => activation,
+                None => return Err(format!("Unknown activation {}. Use linear, relu, tanh, sigmoid, leaky_relu, or softsign.", tokens[1])),
+            };
+            let init = if tokens.len() == 4 {
+                match (tokens[2].parse::<f64>(), tokens[3].parse::<f64>()) {
+                    (Ok(low), Ok(high)) if low <= high => Some((low, high)),
+                    _ => return Err(format!("The weight range in layer '{}' should be two numbers, low then high", spec.trim())),
+                }
+            } else {
+                None
+            };
+            layers.push(LayerConfig { nnodes: nnodes, activation: activation, init: init });
+        }
+
+        if layers[layers.len() - 1].nnodes != 3 {
+            return Err("The last layer gives the base, shoulder, and elbow deltas, so must have 3 nodes".to_string());
+        }
+        Ok(Architecture { layers: layers })
+    }
+}
+
+impl Default for Architecture {
+    /// 25 tanh, 25 tanh, 3 linear: what the experiment has always used.
+    fn default() -> Self {
+        Architecture {
+            layers: vec![
+                LayerConfig { nnodes: 25, activation: Activation::Tanh, init: None },
+                LayerConfig { nnodes: 25, activation: Activation::Tanh, init: None },
+                LayerConfig { nnodes: 3, activation: Activation::Linear, init: None },
+            ],
+        }
+    }
+}
+
+impl fmt::Display for Architecture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let layers: Vec<String> = self.layers.iter().map(|layer| match layer.init {
+            Some((low, high)) => format!("{} {} {} {}", layer.nnodes, layer.activation.name(), low, high),
+            None => format!("{} {}", layer.nnodes, layer.activation.name()),
+        }).collect();
+        write!(f, "{}", layers.join(", "))
+    }
+}
 
-/// Builds the network for the experiment.
+/// Builds the network for the experiment. The first layer takes one input per value in the `observation`.
+/// Weights are drawn from [low, high] unless a layer says otherwise.
 ///
 /// Panics if the network can't be finalized for some reason.
-pub fn build_network(low: f64, high: f64, rng: &mut rand::StdRng) -> MultilayerPerceptron {
-    let net = {
-        MultilayerPerceptron::new()
-            .add_layer(
-                Layer::new()
-                    .length(3)
-                    .activation(linear)
-                    .connect(25)
-                    .initialize_weights(low, high, rng)
-                    .finalize()
-            )
-            .add_layer(
-                Layer::new()
-                    .length(25)
-                    .activation(tanh)
-                    .connect(25)
-                    .initialize_weights(low, high, rng)
-                    .finalize()
-            )
-            .add_layer(
-                Layer::new()
-                    .length(25)
-                    .activation(tanh)
-                    .connect(3)
-                    .initialize_weights(low, high, rng)
-                    .finalize()
-            )
-            .add_layer(
-                Layer::new()
-                    .length(3)
-                    .activation(linear)
-                    .make_output()
-                    .initialize_weights(low, high, rng)
-                    .finalize()
-            )
-            .finalize()
-    };
-    match net {
+pub fn build_network(architecture: &Architecture, observation: &ObservationBuilder, low: f64, high: f64, rng: &mut rand::StdRng) -> MultilayerPerceptron {
+    let mut net = MultilayerPerceptron::new();
+    let mut ninputs = observation.len();
+    for config in architecture.layers.iter() {
+        let (low, high) = config.init.unwrap_or((low, high));
+        net.add_layer(
+            Layer::new()
+                .inputs(ninputs)
+                .length(config.nnodes)
+                .activation(config.activation)
+                .initialize_weights(low, high, rng)
+                .finalize()
+        );
+        ninputs = config.nnodes;
+    }
+
+    match net.finalize() {
         Err(msg) => { println!("Problem building the network: {}", msg); panic!(); },
         Ok(n) => n,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+
+    #[test]
+    fn test_architecture() {
+        let architecture = Architecture::parse("10 sigmoid -0.1 0.1, 5 leaky_relu, 3 softsign").unwrap();
+        assert_eq!(architecture.to_string(), "10 sigmoid -0.1 0.1, 5 leaky_relu, 3 softsign");
+        assert!(Architecture::parse("10 tanh, 2 linear").is_err());
+        assert!(Architecture::parse("10 swish, 3 linear").is_err());
+        assert!(Architecture::parse("10 tanh 1, 3 linear").is_err());
+
+        let mut rng: StdRng = SeedableRng::seed_from_u64(3);
+        let net = build_network(&architecture, &ObservationBuilder::default(), -5.0, 5.0, &mut rng);
+        assert_eq!(net.describe_layers(), "3 inputs, 10 sigmoid, 5 leaky_relu, 3 softsign");
+        assert_eq!(net.nweights(), (3 * 10 + 10) + (10 * 5 + 5) + (5 * 3 + 3));
+    }
+}
@@ -7,7 +7,6 @@ use nalgebra as na;
 use rand::Rng;
 use rand::distributions::Normal;
 use std::io::Read;
-use std::io::Write as w_;
 use std::fmt::Write;
 
 #[allow(unused)]
@@ -29,16 +28,34 @@ pub fn tanh(x: f64) -> f64 {
     x.tanh()
 }
 
+#[allow(unused)]
+pub fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+#[allow(unused)]
+pub fn leaky_relu(x: f64) -> f64 {
+    if x < 0.0 {
+        0.01 * x
+    } else {
+        x
+    }
+}
+
+#[allow(unused)]
+pub fn softsign(x: f64) -> f64 {
+    x / (1.0 + x.abs())
+}
+
 #[derive(Clone)]
 /// MLP Neural Network
 ///
 /// This struct contains a dirt-simple implementation of a
 /// strictly feedforward, fully-connected, multilayer neural network.
 ///
-/// This implementation offers no stochastic gradient descent or
-/// backpropagation. You build it and then run it.
-///
-/// To train it, change its weight matrix.
+/// You build it and then run it. To train it, either change its weights
+/// (as the genetic algorithm does), or backpropagate the mean squared error
+/// over batches of examples with `train_batch`.
 ///
 pub struct MultilayerPerceptron {
     /// The layers present in this network in order from input to output.
@@ -49,23 +66,163 @@ pub struct MultilayerPerceptron {
 
 pub type ActivationFunction = fn(f64) -> f64;
 
+/// Weight files start with a line describing the network's layers, which starts with this.
+const LAYERS_PREFIX: &str = "layers ";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The activation functions a layer can have. Unlike the functions themselves, these have names, so that
+/// they can be written to and read from config and weight files.
+pub enum Activation {
+    Linear,
+    Relu,
+    Tanh,
+    Sigmoid,
+    LeakyRelu,
+    Softsign,
+}
+
+impl Activation {
+    pub fn from_name(name: &str) -> Option<Activation> {
+        match name {
+            "linear" => Some(Activation::Linear),
+            "relu" => Some(Activation::Relu),
+            "tanh" => Some(Activation::Tanh),
+            "sigmoid" => Some(Activation::Sigmoid),
+            "leaky_relu" => Some(Activation::LeakyRelu),
+            "softsign" => Some(Activation::Softsign),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Activation::Linear => "linear",
+            Activation::Relu => "relu",
+            Activation::Tanh => "tanh",
+            Activation::Sigmoid => "sigmoid",
+            Activation::LeakyRelu => "leaky_relu",
+            Activation::Softsign => "softsign",
+        }
+    }
+
+    /// The derivative of the activation at `x`, given that it is `y` there.
+    pub fn derivative(&self, x: f64, y: f64) -> f64 {
+        match self {
+            Activation::Linear => 1.0,
+            Activation::Relu => if x < 0.0 { 0.0 } else { 1.0 },
+            Activation::Tanh => 1.0 - y * y,
+            Activation::Sigmoid => y * (1.0 - y),
+            Activation::LeakyRelu => if x < 0.0 { 0.01 } else { 1.0 },
+            Activation::Softsign => 1.0 / ((1.0 + x.abs()) * (1.0 + x.abs())),
+        }
+    }
+
+    pub fn function(&self) -> ActivationFunction {
+        match self {
+            Activation::Linear => linear,
+            Activation::Relu => relu,
+            Activation::Tanh => tanh,
+            Activation::Sigmoid => sigmoid,
+            Activation::LeakyRelu => leaky_relu,
+            Activation::Softsign => softsign,
+        }
+    }
+}
+
+/// Adam's decay rates for its averages of the gradient and squared gradient, and what keeps it from dividing by zero.
+const ADAM_BETA1: f64 = 0.9;
+const ADAM_BETA2: f64 = 0.999;
+const ADAM_EPSILON: f64 = 1E-8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The ways `train_batch` can step down the gradient.
+pub enum OptimizerKind {
+    /// Plain (stochastic) gradient descent: each step is the gradient times the learning rate.
+    Sgd,
+    /// Adam (Kingma and Ba, 2014): each parameter's step is scaled by running averages of its gradient.
+    Adam,
+}
+
+/// Steps a network's parameters down the gradient, keeping whatever it needs between steps.
+pub struct Optimizer {
+    kind: OptimizerKind,
+    learning_rate: f64,
+    /// Adam's running averages of each parameter's gradient and squared gradient.
+    moments: Vec<(f64, f64)>,
+    /// How many steps have been taken.
+    nsteps: i32,
+}
+
+impl Optimizer {
+    pub fn new(kind: OptimizerKind, learning_rate: f64) -> Self {
+        Optimizer {
+            kind: kind,
+            learning_rate: learning_rate,
+            moments: Vec::new(),
+            nsteps: 0,
+        }
+    }
+
+    /// How many steps it has taken, then its running averages, for a checkpoint to keep.
+    pub fn state(&self) -> Vec<f64> {
+        let mut state = vec![self.nsteps as f64];
+        for &(m, v) in self.moments.iter() {
+            state.push(m);
+            state.push(v);
+        }
+        state
+    }
+
+    /// Carries on from what `state` gave.
+    pub fn restore(&mut self, state: &[f64]) -> Result<(), String> {
+        if state.is_empty() || state.len() % 2 != 1 {
+            return Err(format!("The optimizer's state should be a step count and pairs of averages, but is {} numbers long.", state.len()));
+        }
+        self.nsteps = state[0] as i32;
+        self.moments = state[1..].chunks(2).map(|pair| (pair[0], pair[1])).collect();
+        Ok(())
+    }
+
+    /// Moves each parameter against its gradient.
+    pub fn step<'a, I: Iterator<Item = &'a mut f64>>(&mut self, parameters: I, gradient: &[f64]) {
+        self.nsteps += 1;
+        if self.moments.len() != gradient.len() {
+            self.moments = vec![(0.0, 0.0); gradient.len()];
+        }
+
+        // Adam's averages start at zero, so they are corrected for how few steps they have been averaged over
+        let correction1 = 1.0 - ADAM_BETA1.powi(self.nsteps);
+        let correction2 = 1.0 - ADAM_BETA2.powi(self.nsteps);
+        for ((w, g), moment) in parameters.zip(gradient.iter()).zip(self.moments.iter_mut()) {
+            match self.kind {
+                OptimizerKind::Sgd => *w -= self.learning_rate * g,
+                OptimizerKind::Adam => {
+                    moment.0 = ADAM_BETA1 * moment.0 + (1.0 - ADAM_BETA1) * g;
+                    moment.1 = ADAM_BETA2 * moment.1 + (1.0 - ADAM_BETA2) * g * g;
+                    *w -= self.learning_rate * (moment.0 / correction1) / ((moment.1 / correction2).sqrt() + ADAM_EPSILON);
+                },
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 /// A layer of an MLP
 ///
 /// Layers in the MLP are always feedforward and fully-connected.
-/// They may contain any activation function.
+/// They may contain any activation function, which is applied after the weights and biases.
 ///
 pub struct Layer {
-    /// The number of nodes in this layer
+    /// The number of nodes in this layer, which is how many values it outputs.
     nnodes: usize,
+    /// The number of values coming into this layer: the previous layer's nodes, or the network's inputs.
+    ninputs: usize,
     /// The activation function. Takes an input, applies a nonlinearity to it, and then returns the result.
-    activation_function: ActivationFunction,
-    /// Matrix of weights going *out of* this Layer. Matrix is N_thislayer x N_nextlayer.
-    weights: na::Matrix<f64, na::Dynamic, na::Dynamic, na::MatrixVec<f64, na::Dynamic, na::Dynamic>>,
-    /// The number of weights going out of this Layer.
-    pub nweights: usize,
-    /// Are we the output layer?
-    output: bool,
+    activation: Activation,
+    /// Matrix of weights coming *into* this Layer. Matrix is N_thislayer x N_inputs.
+    weights: na::DMatrix<f64>,
+    /// One bias for each node in this Layer, added to its weighted inputs.
+    biases: na::DVector<f64>,
 }
 
 impl MultilayerPerceptron {
@@ -77,36 +234,115 @@ impl MultilayerPerceptron {
         }
     }
 
-    /// Attempts to save this network's weights to `path`.
-    pub fn save_weights(&self, path: &String) -> std::io::Result<()> {
-        let mut f = std::fs::File::create(path)?;
-        for layer in self.layers.iter() {
-            writeln!(f, "{}", layer.serialize_weights());
+    /// Builds the network saved at `path` in the old text format, with the layers it was saved with. The first line describes
+    /// the layers (`layers 3 inputs, 25 tanh, 3 linear`), then there is a line for each layer: its weights, then its biases.
+    ///
+    /// Returns None for files that don't say what layers they are for, like those saved before they did.
+    /// Networks are saved in the formats in `weightfile` now, which also load these.
+    pub fn load(path: &String) -> std::io::Result<Option<Self>> {
+        let contents = read_weights_file(path)?;
+        let mut lines = contents.lines();
+        let described = match lines.next() {
+            Some(line) if line.starts_with(LAYERS_PREFIX) => parse_layers(&line[LAYERS_PREFIX.len()..]).map_err(other_error)?,
+            _ => return Ok(None),
+        };
+
+        let mut net = MultilayerPerceptron::new();
+        let mut ninputs = described.ninputs;
+        for &(nnodes, activation) in described.layers.iter() {
+            net.add_layer(Layer::new().inputs(ninputs).length(nnodes).activation(activation).finalize());
+            ninputs = nnodes;
         }
-        Ok(())
+        let mut net = net.finalize().map_err(other_error)?;
+        net.deserialize_layers(lines, described.without_biases)?;
+        Ok(Some(net))
     }
 
     #[allow(unused)]
-    /// Loads the weights into a network from the given file path, which should contain weights as saved by save_weights().
+    /// Loads the weights into a network from the given file path, which should contain weights in the old text format (see `load`).
+    ///
+    /// Files without a line describing the layers are taken to be from before networks had biases, for a network shaped
+    /// like this one. Files with one must match it.
     pub fn load_weights(&mut self, path: &String) -> std::io::Result<()> {
-        let mut f = std::fs::File::open(path)?;
-        let mut contents = String::new();
-        f.read_to_string(&mut contents)?;
-        for (i, line) in contents.split('\n').enumerate() {
+        let contents = read_weights_file(path)?;
+        let mut lines = contents.lines().peekable();
+        let mut without_biases = true;
+        if let Some(line) = lines.peek().cloned() {
+            if line.starts_with(LAYERS_PREFIX) {
+                let described = parse_layers(&line[LAYERS_PREFIX.len()..]).map_err(other_error)?;
+                if described.ninputs != self.input_length() || described.layers != self.layers.iter().map(|l| (l.nnodes, l.activation)).collect::<Vec<_>>() {
+                    let mut msg = String::new();
+                    write!(msg, "{} has weights for layers {}, but the network has layers {}.", path, line[LAYERS_PREFIX.len()..].trim(), self.describe_layers());
+                    return Err(other_error(msg));
+                }
+                without_biases = described.without_biases;
+                lines.next();
+            }
+        }
+        self.deserialize_layers(lines, without_biases)
+    }
+
+    /// Fills each layer's weights (and biases, unless the lines are `without_biases`) from its own line of `lines`.
+    ///
+    /// Lines without biases come from before networks had them, when the weights were stored transposed,
+    /// and the output layer had an empty line of its own.
+    fn deserialize_layers<'a, I: Iterator<Item = &'a str>>(&mut self, lines: I, without_biases: bool) -> std::io::Result<()> {
+        let mut nlines = 0;
+        for (i, line) in lines.enumerate() {
             // Ignore any lines after we have enough
             if i >= self.layers.len() {
                 break;
             }
 
             // Parse a line into weights for a layer
-            match self.layers[i].deserialize_weights(&line.trim().to_string()) {
+            let line = line.trim().to_string();
+            let parsed = if without_biases {
+                self.layers[i].deserialize_weights_without_biases(&line)
+            } else {
+                self.layers[i].deserialize_weights(&line)
+            };
+            match parsed {
                 Ok(_) => (),
-                Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::Other, e)),
+                Err(e) => return Err(other_error(e)),
             }
+            nlines += 1;
+        }
+
+        // Every layer needs its weights
+        if nlines < self.layers.len() {
+            let mut msg = String::new();
+            write!(msg, "Found weights for only {} of the network's {} layers.", nlines, self.layers.len());
+            return Err(other_error(msg));
         }
         Ok(())
     }
 
+    /// Describes the network as its number of inputs, then its layers as `<nnodes> <activation>` pairs, like
+    /// `3 inputs, 25 tanh, 3 linear`.
+    pub fn describe_layers(&self) -> String {
+        let mut described = vec![format!("{} inputs", self.input_length())];
+        described.extend(self.layers.iter().map(|layer| format!("{} {}", layer.nnodes, layer.activation.name())));
+        described.join(", ")
+    }
+
+    /// Each layer's shape and parameters, from the input layer to the output layer.
+    pub fn layer_parameters(&self) -> Vec<LayerParameters> {
+        self.layers.iter().map(|layer| layer.to_parameters()).collect()
+    }
+
+    /// Builds the network with the given layers, as returned by `layer_parameters`. Fails if a layer's parameters
+    /// don't fit its shape, or if a layer doesn't take the previous one's nodes as its inputs.
+    pub fn from_layer_parameters(layers: Vec<LayerParameters>) -> Result<Self, String> {
+        let mut net = MultilayerPerceptron::new();
+        for (i, parameters) in layers.into_iter().enumerate() {
+            match Layer::from_parameters(parameters) {
+                Ok(layer) => net.add_layer(layer),
+                Err(e) => return Err(format!("Layer {}: {}", i, e)),
+            };
+        }
+        net.finalize()
+    }
+
     pub fn add_layer(&mut self, layer: Layer) -> &mut Self {
         self.layers.push(layer);
         self
@@ -114,14 +350,15 @@ impl MultilayerPerceptron {
 
     /// Attempts to finalize the building pattern. May fail if configuration doesn't make sense.
     pub fn finalize(&self) -> Result<Self, String> {
-        for i in 0..self.layers.len() {
-            if i < self.layers.len() - 1 {
-                // Check layer i's output weights against layer i+1's input weights
-                if self.layers[i].weights.ncols() != self.layers[i + 1].nnodes {
-                    let mut msg = String::new();
-                    write!(msg, "Layer {} specifies {} connections, but there are {} nodes in layer {}.", i, self.layers[i].weights.ncols(), self.layers[i+1].nnodes, i + 1);
-                    return Err(msg);
-                }
+        if self.layers.is_empty() {
+            return Err("A network needs at least one layer.".to_string());
+        }
+        for i in 1..self.layers.len() {
+            // Check layer i's inputs against layer i-1's outputs
+            if self.layers[i].ninputs != self.layers[i - 1].nnodes {
+                let mut msg = String::new();
+                write!(msg, "Layer {} takes {} inputs, but there are {} nodes in layer {}.", i, self.layers[i].ninputs, self.layers[i - 1].nnodes, i - 1);
+                return Err(msg);
             }
         }
         Ok(MultilayerPerceptron {
@@ -130,18 +367,34 @@ impl MultilayerPerceptron {
         })
     }
 
-    /// Returns the total number of weights in this network.
+    /// Returns the total number of weights in this network, counting the biases.
     pub fn nweights(&self) -> usize {
         let mut total: usize = 0;
         for layer in self.layers.iter() {
-            total += layer.nweights;
+            total += layer.nweights();
         }
         total
     }
 
+    /// All of the network's weights and biases, in one vector (in the order `mutate` picks them from).
+    pub fn flat_weights(&self) -> Vec<f64> {
+        self.layers.iter().flat_map(|layer| layer.parameters()).cloned().collect()
+    }
+
+    /// A copy of this network with its weights and biases replaced by `weights`, which are in the order `flat_weights`
+    /// gives them.
+    pub fn with_flat_weights(&self, weights: &[f64]) -> Self {
+        assert!(weights.len() == self.nweights());
+        let mut network = self.clone();
+        for (w, x) in network.layers.iter_mut().flat_map(|layer| layer.parameters_mut()).zip(weights.iter()) {
+            *w = *x;
+        }
+        network
+    }
+
     /// Clone the current network and mutate the offspring's weights.
     ///
-    /// Weights are mutated by taking `percent_mutate` of the number of weights in the network
+    /// Weights (biases included) are mutated by taking `percent_mutate` of the number of weights in the network
     /// and adjusting them to equal a value drawn from a Gaussian distribution of
     /// mu=current_weight, sigma=`stdev`.
     ///
@@ -154,56 +407,156 @@ impl MultilayerPerceptron {
         let mut mutant = self.clone();
 
         // Figure out how many weights we should mutate
-        let nmutate = (self.nweights() as f64 * percent_mutate).round() as usize;
+        let nweights = self.nweights();
+        let nmutate = (nweights as f64 * percent_mutate).round() as usize;
 
         // Mutate that many weights
         for _ in 0..nmutate {
             // Pick a weight at random. In the interest of speed and simplicity, let's not worry about
             // whether we have picked it already or not
-            let layeridx = rng.gen_range(0, self.layers.len());
-            let randrow = rng.gen_range(0, self.layers[layeridx].weights.nrows());
-            let randcol = rng.gen_range(0, self.layers[layeridx].weights.ncols());
-            let nrows = self.layers[layeridx].weights.nrows();
-            let weight_idx = randcol * nrows + randrow;
-            let original_weight = self.layers[layeridx].weights[weight_idx];
+            let mut weight_idx = rng.gen_range(0, nweights);
+            let mut layeridx = 0;
+            while weight_idx >= self.layers[layeridx].nweights() {
+                weight_idx -= self.layers[layeridx].nweights();
+                layeridx += 1;
+            }
+            let weight = mutant.layers[layeridx].parameters_mut().nth(weight_idx).unwrap();
 
-            let mutant_weight = rng.sample(Normal::new(original_weight, stdev));
-            mutant.layers[layeridx].weights[weight_idx] = mutant_weight;
+            *weight = rng.sample(Normal::new(*weight, stdev));
         }
 
         mutant
     }
 
+    /// Makes a child that takes each weight from one parent or the other, at random.
+    ///
+    /// Both parents must have the same shape.
+    pub fn crossover_uniform(&self, other: &Self, rng: &mut rand::StdRng) -> Self {
+        self.crossover_layers(other, |mine, theirs| {
+            for (w, o) in mine.parameters_mut().zip(theirs.parameters()) {
+                if rng.gen::<bool>() {
+                    *w = *o;
+                }
+            }
+        })
+    }
+
+    /// Makes a child that takes each layer's weights and biases wholesale from one parent or the other, at random.
+    ///
+    /// Both parents must have the same shape.
+    pub fn crossover_layerwise(&self, other: &Self, rng: &mut rand::StdRng) -> Self {
+        self.crossover_layers(other, |mine, theirs| {
+            if rng.gen::<bool>() {
+                *mine = theirs.clone();
+            }
+        })
+    }
+
+    /// Makes a child whose weights are a blend of the parents': `a * mine + (1 - a) * theirs`, with `a` chosen
+    /// at random (once for the whole child).
+    ///
+    /// Both parents must have the same shape.
+    pub fn crossover_arithmetic(&self, other: &Self, rng: &mut rand::StdRng) -> Self {
+        let a = rng.gen_range(0.0, 1.0);
+        self.crossover_layers(other, |mine, theirs| {
+            for (w, o) in mine.parameters_mut().zip(theirs.parameters()) {
+                *w = a * *w + (1.0 - a) * *o;
+            }
+        })
+    }
+
+    /// Clones us, then lets `cross` combine each of the clone's layers with the matching one from `other`.
+    fn crossover_layers<F>(&self, other: &Self, mut cross: F) -> Self
+        where F: FnMut(&mut Layer, &Layer)
+    {
+        assert!(self.layers.len() == other.layers.len());
+
+        let mut child = self.clone();
+        for (layer, otherlayer) in child.layers.iter_mut().zip(other.layers.iter()) {
+            assert!(layer.weights.shape() == otherlayer.weights.shape());
+            cross(layer, otherlayer);
+        }
+        child
+    }
+
     /// Does a forward pass through the MLP.
     ///
     /// Takes a vector, which must be of the same length as the input layer
     /// and returns a vector, which is of the same length as the output layer.
     pub fn forward(&self, input: &na::DVector<f64>) -> na::DVector<f64> {
-        assert!(input.len() == self.layers[0].nnodes);
+        assert!(input.len() == self.input_length());
+
+        let mut output = input.clone();
+        for layer in self.layers.iter() {
+            output = &layer.weights * output + &layer.biases;
+            output.apply(layer.activation.function());
+        }
+        output
+    }
 
+    /// The mean squared error between the network's output for `input` and `target`, and its gradient with respect to each
+    /// of the network's weights and biases (in the order `mutate` picks them from).
+    pub fn mse_gradient(&self, input: &na::DVector<f64>, target: &na::DVector<f64>) -> (f64, Vec<f64>) {
+        assert!(input.len() == self.input_length());
+
+        // Go forward, keeping each layer's inputs, and its outputs before and after the activation
+        let mut inputs = Vec::with_capacity(self.layers.len());
+        let mut sums = Vec::with_capacity(self.layers.len());
         let mut output = input.clone();
         for layer in self.layers.iter() {
-            output.apply(layer.activation_function);
-            output = (output.transpose() * &layer.weights).transpose();
+            let sum = &layer.weights * &output + &layer.biases;
+            inputs.push(output);
+            output = sum.map(layer.activation.function());
+            sums.push(sum);
         }
+        assert!(target.len() == output.len());
+        let error = &output - target;
+        let mse = error.dot(&error) / error.len() as f64;
 
-        // The final output is diagonal due to final layer's weights being ID
-        let mut result = na::DVector::<f64>::from_element(self.layers.last().unwrap().nnodes, 0.0);
-        for c in 0..output.ncols() {
-            for r in 0..output.nrows() {
-                if c == r {
-                    result[c] = output[c * output.nrows() + r];
-                }
+        // Then back, from how the error changes with the output, to how it changes with each layer's outputs in turn
+        let mut d_output = error * (2.0 / output.len() as f64);
+        let mut gradients = Vec::with_capacity(self.layers.len());
+        for (i, layer) in self.layers.iter().enumerate().rev() {
+            let d_sum = na::DVector::<f64>::from_fn(layer.nnodes, |j, _| d_output[j] * layer.activation.derivative(sums[i][j], output[j]));
+            gradients.push((&d_sum * inputs[i].transpose(), d_sum.clone()));
+            d_output = layer.weights.transpose() * &d_sum;
+            output = inputs[i].clone();
+        }
+        gradients.reverse();
+
+        let gradient = gradients.iter().flat_map(|&(ref weights, ref biases)| weights.iter().chain(biases.iter()).cloned()).collect();
+        (mse, gradient)
+    }
+
+    /// Takes a step of `optimizer` down the gradient of the mean squared error, averaged over the batch of (input, target)
+    /// pairs. Returns the average error, from before the step.
+    pub fn train_batch(&mut self, batch: &[(na::DVector<f64>, na::DVector<f64>)], optimizer: &mut Optimizer) -> f64 {
+        let mut total = 0.0;
+        let mut gradient = vec![0.0; self.nweights()];
+        for &(ref input, ref target) in batch.iter() {
+            let (mse, g) = self.mse_gradient(input, target);
+            total += mse;
+            for (sum, g) in gradient.iter_mut().zip(g) {
+                *sum += g / batch.len() as f64;
             }
         }
+        optimizer.step(self.layers.iter_mut().flat_map(|layer| layer.parameters_mut()), &gradient);
+        total / batch.len() as f64
+    }
 
-        result
+    /// The mean squared error over the (input, target) pairs.
+    pub fn mse(&self, samples: &[(na::DVector<f64>, na::DVector<f64>)]) -> f64 {
+        let total: f64 = samples.iter().map(|&(ref input, ref target)| {
+            let error = self.forward(input) - target;
+            error.dot(&error) / error.len() as f64
+        }).sum();
+        total / samples.len() as f64
     }
 
     /// Returns the length of the input vectors this network expects
     pub fn input_length(&self) -> usize {
         if self.layers.len() > 0 {
-            self.layers[0].nnodes
+            self.layers[0].ninputs
         } else {
             0
         }
@@ -215,96 +568,213 @@ impl Layer {
     pub fn new() -> Self {
         Layer {
             nnodes: 0,
-            activation_function: |_| 0.0,
-            weights: na::DMatrix::<f64>::identity(10, 10),
-            output: false,
-            nweights: 100,
+            ninputs: 0,
+            activation: Activation::Linear,
+            weights: na::DMatrix::<f64>::zeros(0, 0),
+            biases: na::DVector::<f64>::zeros(0),
         }
     }
 
-    /// Serializes the Layer's weights into a string representation: a single line of numbers.
-    pub fn serialize_weights(&self) -> String {
-        let mut s = String::new();
-        for i in 0..self.nweights {
-            let w = self.weights[i];
-            s.push_str(&w.to_string());
-            s.push(' ');
-        }
-        s
+    /// The number of weights coming into this Layer, counting the biases.
+    pub fn nweights(&self) -> usize {
+        self.weights.len() + self.biases.len()
+    }
+
+    /// The weights, then the biases.
+    fn parameters<'a>(&'a self) -> impl Iterator<Item = &'a f64> {
+        self.weights.iter().chain(self.biases.iter())
+    }
+
+    /// The weights, then the biases.
+    fn parameters_mut<'a>(&'a mut self) -> impl Iterator<Item = &'a mut f64> {
+        self.weights.iter_mut().chain(self.biases.iter_mut())
     }
 
     #[allow(unused)]
-    /// Deserializes the given string of weights and fills this Layer's weights with the results.
+    /// Deserializes the given string of weights and biases and fills this Layer's weights and biases with the results.
     pub fn deserialize_weights(&mut self, line: &String) -> Result<(), String> {
-        for (i, number) in line.trim().split(' ').enumerate() {
-            // If this is the last layer, we don't really have any weights
-            if self.output {
-                self.weights = na::DMatrix::<f64>::identity(self.nnodes, self.nnodes);
-                break;
-            }
-
-            // Otherwise parse the given number into a weight
-            let n = match number.parse::<f64>() {
-                Ok(res) => res,
-                Err(_) => {
-                    let mut msg = String::new();
-                    write!(msg, "Could not parse {} into an f64 while trying to deserialize some weights for a Layer.", number);
-                    return Err(msg);
-                },
-            };
-            self.weights[i] = n;
+        let numbers = parse_weights(line, self.nweights(), self.nnodes)?;
+        for (w, n) in self.parameters_mut().zip(numbers.into_iter()) {
+            *w = n;
         }
         Ok(())
     }
 
+    /// Deserializes a line of weights saved before layers had biases. Those were N_inputs x N_thislayer, and the
+    /// biases are left at zero.
+    fn deserialize_weights_without_biases(&mut self, line: &String) -> Result<(), String> {
+        let numbers = parse_weights(line, self.weights.len(), self.nnodes)?;
+        self.weights = na::DMatrix::<f64>::from_column_slice(self.ninputs, self.nnodes, &numbers).transpose();
+        self.biases = na::DVector::<f64>::zeros(self.nnodes);
+        Ok(())
+    }
+
+    /// This layer's shape and parameters.
+    pub fn to_parameters(&self) -> LayerParameters {
+        LayerParameters {
+            nnodes: self.nnodes,
+            ninputs: self.ninputs,
+            activation: self.activation,
+            weights: self.weights.transpose().iter().cloned().collect(),
+            biases: self.biases.iter().cloned().collect(),
+        }
+    }
+
+    /// Builds a layer from its shape and parameters. Fails if there aren't exactly enough weights and biases for the shape.
+    pub fn from_parameters(parameters: LayerParameters) -> Result<Self, String> {
+        let LayerParameters { nnodes, ninputs, activation, weights, biases } = parameters;
+        if weights.len() != nnodes * ninputs {
+            let mut msg = String::new();
+            write!(msg, "Expected {} weights for {} nodes with {} inputs each, but found {}.", nnodes * ninputs, nnodes, ninputs, weights.len());
+            return Err(msg);
+        }
+        if biases.len() != nnodes {
+            let mut msg = String::new();
+            write!(msg, "Expected a bias for each of the {} nodes, but found {}.", nnodes, biases.len());
+            return Err(msg);
+        }
+        Ok(Layer {
+            nnodes: nnodes,
+            ninputs: ninputs,
+            activation: activation,
+            weights: na::DMatrix::<f64>::from_row_slice(nnodes, ninputs, &weights),
+            biases: na::DVector::<f64>::from_iterator(nnodes, biases.into_iter()),
+        })
+    }
+
     /// Makes the layer length `nnodes`
     pub fn length(&mut self, nnodes: usize) -> &mut Self {
         self.nnodes = nnodes;
+        self.resize();
         self
     }
 
-    /// Makes this layer's activation function `f`
-    pub fn activation(&mut self, f: ActivationFunction) -> &mut Self {
-        self.activation_function = f;
+    /// Makes the layer take `ninputs` values: the previous layer's length, or the network's input length for the first layer.
+    pub fn inputs(&mut self, ninputs: usize) -> &mut Self {
+        self.ninputs = ninputs;
+        self.resize();
         self
     }
 
-    /// Adjusts the weights of this layer to the appropriate dimensions, given the next layer's size.
-    pub fn connect(&mut self, next_layer_nnodes: usize) -> &mut Self {
-        self.weights = na::DMatrix::<f64>::identity(self.nnodes, next_layer_nnodes);
-        self.nweights = self.nnodes * next_layer_nnodes;
+    /// Makes this layer's activation function `f`
+    pub fn activation(&mut self, f: Activation) -> &mut Self {
+        self.activation = f;
         self
     }
 
-    /// Alerts this layer that it is the output layer. Call this instead of `connect` for the output layer.
-    pub fn make_output(&mut self) -> &mut Self {
-        self.output = true;
-        self.weights = na::DMatrix::<f64>::identity(self.nnodes, self.nnodes);
-        self.nweights = 0;
-        self
+    /// Sizes the weights and biases to fit the layer's length and inputs, all zeros.
+    fn resize(&mut self) {
+        self.weights = na::DMatrix::<f64>::zeros(self.nnodes, self.ninputs);
+        self.biases = na::DVector::<f64>::zeros(self.nnodes);
     }
 
     /// Initializes the weights via random uniform distribution to values in the interval [low, high].
     ///
-    /// If this layer is the output layer, this does nothing.
+    /// The biases start at zero.
     pub fn initialize_weights(&mut self, low: f64, high: f64, rng: &mut rand::StdRng) -> &mut Self {
-        if !self.output {
-            for i in 0..self.nweights {
-                self.weights[i] = rng.gen_range(low, high + 1E-9);
-            }
+        for w in self.weights.iter_mut() {
+            *w = rng.gen_range(low, high + 1E-9);
         }
         self
     }
 
     pub fn finalize(&self) -> Self {
-        Layer {
-            nnodes: self.nnodes,
-            activation_function: self.activation_function.clone(),
-            weights: self.weights.clone(),
-            output: self.output,
-            nweights: self.nweights,
+        self.clone()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// A layer's shape and parameters, apart from the matrices, for saving and loading.
+pub struct LayerParameters {
+    pub nnodes: usize,
+    pub ninputs: usize,
+    pub activation: Activation,
+    /// N_thislayer x N_inputs, row by row: each node's weights in turn.
+    pub weights: Vec<f64>,
+    /// One for each node.
+    pub biases: Vec<f64>,
+}
+
+/// What the first line of a weights file says about the network.
+struct DescribedLayers {
+    ninputs: usize,
+    layers: Vec<(usize, Activation)>,
+    /// Whether the lines of weights are from before layers had biases.
+    without_biases: bool,
+}
+
+/// Parses the description written by `describe_layers`. Also takes descriptions from before layers had biases,
+/// which started with a (linear) input layer rather than the number of inputs, like `3 linear, 25 tanh, 3 linear`.
+fn parse_layers(s: &str) -> Result<DescribedLayers, String> {
+    // Each pair is a number of nodes, then an activation, or None for the number of inputs
+    let mut pairs = Vec::new();
+    for pair in s.split(',') {
+        let tokens: Vec<&str> = pair.split_whitespace().collect();
+        let nnodes = tokens.get(0).and_then(|n| n.parse::<usize>().ok());
+        let activation = match tokens.get(1) {
+            Some(&"inputs") => Some(None),
+            Some(name) => Activation::from_name(name).map(Some),
+            None => None,
+        };
+        match (nnodes, activation) {
+            (Some(nnodes), Some(activation)) if tokens.len() == 2 => pairs.push((nnodes, activation)),
+            _ => return Err(format!("Each layer should be '<nnodes> <activation>', but one is '{}'", pair.trim())),
+        }
+    }
+
+    match pairs[0] {
+        (ninputs, None) => {
+            let mut layers = Vec::new();
+            for &(nnodes, activation) in pairs[1..].iter() {
+                match activation {
+                    Some(activation) => layers.push((nnodes, activation)),
+                    None => return Err("Only the first entry can be the number of inputs".to_string()),
+                }
+            }
+            Ok(DescribedLayers { ninputs: ninputs, layers: layers, without_biases: false })
+        },
+        (ninputs, Some(Activation::Linear)) if pairs[1..].iter().all(|&(_, activation)| activation.is_some()) => {
+            let layers = pairs[1..].iter().map(|&(nnodes, activation)| (nnodes, activation.unwrap())).collect();
+            Ok(DescribedLayers { ninputs: ninputs, layers: layers, without_biases: true })
+        },
+        _ => Err("The layers should start with the number of inputs, like '3 inputs'".to_string()),
+    }
+}
+
+/// Parses a line of `expected` numbers for a layer of `nnodes` nodes.
+fn parse_weights(line: &String, expected: usize, nnodes: usize) -> Result<Vec<f64>, String> {
+    // Make sure there is a weight for every connection, no more and no less
+    let numbers: Vec<&str> = line.split_whitespace().collect();
+    if numbers.len() != expected {
+        let mut msg = String::new();
+        write!(msg, "Expected {} weights for a Layer of {} nodes, but found {}.", expected, nnodes, numbers.len());
+        return Err(msg);
+    }
+
+    let mut weights = Vec::with_capacity(expected);
+    for number in numbers {
+        // Parse the given number into a weight
+        match number.parse::<f64>() {
+            Ok(res) => weights.push(res),
+            Err(_) => {
+                let mut msg = String::new();
+                write!(msg, "Could not parse {} into an f64 while trying to deserialize some weights for a Layer.", number);
+                return Err(msg);
+            },
         }
     }
+    Ok(weights)
+}
+
+fn read_weights_file(path: &String) -> std::io::Result<String> {
+    let mut f = std::fs::File::open(path)?;
+    let mut contents = String::new();
+    f.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+fn other_error<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, e)
 }
 
 #[cfg(test)]
@@ -313,6 +783,7 @@ mod tests {
 
     use rand::prelude::*;
     use std::fs;
+    use std::io::Write as w_;
 
     /// Create a test network
     fn build_network() -> MultilayerPerceptron {
@@ -322,33 +793,25 @@ mod tests {
         MultilayerPerceptron::new()
             .add_layer(
                 Layer::new()
-                    .length(3)
-                    .activation(linear)
-                    .connect(125)
-                    .initialize_weights(low, high, &mut rng)
-                    .finalize()
-            )
-            .add_layer(
-                Layer::new()
+                    .inputs(3)
                     .length(125)
-                    .activation(relu)
-                    .connect(75)
+                    .activation(Activation::Relu)
                     .initialize_weights(low, high, &mut rng)
                     .finalize()
             )
             .add_layer(
                 Layer::new()
+                    .inputs(125)
                     .length(75)
-                    .activation(relu)
-                    .connect(3)
+                    .activation(Activation::Relu)
                     .initialize_weights(low, high, &mut rng)
                     .finalize()
             )
             .add_layer(
                 Layer::new()
+                    .inputs(75)
                     .length(3)
-                    .activation(linear)
-                    .make_output()
+                    .activation(Activation::Linear)
                     .initialize_weights(low, high, &mut rng)
                     .finalize()
             )
@@ -365,16 +828,13 @@ mod tests {
         let (low, high, mut rng) = (-2.0, 2.0, rand::SeedableRng::seed_from_u64(randseed));
         let xornet = MultilayerPerceptron::new()
             .add_layer(
-                Layer::new().length(2).activation(linear).connect(4).initialize_weights(low, high, &mut rng).finalize()
-            )
-            .add_layer(
-                Layer::new().length(4).activation(relu).connect(4).initialize_weights(low, high, &mut rng).finalize()
+                Layer::new().inputs(2).length(4).activation(Activation::Relu).initialize_weights(low, high, &mut rng).finalize()
             )
             .add_layer(
-                Layer::new().length(4).activation(relu).connect(2).initialize_weights(low, high, &mut rng).finalize()
+                Layer::new().inputs(4).length(4).activation(Activation::Relu).initialize_weights(low, high, &mut rng).finalize()
             )
             .add_layer(
-                Layer::new().length(2).activation(linear).make_output().initialize_weights(low, high, &mut rng).finalize()
+                Layer::new().inputs(4).length(2).activation(Activation::Linear).initialize_weights(low, high, &mut rng).finalize()
             )
             .finalize().unwrap();
 
@@ -404,18 +864,24 @@ mod tests {
         a == b
     }
 
+    /// Saves the network in the old text format, with the line describing its layers.
+    fn save_old_text(network: &MultilayerPerceptron, path: &String) {
+        let mut f = fs::File::create(path).unwrap();
+        writeln!(f, "{}{}", LAYERS_PREFIX, network.describe_layers());
+        for layer in network.layers.iter() {
+            let numbers: Vec<String> = layer.parameters().map(|w| w.to_string()).collect();
+            // With the trailing space that files used to have
+            writeln!(f, "{} ", numbers.join(" "));
+        }
+    }
+
     #[test]
     fn test_serde() {
         let net_before = build_small_network();
         let input = build_input(&net_before);
         let forward_before = net_before.forward(&input);
         let path = "serde_test_weights.wghts";
-        match net_before.save_weights(&path.to_string()) {
-            Ok(_) => (),
-            Err(msg) => {
-                panic!("Problem with saving weights: {}", msg);
-            },
-        }
+        save_old_text(&net_before, &path.to_string());
 
         let mut net_after = build_small_network();
         match net_after.load_weights(&path.to_string()) {
@@ -441,13 +907,126 @@ mod tests {
 
         // Test that the forward pass produces the same values before and after serde
         assert_eq!(forward_before, forward_after);
+
+        // And through the parameters, which is how the weight files see them
+        let rebuilt = MultilayerPerceptron::from_layer_parameters(net_before.layer_parameters()).unwrap();
+        assert_eq!(forward_before, rebuilt.forward(&input));
+        let mut parameters = net_before.layer_parameters();
+        parameters[2].biases.pop();
+        assert!(MultilayerPerceptron::from_layer_parameters(parameters).is_err());
+    }
+
+    #[test]
+    fn test_load_rebuilds_layers() {
+        let net_before = build_small_network();
+        let input = build_input(&net_before);
+        let path = "load_test_weights.wghts".to_string();
+        save_old_text(&net_before, &path);
+
+        let net_after = MultilayerPerceptron::load(&path).unwrap().unwrap();
+        assert_eq!(net_after.describe_layers(), "2 inputs, 4 relu, 4 relu, 2 linear");
+        assert_eq!(net_before.forward(&input), net_after.forward(&input));
+
+        // A network with other layers can't take these weights
+        let mut rng: rand::StdRng = rand::SeedableRng::seed_from_u64(1);
+        let mut other = MultilayerPerceptron::new()
+            .add_layer(Layer::new().inputs(2).length(2).activation(Activation::Sigmoid).initialize_weights(-1.0, 1.0, &mut rng).finalize())
+            .finalize().unwrap();
+        let loaded = other.load_weights(&path);
+        fs::remove_file(&path).expect("Could not remove the test weights file for some reason.");
+        assert!(loaded.is_err());
     }
 
     #[test]
     fn test_get_nweights() {
         let xornet = build_small_network();
         let nweights = xornet.nweights();
-        assert_eq!(nweights, (2 * 4) + (4 * 4) + (4 * 2));
+        assert_eq!(nweights, (2 * 4 + 4) + (4 * 4 + 4) + (4 * 2 + 2));
+    }
+
+    #[test]
+    fn test_crossover() {
+        let mut rng: rand::StdRng = rand::SeedableRng::seed_from_u64(7);
+        let mother = build_small_network();
+        let mut father = mother.clone();
+        for layer in father.layers.iter_mut() {
+            layer.weights.apply(|w| w + 1.0);
+            layer.biases.apply(|b| b + 1.0);
+        }
+
+        let child = mother.crossover_uniform(&father, &mut rng);
+        for ((c, m), f) in child.layers.iter().zip(mother.layers.iter()).zip(father.layers.iter()) {
+            assert!(c.parameters().zip(m.parameters()).zip(f.parameters()).all(|((c, m), f)| c == m || c == f));
+        }
+
+        let child = mother.crossover_layerwise(&father, &mut rng);
+        for ((c, m), f) in child.layers.iter().zip(mother.layers.iter()).zip(father.layers.iter()) {
+            assert!((c.weights == m.weights && c.biases == m.biases) || (c.weights == f.weights && c.biases == f.biases));
+        }
+
+        // Every weight of the blend is the same distance along from the mother's to the father's
+        let child = mother.crossover_arithmetic(&father, &mut rng);
+        let along = child.layers[0].weights[0] - mother.layers[0].weights[0];
+        assert!(along >= 0.0 && along <= 1.0);
+        for (c, m) in child.layers.iter().zip(mother.layers.iter()) {
+            assert!(c.parameters().zip(m.parameters()).all(|(c, m)| (c - m - along).abs() < 1E-9));
+        }
+    }
+
+    #[test]
+    fn test_gradient_matches_finite_differences() {
+        let mut rng: rand::StdRng = rand::SeedableRng::seed_from_u64(3);
+        let activations = [Activation::Tanh, Activation::Sigmoid, Activation::Softsign, Activation::LeakyRelu];
+        let mut net = MultilayerPerceptron::new();
+        for (i, &activation) in activations.iter().enumerate() {
+            let ninputs = if i == 0 { 3 } else { 4 };
+            net.add_layer(Layer::new().inputs(ninputs).length(4).activation(activation).initialize_weights(-1.0, 1.0, &mut rng).finalize());
+        }
+        let mut net = net.finalize().unwrap();
+        for layer in net.layers.iter_mut() {
+            layer.biases.apply(|_| rng.gen_range(-1.0, 1.0));
+        }
+        let input = na::DVector::<f64>::from_vec(3, vec![0.3, -0.7, 1.1]);
+        let target = na::DVector::<f64>::from_vec(4, vec![0.5, -0.5, 0.25, 1.0]);
+
+        let (_, gradient) = net.mse_gradient(&input, &target);
+        let h = 1E-6;
+        for (idx, g) in gradient.iter().enumerate() {
+            let nudged = |delta: f64| {
+                let mut nudged = net.clone();
+                *nudged.layers.iter_mut().flat_map(|layer| layer.parameters_mut()).nth(idx).unwrap() += delta;
+                nudged.mse_gradient(&input, &target).0
+            };
+            let numerical = (nudged(h) - nudged(-h)) / (2.0 * h);
+            assert!((numerical - g).abs() < 1E-6, "Weight {}: backprop says {}, but finite differences say {}", idx, g, numerical);
+        }
+    }
+
+    #[test]
+    fn test_training_reduces_error() {
+        // Learn y = (x0 + x1, x0 - x1) with both optimizers
+        let mut rng: rand::StdRng = rand::SeedableRng::seed_from_u64(4);
+        let samples: Vec<_> = (0..64).map(|_| {
+            let (a, b) = (rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0));
+            (na::DVector::<f64>::from_vec(2, vec![a, b]), na::DVector::<f64>::from_vec(2, vec![a + b, a - b]))
+        }).collect();
+        for &(kind, rate) in [(OptimizerKind::Sgd, 0.05), (OptimizerKind::Adam, 0.01)].iter() {
+            // A fixed start, since some random ones leave too few ReLUs alive to learn with
+            let mut initrng: rand::StdRng = rand::SeedableRng::seed_from_u64(9);
+            let mut net = MultilayerPerceptron::new()
+                .add_layer(Layer::new().inputs(2).length(8).activation(Activation::Tanh).initialize_weights(-1.0, 1.0, &mut initrng).finalize())
+                .add_layer(Layer::new().inputs(8).length(2).activation(Activation::Linear).initialize_weights(-1.0, 1.0, &mut initrng).finalize())
+                .finalize().unwrap();
+            let mut optimizer = Optimizer::new(kind, rate);
+            let before = net.mse(&samples);
+            for _ in 0..300 {
+                for batch in samples.chunks(16) {
+                    net.train_batch(batch, &mut optimizer);
+                }
+            }
+            let after = net.mse(&samples);
+            assert!(after < before * 0.1 && after < 0.05, "{:?} only took the error from {} to {}", kind, before, after);
+        }
     }
 
     #[test]
@@ -458,20 +1037,32 @@ mod tests {
         let (low, high, mut rng) = (-2.0, 2.0, rand::SeedableRng::seed_from_u64(randseed));
         let mut net = MultilayerPerceptron::new()
             .add_layer(
-                Layer::new().length(2).activation(linear).connect(2).initialize_weights(low, high, &mut rng).finalize()
-            )
-            .add_layer(
-                Layer::new().length(2).activation(tanh).connect(1).initialize_weights(low, high, &mut rng).finalize()
+                Layer::new().inputs(2).length(2).activation(Activation::Tanh).initialize_weights(low, high, &mut rng).finalize()
             )
             .add_layer(
-                Layer::new().length(1).activation(linear).make_output().initialize_weights(low, high, &mut rng).finalize()
+                Layer::new().inputs(2).length(1).activation(Activation::Linear).initialize_weights(low, high, &mut rng).finalize()
             )
             .finalize().unwrap();
 
+        // Weights are N_thislayer x N_inputs, column by column, then the biases
         let path = "temp.weights".to_string();
         let mut f = fs::File::create(path.clone()).unwrap();
+        writeln!(f, "layers 2 inputs, 2 tanh, 1 linear");
+        writeln!(f, "0.6 0.6 1.1 1.1 0 0");
+        writeln!(f, "-2.0 1.1 0.5");
+        net.load_weights(&path).unwrap();
+        fs::remove_file(path).expect("Could not remove the temp weights file for some reason.");
+
+        // The output's bias is added after the weights
+        let zerozero = net.forward(&na::DVector::<f64>::from_vec(2, vec![0.0, 0.0]))[0];
+        assert!(approx_equal(zerozero, 0.5, 4));
+
+        // Files from before layers had biases have the weights the other way round, and a line for the output layer
+        let path = "temp_legacy.weights".to_string();
+        let mut f = fs::File::create(path.clone()).unwrap();
         writeln!(f, "0.6 1.1 0.6 1.1");
         writeln!(f, "-2.0 1.1");
+        writeln!(f, "");
         net.load_weights(&path).unwrap();
         fs::remove_file(path).expect("Could not remove the temp weights file for some reason.");
 
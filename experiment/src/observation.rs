@@ -0,0 +1,169 @@
+//! What the networks are given to decide each step on.
+//!
+//! The observation is made of features, given in the config as a comma separated list, in the order they
+//! are fed to the network:
+//!
+//! ```yaml
+//! observation: normalized_joint_angles, target, distance_vector, previous_action
+//! ```
+//!
+//! Without one, the networks get only the raw joint angles.
+
+use nalgebra as na;
+use std::fmt;
+
+use super::environment::Observation;
+use super::{ANGLE_LOWER_LIMIT_BASE, ANGLE_LOWER_LIMIT_ELBOW, ANGLE_LOWER_LIMIT_SHOULDER};
+use super::{ANGLE_UPPER_LIMIT_BASE, ANGLE_UPPER_LIMIT_ELBOW, ANGLE_UPPER_LIMIT_SHOULDER};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Feature {
+    /// Base, shoulder, and elbow angles, in degrees.
+    JointAngles,
+    /// Base, shoulder, and elbow angles, scaled so each joint's limits are -1 and 1.
+    NormalizedJointAngles,
+    /// Where the hand should go, in meters.
+    Target,
+    /// Where the hand is, in meters, according to the kinematic model.
+    EndEffector,
+    /// From the hand to the target, in meters.
+    DistanceVector,
+    /// The joint deltas the network asked for last step, in degrees. Zeros on the first step.
+    PreviousAction,
+}
+
+impl Feature {
+    fn from_name(name: &str) -> Option<Feature> {
+        match name {
+            "joint_angles" => Some(Feature::JointAngles),
+            "normalized_joint_angles" => Some(Feature::NormalizedJointAngles),
+            "target" => Some(Feature::Target),
+            "end_effector" => Some(Feature::EndEffector),
+            "distance_vector" => Some(Feature::DistanceVector),
+            "previous_action" => Some(Feature::PreviousAction),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Feature::JointAngles => "joint_angles",
+            Feature::NormalizedJointAngles => "normalized_joint_angles",
+            Feature::Target => "target",
+            Feature::EndEffector => "end_effector",
+            Feature::DistanceVector => "distance_vector",
+            Feature::PreviousAction => "previous_action",
+        }
+    }
+}
+
+/// Puts the features together into a network's input.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObservationBuilder {
+    features: Vec<Feature>,
+}
+
+impl ObservationBuilder {
+    /// Parses the list of features from the config.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut features = Vec::new();
+        for name in s.split(',') {
+            match Feature::from_name(name.trim()) {
+                Some(feature) => features.push(feature),
+                None => return Err(format!("Unknown observation feature '{}'", name.trim())),
+            }
+        }
+        Ok(ObservationBuilder { features: features })
+    }
+
+    /// The features, in the order they are given to the networks.
+    pub fn features(&self) -> &[Feature] {
+        &self.features
+    }
+
+    /// The number of values in an observation, which is how many inputs the networks take.
+    pub fn len(&self) -> usize {
+        // Every feature is three values: one per joint, or one per axis
+        3 * self.features.len()
+    }
+
+    /// Whether the observation depends on the target.
+    pub fn uses_target(&self) -> bool {
+        self.features.iter().any(|&feature| feature == Feature::Target || feature == Feature::DistanceVector)
+    }
+
+    /// Builds the network's input from where the joints are (`obs`), where that puts the hand (`pose`),
+    /// where the hand should go (`target`), and the deltas the network asked for last step (`previous`).
+    pub fn build(&self, obs: &Observation, pose: &na::Isometry3<f64>, target: &na::Translation3<f64>, previous: [f64; 3]) -> Vec<f64> {
+        let mut input = Vec::with_capacity(self.len());
+        for feature in self.features.iter() {
+            let values = match feature {
+                Feature::JointAngles => obs.angles,
+                Feature::NormalizedJointAngles => normalize_angles(obs.angles),
+                Feature::Target => vector_values(&target.vector),
+                Feature::EndEffector => vector_values(&pose.translation.vector),
+                Feature::DistanceVector => vector_values(&(target.vector - pose.translation.vector)),
+                Feature::PreviousAction => previous,
+            };
+            input.extend(values.iter());
+        }
+        input
+    }
+}
+
+impl Default for ObservationBuilder {
+    fn default() -> Self {
+        ObservationBuilder { features: vec![Feature::JointAngles] }
+    }
+}
+
+impl fmt::Display for ObservationBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = self.features.iter().map(|feature| feature.name()).collect();
+        write!(f, "{}", names.join(", "))
+    }
+}
+
+/// Scales each joint's angle so that its lower limit is -1 and its upper limit is 1.
+fn normalize_angles(angles: [f64; 3]) -> [f64; 3] {
+    let lower = [ANGLE_LOWER_LIMIT_BASE, ANGLE_LOWER_LIMIT_SHOULDER, ANGLE_LOWER_LIMIT_ELBOW];
+    let upper = [ANGLE_UPPER_LIMIT_BASE, ANGLE_UPPER_LIMIT_SHOULDER, ANGLE_UPPER_LIMIT_ELBOW];
+    let mut normalized = [0.0; 3];
+    for i in 0..3 {
+        normalized[i] = 2.0 * (angles[i] - lower[i]) / (upper[i] - lower[i]) - 1.0;
+    }
+    normalized
+}
+
+fn vector_values(v: &na::Vector3<f64>) -> [f64; 3] {
+    [v[0], v[1], v[2]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let builder = ObservationBuilder::parse("joint_angles, normalized_joint_angles, target, end_effector, distance_vector, previous_action").unwrap();
+        assert_eq!(builder.len(), 18);
+        assert!(builder.uses_target());
+
+        let obs = Observation { angles: [90.0, 50.0, 100.0] };
+        let pose = na::Isometry3::from_parts(na::Translation3::new(0.1, 0.2, 0.3), na::UnitQuaternion::identity());
+        let target = na::Translation3::new(0.2, 0.2, 0.1);
+        let input = builder.build(&obs, &pose, &target, [1.0, -2.0, 3.0]);
+        assert_eq!(input.len(), 18);
+        assert_eq!(&input[0..6], &[90.0, 50.0, 100.0, 0.0, 1.0, -1.0]);
+        assert_eq!(&input[6..12], &[0.2, 0.2, 0.1, 0.1, 0.2, 0.3]);
+        assert!((input[12] - 0.1).abs() < 1E-12 && input[13].abs() < 1E-12 && (input[14] + 0.2).abs() < 1E-12);
+        assert_eq!(&input[15..18], &[1.0, -2.0, 3.0]);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(ObservationBuilder::parse("target,previous_action").unwrap().to_string(), "target, previous_action");
+        assert!(!ObservationBuilder::default().uses_target());
+        assert!(ObservationBuilder::parse("joint_angles, velocity").is_err());
+    }
+}
@@ -0,0 +1,228 @@
+//! Reports how a genetic or evolution strategy run is going after each generation: how fit its networks are, how
+//! different their weights still are, how close the best one got, and how long the run has left. Each generation's
+//! statistics are printed, and written to the `generations` table (see `records`). With `dashboard: true` in the
+//! config, they are drawn as a dashboard that is redrawn in place instead.
+
+use std::cmp::Ordering;
+use std::f64;
+use std::fmt;
+use std::time::Instant;
+
+use super::expconfig::ExperimentConfig;
+use super::records::Record;
+
+/// How many generations' best fitnesses the dashboard plots.
+const HISTORY_LENGTH: usize = 60;
+
+/// The characters the dashboard plots fitnesses with, from lowest to highest.
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// How wide the dashboard's progress bar is, in characters.
+const BAR_WIDTH: usize = 40;
+
+/// Statistics about a generation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GenerationStats {
+    /// The episode that made the generation.
+    pub episode: u64,
+    pub best_fitness: f64,
+    pub mean_fitness: f64,
+    pub median_fitness: f64,
+    pub worst_fitness: f64,
+    /// How far the networks' weights are from the population's mean weights, on average (Euclidean distance).
+    pub diversity: f64,
+    /// How far (in meters) the best network left the hand from the targets, on average over its reaches.
+    pub best_distance: Option<f64>,
+    /// Seconds since the run started (or was resumed).
+    pub elapsed: f64,
+    /// Seconds the rest of the run should take, at the rate it has gone so far.
+    pub remaining: f64,
+}
+
+impl GenerationStats {
+    /// The statistics of a generation whose networks have `weights` and the fitnesses in `evaluations` (in the same
+    /// order). `records` are what was recorded while it was evaluated, which has each network's distance.
+    pub fn new(episode: u64, evaluations: &[f64], weights: &[Vec<f64>], records: &[Record], elapsed: f64, remaining: f64) -> Self {
+        let mut sorted = evaluations.to_vec();
+        sorted.sort_by(|a, b| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+        let median = match sorted.len() {
+            0 => f64::NAN,
+            n if n % 2 == 0 => (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0,
+            n => sorted[n / 2],
+        };
+
+        // The best network's distance, from its record
+        let mut best_distance = None;
+        let mut best_fitness = f64::NEG_INFINITY;
+        for record in records.iter() {
+            if let Record::Network { fitness, distance, .. } = *record {
+                if best_distance.is_none() || fitness > best_fitness {
+                    best_fitness = fitness;
+                    best_distance = Some(distance);
+                }
+            }
+        }
+
+        GenerationStats {
+            episode: episode,
+            best_fitness: sorted.first().cloned().unwrap_or(f64::NAN),
+            mean_fitness: evaluations.iter().sum::<f64>() / evaluations.len() as f64,
+            median_fitness: median,
+            worst_fitness: sorted.last().cloned().unwrap_or(f64::NAN),
+            diversity: diversity(weights),
+            best_distance: best_distance,
+            elapsed: elapsed,
+            remaining: remaining,
+        }
+    }
+}
+
+impl fmt::Display for GenerationStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Generation {}: best {:.4}, mean {:.4}, median {:.4}, worst {:.4}, diversity {:.4}",
+            self.episode, self.best_fitness, self.mean_fitness, self.median_fitness, self.worst_fitness, self.diversity)?;
+        if let Some(distance) = self.best_distance {
+            write!(f, ", best distance {:.4} m", distance)?;
+        }
+        write!(f, ", {} elapsed, {} left", clock(self.elapsed), clock(self.remaining))
+    }
+}
+
+/// Keeps track of how long the run is taking, and reports each generation.
+pub struct Progress {
+    started: Instant,
+    /// The episode the run started (or was resumed) from.
+    first_episode: u64,
+    nepisodes: u64,
+    dashboard: bool,
+    /// The best fitness of each of the last generations, for the dashboard to plot.
+    history: Vec<f64>,
+}
+
+impl Progress {
+    /// Starts timing a run starting at `first_episode`.
+    pub fn new(experiment: &ExperimentConfig, first_episode: u64) -> Self {
+        Progress {
+            started: Instant::now(),
+            first_episode: first_episode,
+            nepisodes: experiment.nepisodes,
+            dashboard: experiment.dashboard,
+            history: Vec::new(),
+        }
+    }
+
+    /// Seconds since the run started, and seconds it should take to finish, having just finished `episode`.
+    pub fn times(&self, episode: u64) -> (f64, f64) {
+        let elapsed = self.started.elapsed();
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1E-9;
+        let done = episode + 1 - self.first_episode;
+        let left = self.nepisodes.saturating_sub(episode + 1);
+        (elapsed, elapsed / done as f64 * left as f64)
+    }
+
+    /// Prints the generation's statistics, or redraws the dashboard with them.
+    pub fn report(&mut self, stats: &GenerationStats) {
+        self.history.push(stats.best_fitness);
+        if self.history.len() > HISTORY_LENGTH {
+            self.history.remove(0);
+        }
+        if self.dashboard {
+            // Clear the screen and draw from the top left
+            print!("\x1b[2J\x1b[H{}", self.draw(stats));
+        } else {
+            println!("{}", stats);
+        }
+    }
+
+    fn draw(&self, stats: &GenerationStats) -> String {
+        let done = stats.episode + 1;
+        let filled = (BAR_WIDTH as u64 * done / self.nepisodes.max(1)) as usize;
+        let bar: String = (0..BAR_WIDTH).map(|i| if i < filled { '#' } else { '-' }).collect();
+        let distance = stats.best_distance.map_or("-".to_string(), |distance| format!("{:.4} m", distance));
+
+        let mut lines = Vec::new();
+        lines.push(format!("Generation {}  [{}] {} of {} done", stats.episode, bar, done, self.nepisodes));
+        lines.push(format!("Elapsed {}   Remaining {}", clock(stats.elapsed), clock(stats.remaining)));
+        lines.push(String::new());
+        lines.push(format!("  Best fitness    {:>12.4}", stats.best_fitness));
+        lines.push(format!("  Mean fitness    {:>12.4}", stats.mean_fitness));
+        lines.push(format!("  Median fitness  {:>12.4}", stats.median_fitness));
+        lines.push(format!("  Worst fitness   {:>12.4}", stats.worst_fitness));
+        lines.push(format!("  Diversity       {:>12.4}", stats.diversity));
+        lines.push(format!("  Best distance   {:>12}", distance));
+        lines.push(String::new());
+        lines.push(format!("Best fitness, last {} generations:", self.history.len()));
+        lines.push(format!("  {}", sparkline(&self.history)));
+        lines.join("\n") + "\n"
+    }
+}
+
+/// The average Euclidean distance of each network's weights from the mean of all of them.
+fn diversity(weights: &[Vec<f64>]) -> f64 {
+    if weights.is_empty() {
+        return 0.0;
+    }
+    let mut mean = vec![0.0; weights[0].len()];
+    for network in weights.iter() {
+        for (total, weight) in mean.iter_mut().zip(network.iter()) {
+            *total += weight / weights.len() as f64;
+        }
+    }
+    let distances: f64 = weights.iter().map(|network| {
+        network.iter().zip(mean.iter()).map(|(weight, mean)| (weight - mean).powi(2)).sum::<f64>().sqrt()
+    }).sum();
+    distances / weights.len() as f64
+}
+
+/// The values as a row of bars, from the lowest (finite) value to the highest.
+fn sparkline(values: &[f64]) -> String {
+    let finite: Vec<f64> = values.iter().cloned().filter(|value| value.is_finite()).collect();
+    let low = finite.iter().cloned().fold(f64::INFINITY, f64::min);
+    let high = finite.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    values.iter().map(|&value| {
+        if !value.is_finite() {
+            ' '
+        } else if high > low {
+            SPARKS[((value - low) / (high - low) * (SPARKS.len() - 1) as f64).round() as usize]
+        } else {
+            SPARKS[SPARKS.len() - 1]
+        }
+    }).collect()
+}
+
+/// Seconds as hours, minutes, and seconds, like `1:02:03`.
+fn clock(seconds: f64) -> String {
+    if !seconds.is_finite() {
+        return "?".to_string();
+    }
+    let seconds = seconds.round() as u64;
+    format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generation_stats() {
+        let weights = vec![vec![0.0, 0.0], vec![2.0, 0.0], vec![1.0, 1.0], vec![1.0, -1.0]];
+        let records = vec![
+            Record::Network { network: 0, fitness: 1.0, distance: 0.5, objectives: vec![1.0] },
+            Record::Network { network: 1, fitness: 4.0, distance: 0.25, objectives: vec![4.0] },
+        ];
+        let stats = GenerationStats::new(3, &[1.0, 4.0, 2.0, f64::NEG_INFINITY], &weights, &records, 10.0, 20.0);
+        assert_eq!(stats.best_fitness, 4.0);
+        assert_eq!(stats.median_fitness, 1.5);
+        assert_eq!(stats.worst_fitness, f64::NEG_INFINITY);
+        assert_eq!(stats.mean_fitness, f64::NEG_INFINITY);
+        assert_eq!(stats.diversity, 1.0);
+        assert_eq!(stats.best_distance, Some(0.25));
+    }
+
+    #[test]
+    fn test_formatting() {
+        assert_eq!(clock(3723.4), "1:02:03");
+        assert_eq!(clock(f64::INFINITY), "?");
+        assert_eq!(sparkline(&[0.0, 7.0, 3.5, f64::NEG_INFINITY]), "▁█▅ ");
+    }
+}
@@ -0,0 +1,287 @@
+//! Recording what an environment did, and playing it back later as an environment of its own.
+//!
+//! A recording is a text file with a line for every reset and step: what was asked for, then what
+//! came back, which is either the joint angles and end effector pose (position, then orientation as
+//! a quaternion) or why it failed.
+//! Measurements of where the arm really is get a line of their own.
+//!
+//! ```text
+//! episode 0
+//! reset 90 10 155 -> 90 10 155 at 0.12 0.03 0.2 facing 1 0 0 0
+//! step 1.5 -2 0 -> 91.5 8 155 at 0.12 0.04 0.19 facing 0.99 0.01 0 0.02
+//! step 3 0 0 failed: Device did not answer servo 0 94
+//! measure -> 0.12 0.04 0.18
+//! ```
+
+use nalgebra as na;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+use super::environment::{Environment, Observation};
+
+/// Wraps another environment, writing down everything it does.
+pub struct Recording {
+    inner: Box<dyn Environment>,
+    file: io::BufWriter<fs::File>,
+}
+
+impl Recording {
+    /// Starts recording `inner` into a new file at `path`.
+    pub fn create(path: &str, inner: Box<dyn Environment>) -> Result<Self, String> {
+        match fs::File::create(path) {
+            Ok(file) => Ok(Recording { inner: inner, file: io::BufWriter::new(file) }),
+            Err(e) => Err(format!("Could not create recording {}: {:?}", path, e)),
+        }
+    }
+
+    /// Writes a line for what was asked of the environment, and what came of it.
+    fn write(&mut self, kind: &str, input: [f64; 3], outcome: Result<Observation, String>) -> Result<Observation, String> {
+        // The pose goes with the observation, so that a replay can answer for it
+        let outcome = match outcome {
+            Ok(obs) => self.inner.end_effector_pose().map(|pose| (obs, pose)),
+            Err(e) => Err(e),
+        };
+
+        let written = match outcome {
+            Ok((obs, pose)) => {
+                let (position, rotation) = (pose.translation.vector, pose.rotation.quaternion().coords);
+                writeln!(self.file, "{} {} {} {} -> {} {} {} at {} {} {} facing {} {} {} {}", kind, input[0], input[1], input[2],
+                         obs.angles[0], obs.angles[1], obs.angles[2], position[0], position[1], position[2],
+                         rotation[3], rotation[0], rotation[1], rotation[2])
+            },
+            Err(ref e) => writeln!(self.file, "{} {} {} {} failed: {}", kind, input[0], input[1], input[2], e),
+        };
+        if let Err(e) = written {
+            println!("Could not write to the recording: {:?}", e);
+        }
+        outcome.map(|(obs, _)| obs)
+    }
+}
+
+impl Environment for Recording {
+    fn reset(&mut self, start: [f64; 3]) -> Result<Observation, String> {
+        let outcome = self.inner.reset(start);
+        self.write("reset", start, outcome)
+    }
+
+    fn step(&mut self, deltas: [f64; 3]) -> Result<Observation, String> {
+        let outcome = self.inner.step(deltas);
+        self.write("step", deltas, outcome)
+    }
+
+    fn end_effector_pose(&mut self) -> Result<na::Isometry3<f64>, String> {
+        self.inner.end_effector_pose()
+    }
+
+    fn measure(&mut self) -> Result<Option<na::Translation3<f64>>, String> {
+        let outcome = self.inner.measure();
+        let written = match outcome {
+            Ok(Some(pose)) => writeln!(self.file, "measure -> {} {} {}", pose.vector[0], pose.vector[1], pose.vector[2]),
+            Ok(None) => writeln!(self.file, "measure -> none"),
+            Err(ref e) => writeln!(self.file, "measure failed: {}", e),
+        };
+        if let Err(e) = written {
+            println!("Could not write to the recording: {:?}", e);
+        }
+        outcome
+    }
+
+    fn start_episode(&mut self, episode: u64) {
+        writeln!(self.file, "episode {}", episode);
+        self.inner.start_episode(episode);
+    }
+
+    fn finish_episode(&mut self, results: &mut dyn fmt::Write) -> fmt::Result {
+        if let Err(e) = self.file.flush() {
+            println!("Could not write to the recording: {:?}", e);
+        }
+        self.inner.finish_episode(results)
+    }
+}
+
+/// What a recorded reset, step, or measurement came to.
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Moved(Observation, na::Isometry3<f64>),
+    Measured(Option<na::Translation3<f64>>),
+}
+
+/// One reset, step, or measurement from a recording.
+struct Entry {
+    lineno: usize,
+    kind: String,
+    outcome: Result<Outcome, String>,
+}
+
+/// Plays a recording back. Each reset and step returns whatever the recorded one did, whatever it is
+/// asked for, so the episodes must ask in the same order as when it was recorded.
+pub struct Replay {
+    entries: VecDeque<Entry>,
+    pose: Option<na::Isometry3<f64>>,
+}
+
+impl Replay {
+    /// Reads the recording at `path`.
+    pub fn open(path: &str) -> Result<Self, String> {
+        let file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) => return Err(format!("Could not open recording {}: {:?}", path, e)),
+        };
+
+        let mut entries = VecDeque::new();
+        for (lineno, line) in io::BufReader::new(file).lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Err(format!("Couldn't read line {} of {}: {:?}", lineno, path, e)),
+            };
+            match parse_entry(lineno, &line) {
+                Ok(Some(entry)) => entries.push_back(entry),
+                Ok(None) => (),
+                Err(msg) => return Err(format!("Problem with recording {} at line {}: {}", path, lineno, msg)),
+            }
+        }
+        Ok(Replay { entries: entries, pose: None })
+    }
+
+    /// Plays back the next entry, which should be a `kind`.
+    fn next(&mut self, kind: &str) -> Result<Outcome, String> {
+        let entry = match self.entries.pop_front() {
+            Some(entry) => entry,
+            None => return Err(format!("The recording has no more entries, but was asked for a {}.", kind)),
+        };
+        if entry.kind != kind {
+            return Err(format!("Asked for a {}, but the recording has a {} at line {}.", kind, entry.kind, entry.lineno));
+        }
+
+        entry.outcome
+    }
+
+    /// Plays back the next reset or step.
+    fn next_move(&mut self, kind: &str) -> Result<Observation, String> {
+        match self.next(kind)? {
+            Outcome::Moved(obs, pose) => {
+                self.pose = Some(pose);
+                Ok(obs)
+            },
+            Outcome::Measured(_) => Err(format!("The recording has a measurement where a {} should be.", kind)),
+        }
+    }
+}
+
+impl Environment for Replay {
+    fn reset(&mut self, _start: [f64; 3]) -> Result<Observation, String> {
+        self.next_move("reset")
+    }
+
+    fn step(&mut self, _deltas: [f64; 3]) -> Result<Observation, String> {
+        self.next_move("step")
+    }
+
+    fn end_effector_pose(&mut self) -> Result<na::Isometry3<f64>, String> {
+        self.pose.ok_or("Nothing has been played back yet, so there is no pose.".to_string())
+    }
+
+    fn measure(&mut self) -> Result<Option<na::Translation3<f64>>, String> {
+        match self.next("measure")? {
+            Outcome::Measured(pose) => Ok(pose),
+            Outcome::Moved(_, _) => Err("The recording has a move where a measurement should be.".to_string()),
+        }
+    }
+}
+
+/// Parses a single line of a recording. Returns None for blank lines and episode markers.
+fn parse_entry(lineno: usize, line: &str) -> Result<Option<Entry>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with("episode") {
+        return Ok(None);
+    }
+    if line.starts_with("measure ") {
+        return parse_measurement(lineno, &line["measure ".len()..]).map(Some);
+    }
+
+    let tokens: Vec<&str> = line.splitn(5, ' ').collect();
+    if tokens.len() != 5 || (tokens[0] != "reset" && tokens[0] != "step") {
+        return Err("Expected 'reset' or 'step', three numbers, and then what came of it".to_string());
+    }
+    let kind = tokens[0].to_string();
+
+    let rest = tokens[4];
+    if rest.starts_with("failed: ") {
+        return Ok(Some(Entry { lineno: lineno, kind: kind, outcome: Err(rest["failed: ".len()..].to_string()) }));
+    }
+
+    let numbers: Vec<&str> = rest.split_whitespace().collect();
+    if numbers.len() != 13 || numbers[0] != "->" || numbers[4] != "at" || numbers[8] != "facing" {
+        return Err("Expected '-> <base> <shoulder> <elbow> at <x> <y> <z> facing <w> <i> <j> <k>' or 'failed: <why>'".to_string());
+    }
+    let angles = parse_numbers(&numbers[1..4])?;
+    let position = parse_numbers(&numbers[5..8])?;
+    let w = parse_numbers(&numbers[9..10])?[0];
+    let ijk = parse_numbers(&numbers[10..13])?;
+
+    let obs = Observation { angles: angles };
+    let rotation = na::UnitQuaternion::new_unchecked(na::Quaternion::new(w, ijk[0], ijk[1], ijk[2]));
+    let pose = na::Isometry3::from_parts(na::Translation3::new(position[0], position[1], position[2]), rotation);
+    Ok(Some(Entry { lineno: lineno, kind: kind, outcome: Ok(Outcome::Moved(obs, pose)) }))
+}
+
+/// Parses what comes after 'measure' on a line of a recording.
+fn parse_measurement(lineno: usize, rest: &str) -> Result<Entry, String> {
+    let kind = "measure".to_string();
+    if rest.starts_with("failed: ") {
+        return Ok(Entry { lineno: lineno, kind: kind, outcome: Err(rest["failed: ".len()..].to_string()) });
+    }
+
+    let tokens: Vec<&str> = rest.split_whitespace().collect();
+    let pose = match tokens.as_slice() {
+        ["->", "none"] => None,
+        ["->", x, y, z] => {
+            let pose = parse_numbers(&[*x, *y, *z])?;
+            Some(na::Translation3::new(pose[0], pose[1], pose[2]))
+        },
+        _ => return Err("Expected '-> <x> <y> <z>', '-> none', or 'failed: <why>' after 'measure'".to_string()),
+    };
+    Ok(Entry { lineno: lineno, kind: kind, outcome: Ok(Outcome::Measured(pose)) })
+}
+
+/// Parses up to three numbers.
+fn parse_numbers(tokens: &[&str]) -> Result<[f64; 3], String> {
+    let mut values = [0.0; 3];
+    for (value, token) in values.iter_mut().zip(tokens.iter()) {
+        *value = match token.parse::<f64>() {
+            Ok(val) => val,
+            Err(_) => return Err(format!("{} is not a number", token)),
+        };
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_entry() {
+        let entry = parse_entry(3, "step 1.5 -2 0 -> 91.5 8 155 at 0.12 0.04 0.19 facing 1 0 0 0").unwrap().unwrap();
+        assert_eq!(entry.kind, "step");
+        let obs = Observation { angles: [91.5, 8.0, 155.0] };
+        let pose = na::Isometry3::from_parts(na::Translation3::new(0.12, 0.04, 0.19), na::UnitQuaternion::identity());
+        assert_eq!(entry.outcome.unwrap(), Outcome::Moved(obs, pose));
+
+        let entry = parse_entry(4, "reset 90 10 155 failed: Device did not answer servo 0 90").unwrap().unwrap();
+        assert_eq!(entry.outcome.unwrap_err(), "Device did not answer servo 0 90");
+
+        let entry = parse_entry(5, "measure -> 0.1 0.2 0.3").unwrap().unwrap();
+        assert_eq!(entry.outcome.unwrap(), Outcome::Measured(Some(na::Translation3::new(0.1, 0.2, 0.3))));
+        let entry = parse_entry(5, "measure -> none").unwrap().unwrap();
+        assert_eq!(entry.outcome.unwrap(), Outcome::Measured(None));
+        assert!(parse_entry(5, "measure -> 0.1 0.2").is_err());
+
+        assert!(parse_entry(5, "episode 2").unwrap().is_none());
+        assert!(parse_entry(6, "step 1 2 3 -> 4 5 6").is_err());
+        assert!(parse_entry(6, "step 1 2 3 -> 4 5 6 at 7 8 9").is_err());
+        assert!(parse_entry(7, "jump 1 2 3 -> 4 5 6 at 7 8 9 facing 1 0 0 0").is_err());
+    }
+}
@@ -0,0 +1,643 @@
+//! Structured results, for plotting and analysing experiments without scraping `results.txt` (which is still written,
+//! for people to read).
+//!
+//! There are four tables: one row per episode, one per network evaluated in a generation, one per reset or step the
+//! arm took, and one of statistics per generation (see `progress`). Each goes in its own file in the results
+//! directory: `episodes.jsonl`, `networks.csv`, `steps.parquet`, and so on, for each format asked for. JSON Lines and
+//! CSV are written as each episode finishes, so they are there to look at while the experiment runs. Parquet tables
+//! are converted from the CSV ones at the end of the experiment (so asking for Parquet writes CSV too), and only if
+//! the experiment was built with the `parquet` feature.
+//!
+//! Anything a row doesn't have is `null` in JSON Lines, and empty in CSV and Parquet. JSON has no infinities, so a
+//! network that couldn't be evaluated has a `null` fitness there, but `-inf` in CSV.
+//!
+//! `manifest.json` says how the results were made: the config, the seed, the git revision the experiment was built
+//! from, and when it started and finished.
+
+use nalgebra as na;
+use std::f64;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::environment::Observation;
+use super::expconfig::ExperimentConfig;
+use super::progress::GenerationStats;
+use super::weightfile;
+
+/// The tables, in the order they are written.
+const TABLES: [Table; 4] = [Table::Episodes, Table::Networks, Table::Steps, Table::Generations];
+
+const EPISODE_COLUMNS: [(&str, Kind); 7] = [
+    ("episode", Kind::Integer), ("seconds", Kind::Real), ("networks", Kind::Integer), ("best_fitness", Kind::Real),
+    ("mean_fitness", Kind::Real), ("training_loss", Kind::Real), ("validation_loss", Kind::Real),
+];
+const NETWORK_COLUMNS: [(&str, Kind); 4] = [
+    ("episode", Kind::Integer), ("network", Kind::Integer), ("fitness", Kind::Real), ("distance", Kind::Real),
+];
+const STEP_COLUMNS: [(&str, Kind); 17] = [
+    ("episode", Kind::Integer), ("network", Kind::Integer), ("reach", Kind::Integer), ("step", Kind::Integer),
+    ("base", Kind::Real), ("shoulder", Kind::Real), ("elbow", Kind::Real),
+    ("delta_base", Kind::Real), ("delta_shoulder", Kind::Real), ("delta_elbow", Kind::Real),
+    ("x", Kind::Real), ("y", Kind::Real), ("z", Kind::Real), ("roll", Kind::Real), ("pitch", Kind::Real), ("yaw", Kind::Real),
+    ("distance", Kind::Real),
+];
+const GENERATION_COLUMNS: [(&str, Kind); 9] = [
+    ("episode", Kind::Integer), ("best_fitness", Kind::Real), ("mean_fitness", Kind::Real),
+    ("median_fitness", Kind::Real), ("worst_fitness", Kind::Real), ("diversity", Kind::Real),
+    ("best_distance", Kind::Real), ("elapsed", Kind::Real), ("remaining", Kind::Real),
+];
+
+/// How many rows go in each of a Parquet file's row groups.
+#[cfg(feature = "parquet")]
+const ROWS_PER_GROUP: usize = 65536;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    JsonLines,
+    Csv,
+    Parquet,
+}
+
+impl Format {
+    /// Parses a comma separated list of formats, like `jsonl, csv`.
+    pub fn parse_list(s: &str) -> Result<Vec<Format>, String> {
+        let mut formats = Vec::new();
+        for name in s.split(',') {
+            let format = match name.trim() {
+                "jsonl" => Format::JsonLines,
+                "csv" => Format::Csv,
+                "parquet" if cfg!(feature = "parquet") => Format::Parquet,
+                "parquet" => return Err("Parquet results need the experiment to be built with the 'parquet' feature (cargo build --features parquet).".to_string()),
+                other => return Err(format!("Results can be written as 'jsonl', 'csv', or 'parquet', but not '{}'", other)),
+            };
+            if !formats.contains(&format) {
+                formats.push(format);
+            }
+        }
+        Ok(formats)
+    }
+
+    /// The format's name in the config, which is also the extension of its files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::JsonLines => "jsonl",
+            Format::Csv => "csv",
+            Format::Parquet => "parquet",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Table {
+    Episodes,
+    Networks,
+    Steps,
+    Generations,
+}
+
+impl Table {
+    /// The table's name, which its files are named after.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Table::Episodes => "episodes",
+            Table::Networks => "networks",
+            Table::Steps => "steps",
+            Table::Generations => "generations",
+        }
+    }
+
+    /// The table's columns. The networks table has a column for each of the fitness function's terms too.
+    fn columns(&self, objectives: &[String]) -> Vec<(String, Kind)> {
+        let fixed: &[(&str, Kind)] = match self {
+            Table::Episodes => &EPISODE_COLUMNS,
+            Table::Networks => &NETWORK_COLUMNS,
+            Table::Steps => &STEP_COLUMNS,
+            Table::Generations => &GENERATION_COLUMNS,
+        };
+        let mut columns: Vec<(String, Kind)> = fixed.iter().map(|&(name, kind)| (name.to_string(), kind)).collect();
+        if *self == Table::Networks {
+            columns.extend(objectives.iter().map(|name| (format!("objective_{}", name), Kind::Real)));
+        }
+        columns
+    }
+}
+
+/// What a column holds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Integer,
+    Real,
+}
+
+/// A cell in a table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Value {
+    Integer(u64),
+    Real(f64),
+    Missing,
+}
+
+impl Value {
+    fn real(x: Option<f64>) -> Value {
+        x.map_or(Value::Missing, Value::Real)
+    }
+
+    /// Reals are written like `0.5` or `1e-19`, rather than with every zero, which is valid JSON and CSV.
+    fn json(&self) -> String {
+        match *self {
+            Value::Integer(n) => n.to_string(),
+            Value::Real(x) if x.is_finite() => format!("{:?}", x),
+            Value::Real(_) | Value::Missing => "null".to_string(),
+        }
+    }
+
+    fn csv(&self) -> String {
+        match *self {
+            Value::Integer(n) => n.to_string(),
+            Value::Real(x) => format!("{:?}", x),
+            Value::Missing => String::new(),
+        }
+    }
+}
+
+/// Which network a rollout was for, and which of its reaches (one per start position and target) it was.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reach {
+    pub network: usize,
+    pub index: usize,
+}
+
+/// A row of one of the tables.
+#[derive(Clone, Debug)]
+pub enum Record {
+    /// A summary of an episode, once it has finished. Only generations have fitnesses, and only epochs have losses.
+    Episode {
+        seconds: f64,
+        nnetworks: usize,
+        best_fitness: Option<f64>,
+        mean_fitness: Option<f64>,
+        training_loss: Option<f64>,
+        validation_loss: Option<f64>,
+    },
+    /// A network's evaluation: its fitness, the objectives it adds up from, and how far from the target it left the
+    /// hand, averaged over its reaches.
+    Network {
+        network: usize,
+        fitness: f64,
+        distance: f64,
+        objectives: Vec<f64>,
+    },
+    /// Where the arm was after a reset (step 0) or a step, and the deltas (in degrees) that took it there.
+    Step {
+        reach: Option<Reach>,
+        step: u64,
+        angles: [f64; 3],
+        deltas: Option<[f64; 3]>,
+        pose: Option<na::Isometry3<f64>>,
+        /// From the hand to the target, in meters, if there is a target.
+        distance: Option<f64>,
+    },
+    /// Statistics about a generation, once it has been evaluated.
+    Generation(GenerationStats),
+}
+
+impl Record {
+    /// A summary of an episode that took `seconds`, with the fitnesses of its generation's networks (if it made one),
+    /// and the training and validation losses (if it was an epoch of training).
+    pub fn episode(seconds: f64, evaluations: &[f64], losses: Option<(f64, f64)>) -> Record {
+        let (best_fitness, mean_fitness) = if evaluations.is_empty() {
+            (None, None)
+        } else {
+            let best = evaluations.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+            (Some(best), Some(evaluations.iter().sum::<f64>() / evaluations.len() as f64))
+        };
+        Record::Episode {
+            seconds: seconds,
+            nnetworks: evaluations.len(),
+            best_fitness: best_fitness,
+            mean_fitness: mean_fitness,
+            training_loss: losses.map(|(training, _)| training),
+            validation_loss: losses.map(|(_, validation)| validation),
+        }
+    }
+
+    /// Where the arm was after a reset or step, and how far that left the hand from `target`.
+    pub fn step(reach: Option<Reach>, step: u64, obs: &Observation, deltas: Option<[f64; 3]>, pose: Option<&na::Isometry3<f64>>, target: Option<&na::Translation3<f64>>) -> Record {
+        let distance = match (pose, target) {
+            (Some(pose), Some(target)) => Some((pose.translation.vector - target.vector).norm()),
+            _ => None,
+        };
+        Record::Step {
+            reach: reach,
+            step: step,
+            angles: obs.angles,
+            deltas: deltas,
+            pose: pose.cloned(),
+            distance: distance,
+        }
+    }
+
+    /// The table the record goes in, and its row there.
+    fn row(&self, episode: u64) -> (Table, Vec<Value>) {
+        let mut row = vec![Value::Integer(episode)];
+        let table = match self {
+            Record::Episode { seconds, nnetworks, best_fitness, mean_fitness, training_loss, validation_loss } => {
+                row.extend(vec![
+                    Value::Real(*seconds),
+                    Value::Integer(*nnetworks as u64),
+                    Value::real(*best_fitness),
+                    Value::real(*mean_fitness),
+                    Value::real(*training_loss),
+                    Value::real(*validation_loss),
+                ]);
+                Table::Episodes
+            },
+            Record::Network { network, fitness, distance, objectives } => {
+                row.extend(vec![Value::Integer(*network as u64), Value::Real(*fitness), Value::Real(*distance)]);
+                row.extend(objectives.iter().map(|&objective| Value::Real(objective)));
+                Table::Networks
+            },
+            Record::Step { reach, step, angles, deltas, pose, distance } => {
+                row.push(reach.map_or(Value::Missing, |reach| Value::Integer(reach.network as u64)));
+                row.push(reach.map_or(Value::Missing, |reach| Value::Integer(reach.index as u64)));
+                row.push(Value::Integer(*step));
+                row.extend(angles.iter().map(|&angle| Value::Real(angle)));
+                row.extend((0..3).map(|i| Value::real(deltas.map(|deltas| deltas[i]))));
+                let position = pose.map(|pose| pose.translation.vector);
+                row.extend((0..3).map(|i| Value::real(position.map(|position| position[i]))));
+                let (roll, pitch, yaw) = match pose {
+                    Some(pose) => {
+                        let (roll, pitch, yaw) = pose.rotation.euler_angles();
+                        (Some(roll), Some(pitch), Some(yaw))
+                    },
+                    None => (None, None, None),
+                };
+                row.extend(vec![Value::real(roll), Value::real(pitch), Value::real(yaw), Value::real(*distance)]);
+                Table::Steps
+            },
+            Record::Generation(stats) => {
+                row.extend(vec![
+                    Value::Real(stats.best_fitness),
+                    Value::Real(stats.mean_fitness),
+                    Value::Real(stats.median_fitness),
+                    Value::Real(stats.worst_fitness),
+                    Value::Real(stats.diversity),
+                    Value::real(stats.best_distance),
+                    Value::Real(stats.elapsed),
+                    Value::Real(stats.remaining),
+                ]);
+                Table::Generations
+            },
+        };
+        (table, row)
+    }
+}
+
+/// Somewhere to write what happens in an episode: free-form text for `results.txt`, and records for the tables.
+pub trait Log: fmt::Write {
+    fn record(&mut self, record: Record);
+}
+
+/// A log kept apart from the results until it can be added to them, like a network's when networks are evaluated at
+/// the same time.
+#[derive(Debug, Default)]
+pub struct Buffer {
+    pub text: String,
+    pub records: Vec<Record>,
+}
+
+impl fmt::Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.text.push_str(s);
+        Ok(())
+    }
+}
+
+impl Log for Buffer {
+    fn record(&mut self, record: Record) {
+        self.records.push(record);
+    }
+}
+
+/// A table's file in one of the formats written as the experiment goes.
+struct TableFile {
+    table: Table,
+    format: Format,
+    name: String,
+    file: io::BufWriter<fs::File>,
+}
+
+/// Writes the records of each episode to the tables' files.
+pub struct Recorder {
+    dir: PathBuf,
+    formats: Vec<Format>,
+    /// The names of the fitness function's terms.
+    objectives: Vec<String>,
+    files: Vec<TableFile>,
+}
+
+impl Recorder {
+    /// Opens the tables' files in the experiment's results directory. `resumed` has the length each file had at the
+    /// checkpoint an experiment is being resumed from. Those files are cut back to that length and carried on with,
+    /// and the rest are started afresh, as are any that have gone missing or are shorter than they were.
+    pub fn create(experiment: &ExperimentConfig, resumed: &[(String, u64)]) -> io::Result<Self> {
+        let dir = PathBuf::from(&experiment.results_dir);
+        fs::create_dir_all(&dir)?;
+        let objectives = experiment.fitness.term_names();
+
+        // Parquet tables are converted from the CSV ones
+        let mut text_formats = Vec::new();
+        for &format in experiment.results_formats.iter() {
+            let format = if format == Format::Parquet { Format::Csv } else { format };
+            if !text_formats.contains(&format) {
+                text_formats.push(format);
+            }
+        }
+
+        let mut files = Vec::new();
+        for &table in TABLES.iter() {
+            for &format in text_formats.iter() {
+                let name = format!("{}.{}", table.name(), format.name());
+                let path = dir.join(&name);
+                let kept = resumed.iter().find(|&&(ref resumed_name, _)| *resumed_name == name).and_then(|&(_, length)| {
+                    match fs::metadata(&path) {
+                        Ok(ref metadata) if metadata.len() >= length => Some(length),
+                        _ => {
+                            println!("{} is missing or shorter than it was at the checkpoint, so it starts afresh", path.display());
+                            None
+                        },
+                    }
+                });
+                let file = match kept {
+                    Some(length) => {
+                        let file = fs::OpenOptions::new().append(true).open(&path)?;
+                        file.set_len(length)?;
+                        io::BufWriter::new(file)
+                    },
+                    None => {
+                        let mut file = io::BufWriter::new(fs::File::create(&path)?);
+                        if format == Format::Csv {
+                            let names: Vec<String> = table.columns(&objectives).into_iter().map(|(name, _)| name).collect();
+                            writeln!(file, "{}", names.join(","))?;
+                        }
+                        file
+                    },
+                };
+                files.push(TableFile { table: table, format: format, name: name, file: file });
+            }
+        }
+
+        Ok(Recorder {
+            dir: dir,
+            formats: experiment.results_formats.clone(),
+            objectives: objectives,
+            files: files,
+        })
+    }
+
+    /// Adds an episode's records to the tables.
+    pub fn write(&mut self, episode: u64, records: &[Record]) -> io::Result<()> {
+        for record in records.iter() {
+            let (table, row) = record.row(episode);
+            let columns = table.columns(&self.objectives);
+            for table_file in self.files.iter_mut().filter(|table_file| table_file.table == table) {
+                match table_file.format {
+                    Format::JsonLines => {
+                        let fields: Vec<String> = columns.iter().zip(row.iter()).map(|(&(ref name, _), value)| format!("\"{}\":{}", name, value.json())).collect();
+                        writeln!(table_file.file, "{{{}}}", fields.join(","))?;
+                    },
+                    Format::Csv | Format::Parquet => {
+                        let fields: Vec<String> = row.iter().map(|value| value.csv()).collect();
+                        writeln!(table_file.file, "{}", fields.join(","))?;
+                    },
+                }
+            }
+        }
+        for table_file in self.files.iter_mut() {
+            table_file.file.flush()?;
+        }
+        Ok(())
+    }
+
+    /// How long each file is, for a checkpoint to cut it back to when it is resumed from.
+    pub fn lengths(&mut self) -> io::Result<Vec<(String, u64)>> {
+        let mut lengths = Vec::new();
+        for table_file in self.files.iter_mut() {
+            table_file.file.flush()?;
+            lengths.push((table_file.name.clone(), table_file.file.get_ref().metadata()?.len()));
+        }
+        Ok(lengths)
+    }
+
+    /// Finishes the tables, converting them to Parquet if that was asked for.
+    pub fn finish(mut self) -> io::Result<()> {
+        for table_file in self.files.iter_mut() {
+            table_file.file.flush()?;
+        }
+        if self.formats.contains(&Format::Parquet) {
+            for &table in TABLES.iter() {
+                let csv = self.dir.join(format!("{}.csv", table.name()));
+                let parquet = self.dir.join(format!("{}.parquet", table.name()));
+                write_parquet(&csv, &parquet, &table.columns(&self.objectives))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How the results were made.
+pub struct Manifest {
+    /// The config file, as it was written.
+    config: String,
+    config_path: String,
+    config_hash: u64,
+    seed: u64,
+    /// The experiment's command line.
+    command: Vec<String>,
+    formats: Vec<Format>,
+    /// When the experiment started (or was resumed), and finished, in seconds since the Unix epoch.
+    started: f64,
+    finished: Option<f64>,
+    /// The episode the experiment was resumed from, if it was.
+    resumed_from: Option<u64>,
+}
+
+impl Manifest {
+    /// A manifest for an experiment starting now (or resuming from episode `resumed_from`).
+    pub fn new(experiment: &ExperimentConfig, configpath: &Path, command: Vec<String>, resumed_from: Option<u64>) -> io::Result<Self> {
+        Ok(Manifest {
+            config: fs::read_to_string(configpath)?,
+            config_path: configpath.to_string_lossy().to_string(),
+            config_hash: weightfile::hash_config(experiment),
+            seed: experiment.seed,
+            command: command,
+            formats: experiment.results_formats.clone(),
+            started: seconds_since_epoch(),
+            finished: None,
+            resumed_from: resumed_from,
+        })
+    }
+
+    /// Marks the experiment as finished, now.
+    pub fn finish(&mut self) {
+        self.finished = Some(seconds_since_epoch());
+    }
+
+    /// Saves the manifest in the experiment's results directory.
+    pub fn save(&self, experiment: &ExperimentConfig) -> io::Result<()> {
+        fs::create_dir_all(&experiment.results_dir)?;
+        fs::write(Path::new(&experiment.results_dir).join("manifest.json"), self.to_json())
+    }
+
+    fn to_json(&self) -> String {
+        let optional = |x: Option<String>| x.unwrap_or("null".to_string());
+        let strings = |xs: Vec<String>| format!("[{}]", xs.iter().map(|x| json_string(x)).collect::<Vec<String>>().join(", "));
+        let fields = vec![
+            ("version", json_string(env!("CARGO_PKG_VERSION"))),
+            ("git_revision", optional(option_env!("EXPERIMENT_GIT_REVISION").map(json_string))),
+            ("git_dirty", optional(option_env!("EXPERIMENT_GIT_DIRTY").map(String::from))),
+            ("command", strings(self.command.clone())),
+            ("config_path", json_string(&self.config_path)),
+            ("config_hash", json_string(&format!("{:016x}", self.config_hash))),
+            ("config", json_string(&self.config)),
+            ("seed", self.seed.to_string()),
+            ("formats", strings(self.formats.iter().map(|format| format.name().to_string()).collect())),
+            ("tables", strings(TABLES.iter().map(|table| table.name().to_string()).collect())),
+            ("resumed_from_episode", optional(self.resumed_from.map(|episode| episode.to_string()))),
+            ("started", self.started.to_string()),
+            ("finished", optional(self.finished.map(|finished| finished.to_string()))),
+            ("seconds", optional(self.finished.map(|finished| (finished - self.started).to_string()))),
+        ];
+        let fields: Vec<String> = fields.into_iter().map(|(name, value)| format!("  \"{}\": {}", name, value)).collect();
+        format!("{{\n{}\n}}\n", fields.join(",\n"))
+    }
+}
+
+fn seconds_since_epoch() -> f64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as f64 + f64::from(since.subsec_nanos()) * 1E-9,
+        Err(_) => 0.0,
+    }
+}
+
+/// `s` as a JSON string, in quotes and escaped.
+fn json_string(s: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Converts a table from CSV to Parquet, a row group at a time. Every column is optional, as any cell can be missing.
+#[cfg(feature = "parquet")]
+fn write_parquet(csv: &Path, path: &Path, columns: &[(String, Kind)]) -> io::Result<()> {
+    use parquet::data_type::{DoubleType, Int64Type};
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+    use std::io::BufRead;
+    use std::sync::Arc;
+
+    let to_io = |e: parquet::errors::ParquetError| io::Error::new(io::ErrorKind::Other, e.to_string());
+
+    let mut message = String::from("message records {\n");
+    for &(ref name, kind) in columns.iter() {
+        message.push_str(&format!("  optional {} {};\n", if kind == Kind::Integer { "int64" } else { "double" }, name));
+    }
+    message.push('}');
+    let schema = Arc::new(parse_message_type(&message).map_err(to_io)?);
+    let mut writer = SerializedFileWriter::new(fs::File::create(path)?, schema, Arc::new(WriterProperties::builder().build())).map_err(to_io)?;
+
+    let mut lines = io::BufReader::new(fs::File::open(csv)?).lines().skip(1).peekable();
+    while lines.peek().is_some() {
+        // Each column's values, and whether each row has one (1) or not (0)
+        let mut integers: Vec<Vec<i64>> = vec![Vec::new(); columns.len()];
+        let mut reals: Vec<Vec<f64>> = vec![Vec::new(); columns.len()];
+        let mut present: Vec<Vec<i16>> = vec![Vec::new(); columns.len()];
+        for line in lines.by_ref().take(ROWS_PER_GROUP) {
+            let line = line?;
+            for (i, field) in line.split(',').enumerate().take(columns.len()) {
+                let parsed = match columns[i].1 {
+                    Kind::Integer => field.parse::<i64>().ok().map(|n| integers[i].push(n)),
+                    Kind::Real => field.parse::<f64>().ok().map(|x| reals[i].push(x)),
+                };
+                present[i].push(if parsed.is_some() { 1 } else { 0 });
+            }
+        }
+
+        let mut row_group = writer.next_row_group().map_err(to_io)?;
+        let mut i = 0;
+        while let Some(mut column) = row_group.next_column().map_err(to_io)? {
+            match columns[i].1 {
+                Kind::Integer => column.typed::<Int64Type>().write_batch(&integers[i], Some(&present[i]), None),
+                Kind::Real => column.typed::<DoubleType>().write_batch(&reals[i], Some(&present[i]), None),
+            }.map_err(to_io)?;
+            column.close().map_err(to_io)?;
+            i += 1;
+        }
+        row_group.close().map_err(to_io)?;
+    }
+    writer.close().map_err(to_io)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_parquet(_csv: &Path, _path: &Path, _columns: &[(String, Kind)]) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Other, "the experiment was built without the 'parquet' feature"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rows_match_columns() {
+        let objectives = vec!["inverse_distance".to_string(), "path_length".to_string()];
+        let obs = Observation { angles: [90.0, 10.0, 155.0] };
+        let pose = na::Isometry3::from_parts(na::Translation3::new(0.1, 0.2, 0.3), na::UnitQuaternion::identity());
+        let target = na::Translation3::new(0.1, 0.2, 0.0);
+        let records = vec![
+            Record::episode(1.5, &[1.0, 3.0], None),
+            Record::Network { network: 4, fitness: f64::NEG_INFINITY, distance: 0.5, objectives: vec![1.0, -2.0] },
+            Record::step(Some(Reach { network: 4, index: 1 }), 2, &obs, Some([1.0, -1.0, 0.5]), Some(&pose), Some(&target)),
+            Record::step(None, 0, &obs, None, None, None),
+            Record::Generation(GenerationStats::new(7, &[1.0, 3.0], &[vec![0.0], vec![1.0]], &[], 2.0, 4.0)),
+        ];
+        for record in records.iter() {
+            let (table, row) = record.row(7);
+            assert_eq!(row.len(), table.columns(&objectives).len(), "{} row", table.name());
+            assert_eq!(row[0], Value::Integer(7));
+        }
+
+        let (_, episode) = records[0].row(7);
+        assert_eq!(episode[3], Value::Real(3.0));
+        assert_eq!(episode[4], Value::Real(2.0));
+        assert_eq!(episode[5], Value::Missing);
+        let (_, network) = records[1].row(7);
+        assert_eq!(network[2].json(), "null");
+        assert_eq!(network[2].csv(), "-inf");
+        let (_, step) = records[2].row(7);
+        assert!((step.last().unwrap().csv().parse::<f64>().unwrap() - 0.3).abs() < 1E-12);
+    }
+
+    #[test]
+    fn test_parse_formats() {
+        assert_eq!(Format::parse_list("jsonl, csv, jsonl").unwrap(), vec![Format::JsonLines, Format::Csv]);
+        assert!(Format::parse_list("xml").is_err());
+        assert_eq!(Format::parse_list("parquet").is_ok(), cfg!(feature = "parquet"));
+        assert_eq!(json_string("a \"b\"\n\\"), "\"a \\\"b\\\"\\n\\\\\"");
+    }
+}
@@ -0,0 +1,117 @@
+//! Teaches a network inverse kinematics by backpropagation, as a baseline to compare the genetic algorithm with, and
+//! a head start to give it (see `initial_weights`).
+//!
+//! Each example puts the joints at random angles, and picks other random angles for them to get to. Where the
+//! kinematic model says those put the hand is the target. The network is given what it would observe at the
+//! first angles, and should answer with the deltas that get the joints to the others in one step.
+
+use nalgebra as na;
+use rand::Rng;
+use std::f64;
+
+use super::environment::{self, Environment};
+use super::expconfig::ExperimentConfig;
+use super::netconfig;
+use super::network::{MultilayerPerceptron, Optimizer};
+use super::weightfile;
+use super::{ANGLE_LOWER_LIMIT_BASE, ANGLE_LOWER_LIMIT_ELBOW, ANGLE_LOWER_LIMIT_SHOULDER};
+use super::{ANGLE_UPPER_LIMIT_BASE, ANGLE_UPPER_LIMIT_ELBOW, ANGLE_UPPER_LIMIT_SHOULDER};
+
+/// A network's input, and the output it should give for it.
+type Example = (na::DVector<f64>, na::DVector<f64>);
+
+/// One example is kept aside to check the network on for this many it is trained on.
+const EXAMPLES_PER_VALIDATION_EXAMPLE: u64 = 10;
+
+pub struct Trainer {
+    network: MultilayerPerceptron,
+    optimizer: Optimizer,
+    examples: Vec<Example>,
+    /// Examples the network isn't trained on, to see how well it does on ones it hasn't seen.
+    validation: Vec<Example>,
+    /// How many epochs it has been trained for.
+    nepochs: u64,
+    /// The average loss over the batches in the last epoch.
+    training_loss: f64,
+    /// The mean squared error over the validation examples, after the last epoch.
+    validation_loss: f64,
+}
+
+impl Trainer {
+    /// Builds a network for the experiment, and makes the examples to train and check it on by moving the joints in `env`.
+    pub fn new(experiment: &ExperimentConfig, rng: &mut rand::StdRng, env: &mut dyn Environment) -> Result<Self, String> {
+        let network = netconfig::build_network(&experiment.architecture, &experiment.observation, experiment.low, experiment.high, rng);
+        let nvalidation = (experiment.nsamples / EXAMPLES_PER_VALIDATION_EXAMPLE).max(1);
+        let mut examples = Vec::new();
+        for _ in 0..experiment.nsamples + nvalidation {
+            examples.push(make_example(experiment, rng, env)?);
+        }
+        let validation = examples.split_off(experiment.nsamples as usize);
+
+        Ok(Trainer {
+            training_loss: f64::NAN,
+            validation_loss: network.mse(&validation),
+            network: network,
+            optimizer: Optimizer::new(experiment.optimizer, experiment.learning_rate),
+            examples: examples,
+            validation: validation,
+            nepochs: 0,
+        })
+    }
+
+    /// Trains on each example once, in batches of the experiment's batch size, in a random order.
+    /// Returns the average loss over the batches, and the loss on the validation examples afterwards.
+    pub fn run_epoch(&mut self, experiment: &ExperimentConfig, rng: &mut rand::StdRng) -> (f64, f64) {
+        rng.shuffle(&mut self.examples);
+        let mut total = 0.0;
+        let mut nbatches = 0;
+        for batch in self.examples.chunks(experiment.batch_size as usize) {
+            total += self.network.train_batch(batch, &mut self.optimizer);
+            nbatches += 1;
+        }
+        self.nepochs += 1;
+        self.training_loss = total / nbatches as f64;
+        self.validation_loss = self.network.mse(&self.validation);
+        self.losses()
+    }
+
+    /// The average loss over the batches in the last epoch, and the loss on the validation examples afterwards.
+    pub fn losses(&self) -> (f64, f64) {
+        (self.training_loss, self.validation_loss)
+    }
+
+    /// Saves the network to `path`. Its fitness is minus its validation loss, so that higher is still better.
+    pub fn save_network(&self, experiment: &ExperimentConfig, path: &String) -> std::io::Result<()> {
+        let metadata = weightfile::Metadata {
+            seed: experiment.seed,
+            generation: self.nepochs,
+            fitness: -self.validation_loss,
+            config_hash: weightfile::hash_config(experiment),
+        };
+        weightfile::save(&self.network, &metadata, path)
+    }
+}
+
+fn make_example(experiment: &ExperimentConfig, rng: &mut rand::StdRng, env: &mut dyn Environment) -> Result<Example, String> {
+    let start = random_angles(rng);
+    let goal = random_angles(rng);
+
+    // Where the hand ends up is the target
+    env.reset(goal)?;
+    let target = env.end_effector_pose()?.translation;
+
+    let observation = env.reset(start)?;
+    let pose = env.end_effector_pose()?;
+    let input = experiment.observation.build(&observation, &pose, &target, [0.0; 3]);
+    let deltas = vec![goal[0] - start[0], goal[1] - start[1], goal[2] - start[2]];
+    Ok((na::DVector::<f64>::from_vec(input.len(), input), na::DVector::<f64>::from_vec(3, deltas)))
+}
+
+/// Angles anywhere within the joints' limits.
+fn random_angles(rng: &mut rand::StdRng) -> [f64; 3] {
+    environment::clamp_to_limits([
+        rng.gen_range(ANGLE_LOWER_LIMIT_BASE, ANGLE_UPPER_LIMIT_BASE),
+        rng.gen_range(ANGLE_LOWER_LIMIT_SHOULDER, ANGLE_UPPER_LIMIT_SHOULDER),
+        rng.gen_range(ANGLE_LOWER_LIMIT_ELBOW, ANGLE_UPPER_LIMIT_ELBOW),
+    ])
+}
@@ -0,0 +1,471 @@
+//! Files holding a trained network: its layers, their weights and biases, and where the network came from.
+//!
+//! There are two formats, which hold the same things:
+//!
+//! * Text, as TOML, for files ending in `.toml`. Each layer is a `[[layers]]` table with its `inputs`, `nodes`,
+//!   `activation`, `weights` (a row of weights for each node) and `biases`.
+//! * Binary, for anything else: `ROBOARMN`, the format version, the CRC-32 of the rest, then the rest, all little-endian.
+//!
+//! Both say which version of the format they are, and carry a checksum of the network and metadata, so that
+//! a file that was cut short or edited by accident doesn't get used as if it were fine. Files in the text format
+//! from before these (see `MultilayerPerceptron::load`) still load, but have no metadata.
+
+use super::expconfig::ExperimentConfig;
+use super::netconfig;
+use super::network::{Activation, LayerParameters, MultilayerPerceptron};
+use rand;
+use std::fs;
+use std::io;
+use std::path::Path;
+use toml;
+
+/// The version of the formats written by `save`. Files of this version or older can be loaded.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Binary files start with these bytes.
+const MAGIC: &[u8; 8] = b"ROBOARMN";
+
+/// Text files say they are one of these in their `format` key.
+const FORMAT_NAME: &str = "roboarm-network";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Text,
+    Binary,
+}
+
+impl Format {
+    /// Text for paths ending in `.toml`, binary otherwise.
+    pub fn from_path(path: &str) -> Format {
+        match Path::new(path).extension() {
+            Some(extension) if extension == "toml" => Format::Text,
+            _ => Format::Binary,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// How a saved network was trained.
+pub struct Metadata {
+    /// The experiment's random seed.
+    pub seed: u64,
+    /// How many generations had been made when the network was saved (or epochs, if it was trained by backpropagation).
+    pub generation: u64,
+    /// The network's fitness (or minus its validation loss).
+    pub fitness: f64,
+    /// The hash of the experiment's configuration (see `hash_config`), to tell which config the network was trained with.
+    pub config_hash: u64,
+}
+
+/// A network loaded by `load`.
+pub struct SavedNetwork {
+    pub network: MultilayerPerceptron,
+    /// None for files in the old text format, which didn't have any.
+    pub metadata: Option<Metadata>,
+}
+
+/// Hashes the configuration as the experiment understood it (FNV-1a of its printout), so that networks trained with
+/// the same settings have the same hash.
+pub fn hash_config(experiment: &ExperimentConfig) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in experiment.to_string().bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Saves `network` and its `metadata` to `path`, in the format its extension calls for (see `Format::from_path`).
+pub fn save(network: &MultilayerPerceptron, metadata: &Metadata, path: &str) -> io::Result<()> {
+    let layers = network.layer_parameters();
+    let contents = match Format::from_path(path) {
+        Format::Text => to_text(&layers, metadata).into_bytes(),
+        Format::Binary => to_binary(&layers, metadata),
+    };
+    fs::write(path, contents)
+}
+
+/// Loads the network saved at `path`, in whichever format it is.
+///
+/// Returns None for files in the old text format that don't say what layers they are for, which
+/// `MultilayerPerceptron::load_weights` can load into a network of the right shape.
+pub fn load(path: &str) -> io::Result<Option<SavedNetwork>> {
+    let contents = fs::read(path)?;
+    let parsed = if contents.starts_with(MAGIC) {
+        from_binary(&contents)
+    } else {
+        let text = String::from_utf8(contents).map_err(|_| invalid_data("it is neither text nor a binary network file".to_string()))?;
+        if is_old_text(&text) {
+            let network = MultilayerPerceptron::load(&path.to_string())?;
+            return Ok(network.map(|network| SavedNetwork { network: network, metadata: None }));
+        }
+        from_text(&text)
+    };
+
+    let (layers, metadata) = parsed.map_err(invalid_data)?;
+    let network = MultilayerPerceptron::from_layer_parameters(layers).map_err(invalid_data)?;
+    Ok(Some(SavedNetwork { network: network, metadata: Some(metadata) }))
+}
+
+/// Loads the network in `path` (usually the experiment's `weights`), and checks that it takes the experiment's
+/// observation.
+///
+/// Files that don't say what layers they are for get a network shaped as the config says.
+pub fn load_experiment_network(experiment: &ExperimentConfig, path: &String, rng: &mut rand::StdRng) -> Result<SavedNetwork, String> {
+    let saved = match load(path) {
+        Ok(Some(saved)) => saved,
+        Ok(None) => {
+            let mut network = netconfig::build_network(&experiment.architecture, &experiment.observation, 0.0, 1.0, rng);
+            match network.load_weights(path) {
+                Err(e) => return Err(format!("Could not load the network weights from file {}: {}", path, e)),
+                Ok(_) => SavedNetwork { network: network, metadata: None },
+            }
+        },
+        Err(e) => return Err(format!("Could not load the network from file {}: {}", path, e)),
+    };
+    if saved.network.input_length() != experiment.observation.len() {
+        let (inputs, observation) = (saved.network.input_length(), &experiment.observation);
+        return Err(format!("The network in {} takes {} inputs, but the observation ({}) has {} values.", path, inputs, observation, observation.len()));
+    }
+    Ok(saved)
+}
+
+/// The errors say what is wrong with the file, to follow the file's name.
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Whether the text is in the old format: a line describing the layers, or straight into lines of numbers.
+fn is_old_text(text: &str) -> bool {
+    match text.lines().map(|line| line.trim()).find(|line| !line.is_empty()) {
+        Some(line) => line.starts_with("layers ") || line.split_whitespace().next().map_or(false, |n| n.parse::<f64>().is_ok()),
+        None => true,
+    }
+}
+
+/// Writes what the formats have in common, and what the checksum is of: the metadata, then each layer's shape,
+/// activation, weights (row by row), and biases.
+fn encode(layers: &[LayerParameters], metadata: &Metadata) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    put_u64(&mut bytes, metadata.seed);
+    put_u64(&mut bytes, metadata.generation);
+    put_u64(&mut bytes, metadata.fitness.to_bits());
+    put_u64(&mut bytes, metadata.config_hash);
+    put_u64(&mut bytes, layers.len() as u64);
+    for layer in layers.iter() {
+        put_u64(&mut bytes, layer.ninputs as u64);
+        put_u64(&mut bytes, layer.nnodes as u64);
+        let name = layer.activation.name();
+        bytes.push(name.len() as u8);
+        bytes.extend_from_slice(name.as_bytes());
+        for w in layer.weights.iter().chain(layer.biases.iter()) {
+            put_u64(&mut bytes, w.to_bits());
+        }
+    }
+    bytes
+}
+
+fn to_binary(layers: &[LayerParameters], metadata: &Metadata) -> Vec<u8> {
+    let body = encode(layers, metadata);
+    let mut bytes = MAGIC.to_vec();
+    put_u32(&mut bytes, FORMAT_VERSION);
+    put_u32(&mut bytes, crc32(&body));
+    bytes.extend(body);
+    bytes
+}
+
+fn from_binary(bytes: &[u8]) -> Result<(Vec<LayerParameters>, Metadata), String> {
+    let mut reader = Reader { bytes: bytes, at: MAGIC.len() };
+    check_version(reader.u32("the format version")? as i64)?;
+    let checksum = reader.u32("the checksum")?;
+    if crc32(&bytes[reader.at..]) != checksum {
+        return Err("its checksum doesn't match its contents, so it is corrupt or was cut short".to_string());
+    }
+
+    let metadata = Metadata {
+        seed: reader.u64("the metadata")?,
+        generation: reader.u64("the metadata")?,
+        fitness: f64::from_bits(reader.u64("the metadata")?),
+        config_hash: reader.u64("the metadata")?,
+    };
+    let nlayers = reader.u64("the number of layers")?;
+    let mut layers = Vec::new();
+    for i in 0..nlayers {
+        let what = format!("layer {}", i);
+        let ninputs = reader.u64(&what)? as usize;
+        let nnodes = reader.u64(&what)? as usize;
+        let namelen = reader.take(1, &what)?[0] as usize;
+        let name = String::from_utf8_lossy(reader.take(namelen, &what)?).to_string();
+        let activation = parse_activation(&name, i)?;
+        let nweights = match nnodes.checked_mul(ninputs) {
+            Some(n) => n,
+            None => return Err(format!("layer {} has too many weights ({} nodes with {} inputs each)", i, nnodes, ninputs)),
+        };
+        let weights = reader.f64s(nweights, &format!("layer {}'s weights", i))?;
+        let biases = reader.f64s(nnodes, &format!("layer {}'s biases", i))?;
+        layers.push(LayerParameters { nnodes: nnodes, ninputs: ninputs, activation: activation, weights: weights, biases: biases });
+    }
+    if reader.at != bytes.len() {
+        return Err(format!("there are {} bytes left over after the last layer", bytes.len() - reader.at));
+    }
+    Ok((layers, metadata))
+}
+
+fn to_text(layers: &[LayerParameters], metadata: &Metadata) -> String {
+    let mut meta = toml::value::Table::new();
+    // TOML integers are signed, so the seed and hash (which may not fit) are strings
+    meta.insert("seed".to_string(), toml::Value::String(metadata.seed.to_string()));
+    meta.insert("generation".to_string(), toml::Value::Integer(metadata.generation as i64));
+    meta.insert("fitness".to_string(), toml::Value::Float(metadata.fitness));
+    meta.insert("config_hash".to_string(), toml::Value::String(format!("{:016x}", metadata.config_hash)));
+
+    let mut tables = Vec::new();
+    for layer in layers.iter() {
+        let mut table = toml::value::Table::new();
+        table.insert("inputs".to_string(), toml::Value::Integer(layer.ninputs as i64));
+        table.insert("nodes".to_string(), toml::Value::Integer(layer.nnodes as i64));
+        table.insert("activation".to_string(), toml::Value::String(layer.activation.name().to_string()));
+        let rows = layer.weights.chunks(layer.ninputs.max(1)).map(|row| floats(row)).collect();
+        table.insert("weights".to_string(), toml::Value::Array(rows));
+        table.insert("biases".to_string(), floats(&layer.biases));
+        tables.push(toml::Value::Table(table));
+    }
+
+    let mut file = toml::value::Table::new();
+    file.insert("format".to_string(), toml::Value::String(FORMAT_NAME.to_string()));
+    file.insert("version".to_string(), toml::Value::Integer(FORMAT_VERSION as i64));
+    file.insert("checksum".to_string(), toml::Value::String(format!("{:08x}", crc32(&encode(layers, metadata)))));
+    file.insert("metadata".to_string(), toml::Value::Table(meta));
+    file.insert("layers".to_string(), toml::Value::Array(tables));
+    toml::Value::Table(file).to_string()
+}
+
+fn from_text(text: &str) -> Result<(Vec<LayerParameters>, Metadata), String> {
+    let file = match text.parse::<toml::Value>() {
+        Ok(toml::Value::Table(file)) => file,
+        Ok(_) => return Err("it isn't a TOML table".to_string()),
+        Err(e) => return Err(format!("it isn't valid TOML: {}", e)),
+    };
+    if get_str(&file, "format", "the file")? != FORMAT_NAME {
+        return Err(format!("its 'format' should be '{}'", FORMAT_NAME));
+    }
+    check_version(get_int(&file, "version", "the file")?)?;
+
+    let meta = match file.get("metadata") {
+        Some(toml::Value::Table(meta)) => meta,
+        _ => return Err("it has no [metadata] table".to_string()),
+    };
+    let seed = get_str(meta, "seed", "the metadata")?;
+    let config_hash = get_str(meta, "config_hash", "the metadata")?;
+    let metadata = Metadata {
+        seed: seed.parse::<u64>().map_err(|_| format!("the seed should be a whole number, but is '{}'", seed))?,
+        generation: get_int(meta, "generation", "the metadata")? as u64,
+        fitness: get_float(meta.get("fitness"), "the metadata's fitness")?,
+        config_hash: u64::from_str_radix(config_hash, 16).map_err(|_| format!("the config hash should be hexadecimal, but is '{}'", config_hash))?,
+    };
+
+    let tables = match file.get("layers") {
+        Some(toml::Value::Array(tables)) if !tables.is_empty() => tables,
+        _ => return Err("it has no [[layers]]".to_string()),
+    };
+    let mut layers = Vec::new();
+    for (i, table) in tables.iter().enumerate() {
+        let what = format!("layer {}", i);
+        let table = match table.as_table() {
+            Some(table) => table,
+            None => return Err(format!("{} isn't a table", what)),
+        };
+        let ninputs = get_int(table, "inputs", &what)? as usize;
+        let nnodes = get_int(table, "nodes", &what)? as usize;
+        let activation = parse_activation(get_str(table, "activation", &what)?, i as u64)?;
+
+        let rows = match table.get("weights") {
+            Some(toml::Value::Array(rows)) => rows,
+            _ => return Err(format!("{} has no 'weights'", what)),
+        };
+        if rows.len() != nnodes {
+            return Err(format!("{} has {} nodes, but {} rows of weights", what, nnodes, rows.len()));
+        }
+        let mut weights = Vec::new();
+        for (j, row) in rows.iter().enumerate() {
+            let row = get_floats(Some(row), &format!("{}'s weights for node {}", what, j))?;
+            if row.len() != ninputs {
+                return Err(format!("{} has {} inputs, but {} weights for node {}", what, ninputs, row.len(), j));
+            }
+            weights.extend(row);
+        }
+        let biases = get_floats(table.get("biases"), &format!("{}'s biases", what))?;
+        layers.push(LayerParameters { nnodes: nnodes, ninputs: ninputs, activation: activation, weights: weights, biases: biases });
+    }
+
+    let checksum = get_str(&file, "checksum", "the file")?;
+    if format!("{:08x}", crc32(&encode(&layers, &metadata))) != checksum {
+        return Err("its checksum doesn't match its contents, so it is corrupt or was edited".to_string());
+    }
+    Ok((layers, metadata))
+}
+
+fn check_version(version: i64) -> Result<(), String> {
+    if version < 1 || version > FORMAT_VERSION as i64 {
+        return Err(format!("it is format version {}, but only versions 1 to {} can be read", version, FORMAT_VERSION));
+    }
+    Ok(())
+}
+
+fn parse_activation(name: &str, layer: u64) -> Result<Activation, String> {
+    match Activation::from_name(name) {
+        Some(activation) => Ok(activation),
+        None => Err(format!("layer {} has an unknown activation '{}'", layer, name)),
+    }
+}
+
+fn floats(values: &[f64]) -> toml::Value {
+    toml::Value::Array(values.iter().map(|&v| toml::Value::Float(v)).collect())
+}
+
+fn get_str<'a>(table: &'a toml::value::Table, key: &str, what: &str) -> Result<&'a str, String> {
+    match table.get(key) {
+        Some(toml::Value::String(s)) => Ok(s),
+        _ => Err(format!("{} has no '{}' string", what, key)),
+    }
+}
+
+fn get_int(table: &toml::value::Table, key: &str, what: &str) -> Result<i64, String> {
+    match table.get(key) {
+        Some(toml::Value::Integer(n)) if *n >= 0 => Ok(*n),
+        _ => Err(format!("{} has no '{}' (a whole number)", what, key)),
+    }
+}
+
+fn get_float(value: Option<&toml::Value>, what: &str) -> Result<f64, String> {
+    match value {
+        Some(toml::Value::Float(x)) => Ok(*x),
+        Some(toml::Value::Integer(n)) => Ok(*n as f64),
+        _ => Err(format!("{} should be a number", what)),
+    }
+}
+
+fn get_floats(value: Option<&toml::Value>, what: &str) -> Result<Vec<f64>, String> {
+    match value {
+        Some(toml::Value::Array(values)) => values.iter().map(|v| get_float(Some(v), what)).collect(),
+        _ => Err(format!("{} should be a list of numbers", what)),
+    }
+}
+
+/// Reads the little-endian numbers of a binary file in order.
+pub struct Reader<'a> {
+    pub bytes: &'a [u8],
+    /// How far into `bytes` it has read.
+    pub at: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Takes the next `n` bytes, which are (part of) `what`.
+    pub fn take(&mut self, n: usize, what: &str) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.at < n {
+            return Err(format!("it ends in the middle of {}", what));
+        }
+        self.at += n;
+        Ok(&self.bytes[self.at - n..self.at])
+    }
+
+    pub fn u32(&mut self, what: &str) -> Result<u32, String> {
+        Ok(self.take(4, what)?.iter().rev().fold(0, |n, &b| (n << 8) | b as u32))
+    }
+
+    pub fn u64(&mut self, what: &str) -> Result<u64, String> {
+        Ok(self.take(8, what)?.iter().rev().fold(0, |n, &b| (n << 8) | b as u64))
+    }
+
+    pub fn f64s(&mut self, n: usize, what: &str) -> Result<Vec<f64>, String> {
+        let mut values = Vec::new();
+        for _ in 0..n {
+            values.push(f64::from_bits(self.u64(what)?));
+        }
+        Ok(values)
+    }
+}
+
+pub fn put_u32(bytes: &mut Vec<u8>, n: u32) {
+    for i in 0..4 {
+        bytes.push((n >> (8 * i)) as u8);
+    }
+}
+
+pub fn put_u64(bytes: &mut Vec<u8>, n: u64) {
+    for i in 0..8 {
+        bytes.push((n >> (8 * i)) as u8);
+    }
+}
+
+/// The CRC-32 (as in zip and PNG) of `bytes`.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::network::Layer;
+    use nalgebra as na;
+    use rand::prelude::*;
+
+    fn build_network() -> MultilayerPerceptron {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(5);
+        let mut first = Layer::new().inputs(3).length(4).activation(Activation::Tanh).initialize_weights(-2.0, 2.0, &mut rng).finalize();
+        first = Layer::from_parameters(LayerParameters { biases: vec![0.1, -0.2, 0.3, 1E-300], ..first.to_parameters() }).unwrap();
+        MultilayerPerceptron::new()
+            .add_layer(first)
+            .add_layer(Layer::new().inputs(4).length(3).activation(Activation::Softsign).initialize_weights(-2.0, 2.0, &mut rng).finalize())
+            .finalize().unwrap()
+    }
+
+    fn metadata() -> Metadata {
+        Metadata { seed: u64::max_value(), generation: 12, fitness: 0.1 + 0.2, config_hash: 0xfedcba9876543210 }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let network = build_network();
+        let input = na::DVector::<f64>::from_vec(3, vec![0.5, -1.0, 2.0]);
+        for path in ["roundtrip_test.toml", "roundtrip_test.wbin"].iter() {
+            save(&network, &metadata(), path).unwrap();
+            let saved = load(path).unwrap().unwrap();
+            fs::remove_file(path).expect("Could not remove the test network file for some reason.");
+
+            assert_eq!(saved.metadata, Some(metadata()));
+            assert_eq!(saved.network.layer_parameters(), network.layer_parameters());
+            assert_eq!(saved.network.forward(&input), network.forward(&input));
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_files() {
+        let network = build_network();
+
+        // A weight changed by hand fails the checksum
+        let text = to_text(&network.layer_parameters(), &metadata());
+        let first = network.layer_parameters()[0].weights[0].to_string();
+        let edited = text.replacen(&first, "0.5", 1);
+        assert!(from_text(&edited).unwrap_err().contains("checksum"));
+
+        // So does a binary file that was cut short, and one from the future is refused
+        let bytes = to_binary(&network.layer_parameters(), &metadata());
+        assert!(from_binary(&bytes[..bytes.len() - 8]).unwrap_err().contains("checksum"));
+        let mut future = bytes.clone();
+        future[MAGIC.len()] = 2;
+        assert!(from_binary(&future).unwrap_err().contains("version 2"));
+
+        // A node missing a weight is caught before the checksum
+        let mut layers = network.layer_parameters();
+        layers[1].ninputs = 5;
+        assert!(from_text(&to_text(&layers, &metadata())).unwrap_err().contains("layer 1 has 5 inputs"));
+    }
+}
@@ -1,299 +1,89 @@
 """
-Plots the results of an experiment by parsing a results.txt file.
+Plots the results of an experiment from the tables it writes to its results directory
+(episodes, networks and steps, and generations for genetic and evolution strategy experiments),
+from their CSV files, or their JSON Lines files if the experiment wasn't asked for CSV.
 """
+import csv
 import enum
+import json
 import numpy as np
 import matplotlib.pyplot as plt
 import os
 import sys
 
+SERVOS = ["base", "shoulder", "elbow"]
+
 class ExperimentType(enum.Enum):
     RANDOM = 0
     GENETIC = 1
+    SUPERVISED = 2
 
-class Episode:
-    def __init__(self, lines):
-        self.episode_number = int(lines[0].strip().split(' ')[-1])
-
-    @staticmethod
-    def present_in_contents(lines):
-        """
-        Returns if the given lines contains a line that starts with 'episode '.
-        Lines must be a list of strings.
-        """
-        for line in lines:
-            if line.strip().lower().startswith("episode "):
-                return True
-        return False
+def read_table(directory, name):
+    """
+    Reads the named table in the given results directory into a list of dicts, one per row, from its CSV file or
+    else its JSON Lines file. Missing values are None, and the rest are numbers. Returns None if there is neither file.
+    """
+    csvpath = os.path.join(directory, name + ".csv")
+    jsonlpath = os.path.join(directory, name + ".jsonl")
+    if os.path.exists(csvpath):
+        with open(csvpath) as f:
+            return [{column: (float(value) if value != "" else None) for column, value in row.items()} for row in csv.DictReader(f)]
+    elif os.path.exists(jsonlpath):
+        with open(jsonlpath) as f:
+            rows = [json.loads(line) for line in f if line.strip()]
+            return [{column: (float(value) if value is not None else None) for column, value in row.items()} for row in rows]
+    else:
+        return None
 
-    @staticmethod
-    def parse_from_contents(lines):
-        """
-        Removes the lines needed to parse the episode and parses it.
+class ExperimentResults:
+    def __init__(self, path):
         """
-        ended = False
-        parse_mode = True
-        for i, line in enumerate(lines):
-            if line.lower().startswith("episode ") and parse_mode:
-                start_line = i
-                parse_mode = False
-            elif line.lower().startswith("episode ") and not parse_mode:
-                end_line = i
-                ended = True
-                break
-        if not ended:
-            end_line = len(lines)
-
-        genetic = False
-        for line in lines:
-            if "network" in line.lower():
-                genetic = True
-                break
-        if genetic:
-            return GeneticEpisode(lines[start_line:end_line]), lines[end_line:]
+        Reads the tables in the given results directory.
+        """
+        self.episodes = read_table(path, "episodes")
+        self.networks = read_table(path, "networks")
+        self.steps = read_table(path, "steps")
+        if self.episodes is None or self.networks is None or self.steps is None:
+            print("{} has no CSV or JSON Lines tables to plot. Include csv or jsonl in the experiment's 'results_formats'.".format(path))
+            exit(1)
+        self.generations = read_table(path, "generations") or []
+        assert self.episodes, "There are no episodes in {}".format(path)
+
+        if self.episodes[0]["networks"]:
+            self.type = ExperimentType.GENETIC
+        elif self.episodes[0]["training_loss"] is not None:
+            self.type = ExperimentType.SUPERVISED
         else:
-            return RandomEpisode(lines[start_line:end_line]), lines[end_line:]
-
-class Network:
-    def __init__(self, lines):
-        self.fitness = None
-        self.index = None
-        self.servos = {}
-        have_seen_network = False
-        for line in lines:
-            if line.lower().startswith("network ") and not have_seen_network:
-                self.index = int(line.strip().split(' ')[-1])
-                have_seen_network = True
-            elif line.lower().startswith("network ") and have_seen_network:
-                assert False, "Found a second network index in this network's lines for parsing."
-            elif line.lower().startswith("servo "):
-                _, number, value = line.lower().strip().split(' ')
-                if int(number) in self.servos:
-                    self.servos[int(number)].append(float(value))
-                else:
-                    self.servos[int(number)] = [float(value)]
-            elif line.lower().startswith("fitness "):
-                self.fitness = float(line.strip().split(' ')[-1])
-                break
-
-        assert self.index != None, "Could not find an index for this network"
-        assert self.fitness != None, "Could not find a fitness for network index {}".format(self.index)
-
-        # Assert that this network contains at least one recording
-        self.servo_ids = [k for k in self.servos.keys()]
-        self.servo_ids.sort()
-        self.nservos = len(self.servo_ids)
-        assert self.servo_ids, "Could not find any servos in Network {}.".format(self.index)
-
-        # Assert that this network contains the same number of recordings for each servo
-        self.nsteps = len(self.servos[self.servo_ids[0]])
-        for id in self.servo_ids:
-            assert len(self.servos[id]) == self.nsteps, "Number of recordings is not the same for all servos in Network {}.".format(self.index)
-
-    @property
-    def data(self):
-        """
-        Returns a Numpy Array of shape (nservos, nsteps).
-        """
-        servo_data = []
-        for servo in self.servo_ids:
-            servo_data.append(self.servos[servo])
-        ret = np.vstack(servo_data)
-        assert ret.shape == (self.nservos, self.nsteps)
-        return ret
-
-    @staticmethod
-    def present_in_contents(lines):
-        for line in lines:
-            if "network " in line.lower():
-                return True
-        return False
-
-    @staticmethod
-    def parse_from_contents(lines):
-        ended = False
-        parse_mode = True
-        for i, line in enumerate(lines):
-            if line.lower().startswith("network ") and parse_mode:
-                start_line = i
-                parse_mode = False
-            elif line.lower().startswith("network ") and not parse_mode:
-                end_line = i
-                ended = True
-                break
-
-        if not ended:
-            end_line = len(lines)
-        return Network(lines[start_line:end_line]), lines[end_line:]
-
-class GeneticEpisode(Episode):
-    def __init__(self, lines):
-        super().__init__(lines)
-        servo_starting_values = {}
-        self.networks = []
-        # Parse out the starting values
-        for line in lines:
-            if line.lower().startswith("network"):
-                break
-            elif line.lower().startswith("servo "):
-                _, number, value = line.lower().strip().split(' ')
-                servo_starting_values[int(number)] = float(value)
-
-        # Parse out each network
-        while Network.present_in_contents(lines):
-            network, lines = Network.parse_from_contents(lines)
-            self.networks.append(network)
-
-        assert self.networks, "Could not find any networks in GeneticEpisode {}".format(self.episode_number)
-        self.nsteps = self.networks[0].nsteps
-        for net in self.networks:
-            assert net.nsteps == self.nsteps, "Not all networks in GeneticEpisode {} have the same number of steps. Network {} has {} but should have {}".format(
-                self.episode_number, net.index, net.nsteps, self.nsteps
-            )
-        self.nservos = self.networks[0].nservos
-        self.nnetworks = len(self.networks)
-        self.fitnesses = {net.index: net.fitness for net in self.networks}
-        self.highest_fitness = max(self.fitnesses.values())
-        print("Parsed GeneticEpisode (Generation) {} of length {} networks, each evaluated on {} steps.".format(
-            self.episode_number, self.nnetworks, self.nsteps
-        ))
-
-    @property
-    def data(self):
-        """
-        Returns a Numpy Array of shape (nservos, nsteps, nnetworks).
-        Since the networks do not actually have continuity across generations, it probably makes
-        more sense to call episode.data_sorted_by_fitness instead of this.
-        """
-        netdata = []
-        for network in self.networks:
-            netdata.append(network.data)
-        ret = np.array(netdata).reshape((self.nservos, self.nsteps, self.nnetworks))
-        return ret
-
-    @property
-    def data_sorted_by_fitness(self):
-        """
-        Returns a Numpy Array of shape (nservos, nsteps, nnetworks) just like self.data, but
-        rather than aligning the matrices of (servos, steps) to networks based simply on the
-        network indexes, we align them based on the fitness of each network, so that
-        the resulting data array is organized by most fit to least fit in the third dimension.
-        """
-        netids = [i for i in range(self.nnetworks)]
-        network_ids_sorted_by_fitness = sorted(list(netids), key=lambda id: self.fitnesses[id], reverse=True)
-        netdata = []
-        for networkid in network_ids_sorted_by_fitness:
-            netdata.append(self.networks[networkid].data)
-        ret = np.array(netdata).reshape((self.nservos, self.nsteps, self.nnetworks))
-        assert ret.shape == (self.nservos, self.nsteps, self.nnetworks)
-        return ret
-
-class RandomEpisode(Episode):
-    def __init__(self, lines):
-        super().__init__(lines)
-        self.servos = {}
-        for line in lines:
-            if line.lower().strip().startswith("servo "):
-                _, number, value = line.lower().strip().split(' ')
-                if int(number) in self.servos:
-                    self.servos[int(number)].append(float(value))
-                else:
-                    self.servos[int(number)] = [float(value)]
-
-        # Assert that this episode contains at least one recording
-        self.servo_ids = [k for k in self.servos.keys()]
-        self.servo_ids.sort()
-        self.nservos = len(self.servo_ids)
-        assert self.servo_ids, "Could not find any servos in RandomEpisode {}.".format(self.episode_number)
+            self.type = ExperimentType.RANDOM
+        self.nepisodes = len(self.episodes)
 
-        # Assert that this episode contains the same number of recordings for each servo
-        self.nsteps = len(self.servos[self.servo_ids[0]])
-        for id in self.servo_ids:
-            assert len(self.servos[id]) == self.nsteps, "Number of recordings is not the same for all servos in RandomEpisode {}.".format(self.episode_number)
-        print("Parsed RandomEpisode {} of length {}".format(self.episode_number, self.nsteps))
+    def __str__(self):
+        return "Experiment is of type {} and consists of {} episodes.".format(self.type, self.nepisodes)
 
-    @property
-    def data(self):
+    def column(self, table, name):
         """
-        Returns this Episode's data in the form of a NumpyArray of shape (nservos, nsteps)
+        Returns a Numpy Array of the given column of the given table (a list of rows). Missing values are NaN.
         """
-        arrays = []
-        for servo in self.servo_ids:
-            arr = np.array(self.servos[servo])
-            arrays.append(arr)
-        ret = np.vstack(arrays)
-        assert ret.shape == (len(self.servo_ids), self.nsteps)
-        return ret
+        return np.array([row[name] if row[name] is not None else np.nan for row in table])
 
-class ExperimentResults:
-    def __init__(self, path):
+    def servo_data(self, steps):
         """
-        Parses the given file path into an Experiment instance.
+        Returns a Numpy Array of shape (nservos, nsteps) of the joint angles in the given steps.
         """
-        self.type = None
-        self.episodes = []
-
-        with open(path) as f:
-            lines = [line for line in f]
-
-        while Episode.present_in_contents(lines):
-            ep, lines = Episode.parse_from_contents(lines)
-            self.episodes.append(ep)
-
-        if self.episodes:
-            if type(self.episodes[0]) == RandomEpisode:
-                self.type = ExperimentType.RANDOM
-            elif type(self.episodes[0]) == GeneticEpisode:
-                self.type = ExperimentType.GENETIC
-            else:
-                assert False, "ExperimentResults does not understand the type {}".format(type(self.episodes[0]))
-
-        self.nepisodes = len(self.episodes)
-        self.nservos = self.episodes[0].nservos
-        self.nsteps_per_episode = self.episodes[0].nsteps
-        self.nnetworks = self.episodes[0].nnetworks if self.type == ExperimentType.GENETIC else None
-
-    def __str__(self):
-        return "Experiment is of type {} and consists of {} episodes.".format(self.type, len(self.episodes))
+        return np.vstack([self.column(steps, servo) for servo in SERVOS])
 
-    @property
-    def data(self):
+    def best_networks(self):
         """
-        For a RANDOM Experiment:
-        Returns all the servo values for each servo and episode in this experiment
-        in the form of a Numpy Array of shape (nservos, nsteps_per_episode * nepisodes).
-
-        For a GENETIC Experiment:
-        Returns a tuple of the form (fitness_data, servo_data), where:
-
-        - fitness_data is a list of length ngenerations (just nepisodes), where each value in the list is
-          the fitness value for the best network in that generation
-        - servo_data is a Numpy Array of shape (nservos, nsteps_per_generation, nnetworks, ngenerations).
-          Every generation's networks are sorted by most fit to least fit before combining with the rest of the data.
-
+        Returns the index of the best network in each generation.
         """
-        if self.type == ExperimentType.RANDOM:
-            # Compile data for random experiment type
-            eparrays = []
-            for ep in self.episodes:
-                eparrays.append(ep.data)
-            res = np.hstack(eparrays)
-            assert res.shape == (self.nservos, self.nsteps_per_episode * self.nepisodes)
-            return res
-        elif self.type == ExperimentType.GENETIC:
-            # Compile data for genetic experiment type
-            ## Get the Fitnesses
-            fitnesses = [generation.highest_fitness for generation in self.episodes]
-            ## Get the servo data
-            genarrays = []
-            for generation in self.episodes:
-                generation_data = generation.data_sorted_by_fitness
-                genarrays.append(generation_data)
-            res = np.array(genarrays).reshape((self.nservos, self.nsteps_per_episode, self.nnetworks, self.nepisodes))
-            assert res.shape == (self.nservos, self.nsteps_per_episode, self.nnetworks, self.nepisodes)
-            return fitnesses, res
-        else:
-            return None
+        # JSON Lines has no infinities, so a network that couldn't be evaluated has no fitness there
+        fitness = lambda row: row["fitness"] if row["fitness"] is not None else -np.inf
+        best = {}
+        for row in self.networks:
+            episode = int(row["episode"])
+            if episode not in best or fitness(row) > fitness(best[episode]):
+                best[episode] = row
+        return [int(best[episode]["network"]) for episode in sorted(best.keys())]
 
 def plot_random(experiment):
     """
@@ -302,41 +92,71 @@ def plot_random(experiment):
     plt.title("Random Experiment's Servo Values Across Episodes")
     plt.xlabel("Step")
     plt.ylabel("Degrees")
-    data = experiment.data
+    data = experiment.servo_data(experiment.steps)
     for servo in range(data.shape[0]):
-        plt.plot(data[servo, :])
+        plt.plot(data[servo, :], label=SERVOS[servo])
+    plt.legend()
     plt.show()
 
 def plot_genetic(experiment):
     """
     Plots the given experiment, which is assumed to be of type==ExperimentType.GENETIC
     """
-    fitnesses, servo_data = experiment.data
-    plt.title("Each Generation's Best Fitness Value")
+    plt.title("Each Generation's Fitness Values")
     plt.xlabel("Generation")
     plt.ylabel("Fitness Value")
-    plt.plot(fitnesses)
+    if experiment.generations:
+        for statistic in ["best", "mean", "median", "worst"]:
+            plt.plot(experiment.column(experiment.generations, statistic + "_fitness"), label=statistic)
+        plt.legend()
+    else:
+        plt.plot(experiment.column(experiment.episodes, "best_fitness"))
     plt.show()
 
-    distances = [1.0 / f for f in fitnesses]
+    if experiment.generations:
+        plt.title("Each Generation's Weight Diversity")
+        plt.xlabel("Generation")
+        plt.ylabel("Mean Distance from the Mean Weights")
+        plt.plot(experiment.column(experiment.generations, "diversity"))
+        plt.show()
+
+    closest = {}
+    for row in experiment.networks:
+        episode = int(row["episode"])
+        closest[episode] = min(closest.get(episode, np.inf), row["distance"])
     plt.title("Each Generation's Closest Network")
     plt.xlabel("Generation")
     plt.ylabel("Distance (meters)")
-    plt.plot(distances)
+    plt.plot([closest[episode] for episode in sorted(closest.keys())])
     plt.show()
 
+    # The best network's first reach in each generation, one after the other
+    best = experiment.best_networks()
+    steps = [row for row in experiment.steps if row["reach"] == 0 and row["network"] == best[int(row["episode"])]]
     plt.title("Servo Values from Best Network in Each Generation")
-    plt.xlabel("Step (every {} steps is a generation)".format(experiment.nsteps_per_episode))
+    plt.xlabel("Step (every {} steps is a generation)".format(len(steps) // experiment.nepisodes))
     plt.ylabel("Degrees")
-    for servo in range(servo_data.shape[0]):
-        # Since the data is sorted, we can assume the 0 index is the most fit network
-        data = servo_data[servo, :, 0, :].reshape((experiment.nsteps_per_episode * experiment.nepisodes, ))
-        plt.plot(data)
+    data = experiment.servo_data(steps)
+    for servo in range(data.shape[0]):
+        plt.plot(data[servo, :], label=SERVOS[servo])
+    plt.legend()
+    plt.show()
+
+def plot_supervised(experiment):
+    """
+    Plots the given experiment, which is assumed to be of type==ExperimentType.SUPERVISED
+    """
+    plt.title("Loss Over Training")
+    plt.xlabel("Epoch")
+    plt.ylabel("Mean Squared Error")
+    plt.plot(experiment.column(experiment.episodes, "training_loss"), label="training")
+    plt.plot(experiment.column(experiment.episodes, "validation_loss"), label="validation")
+    plt.legend()
     plt.show()
 
 if __name__ == "__main__":
-    if len(sys.argv) != 2 and not os.path.exists("results.txt"):
-        print("Need a path to results.txt file.")
+    if len(sys.argv) != 2 and not os.path.exists("results"):
+        print("Need a path to an experiment's results directory.")
         exit(1)
     elif len(sys.argv) == 2 and not os.path.exists(sys.argv[1]):
         print("{} does not exist.".format(sys.argv[1]))
@@ -345,7 +165,7 @@ if __name__ == "__main__":
     if len(sys.argv) == 2:
         path = sys.argv[1]
     else:
-        path = "results.txt"
+        path = "results"
 
     experiment = ExperimentResults(path)
     print(experiment)
@@ -354,5 +174,7 @@ if __name__ == "__main__":
         plot_random(experiment)
     elif experiment.type == ExperimentType.GENETIC:
         plot_genetic(experiment)
+    elif experiment.type == ExperimentType.SUPERVISED:
+        plot_supervised(experiment)
     else:
         raise Exception("Unsupported experiment type: {}".format(experiment.type))
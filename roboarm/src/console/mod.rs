@@ -27,6 +27,10 @@ impl Console {
         }
     }
 
+    pub fn write_str(&mut self, s: &str) {
+        self.serial.write_all(s.as_bytes());
+    }
+
     pub fn run_statemachine(&mut self) -> Option<Command> {
         // TODO: Return a command if there is a valid one in the UART buffer
         self.serial.write_all("Hello, World!\n".as_bytes());
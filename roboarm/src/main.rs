@@ -12,7 +12,9 @@ extern crate panic_halt;
 /* Use Statements */
 use tm4c123x_hal as tm;
 
-use cortex_m_rt::entry;
+use core::sync::atomic;
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m_rt::{entry, exception};
 use self::tm::prelude::*;
 use self::tm::serial::{NewlineMode, Serial};
 use self::tm::sysctl;
@@ -21,10 +23,13 @@ use self::tm::sysctl;
 mod commands;
 mod console;
 mod leds;
+mod policy;
 mod servos;
 
+/// Set by SysTick every control period, and cleared once the policy has stepped
+static POLICY_DUE: atomic::AtomicBool = atomic::ATOMIC_BOOL_INIT;
 
-fn init() -> (console::Console, tm::delay::Delay, leds::SystemLeds) {
+fn init() -> (console::Console, leds::SystemLeds) {
     /* Take all the peripherals in the system */
     let periph = tm4c123x_hal::Peripherals::take().unwrap();
 
@@ -54,8 +59,13 @@ fn init() -> (console::Console, tm::delay::Delay, leds::SystemLeds) {
     /* Get the core peripherals and then start divvying them out */
     let mut coreperiph = tm::CorePeripherals::take().unwrap();
 
-    /* Take the systick block */
-    let systick = coreperiph.SYST;
+    /* Take the systick block, and have it tick once per control period (well within its 24 bits at 80 MHz) */
+    let mut systick = coreperiph.SYST;
+    systick.set_clock_source(SystClkSource::Core);
+    systick.set_reload(clocks.sysclk.0 / 1000 * policy::CONTROL_PERIOD_MS - 1);
+    systick.clear_current();
+    systick.enable_counter();
+    systick.enable_interrupt();
 
     /* Set up all the interrupts */
     // TODO
@@ -63,16 +73,37 @@ fn init() -> (console::Console, tm::delay::Delay, leds::SystemLeds) {
 
     /* Return all the initialized singletons */
     let con = console::Console::new(uart).unwrap();
-    let delay = tm::delay::Delay::new(systick, &clocks);
     let sysleds = leds::SystemLeds::new(red, green, blue).unwrap();
 
-    (con, delay, sysleds)
+    (con, sysleds)
+}
+
+#[exception]
+fn SysTick() {
+    POLICY_DUE.store(true, atomic::Ordering::Relaxed);
 }
 
 #[entry]
 fn main() -> ! {
-    let (mut con, delay, sysleds) = init();
+    let (mut con, sysleds) = init();
+
+    // A network that can't run here leaves the arm where it is, rather than faulting
+    let mut policy = policy::Policy::new();
+    if policy.is_none() {
+        con.write_str("The exported network is too big to run, or doesn't give a delta for each joint. Not running it.\n");
+    }
+
+    let mut angles = policy::START_ANGLES;
+    servos::move_to(angles);
     loop {
         con.run_statemachine();
+
+        // Step the policy whenever SysTick says a control period has passed
+        if POLICY_DUE.swap(false, atomic::Ordering::Relaxed) {
+            if let Some(ref mut policy) = policy {
+                angles = policy.step(angles);
+                servos::move_to(angles);
+            }
+        }
     }
 }
@@ -0,0 +1,106 @@
+//! Runs a trained network on the arm itself, rather than from the host.
+//!
+//! The network is `model::MODEL`, which `experiment export <config.yaml> src/policy/model.rs` writes from a trained
+//! network. Every `CONTROL_PERIOD_MS`, the policy is given the joint angles and moves them by the deltas the
+//! network asks for, like `experiment` does in inference mode.
+mod model;
+mod network;
+
+use self::network::network::{Feature, Model};
+
+/// How often the policy steps, in milliseconds.
+pub const CONTROL_PERIOD_MS: u32 = 50;
+
+/// The angles (base, shoulder, elbow) the arm starts at, in degrees.
+pub const START_ANGLES: [f32; 3] = [90.0, 10.0, 155.0];
+
+/// Each joint's limits, in degrees, as in `experiment`.
+const ANGLE_LOWER_LIMITS: [f32; 3] = [0.0, 0.0, 100.0];
+const ANGLE_UPPER_LIMITS: [f32; 3] = [180.0, 50.0, 180.0];
+
+/// The most values the network can be given.
+const MAX_INPUTS: usize = 24;
+
+/// Room for the widest of the network's layers, twice.
+const SCRATCH_LENGTH: usize = 128;
+
+pub struct Policy {
+    model: Model<'static>,
+    /// Where the hand should go, in meters, for networks that are told.
+    target: [f32; 3],
+    /// The deltas from the last step.
+    previous: [f32; 3],
+    scratch: [f32; SCRATCH_LENGTH],
+}
+
+impl Policy {
+    /// Returns None if the exported network is too big to run, or doesn't give a delta for each joint.
+    pub fn new() -> Option<Policy> {
+        let model = model::MODEL;
+        if model.input_length() > MAX_INPUTS || model.scratch_length() > SCRATCH_LENGTH {
+            return None;
+        }
+
+        // Check that the network runs, and gives three deltas
+        let mut policy = Policy {
+            model: model,
+            target: [0.0; 3],
+            previous: [0.0; 3],
+            scratch: [0.0; SCRATCH_LENGTH],
+        };
+        let mut deltas = [0.0; 3];
+        match model.forward(&[0.0; MAX_INPUTS][..model.input_length()], &mut policy.scratch, &mut deltas) {
+            Ok(_) => Some(policy),
+            Err(_) => None,
+        }
+    }
+
+    pub fn set_target(&mut self, target: [f32; 3]) {
+        self.target = target;
+    }
+
+    /// Takes the joint angles, in degrees, and returns where the network moves them to, within each joint's limits.
+    pub fn step(&mut self, angles: [f32; 3]) -> [f32; 3] {
+        let mut input = [0.0; MAX_INPUTS];
+        let mut i = 0;
+        while let Some(feature) = self.model.feature(i) {
+            let values = match feature {
+                Feature::JointAngles => angles,
+                Feature::NormalizedJointAngles => normalize_angles(angles),
+                Feature::Target => self.target,
+                Feature::PreviousAction => self.previous,
+            };
+            input[3 * i..3 * i + 3].copy_from_slice(&values);
+            i += 1;
+        }
+
+        // Checked by new()
+        let mut deltas = [0.0; 3];
+        if self.model.forward(&input[..self.model.input_length()], &mut self.scratch, &mut deltas).is_err() {
+            return angles;
+        }
+        self.previous = deltas;
+
+        let mut moved = [0.0; 3];
+        for j in 0..3 {
+            let angle = angles[j] + deltas[j];
+            moved[j] = if angle < ANGLE_LOWER_LIMITS[j] {
+                ANGLE_LOWER_LIMITS[j]
+            } else if angle > ANGLE_UPPER_LIMITS[j] {
+                ANGLE_UPPER_LIMITS[j]
+            } else {
+                angle
+            };
+        }
+        moved
+    }
+}
+
+/// Scales each joint's angle so that its lower limit is -1 and its upper limit is 1.
+fn normalize_angles(angles: [f32; 3]) -> [f32; 3] {
+    let mut normalized = [0.0; 3];
+    for j in 0..3 {
+        normalized[j] = 2.0 * (angles[j] - ANGLE_LOWER_LIMITS[j]) / (ANGLE_UPPER_LIMITS[j] - ANGLE_LOWER_LIMITS[j]) - 1.0;
+    }
+    normalized
+}
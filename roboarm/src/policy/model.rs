@@ -0,0 +1,20 @@
+//! A stand-in until a trained network is exported here with `experiment export`: asks for no movement at all.
+
+use super::network::network::{Activation, Feature, Layer, Model, Weights};
+
+pub static MODEL: Model<'static> = Model::Static {
+    observation: &[Feature::JointAngles],
+    layers: &[
+        Layer {
+            ninputs: 3,
+            nnodes: 3,
+            activation: Activation::Linear,
+            weights: Weights::F32(&[
+                0.0, 0.0, 0.0,
+                0.0, 0.0, 0.0,
+                0.0, 0.0, 0.0,
+            ]),
+            biases: Weights::F32(&[0.0, 0.0, 0.0]),
+        },
+    ],
+};
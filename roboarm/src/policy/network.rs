@@ -0,0 +1,335 @@
+pub mod network {
+    //! Runs the networks trained by `experiment`, exported by `experiment export`, without the standard library
+    //! or an allocator. Everything is `f32`, and only needs `core`, so the host can check it against the
+    //! networks it trained.
+
+    /// Binary models start with these bytes.
+    pub const BLOB_MAGIC: &[u8; 4] = b"RANN";
+
+    /// The version of the binary models this reads.
+    pub const BLOB_VERSION: u8 = 1;
+
+    /// Marks a layer in a binary model as having `f32` weights rather than fixed point ones.
+    const FLOAT_WEIGHTS: u8 = 0xff;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Activation {
+        Linear,
+        Relu,
+        Tanh,
+        Sigmoid,
+        LeakyRelu,
+        Softsign,
+    }
+
+    impl Activation {
+        /// The activation's number in binary models.
+        pub fn from_code(code: u8) -> Option<Activation> {
+            match code {
+                0 => Some(Activation::Linear),
+                1 => Some(Activation::Relu),
+                2 => Some(Activation::Tanh),
+                3 => Some(Activation::Sigmoid),
+                4 => Some(Activation::LeakyRelu),
+                5 => Some(Activation::Softsign),
+                _ => None,
+            }
+        }
+
+        pub fn apply(&self, x: f32) -> f32 {
+            match self {
+                Activation::Linear => x,
+                Activation::Relu => if x < 0.0 { 0.0 } else { x },
+                Activation::Tanh => tanh(x),
+                Activation::Sigmoid => 1.0 / (1.0 + exp(-x)),
+                Activation::LeakyRelu => if x < 0.0 { 0.01 * x } else { x },
+                Activation::Softsign => x / (1.0 + abs(x)),
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    /// What the network is given each step, in the order it is given them. Each is three values.
+    pub enum Feature {
+        /// Base, shoulder, and elbow angles, in degrees.
+        JointAngles,
+        /// Base, shoulder, and elbow angles, scaled so each joint's limits are -1 and 1.
+        NormalizedJointAngles,
+        /// Where the hand should go, in meters.
+        Target,
+        /// The joint deltas the network asked for last step, in degrees.
+        PreviousAction,
+    }
+
+    impl Feature {
+        /// The feature's number in binary models.
+        pub fn from_code(code: u8) -> Option<Feature> {
+            match code {
+                0 => Some(Feature::JointAngles),
+                1 => Some(Feature::NormalizedJointAngles),
+                2 => Some(Feature::Target),
+                3 => Some(Feature::PreviousAction),
+                _ => None,
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    /// A layer's weights.
+    pub enum Weights<'a> {
+        F32(&'a [f32]),
+        /// Fixed point: each weight is `w / 2^frac_bits`. Half the size of `F32`.
+        Fixed(&'a [i16], u8),
+        /// Straight out of a binary model: little-endian `f32`s, or `i16`s if there are `frac_bits`.
+        Bytes(&'a [u8], Option<u8>),
+    }
+
+    impl<'a> Weights<'a> {
+        fn get(&self, i: usize) -> f32 {
+            match *self {
+                Weights::F32(w) => w[i],
+                Weights::Fixed(w, frac_bits) => w[i] as f32 / (1u32 << frac_bits) as f32,
+                Weights::Bytes(b, None) => f32::from_bits(b[4 * i] as u32 | (b[4 * i + 1] as u32) << 8 | (b[4 * i + 2] as u32) << 16 | (b[4 * i + 3] as u32) << 24),
+                Weights::Bytes(b, Some(frac_bits)) => (b[2 * i] as u16 | (b[2 * i + 1] as u16) << 8) as i16 as f32 / (1u32 << frac_bits) as f32,
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    /// A fully-connected layer: each node's output is the activation of its weighted inputs plus its bias.
+    pub struct Layer<'a> {
+        pub ninputs: usize,
+        pub nnodes: usize,
+        pub activation: Activation,
+        /// N_thislayer x N_inputs, row by row: each node's weights in turn.
+        pub weights: Weights<'a>,
+        /// One for each node.
+        pub biases: Weights<'a>,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum ModelError {
+        /// The binary model doesn't start with `BLOB_MAGIC`.
+        NotAModel,
+        /// The binary model is a version this can't read.
+        Version(u8),
+        /// The binary model ends in the middle of something.
+        TooShort,
+        /// The binary model goes on after its last layer.
+        TooLong,
+        /// A feature or activation number that doesn't mean anything.
+        UnknownCode(u8),
+        /// A layer doesn't take the previous one's nodes (or the observation) as its inputs.
+        Shape,
+        /// The input, output, or scratch space given to `forward` is the wrong size.
+        BufferSize,
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    /// A network, with what it should be given.
+    pub enum Model<'a> {
+        /// An exported Rust module, compiled into the firmware.
+        Static {
+            observation: &'a [Feature],
+            layers: &'a [Layer<'a>],
+        },
+        /// A binary model, checked by `from_blob`.
+        Blob(&'a [u8]),
+    }
+
+    impl<'a> Model<'a> {
+        /// Checks the binary model in `bytes` so that it can be run.
+        pub fn from_blob(bytes: &'a [u8]) -> Result<Model<'a>, ModelError> {
+            if bytes.len() < 5 || &bytes[..4] != BLOB_MAGIC {
+                return Err(ModelError::NotAModel);
+            }
+            if bytes[4] != BLOB_VERSION {
+                return Err(ModelError::Version(bytes[4]));
+            }
+            let model = Model::Blob(bytes);
+            let mut reader = BlobReader { bytes: bytes, at: 5 };
+            let nfeatures = reader.u8()? as usize;
+            for _ in 0..nfeatures {
+                let code = reader.u8()?;
+                Feature::from_code(code).ok_or(ModelError::UnknownCode(code))?;
+            }
+            let nlayers = reader.u8()?;
+            if nlayers == 0 {
+                return Err(ModelError::Shape);
+            }
+            let mut ninputs = 3 * nfeatures;
+            for _ in 0..nlayers {
+                let layer = reader.layer()?;
+                if layer.ninputs != ninputs {
+                    return Err(ModelError::Shape);
+                }
+                ninputs = layer.nnodes;
+            }
+            if reader.at != bytes.len() {
+                return Err(ModelError::TooLong);
+            }
+            Ok(model)
+        }
+
+        /// The feature in the observation at `i`, if there are that many.
+        pub fn feature(&self, i: usize) -> Option<Feature> {
+            match *self {
+                Model::Static { observation, .. } => observation.get(i).cloned(),
+                Model::Blob(bytes) => if i < bytes[5] as usize { Feature::from_code(bytes[6 + i]) } else { None },
+            }
+        }
+
+        /// The number of values the network takes.
+        pub fn input_length(&self) -> usize {
+            match *self {
+                Model::Static { observation, .. } => 3 * observation.len(),
+                Model::Blob(bytes) => 3 * bytes[5] as usize,
+            }
+        }
+
+        /// How much scratch space `forward` needs: room for the outputs of the widest layer, twice.
+        pub fn scratch_length(&self) -> usize {
+            let mut widest = 0;
+            self.for_each_layer(|layer| if layer.nnodes > widest { widest = layer.nnodes });
+            2 * widest
+        }
+
+        /// Runs the network on `input`, putting its outputs in `output`. Needs `scratch_length` values of scratch space.
+        pub fn forward(&self, input: &[f32], scratch: &mut [f32], output: &mut [f32]) -> Result<(), ModelError> {
+            if input.len() != self.input_length() || scratch.len() < self.scratch_length() {
+                return Err(ModelError::BufferSize);
+            }
+
+            // Each layer reads the previous one's outputs from one half of the scratch space and writes to the other
+            let (mut from, mut to) = scratch.split_at_mut(self.scratch_length() / 2);
+            let mut ninputs = input.len();
+            let mut first = true;
+            let mut nlayers = 0;
+            self.for_each_layer(|_| nlayers += 1);
+            let mut i = 0;
+            let mut result = Ok(());
+            self.for_each_layer(|layer| {
+                let inputs: &[f32] = if first { input } else { &from[..ninputs] };
+                let last = i + 1 == nlayers;
+                if last && output.len() != layer.nnodes {
+                    result = Err(ModelError::BufferSize);
+                    return;
+                }
+                {
+                    let outputs: &mut [f32] = if last { &mut output[..] } else { &mut to[..layer.nnodes] };
+                    for j in 0..layer.nnodes {
+                        let mut sum = layer.biases.get(j);
+                        for k in 0..layer.ninputs {
+                            sum += layer.weights.get(j * layer.ninputs + k) * inputs[k];
+                        }
+                        outputs[j] = layer.activation.apply(sum);
+                    }
+                }
+                core::mem::swap(&mut from, &mut to);
+                ninputs = layer.nnodes;
+                first = false;
+                i += 1;
+            });
+            result
+        }
+
+        fn for_each_layer<F: FnMut(&Layer<'a>)>(&self, mut f: F) {
+            match *self {
+                Model::Static { layers, .. } => layers.iter().for_each(f),
+                Model::Blob(bytes) => {
+                    let mut reader = BlobReader { bytes: bytes, at: 6 + bytes[5] as usize };
+                    let nlayers = bytes[reader.at];
+                    reader.at += 1;
+                    for _ in 0..nlayers {
+                        // Checked by from_blob
+                        if let Ok(layer) = reader.layer() {
+                            f(&layer);
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    /// Reads a binary model: after the header, each layer's inputs and nodes (`u16`s), activation, and weight format
+    /// (`frac_bits`, or 0xff for `f32`), then its weights and its (`f32`) biases. All little-endian.
+    struct BlobReader<'a> {
+        bytes: &'a [u8],
+        at: usize,
+    }
+
+    impl<'a> BlobReader<'a> {
+        fn take(&mut self, n: usize) -> Result<&'a [u8], ModelError> {
+            if self.bytes.len() - self.at < n {
+                return Err(ModelError::TooShort);
+            }
+            self.at += n;
+            Ok(&self.bytes[self.at - n..self.at])
+        }
+
+        fn u8(&mut self) -> Result<u8, ModelError> {
+            Ok(self.take(1)?[0])
+        }
+
+        fn u16(&mut self) -> Result<u16, ModelError> {
+            let b = self.take(2)?;
+            Ok(b[0] as u16 | (b[1] as u16) << 8)
+        }
+
+        fn layer(&mut self) -> Result<Layer<'a>, ModelError> {
+            let ninputs = self.u16()? as usize;
+            let nnodes = self.u16()? as usize;
+            let code = self.u8()?;
+            let activation = Activation::from_code(code).ok_or(ModelError::UnknownCode(code))?;
+            let frac_bits = match self.u8()? {
+                FLOAT_WEIGHTS => None,
+                frac_bits if frac_bits < 16 => Some(frac_bits),
+                code => return Err(ModelError::UnknownCode(code)),
+            };
+            let size = if frac_bits.is_some() { 2 } else { 4 };
+            let weights = self.take(ninputs * nnodes * size)?;
+            let biases = self.take(nnodes * 4)?;
+            Ok(Layer {
+                ninputs: ninputs,
+                nnodes: nnodes,
+                activation: activation,
+                weights: Weights::Bytes(weights, frac_bits),
+                biases: Weights::Bytes(biases, None),
+            })
+        }
+    }
+
+    fn abs(x: f32) -> f32 {
+        if x < 0.0 { -x } else { x }
+    }
+
+    /// e^x, to about 1E-7 relative error. There is no `f32::exp` without the standard library.
+    fn exp(x: f32) -> f32 {
+        if x > 88.0 {
+            return core::f32::INFINITY;
+        } else if x < -87.0 {
+            return 0.0;
+        }
+
+        // e^x = 2^n * e^r, with |r| <= ln(2) / 2
+        let k = x * core::f32::consts::LOG2_E;
+        let n = if k >= 0.0 { (k + 0.5) as i32 } else { (k - 0.5) as i32 };
+        let r = x - n as f32 * core::f32::consts::LN_2;
+        let er = 1.0 + r * (1.0 + r * (0.5 + r * (1.0 / 6.0 + r * (1.0 / 24.0 + r * (1.0 / 120.0 + r * (1.0 / 720.0))))));
+        er * f32::from_bits(((n + 127) as u32) << 23)
+    }
+
+    fn tanh(x: f32) -> f32 {
+        if abs(x) < 1E-3 {
+            // Avoids the cancellation in the formula below
+            x - x * x * x / 3.0
+        } else if x > 9.0 {
+            1.0
+        } else if x < -9.0 {
+            -1.0
+        } else {
+            let e = exp(2.0 * x);
+            (e - 1.0) / (e + 1.0)
+        }
+    }
+}
@@ -18,3 +18,9 @@ impl ServoId {
         }
     }
 }
+
+/// Moves the base, shoulder, and elbow to `angles`, in degrees. Until the servos can be read back, these are taken
+/// to be where the arm is.
+pub fn move_to(_angles: [f32; 3]) {
+    // TODO: Drive the servos' PWM once they are wired up
+}
@@ -0,0 +1,181 @@
+/// Module for driving the arm from other programs through a typed API rather than the text protocol.
+pub mod arm {
+    use commands;
+    use errors;
+    use errors::TeleopError;
+    use serial::comms::comms;
+    use serial::monitor::monitor;
+    use serial::port::portcomms;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time;
+
+    /// How long to wait for the device to acknowledge a command, unless told otherwise.
+    const DEFAULT_REPLY_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+    /// A connection to the arm. Each method sends its commands through the same serial thread the
+    /// `teleop` binary uses, then waits for the device to acknowledge them, so that every command
+    /// either succeeds or comes back with its own error.
+    ///
+    /// The connection is closed (after the serial thread has sent everything) by `close`, or when the client is dropped.
+    pub struct ArmClient {
+        tx: mpsc::Sender<commands::Command>,
+        /// Listens to the device, and the lines it hears. None if the port could not be shared with a monitor, in which
+        /// case commands aren't acknowledged.
+        device: Option<monitor::DeviceMonitor>,
+        replies: Option<mpsc::Receiver<monitor::DeviceLine>>,
+        commthread: Option<thread::JoinHandle<Result<(), TeleopError>>>,
+        timeout: time::Duration,
+    }
+
+    impl ArmClient {
+        /// Opens the serial port to the arm and starts talking to it. `port` works the same as on the
+        /// command line: a device path, 'test' for the test port, or None to find the device by its USB IDs.
+        pub fn connect(port: Option<&str>) -> Result<ArmClient, TeleopError> {
+            let port = match portcomms::get_serial_port(port.map(|p| p.to_string())) {
+                Some(port) => port,
+                None => return Err(TeleopError::NoPort),
+            };
+
+            let device = match port.try_clone() {
+                Ok(listenport) => Some(monitor::DeviceMonitor::start(listenport, None, false).map_err(|e| TeleopError::Io(String::new(), e))?),
+                Err(e) => {
+                    println!("Could not listen to the device, so commands won't be acknowledged: {:?}", e);
+                    None
+                },
+            };
+            let replies = device.as_ref().map(|device| device.subscribe());
+
+            let (tx, rx) = mpsc::channel();
+            let commthread = thread::spawn(move || comms::communicate_with_device(port, rx, None));
+            Ok(ArmClient { tx: tx, device: device, replies: replies, commthread: Some(commthread), timeout: DEFAULT_REPLY_TIMEOUT })
+        }
+
+        /// Changes how long to wait for the device to acknowledge each command.
+        pub fn set_timeout(&mut self, timeout: time::Duration) {
+            self.timeout = timeout;
+        }
+
+        /// Moves a single servo to `angle` (0 to 180 degrees).
+        pub fn move_joint(&mut self, id: commands::ServoId, angle: u16) -> Result<(), TeleopError> {
+            if angle > 180 {
+                return Err(TeleopError::Rejected(commands::Command::Servo(id, angle).to_string(), "Angle must be in range [0, 180]".to_string()));
+            }
+            self.send(commands::Command::Servo(id, angle)).map(|_| ())
+        }
+
+        /// Moves every servo, one at a time in ID order. `angles` is indexed by servo ID.
+        pub fn move_all(&mut self, angles: [u16; 5]) -> Result<(), TeleopError> {
+            for id in commands::ALL_SERVOS.iter() {
+                self.move_joint(*id, angles[*id as usize])?;
+            }
+            Ok(())
+        }
+
+        /// Sends every servo to its home angle.
+        pub fn home(&mut self) -> Result<(), TeleopError> {
+            self.send(commands::Command::Home).map(|_| ())
+        }
+
+        pub fn led(&mut self, on: bool) -> Result<(), TeleopError> {
+            self.send(commands::Command::Led(on)).map(|_| ())
+        }
+
+        /// Asks the device where its servos are. Returns their angles, indexed by servo ID.
+        pub fn status(&mut self) -> Result<[u16; 5], TeleopError> {
+            if self.replies.is_none() {
+                return Err(TeleopError::NoReply(commands::Command::Status.to_string()));
+            }
+            let telemetry = self.send(commands::Command::Status)?;
+            match telemetry.iter().filter_map(|line| monitor::parse_status(line)).next() {
+                Some(angles) => Ok(angles),
+                None => Err(TeleopError::Rejected(commands::Command::Status.to_string(), "Acknowledged without reporting a status".to_string())),
+            }
+        }
+
+        /// Lets the serial thread finish sending everything, then stops listening to the device and closes the port.
+        pub fn close(mut self) -> Result<(), TeleopError> {
+            self.shutdown()
+        }
+
+        /// Sends the command and waits for the device to acknowledge it. Returns the telemetry it sent along the way.
+        fn send(&mut self, cmd: commands::Command) -> Result<Vec<String>, TeleopError> {
+            if let Some(ref replies) = self.replies {
+                while let Ok(_) = replies.try_recv() {}
+            }
+            if self.tx.send(cmd.clone()).is_err() {
+                return Err(self.serial_thread_error());
+            }
+
+            let replies = match self.replies {
+                Some(ref replies) => replies,
+                None => return Ok(Vec::new()),
+            };
+            match monitor::await_reply(replies, self.timeout) {
+                monitor::Reply::Ack(telemetry) => Ok(telemetry),
+                monitor::Reply::Rejected(text) => Err(TeleopError::Rejected(cmd.to_string(), text)),
+                monitor::Reply::TimedOut | monitor::Reply::Disconnected => Err(TeleopError::NoReply(cmd.to_string())),
+            }
+        }
+
+        /// Works out why the serial thread stopped listening.
+        fn serial_thread_error(&mut self) -> TeleopError {
+            match self.commthread.take().map(errors::join) {
+                Some(Err(e)) => e,
+                _ => TeleopError::ChannelClosed,
+            }
+        }
+
+        fn shutdown(&mut self) -> Result<(), TeleopError> {
+            let result = match self.commthread.take() {
+                Some(commthread) => {
+                    self.tx.send(commands::Command::Quit).ok();
+                    errors::join(commthread)
+                },
+                None => Ok(()),
+            };
+            // The monitor has the other handle on the port, so the port is only closed once it has stopped too
+            if let Some(device) = self.device.take() {
+                device.stop();
+            }
+            result
+        }
+    }
+
+    impl Drop for ArmClient {
+        fn drop(&mut self) {
+            if let Err(e) = self.shutdown() {
+                println!("Problem closing the connection to the arm: {}", e);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_client_against_test_port() {
+            let mut arm = ArmClient::connect(Some("test")).unwrap();
+            arm.move_joint(commands::ServoId::Elbow, 120).unwrap();
+            arm.led(true).unwrap();
+            assert_eq!(arm.status().unwrap(), [90, 10, 120, 90, 90]);
+            arm.move_all([0, 20, 40, 60, 80]).unwrap();
+            assert_eq!(arm.status().unwrap(), [0, 20, 40, 60, 80]);
+            arm.home().unwrap();
+            assert_eq!(arm.status().unwrap(), [90, 10, 155, 90, 90]);
+            assert!(arm.move_joint(commands::ServoId::Base, 181).is_err());
+            arm.close().unwrap();
+        }
+
+        #[test]
+        fn test_reconnect_after_close() {
+            for _ in 0..2 {
+                let mut arm = ArmClient::connect(Some("test")).unwrap();
+                arm.move_joint(commands::ServoId::Base, 45).unwrap();
+                assert_eq!(arm.status().unwrap(), [45, 10, 155, 90, 90]);
+                arm.close().unwrap();
+            }
+        }
+    }
+}
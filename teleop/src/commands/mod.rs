@@ -1,6 +1,9 @@
+use std::fmt;
 use std::path;
 
-#[derive(Clone, Copy, Debug)]
+pub mod poses;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ServoId {
     Base,
     Shoulder,
@@ -20,8 +23,22 @@ impl ServoId {
             _ => None,
         }
     }
+
+    /// Returns the angle the device sends this servo to on a home command.
+    pub fn home_angle(&self) -> u16 {
+        match self {
+            ServoId::Base => 90,
+            ServoId::Shoulder => 10,
+            ServoId::Elbow => 155,
+            ServoId::Wrist => 90,
+            ServoId::Hand => 90,
+        }
+    }
 }
 
+/// All the servos on the arm, in ID order.
+pub const ALL_SERVOS: [ServoId; 5] = [ServoId::Base, ServoId::Shoulder, ServoId::Elbow, ServoId::Wrist, ServoId::Hand];
+
 /// Prints the help message to the console
 pub fn print_help() {
     println!("Help: Prints this help message");
@@ -30,9 +47,10 @@ pub fn print_help() {
     println!("Servo: <id> <angle - 0 to 180>");
     println!("Script: <path to script>");
     println!("Home: Sends all servos to default locations");
+    println!("Status: Asks the device where its servos are");
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Command {
     Help,
     Quit,
@@ -40,6 +58,7 @@ pub enum Command {
     Servo(ServoId, u16),    // ServoID, angle
     Script(String),         // fpath
     Home,
+    Status,
 }
 
 impl Command {
@@ -61,6 +80,7 @@ impl Command {
             "servo" => Command::servo_from_string(line),
             "script" => Command::script_from_string(line),
             "home" => Ok(Command::Home),
+            "status" => Ok(Command::Status),
             _ => Err("Malformed command"),
         }
     }
@@ -173,4 +193,20 @@ impl Command {
 
         Ok(Command::Script(fpath.to_str().unwrap().to_string()))
     }
-}
\ No newline at end of file
+}
+
+/// Formats the command in the same text protocol that `Command::new_from_string` parses,
+/// so that a displayed command can always be read back in (from a script or a session log).
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Help => write!(f, "help"),
+            Command::Quit => write!(f, "quit"),
+            Command::Led(on) => write!(f, "led {}", if *on { "on" } else { "off" }),
+            Command::Servo(id, angle) => write!(f, "servo {} {}", *id as u8, angle),
+            Command::Script(fpath) => write!(f, "script {}", fpath),
+            Command::Home => write!(f, "home"),
+            Command::Status => write!(f, "status"),
+        }
+    }
+}
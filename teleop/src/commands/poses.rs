@@ -0,0 +1,104 @@
+/// Module for named poses: an angle for every servo, which the arm can be sent to in one go.
+///
+/// Poses are kept in a plain text file with one pose per line, giving its name and then the angle
+/// of each servo in ID order:
+///
+/// ```text
+/// # name base shoulder elbow wrist hand
+/// reach 90 60 90 90 90
+/// ```
+///
+/// 'home' is always available, and is where the device's home command sends the arm.
+pub mod poses {
+    use commands;
+    use std::fs;
+    use std::io;
+    use std::io::BufRead;
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Pose {
+        pub name: String,
+        /// The angle of each servo, indexed by servo ID.
+        pub angles: [u16; 5],
+    }
+
+    impl Pose {
+        /// The commands that move the arm into this pose, one servo at a time in ID order.
+        pub fn commands(&self) -> Vec<commands::Command> {
+            commands::ALL_SERVOS.iter().map(|id| commands::Command::Servo(*id, self.angles[*id as usize])).collect()
+        }
+    }
+
+    /// The pose the home command puts the arm in.
+    pub fn home() -> Pose {
+        let mut angles = [0; 5];
+        for id in commands::ALL_SERVOS.iter() {
+            angles[*id as usize] = id.home_angle();
+        }
+        Pose { name: "home".to_string(), angles: angles }
+    }
+
+    /// Reads the poses in the file at `fpath`. The home pose comes first, unless the file has its own.
+    pub fn load_poses(fpath: &str) -> Result<Vec<Pose>, String> {
+        let file = match fs::File::open(fpath) {
+            Ok(file) => file,
+            Err(e) => return Err(format!("Could not open {}. Error: {:?}", fpath, e)),
+        };
+
+        let mut poses = vec![home()];
+        for (lineno, line) in io::BufReader::new(file).lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Err(format!("Couldn't read line {}: {:?}", lineno, e)),
+            };
+
+            match parse_pose(&line) {
+                Ok(Some(pose)) => {
+                    poses.retain(|p| p.name != pose.name);
+                    poses.push(pose);
+                },
+                Ok(None) => (),
+                Err(msg) => return Err(format!("Problem with poses at line {}: {}", lineno, msg)),
+            }
+        }
+        Ok(poses)
+    }
+
+    /// Parses a single line of a poses file. Returns None for blank lines and comments.
+    fn parse_pose(line: &str) -> Result<Option<Pose>, String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.len() != 6 {
+            return Err("USAGE for a pose: <name> <base> <shoulder> <elbow> <wrist> <hand>".to_string());
+        }
+
+        let mut angles = [0; 5];
+        for (angle, token) in angles.iter_mut().zip(tokens[1..].iter()) {
+            *angle = match token.parse::<u16>() {
+                Ok(val) if val <= 180 => val,
+                _ => return Err(format!("Angle {} is not a whole number in range [0, 180]", token)),
+            };
+        }
+        Ok(Some(Pose { name: tokens[0].to_string(), angles: angles }))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_parse_pose() {
+            let pose = parse_pose("reach 90 60 90 90 45").unwrap().unwrap();
+            assert_eq!(pose, Pose { name: "reach".to_string(), angles: [90, 60, 90, 90, 45] });
+            assert_eq!(pose.commands()[4], commands::Command::Servo(commands::ServoId::Hand, 45));
+
+            assert_eq!(parse_pose("# name base shoulder elbow wrist hand").unwrap(), None);
+            assert!(parse_pose("reach 90 60 90 90").is_err());
+            assert!(parse_pose("reach 90 60 90 90 181").is_err());
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::any::Any;
+use std::fmt;
+use std::io;
+use std::thread;
+
+/* Exit codes. Other programs (such as experiment) use these to work out why teleop stopped. */
+/// Everything went fine.
+pub const EXIT_OK: i32 = 0;
+/// Could not find or open a serial port to the device.
+pub const EXIT_NO_PORT: i32 = 1;
+/// A script or session log could not be read, parsed, or run.
+pub const EXIT_SCRIPT: i32 = 2;
+/// The device stopped accepting commands partway through.
+pub const EXIT_DEVICE: i32 = 3;
+/// One of our own threads fell over.
+pub const EXIT_INTERNAL: i32 = 4;
+/// Could not read or write a file of ours, such as a session log.
+pub const EXIT_IO: i32 = 5;
+/// Could not listen for network clients.
+pub const EXIT_NETWORK: i32 = 6;
+/// The command line did not make sense.
+pub const EXIT_USAGE: i32 = 64;
+/// The user pressed Ctrl-C. The arm was sent home before we stopped.
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// Everything that can make teleop stop early.
+#[derive(Debug)]
+pub enum TeleopError {
+    /// Could not find or open a serial port to the device.
+    NoPort,
+    /// The command line did not make sense.
+    Usage(String),
+    /// A script or session log could not be read, parsed, or run.
+    Script(String),
+    /// Writing a command to the device failed.
+    Device(io::Error),
+    /// The device said it could not do the command (given in the text protocol), and why.
+    Rejected(String, String),
+    /// The device did not acknowledge the command in time.
+    NoReply(String),
+    /// The channel between two of our threads closed while one of them still needed it.
+    ChannelClosed,
+    /// One of our threads panicked.
+    ThreadPanicked(String),
+    /// Could not read or write one of our own files.
+    Io(String, io::Error),
+    /// Could not listen on the given address.
+    Network(String, io::Error),
+    /// The user pressed Ctrl-C.
+    Interrupted,
+}
+
+impl TeleopError {
+    /// The code the program should exit with because of this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            TeleopError::NoPort => EXIT_NO_PORT,
+            TeleopError::Usage(_) => EXIT_USAGE,
+            TeleopError::Script(_) => EXIT_SCRIPT,
+            TeleopError::Device(_) | TeleopError::Rejected(_, _) | TeleopError::NoReply(_) => EXIT_DEVICE,
+            TeleopError::ChannelClosed | TeleopError::ThreadPanicked(_) => EXIT_INTERNAL,
+            TeleopError::Io(_, _) => EXIT_IO,
+            TeleopError::Network(_, _) => EXIT_NETWORK,
+            TeleopError::Interrupted => EXIT_INTERRUPTED,
+        }
+    }
+}
+
+impl fmt::Display for TeleopError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TeleopError::NoPort => write!(f, "Could not find a serial port with the appropriate device."),
+            TeleopError::Usage(msg) => write!(f, "{}", msg),
+            TeleopError::Script(msg) => write!(f, "Problem running script:\n{}", msg),
+            TeleopError::Device(e) => write!(f, "Could not write to the device: {:?}", e),
+            TeleopError::Rejected(cmd, reply) => write!(f, "Device rejected '{}': {}", cmd, reply),
+            TeleopError::NoReply(cmd) => write!(f, "Device did not acknowledge '{}' in time.", cmd),
+            TeleopError::ChannelClosed => write!(f, "The serial thread stopped listening before we were done with it."),
+            TeleopError::ThreadPanicked(msg) => write!(f, "A thread panicked: {}", msg),
+            TeleopError::Io(fpath, e) => write!(f, "Problem with file {}: {:?}", fpath, e),
+            TeleopError::Network(addr, e) => write!(f, "Could not listen on {}: {:?}", addr, e),
+            TeleopError::Interrupted => write!(f, "Interrupted. Sent the arm home and closed the port."),
+        }
+    }
+}
+
+/// Joins the thread, turning a panic into an error.
+pub fn join<T>(handle: thread::JoinHandle<Result<T, TeleopError>>) -> Result<T, TeleopError> {
+    match handle.join() {
+        Ok(result) => result,
+        Err(payload) => Err(TeleopError::ThreadPanicked(panic_message(payload))),
+    }
+}
+
+/// Pulls the message out of a panic, if it has one we can read.
+fn panic_message(payload: Box<Any + Send>) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
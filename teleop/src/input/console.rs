@@ -0,0 +1,65 @@
+/// Module for sharing the console between the thread that reads commands from it and
+/// anything else that needs to ask the user a question, such as the serial thread after a reconnect.
+pub mod console {
+    use std::io;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc;
+
+    /// A handle on stdin. If a thread is reading commands from the console, the next line the user
+    /// types after a question is asked goes to whoever asked it instead of being parsed as a command.
+    #[derive(Clone)]
+    pub struct Console {
+        /// Where the next line goes, if someone is waiting on an answer.
+        answer: Arc<Mutex<Option<mpsc::Sender<String>>>>,
+        /// Whether a thread is reading commands from the console.
+        reading: Arc<AtomicBool>,
+    }
+
+    impl Console {
+        pub fn new() -> Self {
+            Console {
+                answer: Arc::new(Mutex::new(None)),
+                reading: Arc::new(AtomicBool::new(false)),
+            }
+        }
+
+        /// Reads the next line meant for the command reader into `buf`, passing along any answers on the way.
+        /// Returns the number of bytes read, which is 0 once stdin closes.
+        pub fn read_command_line(&self, buf: &mut String) -> io::Result<usize> {
+            self.reading.store(true, Ordering::SeqCst);
+            loop {
+                buf.clear();
+                let nbytes = io::stdin().read_line(buf)?;
+                match self.answer.lock().unwrap().take() {
+                    Some(ref tx) if nbytes > 0 => { tx.send(buf.trim().to_string()).ok(); },
+                    _ => return Ok(nbytes),
+                }
+            }
+        }
+
+        /// Prints the question and returns the line the user typed in response (without its line ending).
+        /// Returns an empty string if stdin is closed.
+        pub fn ask(&self, question: &str) -> String {
+            print!("{} ", question);
+            io::stdout().flush().ok();
+
+            if !self.reading.load(Ordering::SeqCst) {
+                let mut answer = String::new();
+                io::stdin().read_line(&mut answer).ok();
+                return answer.trim().to_string();
+            }
+
+            let (tx, rx) = mpsc::channel();
+            *self.answer.lock().unwrap() = Some(tx);
+            rx.recv().unwrap_or_default()
+        }
+
+        /// Asks a yes or no question. Anything other than yes counts as no.
+        pub fn confirm(&self, question: &str) -> bool {
+            let answer = self.ask(&format!("{} [y/N]", question)).to_ascii_lowercase();
+            answer == "y" || answer == "yes"
+        }
+    }
+}
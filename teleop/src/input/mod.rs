@@ -1 +1,3 @@
+pub mod console;
+pub mod pacing;
 pub mod user_input;
\ No newline at end of file
@@ -0,0 +1,199 @@
+/// Module for deciding how long to wait between the commands of a script.
+///
+/// The command parser on the device is not meant to take in commands faster than they can be typed,
+/// so a script needs to give the device some time after each command. How much time is decided by
+/// the pacing strategy, which can be given on the command line or changed partway through a script
+/// with a `pace` line:
+///
+/// ```text
+/// pace fixed 500          # wait 500 ms after every command
+/// pace ack                # wait until the device says it is done with the command
+/// pace ack 3000           # same, but give up waiting after 3000 ms
+/// pace estimate 90        # wait as long as the move should take at 90 degrees per second
+/// pace estimate 90 200    # same, but with 200 ms of settling time after each move
+/// ```
+pub mod pacing {
+    use commands;
+    use serial::monitor::monitor;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time;
+
+    /// How long to wait after sending a command before sending the next one.
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum Pacing {
+        /// Always wait the same amount of time.
+        Fixed(time::Duration),
+        /// Wait for the device to acknowledge the command, but no longer than the given timeout.
+        Ack(time::Duration),
+        /// Wait for as long as the commanded move should take, based on how fast the joints turn.
+        Estimated { degrees_per_second: f64, settle: time::Duration },
+    }
+
+    impl Default for Pacing {
+        fn default() -> Self {
+            Pacing::Fixed(time::Duration::from_millis(1500))
+        }
+    }
+
+    const DEFAULT_ACK_TIMEOUT_MS: u64 = 5000;
+    const DEFAULT_SETTLE_MS: u64 = 100;
+
+    impl Pacing {
+        /// Returns a new Pacing from a string of the form `<fixed/ack/estimate> [args...]`,
+        /// optionally starting with 'pace'. Colons are accepted in place of spaces, so that
+        /// the command line can take `--pace fixed:500`.
+        pub fn new_from_string(line: &str) -> Result<Pacing, &'static str> {
+            let line = line.replace(':', " ");
+            let mut tokens: Vec<&str> = line.trim().split_whitespace().collect();
+            if tokens.len() > 0 && tokens[0].to_ascii_lowercase() == "pace" {
+                tokens.remove(0);
+            }
+            if tokens.is_empty() {
+                return Err("USAGE for pace: pace <fixed/ack/estimate> [args...]");
+            }
+
+            let numbers: Vec<Option<f64>> = tokens[1..].iter().map(|t| t.parse::<f64>().ok().filter(|x| *x >= 0.0)).collect();
+            if numbers.iter().any(|n| n.is_none()) {
+                return Err("Pacing arguments must be non-negative numbers");
+            }
+            let numbers: Vec<f64> = numbers.into_iter().map(|n| n.unwrap()).collect();
+
+            match (tokens[0].to_ascii_lowercase().as_str(), numbers.len()) {
+                ("fixed", 1) => Ok(Pacing::Fixed(millis(numbers[0]))),
+                ("fixed", _) => Err("USAGE for fixed pacing: pace fixed <milliseconds>"),
+                ("ack", 0) => Ok(Pacing::Ack(time::Duration::from_millis(DEFAULT_ACK_TIMEOUT_MS))),
+                ("ack", 1) => Ok(Pacing::Ack(millis(numbers[0]))),
+                ("ack", _) => Err("USAGE for ack pacing: pace ack [timeout milliseconds]"),
+                ("estimate", 1) | ("estimate", 2) if numbers[0] > 0.0 => {
+                    let settle = numbers.get(1).map(|ms| millis(*ms)).unwrap_or(time::Duration::from_millis(DEFAULT_SETTLE_MS));
+                    Ok(Pacing::Estimated { degrees_per_second: numbers[0], settle: settle })
+                },
+                ("estimate", _) => Err("USAGE for estimated pacing: pace estimate <degrees per second - above 0> [settle milliseconds]"),
+                _ => Err("Pacing must be one of 'fixed', 'ack', or 'estimate'"),
+            }
+        }
+    }
+
+    fn millis(ms: f64) -> time::Duration {
+        time::Duration::from_millis(ms.round() as u64)
+    }
+
+    /// Keeps whatever state the pacing strategies need between commands and does the actual waiting.
+    pub struct Pacer {
+        /// The strategy currently in use.
+        pub pacing: Pacing,
+        /// Where we think each servo is, indexed by servo ID. None if we haven't commanded it yet.
+        angles: [Option<u16>; 5],
+        /// Lines from the device, if anything is listening to it.
+        device: Option<mpsc::Receiver<monitor::DeviceLine>>,
+    }
+
+    impl Pacer {
+        pub fn new(pacing: Pacing, device: Option<mpsc::Receiver<monitor::DeviceLine>>) -> Self {
+            Pacer {
+                pacing: pacing,
+                angles: [None; 5],
+                device: device,
+            }
+        }
+
+        /// Throws away anything the device said about commands we were not waiting on,
+        /// so that it is not mistaken for the reply to the next command.
+        pub fn discard_replies(&self) {
+            if let Some(ref device) = self.device {
+                while let Ok(_) = device.try_recv() {}
+            }
+        }
+
+        /// Blocks for as long as the current pacing strategy says we should after sending `cmd`.
+        pub fn wait_after(&mut self, cmd: &commands::Command) {
+            let travel = self.track(cmd);
+            match self.pacing {
+                Pacing::Fixed(delay) => thread::sleep(delay),
+                Pacing::Ack(timeout) => match self.device {
+                    Some(ref device) => wait_for_reply(device, timeout, cmd),
+                    None => {
+                        println!("Nothing is listening for acknowledgements from the device. Waiting {:?} instead.", timeout);
+                        thread::sleep(timeout);
+                    },
+                },
+                Pacing::Estimated { degrees_per_second, settle } => {
+                    thread::sleep(millis(1000.0 * travel / degrees_per_second) + settle);
+                },
+            }
+        }
+
+        /// Updates where we think the servos are after `cmd` and returns how far the servo that moved
+        /// the most has to travel, in degrees. Servos we don't know the position of are assumed to
+        /// need the whole range.
+        fn track(&mut self, cmd: &commands::Command) -> f64 {
+            let mut moves = Vec::new();
+            match cmd {
+                commands::Command::Servo(id, angle) => moves.push((*id, *angle)),
+                commands::Command::Home => {
+                    for id in commands::ALL_SERVOS.iter() {
+                        moves.push((*id, id.home_angle()));
+                    }
+                },
+                _ => (),
+            }
+
+            let mut travel: f64 = 0.0;
+            for (id, angle) in moves {
+                let distance = match self.angles[id as usize] {
+                    Some(current) => (current as f64 - angle as f64).abs(),
+                    None => 180.0,
+                };
+                travel = travel.max(distance);
+                self.angles[id as usize] = Some(angle);
+            }
+            travel
+        }
+    }
+
+    /// Waits until the device either acknowledges or rejects a command, or until `timeout` is up.
+    /// Either way, the device is done with the command, so we just let the user know if it failed.
+    fn wait_for_reply(device: &mpsc::Receiver<monitor::DeviceLine>, timeout: time::Duration, cmd: &commands::Command) {
+        match monitor::await_reply(device, timeout) {
+            monitor::Reply::Ack(_) => (),
+            monitor::Reply::Rejected(text) => println!("Device rejected {:?}: {}", cmd, text),
+            monitor::Reply::TimedOut => println!("Device did not acknowledge {:?} within {:?}. Moving on.", cmd, timeout),
+            monitor::Reply::Disconnected => println!("Lost the connection to the device while waiting on {:?}.", cmd),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_pacing_from_string() {
+            assert_eq!(Pacing::new_from_string("pace fixed 500"), Ok(Pacing::Fixed(time::Duration::from_millis(500))));
+            assert_eq!(Pacing::new_from_string("fixed:250"), Ok(Pacing::Fixed(time::Duration::from_millis(250))));
+            assert_eq!(Pacing::new_from_string("pace ack"), Ok(Pacing::Ack(time::Duration::from_millis(DEFAULT_ACK_TIMEOUT_MS))));
+            assert_eq!(Pacing::new_from_string("ACK 3000"), Ok(Pacing::Ack(time::Duration::from_millis(3000))));
+            assert_eq!(Pacing::new_from_string("pace estimate 90 200"),
+                       Ok(Pacing::Estimated { degrees_per_second: 90.0, settle: time::Duration::from_millis(200) }));
+            assert!(Pacing::new_from_string("pace").is_err());
+            assert!(Pacing::new_from_string("pace fixed").is_err());
+            assert!(Pacing::new_from_string("pace fixed -5").is_err());
+            assert!(Pacing::new_from_string("pace estimate 0").is_err());
+            assert!(Pacing::new_from_string("pace sometimes").is_err());
+        }
+
+        #[test]
+        fn test_estimate_tracks_servo_positions() {
+            let mut pacer = Pacer::new(Pacing::default(), None);
+
+            // Unknown positions need the full range, known ones only the difference
+            assert_eq!(pacer.track(&commands::Command::Servo(commands::ServoId::Base, 90)), 180.0);
+            assert_eq!(pacer.track(&commands::Command::Servo(commands::ServoId::Base, 120)), 30.0);
+            assert_eq!(pacer.track(&commands::Command::Led(true)), 0.0);
+
+            // Home moves every servo, so the slowest one decides
+            assert_eq!(pacer.track(&commands::Command::Home), 180.0);
+            assert_eq!(pacer.track(&commands::Command::Servo(commands::ServoId::Elbow, 100)), 55.0);
+        }
+    }
+}
@@ -1,21 +1,27 @@
 pub mod user_input {
     use commands;
+    use errors::TeleopError;
+    use input::console::console;
+    use input::pacing::pacing;
     use std::fs;
-    use std::io;
-    use std::io::BufRead;
+    use std::io::Read;
     use std::sync::mpsc;
-    use std::thread;
-    use std::time;
 
-    /// Reads lines from the user until the quit command is given.
+    /// Reads lines from the user until the quit command is given (or stdin closes, which counts as quit).
     /// Attempts to parse the line into a valid command. If it fails,
     /// will pipe something useful to the user over stdout. If succeeds,
-    /// gives the resultant command to the serial channel.
-    pub fn read_from_user_until_quit(tx: mpsc::Sender<commands::Command>) {
+    /// gives the resultant command to the serial channel. Scripts run from the console
+    /// are paced by `pacer`. Returns an error if the serial thread stops listening.
+    /// Lines that answer a question asked through `console` are not treated as commands.
+    pub fn read_from_user_until_quit(tx: mpsc::Sender<commands::Command>, mut pacer: pacing::Pacer, console: console::Console) -> Result<(), TeleopError> {
         let mut should_quit = false;
         while !should_quit {
             let mut input = String::new();
-            let parsed = match io::stdin().read_line(&mut input) {
+            let parsed = match console.read_command_line(&mut input) {
+                Ok(0) => {
+                    println!("Input closed. Quitting.");
+                    Ok(commands::Command::Quit)
+                },
                 Ok(_nbytes) => {
                     commands::Command::new_from_string(&input)
                 },
@@ -26,65 +32,102 @@ pub mod user_input {
             };
 
             match parsed {
-                Ok(cmd) => { should_quit = execute_command(cmd, &tx); },
+                Ok(cmd) => { should_quit = execute_command(cmd, &tx, &mut pacer)?; },
                 Err(msg) => println!("Error parsing input: {}", msg),
             }
         }
+        Ok(())
     }
 
     /// Executes the command, returning true if the command is 'quit'.
-    fn execute_command(cmd: commands::Command, tx: &mpsc::Sender<commands::Command>) -> bool {
+    /// Problems with a script are reported to the user rather than returned, since the user can just try again.
+    fn execute_command(cmd: commands::Command, tx: &mpsc::Sender<commands::Command>, pacer: &mut pacing::Pacer) -> Result<bool, TeleopError> {
         match cmd {
             commands::Command::Help => {
                 commands::print_help();
-                false
+                Ok(false)
             },
             commands::Command::Quit => {
-                tx.send(cmd).expect("Couldn't send the message to the Serial thread.");
-                true
+                tx.send(cmd).map_err(|_| TeleopError::ChannelClosed)?;
+                Ok(true)
             },
             commands::Command::Script(fpath) => {
-                if let Err(msg) = run_script(tx, &fpath) {
-                    println!("Problem running script:\n{}", msg);
+                match run_script(tx, &fpath, pacer) {
+                    Err(TeleopError::Script(msg)) => println!("Problem running script:\n{}", msg),
+                    Err(e) => return Err(e),
+                    Ok(_) => (),
                 }
-                false
+                Ok(false)
             },
             _ => {
                 println!("Sending command {:?}", cmd);
-                tx.send(cmd).expect("Couldn't send the message to the Serial thread.");
-                false
+                tx.send(cmd).map_err(|_| TeleopError::ChannelClosed)?;
+                Ok(false)
             }
         }
     }
 
+    /// A line of a script: either a command or a change of pacing for the rest of the script.
+    enum ScriptLine {
+        Command(commands::Command),
+        Pace(pacing::Pacing),
+    }
+
     /// Opens the given file, reads its contents, then executes each line as if it were
     /// a command entered into the console. Does not accept Quit commands or other script commands.
-    pub fn run_script(tx: &mpsc::Sender<commands::Command>, fpath: &str) -> Result<(), String> {
-        let mut cmds = Vec::new();
+    ///
+    /// Lines starting with 'pace' change how long we wait between the commands that follow
+    /// (see the `pacing` module). The pacer's strategy is restored once the script is done.
+    pub fn run_script(tx: &mpsc::Sender<commands::Command>, fpath: &str, pacer: &mut pacing::Pacer) -> Result<(), TeleopError> {
+        let mut text = String::new();
         match fs::File::open(fpath) {
-            Ok(file) => {
-                for (lineno, line) in io::BufReader::new(file).lines().enumerate() {
-                    // try to convert into a cmd
-                    match commands::Command::new_from_string(&line.expect(&format!("Couldn't read line {}", lineno))) {
-                        Ok(cmd) => cmds.push(cmd),
-                        Err(msg) => {
-                            return Err(format!("Problem with script at line {}: {}", lineno, msg));
-                        },
-                    }
-                }
-            },
-            Err(e) => {
-                println!("Could not open {}. Error: {:?}", fpath, e);
+            Ok(mut file) => if let Err(e) = file.read_to_string(&mut text) {
+                return Err(TeleopError::Script(format!("Couldn't read {}: {:?}", fpath, e)));
             },
+            Err(e) => return Err(TeleopError::Script(format!("Could not open {}. Error: {:?}", fpath, e))),
+        };
+        run_script_text(tx, &text, pacer)
+    }
+
+    /// Same as `run_script`, but for a script that is already in memory.
+    pub fn run_script_text(tx: &mpsc::Sender<commands::Command>, text: &str, pacer: &mut pacing::Pacer) -> Result<(), TeleopError> {
+        let mut lines = Vec::new();
+        for (lineno, line) in text.lines().enumerate() {
+            // Pacing directives are not commands, so they are handled before the command parser sees them
+            let parsed = if line.trim().to_ascii_lowercase().starts_with("pace") {
+                pacing::Pacing::new_from_string(line).map(ScriptLine::Pace)
+            } else {
+                match commands::Command::new_from_string(line) {
+                    Ok(commands::Command::Quit) => Err("Scripts may not quit. The program quits once the script is done."),
+                    Ok(commands::Command::Script(_)) => Err("Scripts may not run other scripts"),
+                    other => other.map(ScriptLine::Command),
+                }
+            };
+
+            match parsed {
+                Ok(scriptline) => lines.push(scriptline),
+                Err(msg) => {
+                    return Err(TeleopError::Script(format!("Problem with script at line {}: {}", lineno, msg)));
+                },
+            }
         }
 
-        // Try to execute each command. Note that we need to wait a moment between commands
-        // since the command parser on the Arduino is not meant to take in commands faster than
-        // they can be typed
-        for c in cmds {
-            execute_command(c, tx);
-            thread::sleep(time::Duration::from_millis(1500));
+        // Try to execute each command, waiting between them according to the pacing strategy
+        let original_pacing = pacer.pacing.clone();
+        for line in lines {
+            match line {
+                ScriptLine::Pace(pacing) => pacer.pacing = pacing,
+                ScriptLine::Command(c) => {
+                    pacer.discard_replies();
+                    if let Err(e) = execute_command(c.clone(), tx, pacer) {
+                        pacer.pacing = original_pacing;
+                        return Err(e);
+                    }
+                    pacer.wait_after(&c);
+                },
+            }
         }
+        pacer.pacing = original_pacing;
 
         Ok(())
     }
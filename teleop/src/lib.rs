@@ -0,0 +1,34 @@
+//! Teleoperation of the robot arm over its serial link.
+//!
+//! The `teleop` binary is built on this library, and other programs can use it to drive the arm
+//! directly. `ArmClient` is the easiest way in:
+//!
+//! ```no_run
+//! extern crate teleop;
+//!
+//! use teleop::ArmClient;
+//! use teleop::commands::ServoId;
+//!
+//! let mut arm = ArmClient::connect(Some("test")).unwrap();
+//! arm.move_joint(ServoId::Elbow, 120).unwrap();
+//! println!("Servos are at {:?}", arm.status().unwrap());
+//! arm.close().unwrap();
+//! ```
+extern crate base64;
+#[macro_use]
+extern crate serde_json;
+extern crate serialport;
+extern crate sha1;
+
+pub mod client;
+pub mod commands;
+pub mod errors;
+pub mod input;
+pub mod remote;
+pub mod serial;
+pub mod session;
+
+pub use self::client::arm::arm::ArmClient;
+
+// The port module finds the test port at the crate root
+use self::serial::testport;
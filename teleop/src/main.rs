@@ -1,74 +1,355 @@
+extern crate ctrlc;
 extern crate serialport;
+extern crate teleop;
 
-mod commands;
-
-mod input;
-use self::input::user_input::user_input;
-
-mod serial;
-use self::serial::comms::comms;
-use self::serial::port::portcomms;
-use self::serial::testport;
+use teleop::commands;
+use teleop::commands::poses::poses;
+use teleop::errors;
+use teleop::errors::{join, TeleopError};
+use teleop::input::console::console;
+use teleop::input::pacing::pacing;
+use teleop::input::user_input::user_input;
+use teleop::remote::http::http;
+use teleop::remote::server::server;
+use teleop::serial::comms::comms;
+use teleop::serial::monitor::monitor;
+use teleop::serial::port::portcomms;
+use teleop::session::recording::recording;
 
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{Sender, Receiver};
 use std::sync::mpsc;
+use std::sync::Arc;
 use std::thread;
-use std::time;
 
 fn main() {
-    // Did the user pass in a COM port?
-    let user_requested_port = std::env::args().nth(1);
+    let args: Vec<String> = std::env::args().collect();
+
+    // Did the user ask for one of the session subcommands?
+    let result = match args.get(1).map(|s| s.as_str()) {
+        Some("record") => record(&args[2..]),
+        Some("replay") => replay(&args[2..]),
+        Some("convert") => convert(&args[2..]),
+        Some("serve") => serve(&args[2..]),
+        Some("http") => serve_http(&args[2..]),
+        _ => run(&args[1..]),
+    };
+
+    // Let whoever ran us know why we stopped
+    match result {
+        Ok(_) => std::process::exit(errors::EXIT_OK),
+        Err(e) => {
+            println!("{}", e);
+            if let TeleopError::Usage(_) = e {
+                print_usage();
+            }
+            std::process::exit(e.exit_code());
+        },
+    }
+}
+
+fn print_usage() {
+    println!("USAGE:");
+    println!("    teleop [port] [script] [--pace <fixed:ms/ack[:timeout ms]/estimate:deg per s[:settle ms]>]");
+    println!("    teleop record <session log> [port] [--pace <pacing>]");
+    println!("    teleop replay <session log> [port] [--speed <factor>]");
+    println!("    teleop convert <session log> <script>");
+    println!("    teleop serve [port] [--listen <address:port>]");
+    println!("    teleop http [port] [--listen <address:port>] [--poses <poses file>] [--pace <pacing>]");
+    println!("Any command that opens a port also takes:");
+    println!("    --device-log <path>   Append everything the device sends to <path>");
+    println!("    --device-debug        Also print the device's debug output to the console");
+    println!("    --reconnect <resume/ask/off>");
+    println!("                          What to do if the device disconnects: reconnect and re-send the commands");
+    println!("                          it missed (the default), reconnect and ask first, or give up");
+    println!("Exit codes:");
+    println!("    {:<3} Success", errors::EXIT_OK);
+    println!("    {:<3} No serial port to the device", errors::EXIT_NO_PORT);
+    println!("    {:<3} Problem with a script or session log", errors::EXIT_SCRIPT);
+    println!("    {:<3} The device stopped accepting commands", errors::EXIT_DEVICE);
+    println!("    {:<3} Internal error", errors::EXIT_INTERNAL);
+    println!("    {:<3} Problem with a file", errors::EXIT_IO);
+    println!("    {:<3} Could not listen for network clients", errors::EXIT_NETWORK);
+    println!("    {:<3} Bad usage", errors::EXIT_USAGE);
+    println!("    {:<3} Interrupted by Ctrl-C (the arm was sent home first)", errors::EXIT_INTERRUPTED);
+}
 
-    // Try to get a handle on the port. Fail loudly.
+/// Opens the port the user asked for (or finds one).
+fn open_port(user_requested_port: Option<String>) -> Result<Box<serialport::SerialPort>, TeleopError> {
     if let Some(port) = portcomms::get_serial_port(user_requested_port) {
         println!("Got a port named {:?}", port.name());
+        Ok(port)
+    } else {
+        Err(TeleopError::NoPort)
+    }
+}
 
-        // Did the user pass in a script?
-        let script = std::env::args().nth(2);
+/// Options that every command that opens a port accepts.
+const MONITOR_FLAGS: [&str; 2] = ["--device-log", "--reconnect"];
+const MONITOR_SWITCHES: [&str; 1] = ["--device-debug"];
 
-        if let Some(script) = script {
-            println!("Executing script {:?}", script);
-            run_script(port, script);
+/// Splits the arguments into positional arguments and the values of the given `--flag value` options.
+/// Switches (options without a value) are given the value "true" if present.
+fn parse_flags(args: &[String], flags: &[&str], switches: &[&str]) -> Result<(Vec<String>, HashMap<String, String>), TeleopError> {
+    let mut positional = Vec::new();
+    let mut values = HashMap::new();
+    let mut argiter = args.iter();
+    while let Some(arg) = argiter.next() {
+        if flags.contains(&arg.as_str()) || MONITOR_FLAGS.contains(&arg.as_str()) {
+            match argiter.next() {
+                Some(val) => { values.insert(arg.clone(), val.clone()); },
+                None => return Err(TeleopError::Usage(format!("{} needs a value", arg))),
+            }
+        } else if switches.contains(&arg.as_str()) || MONITOR_SWITCHES.contains(&arg.as_str()) {
+            values.insert(arg.clone(), "true".to_string());
         } else {
-            println!("Executing spin");
-            spin(port);
+            positional.push(arg.clone());
         }
+    }
+    Ok((positional, values))
+}
+
+/// Starts the thread that listens to everything the device sends back, according to the user's options.
+/// Returns None if the port can't be shared with another thread.
+fn start_monitor(port: &Box<serialport::SerialPort>, flags: &HashMap<String, String>) -> Result<Option<monitor::DeviceMonitor>, TeleopError> {
+    let listenport = match port.try_clone() {
+        Ok(listenport) => listenport,
+        Err(e) => {
+            println!("Could not listen to the device: {:?}", e);
+            return Ok(None);
+        },
+    };
+
+    let logpath = flags.get("--device-log").map(|s| s.as_str());
+    match monitor::DeviceMonitor::start(listenport, logpath, flags.contains_key("--device-debug")) {
+        Ok(mon) => Ok(Some(mon)),
+        Err(e) => Err(TeleopError::Io(logpath.unwrap_or("").to_string(), e)),
+    }
+}
+
+/// Builds what the serial thread needs to get the device back if it disconnects, according to the
+/// user's `--reconnect` option. Returns None if the user would rather we gave up.
+fn build_reconnect(port: &Box<serialport::SerialPort>, flags: &HashMap<String, String>, device: Option<&monitor::DeviceMonitor>, console: &console::Console) -> Result<Option<comms::Reconnect>, TeleopError> {
+    let resend = match flags.get("--reconnect").map(|s| s.as_str()) {
+        None | Some("resume") => comms::Resend::Resume,
+        Some("ask") => comms::Resend::Ask,
+        Some("off") => return Ok(None),
+        Some(other) => return Err(TeleopError::Usage(format!("--reconnect must be resume, ask or off, not {}", other))),
+    };
+    Ok(Some(comms::Reconnect {
+        identity: portcomms::identify_port(port),
+        monitor: device.cloned(),
+        resend: resend,
+        console: console.clone(),
+    }))
+}
+
+/// Builds the pacer for scripts from the user's `--pace` option (or the default pacing if none was given).
+fn build_pacer(pacestr: Option<&String>, device: Option<&monitor::DeviceMonitor>) -> Result<pacing::Pacer, TeleopError> {
+    let pace = match pacestr.map(|p| pacing::Pacing::new_from_string(p)) {
+        None => pacing::Pacing::default(),
+        Some(Ok(pace)) => pace,
+        Some(Err(msg)) => return Err(TeleopError::Usage(format!("Problem with --pace: {}", msg))),
+    };
+    Ok(pacing::Pacer::new(pace, device.map(|d| d.subscribe())))
+}
+
+/// Sends the arm home and tells the serial thread to quit when the user presses Ctrl-C.
+/// Returns a flag that gets set when that happens, so we can tell the user why we stopped.
+/// Pressing Ctrl-C a second time exits right away, in case the serial thread is stuck.
+fn handle_ctrlc(tx: Sender<commands::Command>) -> Arc<AtomicBool> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let flag = interrupted.clone();
+    let result = ctrlc::set_handler(move || {
+        if flag.swap(true, Ordering::SeqCst) {
+            std::process::exit(errors::EXIT_INTERRUPTED);
+        }
+        println!("Caught Ctrl-C. Sending the arm home and closing the port.");
+        tx.send(commands::Command::Home).ok();
+        tx.send(commands::Command::Quit).ok();
+    });
+    if let Err(e) = result {
+        println!("Could not set up Ctrl-C handling: {:?}", e);
+    }
+    interrupted
+}
+
+/// If the user pressed Ctrl-C, that is why we stopped, unless the device gave out first.
+fn check_interrupted(interrupted: &AtomicBool, result: Result<(), TeleopError>) -> Result<(), TeleopError> {
+    match result {
+        Err(TeleopError::Device(e)) => Err(TeleopError::Device(e)),
+        _ if interrupted.load(Ordering::SeqCst) => Err(TeleopError::Interrupted),
+        other => other,
+    }
+}
+
+/// The original mode of operation: `teleop [port] [script]`.
+fn run(args: &[String]) -> Result<(), TeleopError> {
+    let (args, flags) = parse_flags(args, &["--pace"], &[])?;
+
+    // Did the user pass in a COM port?
+    let port = open_port(args.get(0).cloned())?;
+    let device = start_monitor(&port, &flags)?;
+    let pacer = build_pacer(flags.get("--pace"), device.as_ref())?;
+    let console = console::Console::new();
+    let reconnect = build_reconnect(&port, &flags, device.as_ref(), &console)?;
+
+    // Did the user pass in a script?
+    if let Some(script) = args.get(1).cloned() {
+        println!("Executing script {:?}", script);
+        run_script(port, script, pacer, reconnect)
     } else {
-        println!("Could not find a serial port with the appropriate device.");
-        std::process::exit(1);
+        println!("Executing spin");
+        spin(port, pacer, console, reconnect)
     }
 }
 
+/// `teleop record <session log> [port]`: like spin, but every command sent to the arm is logged with its timing.
+fn record(args: &[String]) -> Result<(), TeleopError> {
+    let (args, flags) = parse_flags(args, &["--pace"], &[])?;
+    let logpath = match args.get(0) {
+        Some(logpath) => logpath.clone(),
+        None => return Err(TeleopError::Usage("record needs a path to write the session log to".to_string())),
+    };
+    let port = open_port(args.get(1).cloned())?;
+    let device = start_monitor(&port, &flags)?;
+    let pacer = build_pacer(flags.get("--pace"), device.as_ref())?;
+    let console = console::Console::new();
+    let reconnect = build_reconnect(&port, &flags, device.as_ref(), &console)?;
+
+    println!("Recording session to {:?}", logpath);
+    let (tx, rx): (Sender<commands::Command>, Receiver<commands::Command>) = mpsc::channel();
+    let (rectx, recrx): (Sender<commands::Command>, Receiver<commands::Command>) = mpsc::channel();
+    let interrupted = handle_ctrlc(tx.clone());
+    let commthread = thread::spawn(move || comms::communicate_with_device(port, recrx, reconnect));
+    let recordthread = thread::spawn(move || {
+        recording::record_until_quit(rx, rectx, &logpath).map_err(|e| TeleopError::Io(logpath.clone(), e))
+    });
+    let inputthread = thread::spawn(move || user_input::read_from_user_until_quit(tx, pacer, console));
+
+    // The input thread is left behind if the serial thread stopped on its own, since it may be stuck waiting on the console
+    let result = join(commthread).and(join(recordthread));
+    let result = if result.is_ok() && !interrupted.load(Ordering::SeqCst) { join(inputthread) } else { result };
+    check_interrupted(&interrupted, result)
+}
+
+/// `teleop replay <session log> [port] [--speed <factor>]`: re-sends a recorded session with its original timing.
+fn replay(args: &[String]) -> Result<(), TeleopError> {
+    let (positional, flags) = parse_flags(args, &["--speed"], &[])?;
+    let speed = match flags.get("--speed").map(|s| s.parse::<f64>()) {
+        None => 1.0,
+        Some(Ok(val)) => val,
+        Some(Err(_)) => return Err(TeleopError::Usage("--speed needs a numeric factor".to_string())),
+    };
+
+    let logpath = match positional.get(0) {
+        Some(logpath) => logpath.clone(),
+        None => return Err(TeleopError::Usage("replay needs a session log".to_string())),
+    };
+    let entries = recording::load_session(&logpath).map_err(TeleopError::Script)?;
+    let port = open_port(positional.get(1).cloned())?;
+    let device = start_monitor(&port, &flags)?;
+    let reconnect = build_reconnect(&port, &flags, device.as_ref(), &console::Console::new())?;
+
+    println!("Replaying session {:?} at speed {}", logpath, speed);
+    let (tx, rx): (Sender<commands::Command>, Receiver<commands::Command>) = mpsc::channel();
+    let interrupted = handle_ctrlc(tx.clone());
+    let commthread = thread::spawn(move || comms::communicate_with_device(port, rx, reconnect));
+
+    let replayresult = recording::replay_session(&tx, &entries, speed);
+
+    // Make sure the serial thread shuts down even if the session was cut short before a quit was recorded
+    tx.send(commands::Command::Quit).ok();
+    check_interrupted(&interrupted, join(commthread).and(replayresult))
+}
+
+/// `teleop convert <session log> <script>`: turns a recorded session into a script for `teleop [port] [script]`.
+fn convert(args: &[String]) -> Result<(), TeleopError> {
+    if args.len() != 2 {
+        return Err(TeleopError::Usage("convert needs a session log and a path to write the script to".to_string()));
+    }
+
+    let entries = recording::load_session(&args[0]).map_err(TeleopError::Script)?;
+    recording::save_as_script(&entries, &args[1]).map_err(|e| TeleopError::Io(args[1].clone(), e))
+}
+
+/// Where `teleop serve` listens if not told otherwise.
+const DEFAULT_LISTEN_ADDRESS: &str = "127.0.0.1:7777";
+
+/// `teleop serve [port] [--listen <address:port>]`: lets clients on the network drive the arm over TCP or WebSocket.
+/// Runs until Ctrl-C, which sends the arm home.
+fn serve(args: &[String]) -> Result<(), TeleopError> {
+    let (args, flags) = parse_flags(args, &["--listen"], &[])?;
+    let address = flags.get("--listen").cloned().unwrap_or_else(|| DEFAULT_LISTEN_ADDRESS.to_string());
+    let port = open_port(args.get(0).cloned())?;
+    let device = start_monitor(&port, &flags)?;
+    let reconnect = build_reconnect(&port, &flags, device.as_ref(), &console::Console::new())?;
+    let listener = TcpListener::bind(address.as_str()).map_err(|e| TeleopError::Network(address.clone(), e))?;
+
+    println!("Listening for clients on {}", address);
+    let (tx, rx): (Sender<commands::Command>, Receiver<commands::Command>) = mpsc::channel();
+    let interrupted = handle_ctrlc(tx.clone());
+    let commthread = thread::spawn(move || comms::communicate_with_device(port, rx, reconnect));
+    server::start(listener, tx, device.as_ref());
+
+    check_interrupted(&interrupted, join(commthread))
+}
+
+/// Where `teleop http` listens if not told otherwise.
+const DEFAULT_HTTP_ADDRESS: &str = "127.0.0.1:8080";
+
+/// `teleop http [port] [--listen <address:port>] [--poses <poses file>]`: lets other programs drive the arm
+/// with HTTP requests carrying JSON (see openapi.json). Runs until Ctrl-C, which sends the arm home.
+fn serve_http(args: &[String]) -> Result<(), TeleopError> {
+    let (args, flags) = parse_flags(args, &["--listen", "--poses", "--pace"], &[])?;
+    let address = flags.get("--listen").cloned().unwrap_or_else(|| DEFAULT_HTTP_ADDRESS.to_string());
+    let known_poses = match flags.get("--poses") {
+        Some(fpath) => poses::load_poses(fpath).map_err(TeleopError::Script)?,
+        None => vec![poses::home()],
+    };
+    let port = open_port(args.get(0).cloned())?;
+    let device = start_monitor(&port, &flags)?;
+    let pacer = build_pacer(flags.get("--pace"), device.as_ref())?;
+    let reconnect = build_reconnect(&port, &flags, device.as_ref(), &console::Console::new())?;
+    let listener = TcpListener::bind(address.as_str()).map_err(|e| TeleopError::Network(address.clone(), e))?;
+
+    println!("Answering HTTP requests on {}", address);
+    let (tx, rx): (Sender<commands::Command>, Receiver<commands::Command>) = mpsc::channel();
+    let interrupted = handle_ctrlc(tx.clone());
+    let commthread = thread::spawn(move || comms::communicate_with_device(port, rx, reconnect));
+    http::start(listener, tx, device.as_ref(), pacer, known_poses);
+
+    check_interrupted(&interrupted, join(commthread))
+}
+
 /// Spawns a thread that runs the serial port and a thread that reads
 /// commands from the console. Joins the threads once the user enters
 /// the quit command.
-fn spin(port: Box<serialport::SerialPort>) {
+fn spin(port: Box<serialport::SerialPort>, pacer: pacing::Pacer, console: console::Console, reconnect: Option<comms::Reconnect>) -> Result<(), TeleopError> {
     let (tx, rx): (Sender<commands::Command>, Receiver<commands::Command>) = mpsc::channel();
-    let commthread = thread::spawn(move || comms::communicate_with_device(port, rx));
-    let inputthread = thread::spawn(move || user_input::read_from_user_until_quit(tx));
+    let interrupted = handle_ctrlc(tx.clone());
+    let commthread = thread::spawn(move || comms::communicate_with_device(port, rx, reconnect));
+    let inputthread = thread::spawn(move || user_input::read_from_user_until_quit(tx, pacer, console));
 
-    if let Err(msg) = commthread.join() {
-        println!("Problem joining comm thread: {:?}", msg);
-    }
-    if let Err(msg) = inputthread.join() {
-        println!("Problem joining input thread: {:?}", msg);
-    }
+    // The input thread is left behind if the serial thread stopped on its own, since it may be stuck waiting on the console
+    let result = join(commthread);
+    let result = if result.is_ok() && !interrupted.load(Ordering::SeqCst) { join(inputthread) } else { result };
+    check_interrupted(&interrupted, result)
 }
 
-fn run_script(port: Box<serialport::SerialPort>, scriptpath: String) {
+/// Runs the script, then shuts down the serial thread once it has sent everything.
+fn run_script(port: Box<serialport::SerialPort>, scriptpath: String, mut pacer: pacing::Pacer, reconnect: Option<comms::Reconnect>) -> Result<(), TeleopError> {
     let (tx, rx): (Sender<commands::Command>, Receiver<commands::Command>) = mpsc::channel();
-    let _commthread = thread::spawn(move || comms::communicate_with_device(port, rx));
+    let interrupted = handle_ctrlc(tx.clone());
+    let commthread = thread::spawn(move || comms::communicate_with_device(port, rx, reconnect));
 
-    if let Err(msg) = user_input::run_script(&tx, scriptpath.as_str()) {
-        println!("Problem running script:\n{}", msg);
-        std::process::exit(2);
-    }
+    let scriptresult = user_input::run_script(&tx, scriptpath.as_str(), &mut pacer);
 
+    // Whether or not the script worked, the serial thread gets to finish what it has and close the port.
+    // Its error comes first, since a dead device is also why a script stops early.
     println!("Sending QUIT");
-    for _ in 0..10 {
-        match tx.send(commands::Command::Quit) {
-            _ => ()
-        }
-        thread::sleep(time::Duration::from_millis(100));
-    }
+    tx.send(commands::Command::Quit).ok();
+    check_interrupted(&interrupted, join(commthread).and(scriptresult))
 }
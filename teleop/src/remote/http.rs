@@ -0,0 +1,330 @@
+/// Module for driving the arm with HTTP requests that carry JSON, so that other programs can
+/// control it without writing scripts to disk and running teleop on them.
+///
+/// The endpoints are described in `openapi.json` at the root of the teleop crate, which is also
+/// served at `GET /openapi.json`. Every request that moves the arm is turned into `Command`s and
+/// only answered once the device has acknowledged them. Requests take turns with the arm.
+pub mod http {
+    use commands;
+    use commands::poses::poses;
+    use errors::TeleopError;
+    use input::pacing::pacing;
+    use input::user_input::user_input;
+    use serde_json;
+    use serial::monitor::monitor;
+    use std::io;
+    use std::io::{BufRead, Write};
+    use std::net;
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time;
+
+    /// The description of the API.
+    const OPENAPI: &str = include_str!("../../openapi.json");
+    /// How long to wait for the device to acknowledge a command.
+    const REPLY_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+    /// The largest request body we accept. Even a long script is far smaller.
+    const MAX_BODY: usize = 256 * 1024;
+
+    #[derive(Debug)]
+    struct Request {
+        method: String,
+        path: String,
+        body: Vec<u8>,
+    }
+
+    /// Something that went wrong with a request, along with the HTTP status it should be answered with.
+    #[derive(Debug)]
+    struct ApiError {
+        status: u16,
+        message: String,
+    }
+
+    impl ApiError {
+        fn new(status: u16, message: &str) -> Self {
+            ApiError { status: status, message: message.to_string() }
+        }
+    }
+
+    /// The arm, as far as requests are concerned.
+    struct Arm {
+        tx: mpsc::Sender<commands::Command>,
+        /// Lines from the device, if anything is listening to it.
+        replies: Option<mpsc::Receiver<monitor::DeviceLine>>,
+        /// Paces the scripts that are posted to us.
+        pacer: pacing::Pacer,
+        poses: Vec<poses::Pose>,
+    }
+
+    impl Arm {
+        /// Sends the command and waits for the device to be done with it. Returns the lines the device
+        /// sent about it (its telemetry and acknowledgement). If nothing is listening to the device, returns right away.
+        fn send(&mut self, cmd: commands::Command) -> Result<Vec<String>, ApiError> {
+            if let Some(ref replies) = self.replies {
+                while let Ok(_) = replies.try_recv() {}
+            }
+            println!("Sending command {:?}", cmd);
+            if self.tx.send(cmd.clone()).is_err() {
+                return Err(ApiError::new(503, "The serial thread has stopped"));
+            }
+
+            let replies = match self.replies {
+                Some(ref replies) => replies,
+                None => return Ok(Vec::new()),
+            };
+            match monitor::await_reply(replies, REPLY_TIMEOUT) {
+                monitor::Reply::Ack(mut lines) => {
+                    lines.push("ok".to_string());
+                    Ok(lines)
+                },
+                monitor::Reply::Rejected(text) => Err(ApiError::new(502, &format!("Device rejected {}: {}", cmd, text))),
+                monitor::Reply::TimedOut => Err(ApiError::new(504, &format!("Device did not acknowledge {} within {:?}", cmd, REPLY_TIMEOUT))),
+                monitor::Reply::Disconnected => Err(ApiError::new(503, "Lost the connection to the device")),
+            }
+        }
+
+        /// Sends each command in turn, stopping at the first one that fails.
+        fn send_all(&mut self, cmds: Vec<commands::Command>) -> Result<serde_json::Value, ApiError> {
+            let mut sent = Vec::new();
+            let mut replies = Vec::new();
+            for cmd in cmds {
+                sent.push(cmd.to_string());
+                replies.extend(self.send(cmd)?);
+            }
+            Ok(json!({ "sent": sent, "replies": replies }))
+        }
+    }
+
+    /// Starts answering requests on `listener`, handing the commands they turn into to the serial thread over `tx`.
+    /// Scripts are paced by `pacer`. Returns right away; the server runs until the program stops.
+    pub fn start(listener: net::TcpListener, tx: mpsc::Sender<commands::Command>, device: Option<&monitor::DeviceMonitor>, pacer: pacing::Pacer, poses: Vec<poses::Pose>) {
+        let arm = Arc::new(Mutex::new(Arm { tx: tx, replies: device.map(|d| d.subscribe()), pacer: pacer, poses: poses }));
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let arm = arm.clone();
+                        thread::spawn(move || {
+                            if let Err(e) = handle_connection(stream, &arm) {
+                                println!("Problem answering a request: {:?}", e);
+                            }
+                        });
+                    },
+                    Err(e) => println!("Could not accept a connection: {:?}", e),
+                }
+            }
+        });
+    }
+
+    /// Answers a single request, then closes the connection.
+    fn handle_connection(stream: net::TcpStream, arm: &Mutex<Arm>) -> io::Result<()> {
+        let mut reader = io::BufReader::new(stream.try_clone()?);
+        let (status, body) = match read_request(&mut reader) {
+            Ok(req) => {
+                println!("{} {}", req.method, req.path);
+                match route(&req, arm) {
+                    Ok(body) => (200, body),
+                    Err(e) => (e.status, json!({ "error": e.message })),
+                }
+            },
+            Err(e) => (e.status, json!({ "error": e.message })),
+        };
+        write_response(stream, status, &body.to_string())
+    }
+
+    /// Works out which endpoint the request is for and runs it.
+    fn route(req: &Request, arm: &Mutex<Arm>) -> Result<serde_json::Value, ApiError> {
+        match (req.method.as_str(), req.path.as_str()) {
+            ("POST", "/servo") => {
+                let body = parse_body(req)?;
+                let cmd = servo_command(&body["servo"], &body["angle"])?;
+                arm.lock().unwrap().send_all(vec![cmd])
+            },
+            ("POST", "/move") => {
+                let body = parse_body(req)?;
+                let mut arm = arm.lock().unwrap();
+                let cmds = move_commands(&body, &arm.poses)?;
+                arm.send_all(cmds)
+            },
+            ("POST", "/home") => arm.lock().unwrap().send_all(vec![commands::Command::Home]),
+            ("GET", "/status") => {
+                let mut arm = arm.lock().unwrap();
+                if arm.replies.is_none() {
+                    return Err(ApiError::new(503, "Not listening to the device, so can't ask it for its status"));
+                }
+                let replies = arm.send(commands::Command::Status)?;
+                match replies.iter().filter_map(|line| monitor::parse_status(line)).next() {
+                    Some(angles) => Ok(json!({ "angles": angles, "servos": name_angles(&angles) })),
+                    None => Err(ApiError::new(502, "Device acknowledged the status command without reporting its status")),
+                }
+            },
+            ("POST", "/script") => {
+                let body = parse_body(req)?;
+                let script = match body["script"].as_str() {
+                    Some(script) => script,
+                    None => return Err(ApiError::new(400, "Body needs a 'script' string")),
+                };
+                let mut arm = arm.lock().unwrap();
+                let arm = &mut *arm;
+                match user_input::run_script_text(&arm.tx, script, &mut arm.pacer) {
+                    Ok(_) => Ok(json!({ "ok": true })),
+                    Err(TeleopError::Script(msg)) => Err(ApiError::new(400, &msg)),
+                    Err(e) => Err(ApiError::new(503, &e.to_string())),
+                }
+            },
+            ("GET", "/poses") => {
+                let arm = arm.lock().unwrap();
+                let poses: Vec<serde_json::Value> = arm.poses.iter().map(|p| json!({ "name": p.name, "angles": p.angles, "servos": name_angles(&p.angles) })).collect();
+                Ok(json!({ "poses": poses }))
+            },
+            ("GET", "/openapi.json") => serde_json::from_str(OPENAPI).map_err(|e| ApiError::new(500, &format!("{:?}", e))),
+            (_, "/servo") | (_, "/move") | (_, "/home") | (_, "/status") | (_, "/script") | (_, "/poses") | (_, "/openapi.json") => {
+                Err(ApiError::new(405, &format!("{} is not allowed on {}", req.method, req.path)))
+            },
+            _ => Err(ApiError::new(404, &format!("No such endpoint: {}", req.path))),
+        }
+    }
+
+    fn parse_body(req: &Request) -> Result<serde_json::Value, ApiError> {
+        serde_json::from_slice(&req.body).map_err(|e| ApiError::new(400, &format!("Body is not valid JSON: {}", e)))
+    }
+
+    /// The lower case name of the servo, which requests can use in place of its ID.
+    fn servo_name(id: commands::ServoId) -> String {
+        format!("{:?}", id).to_ascii_lowercase()
+    }
+
+    /// Maps each servo's name to its angle.
+    fn name_angles(angles: &[u16; 5]) -> serde_json::Map<String, serde_json::Value> {
+        commands::ALL_SERVOS.iter().map(|id| (servo_name(*id), json!(angles[*id as usize]))).collect()
+    }
+
+    /// Builds a servo command from a servo (its ID or name) and an angle, with the same checks as the console.
+    fn servo_command(servo: &serde_json::Value, angle: &serde_json::Value) -> Result<commands::Command, ApiError> {
+        let id = match (servo.as_u64(), servo.as_str()) {
+            (Some(id), _) => id.to_string(),
+            (None, Some(name)) => match commands::ALL_SERVOS.iter().find(|id| servo_name(**id) == name.to_ascii_lowercase()) {
+                Some(id) => (*id as u8).to_string(),
+                None => return Err(ApiError::new(400, &format!("No servo named {}", name))),
+            },
+            (None, None) => return Err(ApiError::new(400, "'servo' must be a servo ID or name")),
+        };
+        let angle = match angle.as_f64() {
+            Some(angle) => angle,
+            None => return Err(ApiError::new(400, "'angle' must be a number")),
+        };
+        commands::Command::new_from_string(&format!("servo {} {}", id, angle)).map_err(|msg| ApiError::new(400, msg))
+    }
+
+    /// Builds the commands for a move: either to a named pose (`{"pose": "home"}`), to an angle for every servo
+    /// in ID order (`{"angles": [90, 10, 155, 90, 90]}`), or to new angles for some servos (`{"angles": {"elbow": 120}}`).
+    fn move_commands(body: &serde_json::Value, known_poses: &[poses::Pose]) -> Result<Vec<commands::Command>, ApiError> {
+        if let Some(name) = body["pose"].as_str() {
+            return match known_poses.iter().find(|p| p.name == name) {
+                Some(pose) => Ok(pose.commands()),
+                None => Err(ApiError::new(404, &format!("No pose named {}", name))),
+            };
+        }
+
+        match body["angles"] {
+            serde_json::Value::Array(ref angles) if angles.len() == commands::ALL_SERVOS.len() => {
+                commands::ALL_SERVOS.iter().zip(angles.iter()).map(|(id, angle)| servo_command(&json!(*id as u8), angle)).collect()
+            },
+            serde_json::Value::Object(ref angles) => {
+                angles.iter().map(|(servo, angle)| servo_command(&json!(servo), angle)).collect()
+            },
+            _ => Err(ApiError::new(400, "Body needs a 'pose' name, or 'angles' as a list of 5 angles or an object of servo names to angles")),
+        }
+    }
+
+    /// Reads the request line, headers, and body of an HTTP/1.1 request.
+    fn read_request<R: BufRead>(reader: &mut R) -> Result<Request, ApiError> {
+        let bad = |msg: &str| ApiError::new(400, msg);
+
+        let mut line = String::new();
+        reader.read_line(&mut line).map_err(|_| bad("Could not read the request"))?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.len() != 3 {
+            return Err(bad("Malformed request line"));
+        }
+        let method = tokens[0].to_ascii_uppercase();
+        let path = tokens[1].split('?').next().unwrap_or("").to_string();
+
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header).map_err(|_| bad("Could not read the headers"))? == 0 {
+                break;
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+
+            let mut split = header.splitn(2, ':');
+            if split.next().unwrap_or("").trim().to_ascii_lowercase() == "content-length" {
+                content_length = split.next().unwrap_or("").trim().parse::<usize>().map_err(|_| bad("Content-Length is not a number"))?;
+            }
+        }
+        if content_length > MAX_BODY {
+            return Err(ApiError::new(413, "Request body is too big"));
+        }
+
+        let mut body = vec![0u8; content_length];
+        reader.read_exact(&mut body).map_err(|_| bad("Request body is shorter than its Content-Length"))?;
+        Ok(Request { method: method, path: path, body: body })
+    }
+
+    fn write_response<W: Write>(mut writer: W, status: u16, body: &str) -> io::Result<()> {
+        let reason = match status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            500 => "Internal Server Error",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            _ => "Unknown",
+        };
+        write!(writer, "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, reason, body.len(), body)?;
+        writer.flush()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_read_request() {
+            let raw = "POST /servo?verbose HTTP/1.1\r\nHost: localhost\r\nContent-Length: 25\r\n\r\n{\"servo\": 0, \"angle\": 90}";
+            let req = read_request(&mut io::BufReader::new(raw.as_bytes())).unwrap();
+            assert_eq!(req.method, "POST");
+            assert_eq!(req.path, "/servo");
+            assert_eq!(parse_body(&req).unwrap(), json!({ "servo": 0, "angle": 90 }));
+
+            assert_eq!(read_request(&mut io::BufReader::new("garbage\r\n\r\n".as_bytes())).unwrap_err().status, 400);
+        }
+
+        #[test]
+        fn test_servo_command() {
+            assert_eq!(servo_command(&json!(2), &json!(120)).unwrap(), commands::Command::Servo(commands::ServoId::Elbow, 120));
+            assert_eq!(servo_command(&json!("Elbow"), &json!(120.4)).unwrap(), commands::Command::Servo(commands::ServoId::Elbow, 120));
+            assert!(servo_command(&json!(7), &json!(120)).is_err());
+            assert!(servo_command(&json!("knee"), &json!(120)).is_err());
+            assert!(servo_command(&json!(2), &json!(200)).is_err());
+        }
+
+        #[test]
+        fn test_move_commands() {
+            let known = vec![poses::home()];
+            assert_eq!(move_commands(&json!({ "pose": "home" }), &known).unwrap(), poses::home().commands());
+            assert_eq!(move_commands(&json!({ "angles": [90, 10, 155, 90, 90] }), &known).unwrap(), poses::home().commands());
+            assert_eq!(move_commands(&json!({ "angles": { "hand": 30 } }), &known).unwrap(), vec![commands::Command::Servo(commands::ServoId::Hand, 30)]);
+            assert_eq!(move_commands(&json!({ "pose": "wave" }), &known).unwrap_err().status, 404);
+            assert_eq!(move_commands(&json!({ "angles": [90, 10] }), &known).unwrap_err().status, 400);
+        }
+    }
+}
@@ -0,0 +1,376 @@
+/// Module for driving the arm from other machines.
+///
+/// Clients connect over TCP and either speak the console's text protocol a line at a time
+/// (so `telnet` or `nc` work), or open a WebSocket and send the same lines as text frames.
+/// One client at a time has control of the arm; everyone else can watch, but their commands are refused.
+/// Besides commands, clients can send 'control' to take control if nobody has it, and 'release' to give it up.
+///
+/// Everything the device says (other than its debug output) is sent to every client as is, so 'ok',
+/// 'error: ...' and 'status ...' lines can be told apart the same way `monitor::classify` does.
+/// Notices from the server, including the commands the controlling client sends, start with '#'.
+pub mod server {
+    use commands;
+    use remote::websocket::websocket;
+    use serial::monitor::monitor;
+    use std::io;
+    use std::io::{BufRead, Write};
+    use std::net;
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time;
+
+    /// How long to wait for a new connection to start a WebSocket handshake before deciding it is a plain TCP client.
+    const SNIFF_TIMEOUT: time::Duration = time::Duration::from_millis(300);
+
+    /// How a client talks to us.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Protocol {
+        /// A line of text at a time.
+        Lines,
+        /// Text frames, each holding one or more lines.
+        WebSocket,
+    }
+
+    /// One connected client.
+    struct Client {
+        id: usize,
+        protocol: Protocol,
+        stream: Mutex<net::TcpStream>,
+    }
+
+    impl Client {
+        /// Sends a line of text to the client, however it wants it.
+        fn send(&self, text: &str) -> io::Result<()> {
+            let mut stream = self.stream.lock().unwrap();
+            match self.protocol {
+                Protocol::Lines => writeln!(stream, "{}", text),
+                Protocol::WebSocket => websocket::write_frame(&mut *stream, websocket::Opcode::Text, text.as_bytes()),
+            }
+        }
+    }
+
+    /// Everyone who is connected, and which of them has control of the arm.
+    struct Hub {
+        clients: Mutex<Vec<Arc<Client>>>,
+        controller: Mutex<Option<usize>>,
+    }
+
+    impl Hub {
+        fn new() -> Self {
+            Hub { clients: Mutex::new(Vec::new()), controller: Mutex::new(None) }
+        }
+
+        /// Adds the client, giving it control if nobody else has it.
+        fn join(&self, client: Arc<Client>) {
+            self.clients.lock().unwrap().push(client.clone());
+            client.send(&format!("# connected as client {}", client.id)).ok();
+            if self.take_control(client.id).is_err() {
+                client.send(&format!("# read only: {}", self.describe_controller())).ok();
+            }
+        }
+
+        /// Removes the client. If it had control, control passes to whoever has been connected longest.
+        fn leave(&self, id: usize) {
+            self.clients.lock().unwrap().retain(|c| c.id != id);
+            if self.has_control(id) {
+                *self.controller.lock().unwrap() = None;
+                let next = self.clients.lock().unwrap().first().map(|c| c.id);
+                if let Some(next) = next {
+                    self.take_control(next).ok();
+                }
+            }
+        }
+
+        /// Sends the line to every client. Clients that can't be written to are left for their own thread to clean up.
+        fn broadcast(&self, text: &str) {
+            let clients = self.clients.lock().unwrap().clone();
+            for client in clients {
+                client.send(text).ok();
+            }
+        }
+
+        fn has_control(&self, id: usize) -> bool {
+            *self.controller.lock().unwrap() == Some(id)
+        }
+
+        /// Gives the client control, as long as nobody else has it.
+        fn take_control(&self, id: usize) -> Result<(), String> {
+            {
+                let mut controller = self.controller.lock().unwrap();
+                match *controller {
+                    Some(holder) if holder == id => return Ok(()),
+                    Some(holder) => return Err(format!("client {} has control", holder)),
+                    None => *controller = Some(id),
+                }
+            }
+            self.broadcast(&format!("# client {} has control", id));
+            Ok(())
+        }
+
+        /// Takes control away from the client, if it has it.
+        fn release(&self, id: usize) {
+            {
+                let mut controller = self.controller.lock().unwrap();
+                if *controller != Some(id) {
+                    return;
+                }
+                *controller = None;
+            }
+            self.broadcast(&format!("# client {} released control", id));
+        }
+
+        fn describe_controller(&self) -> String {
+            match *self.controller.lock().unwrap() {
+                Some(holder) => format!("client {} has control", holder),
+                None => "nobody has control".to_string(),
+            }
+        }
+    }
+
+    /// Starts accepting clients on `listener` and handing their commands to the serial thread over `tx`.
+    /// Everything `device` hears is passed on to all clients. Returns right away; the server runs
+    /// until the program stops.
+    pub fn start(listener: net::TcpListener, tx: mpsc::Sender<commands::Command>, device: Option<&monitor::DeviceMonitor>) {
+        let hub = Arc::new(Hub::new());
+
+        if let Some(device) = device {
+            let replies = device.subscribe();
+            let hub = hub.clone();
+            thread::spawn(move || {
+                for line in replies.iter() {
+                    if line.kind != monitor::LineKind::Debug {
+                        hub.broadcast(&line.text);
+                    }
+                }
+            });
+        }
+
+        thread::spawn(move || {
+            let mut next_id = 1;
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        println!("Could not accept a connection: {:?}", e);
+                        continue;
+                    },
+                };
+
+                let id = next_id;
+                next_id += 1;
+                let hub = hub.clone();
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    if let Err(e) = handle_client(stream, id, &hub, &tx) {
+                        println!("Lost client {}: {:?}", id, e);
+                    }
+                    println!("Client {} disconnected", id);
+                    hub.leave(id);
+                });
+            }
+        });
+    }
+
+    /// Works out whether the client is opening a WebSocket. WebSocket clients start with an HTTP request
+    /// straight away, while people on a plain TCP connection usually wait for us to say something first.
+    fn sniff(stream: &net::TcpStream) -> io::Result<Protocol> {
+        stream.set_read_timeout(Some(SNIFF_TIMEOUT))?;
+        let start = time::Instant::now();
+        let mut buf = [0u8; 4];
+        let protocol = loop {
+            match stream.peek(&mut buf) {
+                Ok(4) => break if &buf == b"GET " { Protocol::WebSocket } else { Protocol::Lines },
+                Ok(0) => break Protocol::Lines,
+                Ok(_) if start.elapsed() < SNIFF_TIMEOUT => thread::sleep(time::Duration::from_millis(10)),
+                Ok(_) => break Protocol::Lines,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => break Protocol::Lines,
+                Err(e) => return Err(e),
+            }
+        };
+        stream.set_read_timeout(None)?;
+        Ok(protocol)
+    }
+
+    /// Talks to a single client until it hangs up or quits.
+    fn handle_client(stream: net::TcpStream, id: usize, hub: &Hub, tx: &mpsc::Sender<commands::Command>) -> io::Result<()> {
+        let peer = stream.peer_addr()?;
+        let protocol = sniff(&stream)?;
+        let mut reader = io::BufReader::new(stream.try_clone()?);
+        let client = Arc::new(Client { id: id, protocol: protocol, stream: Mutex::new(stream) });
+        if protocol == Protocol::WebSocket {
+            websocket::handshake(&mut reader, &mut *client.stream.lock().unwrap())?;
+        }
+
+        println!("Client {} connected from {} ({:?})", id, peer, protocol);
+        hub.join(client.clone());
+
+        loop {
+            let lines: Vec<String> = match protocol {
+                Protocol::Lines => {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line)? == 0 {
+                        return Ok(());
+                    }
+                    vec![line]
+                },
+                Protocol::WebSocket => {
+                    let frame = websocket::read_frame(&mut reader)?;
+                    match frame.opcode {
+                        websocket::Opcode::Text => String::from_utf8_lossy(&frame.payload).lines().map(|l| l.to_string()).collect(),
+                        websocket::Opcode::Ping => {
+                            websocket::write_frame(&mut *client.stream.lock().unwrap(), websocket::Opcode::Pong, &frame.payload)?;
+                            continue;
+                        },
+                        websocket::Opcode::Close => {
+                            websocket::write_frame(&mut *client.stream.lock().unwrap(), websocket::Opcode::Close, &frame.payload)?;
+                            return Ok(());
+                        },
+                        _ => continue,
+                    }
+                },
+            };
+
+            for line in lines {
+                if !handle_line(&line, &client, hub, tx)? {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Acts on a line from the client. Returns false if the client has asked to quit.
+    fn handle_line(line: &str, client: &Client, hub: &Hub, tx: &mpsc::Sender<commands::Command>) -> io::Result<bool> {
+        match line.trim().to_ascii_lowercase().as_str() {
+            "" => return Ok(true),
+            "control" => {
+                if let Err(msg) = hub.take_control(client.id) {
+                    client.send(&format!("error: {}", msg))?;
+                }
+                return Ok(true);
+            },
+            "release" => {
+                hub.release(client.id);
+                return Ok(true);
+            },
+            _ => (),
+        }
+
+        match commands::Command::new_from_string(line) {
+            Err(msg) => client.send(&format!("error: {}", msg))?,
+            // Quitting only disconnects this client. The server keeps running for everyone else.
+            Ok(commands::Command::Quit) => return Ok(false),
+            Ok(commands::Command::Help) | Ok(commands::Command::Script(_)) => {
+                client.send("error: Help and scripts are only available from the console")?;
+            },
+            Ok(cmd) => {
+                if !hub.has_control(client.id) {
+                    client.send(&format!("error: Read only, {}", hub.describe_controller()))?;
+                } else {
+                    hub.broadcast(&format!("# client {}: {}", client.id, cmd));
+                    if tx.send(cmd).is_err() {
+                        client.send("error: The serial thread has stopped")?;
+                    }
+                }
+            },
+        }
+        Ok(true)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use serial::comms::comms;
+        use serial::testport;
+        use serialport;
+
+        /// Starts a server on a free local port, driving the test port.
+        fn start_test_server() -> net::SocketAddr {
+            let port: Box<serialport::SerialPort> = Box::new(testport::TestPort::new());
+            let device = monitor::DeviceMonitor::start(port.try_clone().unwrap(), None, false).unwrap();
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || comms::communicate_with_device(port, rx, None));
+            let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            start(listener, tx, Some(&device));
+            address
+        }
+
+        /// A client talking the line protocol.
+        struct TestClient {
+            stream: net::TcpStream,
+            reader: io::BufReader<net::TcpStream>,
+        }
+
+        impl TestClient {
+            fn connect(address: &net::SocketAddr) -> Self {
+                let stream = net::TcpStream::connect(address).unwrap();
+                stream.set_read_timeout(Some(time::Duration::from_secs(5))).unwrap();
+                TestClient { reader: io::BufReader::new(stream.try_clone().unwrap()), stream: stream }
+            }
+
+            fn send(&mut self, line: &str) {
+                writeln!(self.stream, "{}", line).unwrap();
+            }
+
+            /// Reads lines until `wanted` comes, failing if it doesn't come in time.
+            fn expect(&mut self, wanted: &str) {
+                loop {
+                    let mut line = String::new();
+                    match self.reader.read_line(&mut line) {
+                        Ok(0) => panic!("Disconnected while waiting for '{}'", wanted),
+                        Ok(_) if line.trim_end() == wanted => return,
+                        Ok(_) => (),
+                        Err(e) => panic!("Gave up waiting for '{}': {:?}", wanted, e),
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn test_one_client_controls_the_arm() {
+            let address = start_test_server();
+            let mut first = TestClient::connect(&address);
+            first.expect("# connected as client 1");
+            first.expect("# client 1 has control");
+            let mut second = TestClient::connect(&address);
+            second.expect("# connected as client 2");
+            second.expect("# read only: client 1 has control");
+
+            // The second client can watch, but not move the arm or take control
+            second.send("servo 0 30");
+            second.expect("error: Read only, client 1 has control");
+            second.send("control");
+            second.expect("error: client 1 has control");
+            first.send("servo 0 45");
+            second.expect("# client 1: servo 0 45");
+            first.send("status");
+            second.expect("status 45 10 155 90 90");
+
+            // Once the first client lets go, the second can take over
+            first.send("release");
+            second.expect("# client 1 released control");
+            second.send("control");
+            first.expect("# client 2 has control");
+            first.send("home");
+            first.expect("error: Read only, client 2 has control");
+            second.send("home");
+            first.expect("# client 2: home");
+        }
+
+        #[test]
+        fn test_control_passes_on_when_controller_leaves() {
+            let address = start_test_server();
+            let mut first = TestClient::connect(&address);
+            first.expect("# client 1 has control");
+            let mut second = TestClient::connect(&address);
+            second.expect("# read only: client 1 has control");
+
+            first.send("quit");
+            second.expect("# client 2 has control");
+            second.send("servo 2 100");
+            second.send("status");
+            second.expect("status 90 10 100 90 90");
+        }
+    }
+}
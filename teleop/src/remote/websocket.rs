@@ -0,0 +1,219 @@
+/// Module with just enough of the WebSocket protocol (RFC 6455) to exchange text commands with
+/// a browser or a scripting language's WebSocket client: the opening handshake, and unfragmented frames.
+pub mod websocket {
+    use base64;
+    use sha1;
+    use std::io;
+    use std::io::{BufRead, Read, Write};
+
+    /// Appended to the client's key before hashing, as the RFC requires.
+    const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+    /// Frame types we care about. Everything else is treated as a protocol error.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Opcode {
+        Continuation,
+        Text,
+        Binary,
+        Close,
+        Ping,
+        Pong,
+    }
+
+    impl Opcode {
+        fn from_u8(x: u8) -> Option<Opcode> {
+            match x {
+                0x0 => Some(Opcode::Continuation),
+                0x1 => Some(Opcode::Text),
+                0x2 => Some(Opcode::Binary),
+                0x8 => Some(Opcode::Close),
+                0x9 => Some(Opcode::Ping),
+                0xA => Some(Opcode::Pong),
+                _ => None,
+            }
+        }
+
+        fn as_u8(&self) -> u8 {
+            match self {
+                Opcode::Continuation => 0x0,
+                Opcode::Text => 0x1,
+                Opcode::Binary => 0x2,
+                Opcode::Close => 0x8,
+                Opcode::Ping => 0x9,
+                Opcode::Pong => 0xA,
+            }
+        }
+    }
+
+    /// A single frame, with its payload already unmasked.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Frame {
+        pub opcode: Opcode,
+        pub payload: Vec<u8>,
+    }
+
+    /// The largest frame we accept from a client. Commands are a few bytes long, so anything bigger is a mistake.
+    const MAX_PAYLOAD: u64 = 64 * 1024;
+
+    /// Computes the Sec-WebSocket-Accept value for the client's Sec-WebSocket-Key.
+    pub fn accept_key(key: &str) -> String {
+        let mut hasher = sha1::Sha1::new();
+        hasher.update(key.trim().as_bytes());
+        hasher.update(HANDSHAKE_GUID.as_bytes());
+        base64::encode(&hasher.digest().bytes())
+    }
+
+    /// Reads the client's HTTP upgrade request from `reader` and answers it on `writer`. Anything other than a
+    /// WebSocket upgrade (an 'Upgrade: websocket' header and a Sec-WebSocket-Key) is answered with a 400.
+    pub fn handshake<R: BufRead, W: Write>(reader: &mut R, writer: &mut W) -> io::Result<()> {
+        let mut upgrade = false;
+        let mut key = None;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed during the WebSocket handshake"));
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+
+            let mut split = line.splitn(2, ':');
+            let name = split.next().unwrap_or("").trim().to_ascii_lowercase();
+            let value = split.next().unwrap_or("").trim();
+            match name.as_str() {
+                "upgrade" => upgrade = value.eq_ignore_ascii_case("websocket"),
+                "sec-websocket-key" if !value.is_empty() => key = Some(value.to_string()),
+                _ => (),
+            }
+        }
+
+        let key = match key {
+            Some(ref key) if upgrade => key,
+            _ => {
+                write!(writer, "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")?;
+                writer.flush()?;
+                let problem = if upgrade { "had no Sec-WebSocket-Key" } else { "did not ask to upgrade to a WebSocket" };
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("WebSocket handshake {}", problem)));
+            },
+        };
+        write!(writer, "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n", accept_key(key))?;
+        writer.flush()
+    }
+
+    /// Reads the next frame from a client, unmasking it if need be.
+    pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<Frame> {
+        let mut head = [0u8; 2];
+        reader.read_exact(&mut head)?;
+
+        let opcode = match Opcode::from_u8(head[0] & 0x0F) {
+            Some(opcode) => opcode,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, "Unknown WebSocket opcode")),
+        };
+        let masked = head[1] & 0x80 != 0;
+        let len = match head[1] & 0x7F {
+            126 => {
+                let mut ext = [0u8; 2];
+                reader.read_exact(&mut ext)?;
+                ((ext[0] as u64) << 8) | ext[1] as u64
+            },
+            127 => {
+                let mut ext = [0u8; 8];
+                reader.read_exact(&mut ext)?;
+                ext.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64)
+            },
+            n => n as u64,
+        };
+        if len > MAX_PAYLOAD {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "WebSocket frame is too big"));
+        }
+
+        let mut mask = [0u8; 4];
+        if masked {
+            reader.read_exact(&mut mask)?;
+        }
+        let mut payload = vec![0u8; len as usize];
+        reader.read_exact(&mut payload)?;
+        if masked {
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+        }
+
+        Ok(Frame { opcode: opcode, payload: payload })
+    }
+
+    /// Writes a single, final, unmasked frame (servers never mask).
+    pub fn write_frame<W: Write>(writer: &mut W, opcode: Opcode, payload: &[u8]) -> io::Result<()> {
+        let mut head = vec![0x80 | opcode.as_u8()];
+        match payload.len() {
+            n if n < 126 => head.push(n as u8),
+            n if n <= 0xFFFF => {
+                head.push(126);
+                head.push((n >> 8) as u8);
+                head.push(n as u8);
+            },
+            n => {
+                head.push(127);
+                for shift in (0..8).rev() {
+                    head.push(((n as u64) >> (shift * 8)) as u8);
+                }
+            },
+        }
+        writer.write_all(&head)?;
+        writer.write_all(payload)?;
+        writer.flush()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_accept_key() {
+            // The example from RFC 6455
+            assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        }
+
+        #[test]
+        fn test_handshake() {
+            let request = "GET / HTTP/1.1\r\nHost: arm\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+            let mut response = Vec::new();
+            handshake(&mut request.as_bytes(), &mut response).unwrap();
+            let response = String::from_utf8(response).unwrap();
+            assert!(response.starts_with("HTTP/1.1 101 "));
+            assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+            // Plain HTTP requests, and upgrades without a key, are refused
+            for request in ["GET / HTTP/1.1\r\nHost: arm\r\n\r\n", "GET / HTTP/1.1\r\nUpgrade: websocket\r\n\r\n",
+                            "GET / HTTP/1.1\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n"].iter() {
+                let mut response = Vec::new();
+                assert!(handshake(&mut request.as_bytes(), &mut response).is_err());
+                assert!(String::from_utf8(response).unwrap().starts_with("HTTP/1.1 400 "));
+            }
+        }
+
+        #[test]
+        fn test_read_masked_frame() {
+            // A masked 'Hello' from RFC 6455
+            let bytes = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+            let frame = read_frame(&mut &bytes[..]).unwrap();
+            assert_eq!(frame, Frame { opcode: Opcode::Text, payload: b"Hello".to_vec() });
+        }
+
+        #[test]
+        fn test_frame_round_trip() {
+            for len in [0, 5, 125, 126, 300, 70000].iter() {
+                let payload = vec![b'x'; *len];
+                let mut bytes = Vec::new();
+                write_frame(&mut bytes, Opcode::Text, &payload).unwrap();
+                let frame = read_frame(&mut &bytes[..]);
+                if *len as u64 > MAX_PAYLOAD {
+                    assert!(frame.is_err());
+                } else {
+                    assert_eq!(frame.unwrap(), Frame { opcode: Opcode::Text, payload: payload });
+                }
+            }
+        }
+    }
+}
@@ -1,49 +1,237 @@
 pub mod comms {
     use commands;
+    use errors::TeleopError;
+    use input::console::console;
+    use serial::monitor::monitor;
+    use serial::port::portcomms;
     use serialport;
+    use std::collections::VecDeque;
     use std::io;
     use std::sync::mpsc;
+    use std::thread;
+    use std::time;
+
+    /// How many times to try opening the device again before giving up on it.
+    const RECONNECT_ATTEMPTS: u32 = 10;
+    /// How long to wait before the first attempt. Doubles after each failed attempt, up to the maximum.
+    const RECONNECT_FIRST_DELAY: time::Duration = time::Duration::from_millis(250);
+    const RECONNECT_MAX_DELAY: time::Duration = time::Duration::from_secs(4);
+    /// How many times to ask a reconnected device for its status, and how long to wait for each answer.
+    /// The device may still be booting when we get the port back, so the first few can go unanswered.
+    const STATUS_ATTEMPTS: u32 = 5;
+    const STATUS_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+    /// How long to leave between commands that are re-sent after a reconnect, since they go out back to back.
+    const RESEND_SPACING: time::Duration = time::Duration::from_millis(250);
+
+    /// What to do with the commands that had not reached the device when it disconnected.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Resend {
+        /// Send them as soon as the device is back.
+        Resume,
+        /// Show them to the user and only send them if the user says so.
+        Ask,
+    }
+
+    /// Everything the serial thread needs to get the device back if it disconnects.
+    pub struct Reconnect {
+        /// The device to look for.
+        pub identity: portcomms::PortIdentity,
+        /// Listens to the device. It is moved over to the new port so that we can ask the device for its state.
+        pub monitor: Option<monitor::DeviceMonitor>,
+        pub resend: Resend,
+        pub console: console::Console,
+    }
+
+    impl Reconnect {
+        /// Tries to open the same device again, backing off between attempts.
+        fn reopen(&self) -> Option<Box<serialport::SerialPort>> {
+            let mut delay = RECONNECT_FIRST_DELAY;
+            for attempt in 1..RECONNECT_ATTEMPTS + 1 {
+                thread::sleep(delay);
+                if let Some(port) = portcomms::reopen_serial_port(&self.identity) {
+                    println!("Reconnected to the device on attempt {}.", attempt);
+                    return Some(port);
+                }
+                println!("Could not reconnect to the device (attempt {} of {}).", attempt, RECONNECT_ATTEMPTS);
+                delay = if delay * 2 < RECONNECT_MAX_DELAY { delay * 2 } else { RECONNECT_MAX_DELAY };
+            }
+            None
+        }
+
+        /// Starts listening on the new port and asks the device where its servos are, telling the
+        /// user about any that are not where we last sent them (the device goes home when it resets).
+        fn resync(&self, port: &mut Box<serialport::SerialPort>, sent: &[Option<u16>; 5]) {
+            let monitor = match self.monitor {
+                Some(ref monitor) => monitor,
+                None => {
+                    println!("Not listening to the device, so can't check what state it is in.");
+                    return;
+                },
+            };
+            match port.try_clone() {
+                Ok(listenport) => monitor.attach(listenport),
+                Err(e) => {
+                    println!("Could not listen to the reconnected device: {:?}", e);
+                    return;
+                },
+            }
+
+            let replies = monitor.subscribe();
+            for _ in 0..STATUS_ATTEMPTS {
+                if write_to_port(port, &commands::Command::Status).is_err() {
+                    thread::sleep(STATUS_TIMEOUT);
+                    continue;
+                }
+
+                // Wait out whatever is left of the timeout, which is nothing once it has passed
+                let asked = time::Instant::now();
+                while let Some(left) = STATUS_TIMEOUT.checked_sub(asked.elapsed()) {
+                    match replies.recv_timeout(left) {
+                        Ok(line) => {
+                            if let Some(angles) = monitor::parse_status(&line.text) {
+                                report_differences(&angles, sent);
+                                return;
+                            }
+                        },
+                        Err(_) => break,
+                    }
+                }
+            }
+            println!("The device did not report its state. It may have reset and gone home.");
+        }
+
+        /// Decides whether the commands that did not make it to the device should be sent now.
+        fn should_resend(&self, unsent: &[commands::Command]) -> bool {
+            let cmds: Vec<&commands::Command> = unsent.iter().filter(|c| **c != commands::Command::Quit).collect();
+            if cmds.is_empty() {
+                return true;
+            }
+
+            match self.resend {
+                Resend::Resume => {
+                    println!("Re-sending {} command(s) that did not reach the device.", cmds.len());
+                    true
+                },
+                Resend::Ask => {
+                    println!("These commands did not reach the device:");
+                    for cmd in cmds.iter() {
+                        println!("    {}", cmd);
+                    }
+                    self.console.confirm("Send them now?")
+                },
+            }
+        }
+    }
 
     /// Communicate with the device by listening on a channel from the console
     /// thread and sending the received commands over UART.
     /// Closes its resources and quits running when it receives the special
-    /// quit command.
-    pub fn communicate_with_device(mut port: Box<serialport::SerialPort>, rx: mpsc::Receiver<commands::Command>) {
-        let mut should_quit = false;
-        while !should_quit {
+    /// quit command. Returns an error if the device can't be written to or if
+    /// every sender hangs up without sending quit.
+    ///
+    /// If `reconnect` is given, losing the device is not an error unless it can't be opened again.
+    pub fn communicate_with_device(mut port: Box<serialport::SerialPort>, rx: mpsc::Receiver<commands::Command>, reconnect: Option<Reconnect>) -> Result<(), TeleopError> {
+        // Commands that did not make it to the device before it disconnected. These go out before anything new.
+        let mut unsent: VecDeque<commands::Command> = VecDeque::new();
+        // Where we last sent each servo, so we can tell what a reset undid
+        let mut sent = [None; 5];
+
+        loop {
             // Get the next command
-            let cmd = rx.recv().expect("Problem reading from the Input channel");
+            let resending = !unsent.is_empty();
+            let cmd = match unsent.pop_front() {
+                Some(cmd) => cmd,
+                None => match rx.recv() {
+                    Ok(cmd) => cmd,
+                    Err(_) => return Err(TeleopError::ChannelClosed),
+                },
+            };
 
             match cmd {
-                commands::Command::Help => panic!("Should not have gotten help command on this thread."),
-                commands::Command::Quit => { should_quit = true; },
-                commands::Command::Script(_) => panic!("Should not have gotten script command on this thread."),
-                commands::Command::Led(_on) => { write_to_port(&mut port, cmd).unwrap(); },
-                commands::Command::Servo(_id, _angle) => { write_to_port(&mut port, cmd).unwrap(); },
-                commands::Command::Home => { write_to_port(&mut port, cmd).unwrap(); },
+                commands::Command::Quit => break,
+                commands::Command::Help | commands::Command::Script(_) => {
+                    println!("Ignoring {:?}, which is not meant for the device.", cmd);
+                },
+                commands::Command::Led(_) | commands::Command::Servo(_, _) | commands::Command::Home | commands::Command::Status => {
+                    if resending {
+                        thread::sleep(RESEND_SPACING);
+                    }
+
+                    let e = match write_to_port(&mut port, &cmd) {
+                        Ok(_) => {
+                            track(&mut sent, &cmd);
+                            continue;
+                        },
+                        Err(e) => e,
+                    };
+                    let reconnect = match reconnect {
+                        Some(ref reconnect) => reconnect,
+                        None => return Err(TeleopError::Device(e)),
+                    };
+
+                    println!("Lost the device: {:?}. Trying to reconnect.", e);
+                    port = reconnect.reopen().ok_or(TeleopError::Device(e))?;
+                    reconnect.resync(&mut port, &sent);
+
+                    // Everything that has not reached the device: this command, anything left over from an
+                    // earlier reconnect, and anything that queued up while we were reconnecting
+                    let mut pending: Vec<commands::Command> = Some(cmd).into_iter().chain(unsent.drain(..)).chain(rx.try_iter()).collect();
+                    if !reconnect.should_resend(&pending) {
+                        pending.retain(|c| *c == commands::Command::Quit);
+                    }
+                    unsent.extend(pending);
+                },
             }
         }
+
+        // Make sure everything we wrote has gone out before the port is closed by dropping it
+        port.flush().map_err(TeleopError::Device)
+    }
+
+    /// Attempts to write the whole command to the port.
+    fn write_to_port(port: &mut Box<serialport::SerialPort>, cmd: &commands::Command) -> io::Result<()> {
+        let msg = format!("{}\n", cmd);
+        port.write_all(msg.as_bytes())
     }
 
-    /// Attempts to write the command to the port. Returns whether or not it worked.
-    fn write_to_port(port: &mut Box<serialport::SerialPort>, cmd: commands::Command) -> io::Result<usize> {
+    /// Remembers where the command sends each servo.
+    fn track(sent: &mut [Option<u16>; 5], cmd: &commands::Command) {
         match cmd {
-            commands::Command::Led(on) => {
-                let onoff = if on { "on" } else { "off" };
-                let msg = format!("led {}\n", onoff);
-                port.write(msg.as_bytes())
-            },
-            commands::Command::Servo(id, angle) => {
-                let msg = format!("servo {} {}\n", id as u8, angle);
-                port.write(msg.as_bytes())
-            },
-            commands::Command::Quit => panic!("Should not have gotten quit command in 'write_to_port'"),
-            commands::Command::Script(_) => panic!("Should not have gotten script command in 'write_to_port'"),
-            commands::Command::Help => panic!("Should not have gotten help command in 'write_to_port'"),
+            commands::Command::Servo(id, angle) => sent[*id as usize] = Some(*angle),
             commands::Command::Home => {
-                let msg = "home\n";
-                port.write(msg.as_bytes())
+                for id in commands::ALL_SERVOS.iter() {
+                    sent[*id as usize] = Some(id.home_angle());
+                }
+            },
+            _ => (),
+        }
+    }
+
+    /// Tells the user where the device says its servos are, pointing out any that are not where we last sent them.
+    fn report_differences(angles: &[u16; 5], sent: &[Option<u16>; 5]) {
+        println!("Device reports servos at {:?}.", angles);
+        for id in commands::ALL_SERVOS.iter() {
+            if let Some(angle) = sent[*id as usize] {
+                if angle != angles[*id as usize] {
+                    println!("    {:?} is at {} but was last sent to {}.", id, angles[*id as usize], angle);
+                }
             }
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_track() {
+            let mut sent = [None; 5];
+            track(&mut sent, &commands::Command::Servo(commands::ServoId::Elbow, 30));
+            assert_eq!(sent, [None, None, Some(30), None, None]);
+            track(&mut sent, &commands::Command::Home);
+            assert_eq!(sent, [Some(90), Some(10), Some(155), Some(90), Some(90)]);
+            track(&mut sent, &commands::Command::Led(true));
+            assert_eq!(sent, [Some(90), Some(10), Some(155), Some(90), Some(90)]);
+        }
+    }
 }
@@ -1,3 +1,4 @@
 pub mod port;
 pub mod comms;
+pub mod monitor;
 pub mod testport;
\ No newline at end of file
@@ -0,0 +1,293 @@
+/// Module for reading and making sense of everything the device sends back to us.
+pub mod monitor {
+    use serialport;
+    use std::fmt;
+    use std::fs;
+    use std::io;
+    use std::io::Read;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time;
+
+    /// What a line from the device is telling us.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum LineKind {
+        /// The device is done with a command ('ok').
+        Ack,
+        /// The device could not do a command ('error: ...').
+        Error,
+        /// The device is reporting its state ('status ...' or 'telemetry ...').
+        Telemetry,
+        /// Anything else, such as the debug prints the command parser does.
+        Debug,
+    }
+
+    /// A single line from the device.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct DeviceLine {
+        pub kind: LineKind,
+        /// The line with the line ending removed.
+        pub text: String,
+        /// When the line came in, relative to when the monitor started.
+        pub elapsed: time::Duration,
+    }
+
+    impl fmt::Display for DeviceLine {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let kind = match self.kind {
+                LineKind::Ack => "ack",
+                LineKind::Error => "error",
+                LineKind::Telemetry => "telemetry",
+                LineKind::Debug => "debug",
+            };
+            write!(f, "[device {}] {}", kind, self.text)
+        }
+    }
+
+    /// Works out what kind of line the device has sent.
+    pub fn classify(line: &str) -> LineKind {
+        let lower = line.trim().to_ascii_lowercase();
+        if lower == "ok" {
+            LineKind::Ack
+        } else if lower.starts_with("error") || lower.starts_with("illegal") || lower.starts_with("usage") {
+            LineKind::Error
+        } else if lower.starts_with("status") || lower.starts_with("telemetry") {
+            LineKind::Telemetry
+        } else {
+            LineKind::Debug
+        }
+    }
+
+    /// Parses the device's answer to a status command: 'status <angle of servo 0> ... <angle of servo 4>'.
+    pub fn parse_status(line: &str) -> Option<[u16; 5]> {
+        let mut split = line.split_whitespace();
+        if split.next() != Some("status") {
+            return None;
+        }
+
+        let mut angles = [0; 5];
+        for angle in angles.iter_mut() {
+            *angle = split.next()?.parse().ok()?;
+        }
+        Some(angles)
+    }
+
+    /// How the device answered a command.
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum Reply {
+        /// The device did the command. Holds any telemetry it sent along the way.
+        Ack(Vec<String>),
+        /// The device could not do the command. Holds what it said.
+        Rejected(String),
+        /// The device did not answer in time.
+        TimedOut,
+        /// The monitor stopped listening to the device.
+        Disconnected,
+    }
+
+    /// Waits until the device either acknowledges or rejects the last command sent to it, or until `timeout` is up.
+    pub fn await_reply(device: &mpsc::Receiver<DeviceLine>, timeout: time::Duration) -> Reply {
+        let start = time::Instant::now();
+        let mut telemetry = Vec::new();
+        while let Some(left) = timeout.checked_sub(start.elapsed()) {
+            match device.recv_timeout(left) {
+                Ok(line) => match line.kind {
+                    LineKind::Ack => return Reply::Ack(telemetry),
+                    LineKind::Error => return Reply::Rejected(line.text),
+                    LineKind::Telemetry => telemetry.push(line.text),
+                    LineKind::Debug => (),
+                },
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Reply::Disconnected,
+            }
+        }
+        Reply::TimedOut
+    }
+
+    /// Collects bytes until they make up whole lines.
+    struct LineSplitter {
+        partial: Vec<u8>,
+    }
+
+    impl LineSplitter {
+        fn new() -> Self {
+            LineSplitter { partial: Vec::new() }
+        }
+
+        /// Adds `bytes` and returns any lines they complete, without their line endings. Blank lines are dropped.
+        fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+            let mut lines = Vec::new();
+            for byte in bytes {
+                if *byte != b'\n' {
+                    self.partial.push(*byte);
+                    continue;
+                }
+
+                let line = String::from_utf8_lossy(&self.partial).trim_end_matches('\r').to_string();
+                if !line.trim().is_empty() {
+                    lines.push(line);
+                }
+                self.partial.clear();
+            }
+            lines
+        }
+    }
+
+    /// A handle on the thread that reads from the device. Any number of other threads can
+    /// subscribe to get their own copy of every line the device sends from then on.
+    ///
+    /// The thread keeps its port open until it errors out or `stop` is called.
+    #[derive(Clone)]
+    pub struct DeviceMonitor {
+        subscribers: Arc<Mutex<Vec<mpsc::Sender<DeviceLine>>>>,
+        log: Arc<Mutex<Option<fs::File>>>,
+        echo_debug: bool,
+        start: time::Instant,
+        /// Tells the reading threads to stop, which they notice the next time a read times out (or succeeds).
+        stopping: Arc<AtomicBool>,
+        readers: Arc<Mutex<Vec<thread::JoinHandle<()>>>>,
+    }
+
+    impl DeviceMonitor {
+        /// Starts a thread that reads lines from `port` until it errors out. Each line is classified,
+        /// printed to the console (debug lines only if `echo_debug`), appended to the log at `logpath`
+        /// if there is one, and sent to every subscriber.
+        pub fn start(port: Box<serialport::SerialPort>, logpath: Option<&str>, echo_debug: bool) -> io::Result<DeviceMonitor> {
+            let log = match logpath {
+                Some(path) => Some(fs::OpenOptions::new().create(true).append(true).open(path)?),
+                None => None,
+            };
+
+            let monitor = DeviceMonitor {
+                subscribers: Arc::new(Mutex::new(Vec::new())),
+                log: Arc::new(Mutex::new(log)),
+                echo_debug: echo_debug,
+                start: time::Instant::now(),
+                stopping: Arc::new(AtomicBool::new(false)),
+                readers: Arc::new(Mutex::new(Vec::new())),
+            };
+            monitor.attach(port);
+            Ok(monitor)
+        }
+
+        /// Starts another reading thread on `port`, feeding the same log and subscribers.
+        /// Used once the device has been reconnected, after the thread on the old port has errored out.
+        pub fn attach(&self, mut port: Box<serialport::SerialPort>) {
+            let monitor = self.clone();
+            let reader = thread::spawn(move || {
+                let mut splitter = LineSplitter::new();
+                let mut buf = [0u8; 64];
+                while !monitor.stopping.load(Ordering::SeqCst) {
+                    let nbytes = match port.read(&mut buf) {
+                        Ok(n) => n,
+                        Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                        Err(e) => {
+                            println!("Stopped listening to the device due to error: {:?}", e);
+                            return;
+                        },
+                    };
+
+                    for text in splitter.push(&buf[..nbytes]) {
+                        monitor.publish(DeviceLine { kind: classify(&text), text: text, elapsed: monitor.start.elapsed() });
+                    }
+                }
+            });
+            self.readers.lock().unwrap().push(reader);
+        }
+
+        /// Stops every reading thread, closing their ports, and hangs up on the subscribers.
+        /// Returns once the threads have finished.
+        pub fn stop(&self) {
+            self.stopping.store(true, Ordering::SeqCst);
+            let readers: Vec<thread::JoinHandle<()>> = self.readers.lock().unwrap().drain(..).collect();
+            for reader in readers {
+                reader.join().ok();
+            }
+            self.subscribers.lock().unwrap().clear();
+        }
+
+        /// Prints, logs and hands out a line from the device.
+        fn publish(&self, line: DeviceLine) {
+            if line.kind != LineKind::Debug || self.echo_debug {
+                println!("{}", line);
+            }
+            if let Some(ref mut f) = *self.log.lock().unwrap() {
+                let ms = line.elapsed.as_secs() * 1000 + (line.elapsed.subsec_nanos() / 1_000_000) as u64;
+                if let Err(e) = writeln!(f, "{} {}", ms, line) {
+                    println!("Could not write to the device log: {:?}", e);
+                }
+            }
+
+            // Hand the line to everyone who wants it, forgetting anyone who has hung up
+            self.subscribers.lock().unwrap().retain(|tx| tx.send(line.clone()).is_ok());
+        }
+
+        /// Returns a channel that gets every line the device sends from now on.
+        pub fn subscribe(&self) -> mpsc::Receiver<DeviceLine> {
+            let (tx, rx) = mpsc::channel();
+            self.subscribers.lock().unwrap().push(tx);
+            rx
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use serial::testport;
+
+        #[test]
+        fn test_classify() {
+            assert_eq!(classify("ok"), LineKind::Ack);
+            assert_eq!(classify("OK\r"), LineKind::Ack);
+            assert_eq!(classify("error: Illegal angle"), LineKind::Error);
+            assert_eq!(classify("Illegal servo ID"), LineKind::Error);
+            assert_eq!(classify("USAGE: servo <id> <angle>"), LineKind::Error);
+            assert_eq!(classify("status 90 10 155 90 90"), LineKind::Telemetry);
+            assert_eq!(classify("Newline present. Processing command."), LineKind::Debug);
+            assert_eq!(classify("okay then"), LineKind::Debug);
+        }
+
+        #[test]
+        fn test_parse_status() {
+            assert_eq!(parse_status("status 90 10 155 90 90"), Some([90, 10, 155, 90, 90]));
+            assert_eq!(parse_status("status 90 10 155"), None);
+            assert_eq!(parse_status("status 90 10 155 90 abc"), None);
+            assert_eq!(parse_status("ok"), None);
+        }
+
+        #[test]
+        fn test_await_reply() {
+            let (tx, rx) = mpsc::channel();
+            let line = |kind, text: &str| DeviceLine { kind: kind, text: text.to_string(), elapsed: time::Duration::from_millis(0) };
+            tx.send(line(LineKind::Telemetry, "status 90 10 155 90 90")).unwrap();
+            tx.send(line(LineKind::Debug, "Processing command.")).unwrap();
+            tx.send(line(LineKind::Ack, "ok")).unwrap();
+            assert_eq!(await_reply(&rx, time::Duration::from_secs(1)), Reply::Ack(vec!["status 90 10 155 90 90".to_string()]));
+            assert_eq!(await_reply(&rx, time::Duration::from_millis(10)), Reply::TimedOut);
+            drop(tx);
+            assert_eq!(await_reply(&rx, time::Duration::from_secs(1)), Reply::Disconnected);
+        }
+
+        #[test]
+        fn test_stop_lets_go_of_port() {
+            let port = testport::TestPort::new();
+            let monitor = DeviceMonitor::start(serialport::SerialPort::try_clone(&port).unwrap(), None, false).unwrap();
+            let lines = monitor.subscribe();
+            assert_eq!(port.handles(), 2);
+            monitor.stop();
+            assert_eq!(port.handles(), 1);
+            assert!(lines.recv().is_err());
+        }
+
+        #[test]
+        fn test_line_splitter() {
+            let mut splitter = LineSplitter::new();
+            assert!(splitter.push(b"Newline pres").is_empty());
+            assert_eq!(splitter.push(b"ent.\r\nok\r\n\r\nerr"), vec!["Newline present.".to_string(), "ok".to_string()]);
+            assert_eq!(splitter.push(b"or: Illegal angle\n"), vec!["error: Illegal angle".to_string()]);
+        }
+    }
+}
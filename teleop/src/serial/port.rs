@@ -7,6 +7,9 @@ pub mod portcomms {
     const FTDI_2232H_VID: u16 = 0x0403;
     const FTDI_2232H_PID: u16 = 0x6010;
 
+    /// What the user passes in to get the test port.
+    const TEST_PORT_ARG: &str = "test";
+
     /// Builds a new instance of SerialPortSettings, using the default settings for this program.
     /// Returns the struct by ownership.
     fn build_default_port_settings() -> serialport::SerialPortSettings {
@@ -32,15 +35,19 @@ pub mod portcomms {
         }
     }
 
-    /// Returns Some(open serial port) or None, by finding and opening the appropriate port
-    /// from a list of serial port info objects.
-    fn get_serial_port_by_vidpid(ports: Vec<serialport::SerialPortInfo>) -> Option<Box<serialport::SerialPort>> {
-        // result should be the first item with the appropriate VID and PID
+    /// Returns true if the USB port is the one described by `vid`, `pid` and (if we know it) `serial_number`.
+    fn usb_port_matches(info: &serialport::UsbPortInfo, vid: u16, pid: u16, serial_number: &Option<String>) -> bool {
+        info.vid == vid && info.pid == pid && (serial_number.is_none() || info.serial_number == *serial_number)
+    }
+
+    /// Returns Some(open serial port) or None, by finding and opening the first port
+    /// from a list of serial port info objects that matches the given USB IDs.
+    fn get_serial_port_by_vidpid(ports: Vec<serialport::SerialPortInfo>, vid: u16, pid: u16, serial_number: &Option<String>) -> Option<Box<serialport::SerialPort>> {
         for p in ports {
             let dup = p.clone();
             match dup.port_type {
                 serialport::SerialPortType::UsbPort(info) => {
-                    if info.vid == FTDI_2232H_VID && info.pid == FTDI_2232H_PID {
+                    if usb_port_matches(&info, vid, pid, serial_number) {
                         let result = open_serial_port(p);
                         return result;
                     }
@@ -51,6 +58,55 @@ pub mod portcomms {
         None
     }
 
+    /// Enough about an open port to find the same device again if it goes away.
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum PortIdentity {
+        /// The test port, which can always be opened again.
+        Test,
+        /// A USB serial device. Its path may change when it is plugged back in, but its IDs won't.
+        Usb { port_name: String, vid: u16, pid: u16, serial_number: Option<String> },
+        /// Any other port, which we can only find again by its path.
+        Path(String),
+    }
+
+    /// Works out which device the port is connected to, so that `reopen_serial_port` can find it again.
+    pub fn identify_port(port: &Box<serialport::SerialPort>) -> PortIdentity {
+        let port_name = match port.name() {
+            Some(ref name) if name == testport::NAME => return PortIdentity::Test,
+            Some(name) => name,
+            None => String::new(),
+        };
+
+        if let Ok(ports) = serialport::available_ports() {
+            for p in ports {
+                if let serialport::SerialPortType::UsbPort(info) = p.port_type {
+                    if p.port_name == port_name {
+                        return PortIdentity::Usb { port_name: port_name, vid: info.vid, pid: info.pid, serial_number: info.serial_number };
+                    }
+                }
+            }
+        }
+        PortIdentity::Path(port_name)
+    }
+
+    /// Tries once to open the device described by `identity`, looking it up by VID/PID/serial number first
+    /// and falling back on its old path. Returns None if it isn't there (yet).
+    pub fn reopen_serial_port(identity: &PortIdentity) -> Option<Box<serialport::SerialPort>> {
+        let port_name = match identity {
+            PortIdentity::Test => return Some(Box::new(testport::TestPort::new())),
+            PortIdentity::Usb { port_name, vid, pid, serial_number } => {
+                if let Ok(ports) = serialport::available_ports() {
+                    if let Some(port) = get_serial_port_by_vidpid(ports, *vid, *pid, serial_number) {
+                        return Some(port);
+                    }
+                }
+                port_name
+            },
+            PortIdentity::Path(port_name) => port_name,
+        };
+        serialport::open_with_settings(port_name.as_str(), &build_default_port_settings()).ok()
+    }
+
     /// Get the serial port to the robot arm or None.
     /// If the user has requested a particular com port, that one is tried first.
     /// If the special string 'test' is passed in, we give a test port.
@@ -58,8 +114,8 @@ pub mod portcomms {
         // If the user has requested a port
         if let Some(comname) = user_requested_port {
             // Check if it is the test port
-            if comname.trim().to_ascii_lowercase() == "test" {
-                return Some(Box::new(testport::TestPort));
+            if comname.trim().to_ascii_lowercase() == TEST_PORT_ARG {
+                return Some(Box::new(testport::TestPort::new()));
             }
 
             // If it is a real port, try opening it
@@ -74,7 +130,7 @@ pub mod portcomms {
         if let Ok(ports) = serialport::available_ports() {
             match ports.len() {
                 0 => None,
-                _n => get_serial_port_by_vidpid(ports),
+                _n => get_serial_port_by_vidpid(ports, FTDI_2232H_VID, FTDI_2232H_PID, &None),
             }
         } else {
             panic!("Error listing serial ports.");
@@ -1,15 +1,77 @@
+use commands;
 use serialport;
 use serialport::Result;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::Duration;
 use std::io;
 
-pub struct TestPort;
+/// A fake serial port that stands in for the device when we don't have one plugged in.
+///
+/// It answers every line written to it with 'ok', the way the device acknowledges commands,
+/// and keeps track of where servo commands would have moved the arm so it can answer 'status'.
+/// Clones share the same state, so one thread can write to the port while another reads.
+pub struct TestPort {
+    device: Arc<Mutex<FakeDevice>>,
+}
+
+/// What the fake device has seen and has to say.
+struct FakeDevice {
+    /// Bytes of a line that has not been finished yet.
+    partial: Vec<u8>,
+    /// Where each servo would be.
+    angles: [u16; 5],
+    /// Bytes the fake device has sent that have not been read yet.
+    replies: VecDeque<u8>,
+}
+
+impl FakeDevice {
+    /// Answers a whole line the way the device would (minus the debug output).
+    fn handle_line(&mut self, line: &str) {
+        match commands::Command::new_from_string(line) {
+            Ok(commands::Command::Servo(id, angle)) => self.angles[id as usize] = angle,
+            Ok(commands::Command::Home) => {
+                for id in commands::ALL_SERVOS.iter() {
+                    self.angles[*id as usize] = id.home_angle();
+                }
+            },
+            Ok(commands::Command::Status) => {
+                let angles: Vec<String> = self.angles.iter().map(|a| a.to_string()).collect();
+                self.replies.extend(format!("status {}\n", angles.join(" ")).bytes());
+            },
+            _ => (),
+        }
+        self.replies.extend(b"ok\n".iter());
+    }
+}
 
 const DEFAULT_BAUD_RATE: u32 = 115200;
 
+/// What the test port calls itself.
+pub const NAME: &str = "Test Port";
+
+impl TestPort {
+    pub fn new() -> Self {
+        let mut angles = [0; 5];
+        for id in commands::ALL_SERVOS.iter() {
+            angles[*id as usize] = id.home_angle();
+        }
+
+        TestPort {
+            device: Arc::new(Mutex::new(FakeDevice { partial: Vec::new(), angles: angles, replies: VecDeque::new() })),
+        }
+    }
+
+    /// How many handles (this one and its clones) are open on the fake device, so tests can check that a port was let go of.
+    pub fn handles(&self) -> usize {
+        Arc::strong_count(&self.device)
+    }
+}
+
 impl serialport::SerialPort for TestPort {
     fn name(&self) -> Option<String> {
-        Some("Test Port".to_string())
+        Some(NAME.to_string())
     }
 
     fn settings(&self) -> serialport::SerialPortSettings {
@@ -100,12 +162,22 @@ impl serialport::SerialPort for TestPort {
     }
 
     fn try_clone(&self) -> Result<Box<serialport::SerialPort>> {
-        Ok(Box::new(TestPort))
+        Ok(Box::new(TestPort { device: self.device.clone() }))
     }
 }
 
 impl io::Write for TestPort {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut device = self.device.lock().unwrap();
+        for byte in buf {
+            if *byte == b'\n' {
+                let line = String::from_utf8_lossy(&device.partial).to_string();
+                device.partial.clear();
+                device.handle_line(&line);
+            } else {
+                device.partial.push(*byte);
+            }
+        }
         Ok(buf.len())
     }
 
@@ -117,9 +189,20 @@ impl io::Write for TestPort {
 impl io::Read for TestPort {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let mut n = 0;
-        for (i, c) in "test".as_bytes().iter().enumerate() {
-            buf[i] = *c;
-            n += 1;
+        {
+            let mut device = self.device.lock().unwrap();
+            while n < buf.len() {
+                match device.replies.pop_front() {
+                    Some(c) => { buf[n] = c; n += 1; },
+                    None => break,
+                }
+            }
+        }
+
+        // Behave like a real port with nothing to say: wait out the timeout, then report it
+        if n == 0 {
+            thread::sleep(serialport::SerialPort::timeout(self));
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "Operation timed out"));
         }
         Ok(n)
     }
@@ -0,0 +1,182 @@
+/// Module for recording the commands an operator sends to the arm and for replaying them later.
+///
+/// A session log is a plain text file with one command per line, each prefixed by the number of
+/// milliseconds since the start of the recording at which the command was sent:
+///
+/// ```text
+/// # teleop session log
+/// 0 home
+/// 1520 servo 0 120
+/// 3044 led on
+/// 5210 quit
+/// ```
+pub mod recording {
+    use commands;
+    use errors::TeleopError;
+    use std::fs;
+    use std::io;
+    use std::io::BufRead;
+    use std::io::Write;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time;
+
+    /// A single command from a session log, along with when it was sent relative to the start of the session.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Entry {
+        pub timestamp: time::Duration,
+        pub cmd: commands::Command,
+    }
+
+    /// Sits between the console thread and the serial thread: every command received on `rx`
+    /// is written to the log at `logpath` along with a monotonic timestamp, then forwarded on `tx`.
+    /// Returns once the quit command has been recorded and forwarded.
+    pub fn record_until_quit(rx: mpsc::Receiver<commands::Command>, tx: mpsc::Sender<commands::Command>, logpath: &str) -> io::Result<()> {
+        let mut log = fs::File::create(logpath)?;
+        writeln!(log, "# teleop session log")?;
+
+        let start = time::Instant::now();
+        let mut should_quit = false;
+        while !should_quit {
+            let cmd = match rx.recv() {
+                Ok(cmd) => cmd,
+                Err(_) => break,
+            };
+
+            // Write (and flush) the entry before forwarding, so that a crash never loses a command the arm saw
+            writeln!(log, "{} {}", duration_to_millis(start.elapsed()), cmd)?;
+            log.flush()?;
+
+            should_quit = cmd == commands::Command::Quit;
+            if tx.send(cmd).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a session log from `fpath` and parses it into its entries.
+    pub fn load_session(fpath: &str) -> Result<Vec<Entry>, String> {
+        let file = match fs::File::open(fpath) {
+            Ok(file) => file,
+            Err(e) => return Err(format!("Could not open {}. Error: {:?}", fpath, e)),
+        };
+
+        let mut entries = Vec::new();
+        for (lineno, line) in io::BufReader::new(file).lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Err(format!("Couldn't read line {}: {:?}", lineno, e)),
+            };
+
+            match parse_entry(&line) {
+                Ok(Some(entry)) => entries.push(entry),
+                Ok(None) => (),
+                Err(msg) => return Err(format!("Problem with session log at line {}: {}", lineno, msg)),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Parses a single line of a session log. Returns None for blank lines and comments.
+    fn parse_entry(line: &str) -> Result<Option<Entry>, String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        let mut split = line.splitn(2, char::is_whitespace);
+        let millis = match split.next().unwrap().parse::<u64>() {
+            Ok(ms) => ms,
+            Err(_) => return Err("Line does not start with a timestamp in milliseconds".to_string()),
+        };
+        let cmd = match split.next() {
+            Some(cmdstr) => commands::Command::new_from_string(cmdstr)?,
+            None => return Err("Line has a timestamp but no command".to_string()),
+        };
+
+        Ok(Some(Entry { timestamp: time::Duration::from_millis(millis), cmd: cmd }))
+    }
+
+    /// Sends each entry over `tx` with the same spacing as when it was recorded, scaled by `speed`
+    /// (so a speed of 0.5 takes twice as long as the original session). Stops after sending a quit command.
+    pub fn replay_session(tx: &mpsc::Sender<commands::Command>, entries: &[Entry], speed: f64) -> Result<(), TeleopError> {
+        if !(speed > 0.0) {
+            return Err(TeleopError::Usage(format!("Replay speed must be greater than zero, but is {}", speed)));
+        }
+
+        // Schedule against the start of the replay rather than the previous command so that errors don't accumulate
+        let start = time::Instant::now();
+        for entry in entries {
+            let due = time::Duration::from_millis((duration_to_millis(entry.timestamp) as f64 / speed).round() as u64);
+            let elapsed = start.elapsed();
+            if due > elapsed {
+                thread::sleep(due - elapsed);
+            }
+
+            match entry.cmd {
+                commands::Command::Help | commands::Command::Script(_) => continue,
+                _ => (),
+            }
+
+            println!("Sending command {:?}", entry.cmd);
+            if tx.send(entry.cmd.clone()).is_err() {
+                return Err(TeleopError::ChannelClosed);
+            }
+            if entry.cmd == commands::Command::Quit {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the session out in the plain script format consumed by `user_input::run_script`.
+    /// Timing is dropped, and so are the commands that a script may not contain.
+    pub fn save_as_script(entries: &[Entry], fpath: &str) -> io::Result<()> {
+        let mut f = fs::File::create(fpath)?;
+        for entry in entries {
+            match entry.cmd {
+                commands::Command::Quit | commands::Command::Script(_) | commands::Command::Help => (),
+                _ => writeln!(f, "{}", entry.cmd)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn duration_to_millis(d: time::Duration) -> u64 {
+        d.as_secs() * 1000 + (d.subsec_nanos() / 1_000_000) as u64
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_parse_entry() {
+            let entry = parse_entry("1520 servo 0 120").unwrap().unwrap();
+            assert_eq!(entry.timestamp, time::Duration::from_millis(1520));
+            assert_eq!(entry.cmd, commands::Command::Servo(commands::ServoId::Base, 120));
+
+            assert_eq!(parse_entry("   ").unwrap(), None);
+            assert_eq!(parse_entry("# teleop session log").unwrap(), None);
+            assert!(parse_entry("servo 0 120").is_err());
+            assert!(parse_entry("1520").is_err());
+            assert!(parse_entry("1520 servo 9 120").is_err());
+        }
+
+        #[test]
+        fn test_logged_commands_parse_back() {
+            let cmds = vec![
+                commands::Command::Home,
+                commands::Command::Led(true),
+                commands::Command::Led(false),
+                commands::Command::Servo(commands::ServoId::Elbow, 155),
+                commands::Command::Quit,
+            ];
+            for cmd in cmds {
+                let line = format!("{} {}", 42, cmd);
+                assert_eq!(parse_entry(&line).unwrap().unwrap().cmd, cmd);
+            }
+        }
+    }
+}